@@ -0,0 +1,33 @@
+use std::io::{self, Write};
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+
+use log::{debug, error};
+use common::control_socket;
+
+use crate::PID;
+
+const WRITE_TIMEOUT: Duration = Duration::from_millis(100);
+
+// a single line to the control socket of the loader daemon, without waiting for the reply;
+// the daemon takes the pid from the connection, so none is sent
+fn send(command: &str) -> io::Result<()> {
+    let mut stream = UnixStream::connect(control_socket())?;
+    stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+    stream.write_all(format!("{command}\n").as_bytes())
+}
+
+// best effort, the daemon may be unreachable from the context of the host process
+pub fn report_panic(message: &str) {
+    if let Err(err) = send(&format!("panic {}", message.replace('\n', " "))) {
+        error!("[{}] failed to report panic to daemon: {err}", *PID);
+    }
+}
+
+// tells the daemon the bridge is up, an injected process that never sends it runs half-injected
+pub fn heartbeat() {
+    match send("heartbeat") {
+        Ok(()) => debug!("[{}] heartbeat sent", *PID),
+        Err(err) => error!("[{}] failed to send heartbeat to daemon: {err}", *PID)
+    }
+}
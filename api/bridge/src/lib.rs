@@ -1,26 +1,68 @@
 use std::arch::asm;
-use std::env;
+use std::ffi::CStr;
+use std::path::PathBuf;
+use std::sync::Once;
+use std::ptr;
 
 use ctor::ctor;
 use log::{debug, error, LevelFilter};
-use common::debug_select;
-use common::zygote::SpecializeArgs;
+use common::{debug_select, features, rootimpl};
+use common::zygote::{ArgLayout, SpecializeArgs};
 
 use common::lazy::{LateInit, Lazy};
 
+mod control;
+pub mod libs;
+pub mod panic;
+pub mod worker;
+
 extern {
     fn bridge_main();
 }
 
+// checked by the loader right after dlopen, a bridge left over from a partial update is never called into
+#[no_mangle]
+pub static ZLB_ABI: u64 = common::bridge_abi();
+
 #[no_mangle]
 pub static mut ZLB_CALLBACK_PRE: usize = 0;
 
+#[no_mangle]
+pub static mut ZLB_CALLBACK_POST: usize = 0;
+
 #[no_mangle]
 pub static mut ZLB_TRAMPOLINE: usize = 0;
 
 #[no_mangle]
 pub static mut ZLB_RETURN_ADDRESS: usize = 0;
 
+// written by the loader once, shared by pre and post specialize
+#[no_mangle]
+pub static mut ZLB_ARGS: [u64; 32] = [0; 32];
+
+#[no_mangle]
+pub static mut ZLB_ARGS_LEN: usize = 0;
+
+// derived from the signature of SpecializeCommon by the loader, unknown if it couldn't be
+#[no_mangle]
+pub static mut ZLB_ARGS_LAYOUT: ArgLayout = ArgLayout::UNKNOWN;
+
+// written by the loader right after dlopen, apps can't tell the root implementation by themselves
+#[no_mangle]
+pub static mut ZLB_STAGING_DIR: [u8; 256] = [0; 256];
+
+// written by the loader right after dlopen as well, where this bridge and its new dependencies are mapped
+#[no_mangle]
+pub static mut ZLB_BASE: usize = 0;
+
+#[no_mangle]
+pub static mut ZLB_RANGES: [[usize; 2]; 32] = [[0; 2]; 32];
+
+#[no_mangle]
+pub static mut ZLB_RANGES_LEN: usize = 0;
+
+static LOADED: Once = Once::new();
+
 static G_BRIDGE: LateInit<Box<dyn ApiBridge>> = LateInit::new();
 
 static PID: Lazy<i32> = Lazy::new(|| unsafe { libc::getpid() });
@@ -28,33 +70,52 @@ static PID: Lazy<i32> = Lazy::new(|| unsafe { libc::getpid() });
 pub trait ApiBridge: Send + Sync {
     fn on_dlopen(&self);
     fn on_specialize(&self, args: SpecializeArgs);
-    fn after_specialize(&self);
+    fn after_specialize(&self, args: SpecializeArgs);
 }
 
 #[ctor]
 fn init() {
-    if env::var("ZLB_NOLOAD").is_ok() {
+    if features::noload() {
         return;
     }
     
     android_logger::init_once(
         android_logger::Config::default()
-            .with_max_level(debug_select!(LevelFilter::Trace, LevelFilter::Info))
+            .with_max_level(features::log_level(debug_select!(LevelFilter::Trace, LevelFilter::Info)))
             .with_tag("ZLoader-Bridge")
     );
 
     unsafe {
         ZLB_CALLBACK_PRE = on_specialize as usize;
+        ZLB_CALLBACK_POST = after_specialize as usize;
         ZLB_TRAMPOLINE = trampoline as usize;
     }
 
+    panic::install_hook();
+
     debug!("[{}] api bridge initialized", *PID);
+    features::log_effective("bridge");
 
-    unsafe {
-        bridge_main();
-    }
+    panic::guard("bridge_main", || unsafe { bridge_main() });
+}
 
-    G_BRIDGE.on_dlopen()
+// modules are loaded from the staging dir, which is only known once the loader has written it
+fn ensure_loaded() {
+    LOADED.call_once(|| {
+        let dir = unsafe { CStr::from_bytes_until_nul(&*ptr::addr_of!(ZLB_STAGING_DIR)) };
+
+        match dir.map(|dir| dir.to_string_lossy()) {
+            Ok(dir) if !dir.is_empty() => {
+                debug!("[{}] staging dir: {dir}", *PID);
+                rootimpl::set_staging_dir(PathBuf::from(dir.as_ref()));
+            }
+            _ => error!("[{}] staging dir is not set by the loader, falling back to the default", *PID)
+        }
+
+        if panic::guard("on_dlopen", || G_BRIDGE.on_dlopen()).is_some() {
+            control::heartbeat();
+        }
+    });
 }
 
 pub fn register(bridge: impl ApiBridge + 'static) {
@@ -63,21 +124,42 @@ pub fn register(bridge: impl ApiBridge + 'static) {
     }
 }
 
-// `args[n]` is not valid after return, copy and save them
-extern "C" fn on_specialize(args: *mut u64, _args_len: usize) {
-    let args = SpecializeArgs::from(args);
+// none if the layout is unknown, reading the args then could crash the app
+fn shared_args() -> Option<SpecializeArgs> {
+    let args = unsafe {
+        SpecializeArgs::with_layout(ptr::addr_of_mut!(ZLB_ARGS) as *mut u64, ZLB_ARGS_LEN, ZLB_ARGS_LAYOUT.or_sdk())
+    };
+
+    match args.arg_count() {
+        Ok(_) => Some(args),
+        Err(err) => {
+            error!("[{}] unknown layout of specialize args, callbacks skipped: {err}", *PID);
+            None
+        }
+    }
+}
 
+extern "C" fn on_specialize() {
     debug!("[{}] on specialize", *PID);
-    debug!("[{}] specialize args = {args:?}", *PID);
+    ensure_loaded();
 
-    G_BRIDGE.on_specialize(args);
+    if let Some(args) = shared_args() {
+        debug!("[{}] specialize args = {args:?}", *PID);
+        panic::guard("on_specialize", || G_BRIDGE.on_specialize(args));
+    }
 }
 
 extern "C" fn after_specialize() {
     debug!("[{}] after specialize", *PID);
+    ensure_loaded();
+
+    if let Some(args) = shared_args() {
+        panic::guard("after_specialize", || G_BRIDGE.after_specialize(args));
+    }
+
+    // work deferred by modules so far, they may keep deferring while it runs
+    worker::start();
 
-    G_BRIDGE.after_specialize();
-    
     // Todo: dlclose
 }
 
@@ -0,0 +1,161 @@
+use std::ffi::{c_void, CStr};
+use std::fs;
+use std::mem::MaybeUninit;
+use std::ptr;
+use std::sync::Mutex;
+
+use log::{debug, error};
+
+use crate::{ZLB_BASE, ZLB_RANGES, ZLB_RANGES_LEN};
+
+#[derive(Debug, Clone)]
+pub struct LibraryRecord {
+    pub name: String,
+    pub base: usize,
+    pub ranges: Vec<(usize, usize)>
+}
+
+static G_LIBRARIES: Mutex<Vec<LibraryRecord>> = Mutex::new(Vec::new());
+
+struct MapEntry<'a> {
+    begin: usize,
+    end: usize,
+    pathname: &'a str
+}
+
+fn parse_maps_line(line: &str) -> Option<MapEntry> {
+    let mut rest = line;
+    let mut range = "";
+
+    // address, perms, offset, dev, inode
+    for i in 0 .. 5 {
+        rest = rest.trim_start();
+        let end = rest.find(' ')?;
+        if i == 0 {
+            range = &rest[.. end];
+        }
+        rest = &rest[end ..];
+    }
+
+    let (begin, end) = range.split_once('-')?;
+
+    Some(MapEntry {
+        begin: usize::from_str_radix(begin, 16).ok()?,
+        end: usize::from_str_radix(end, 16).ok()?,
+        pathname: rest.trim()
+    })
+}
+
+// the loader reports a fake name for libraries loaded from fds, so match the mappings by pathname in maps
+fn find_ranges(base: usize) -> Vec<(usize, usize)> {
+    let maps = match fs::read_to_string("/proc/self/maps") {
+        Ok(maps) => maps,
+        Err(err) => {
+            error!("failed to read maps: {err}");
+            return Vec::new()
+        }
+    };
+
+    let entries: Vec<_> = maps.lines().filter_map(parse_maps_line).collect();
+
+    let pathname = match entries.iter().find(|entry| entry.begin <= base && base < entry.end) {
+        Some(entry) if !entry.pathname.is_empty() => entry.pathname,
+        _ => return Vec::new()
+    };
+
+    entries.iter()
+        .filter(|entry| entry.pathname == pathname)
+        .map(|entry| (entry.begin, entry.end))
+        .collect()
+}
+
+// record the library that contains `addr`, so that maps cleanup and unload can account for it
+pub fn register_library(addr: *const c_void) -> bool {
+    let mut info: MaybeUninit<libc::Dl_info> = MaybeUninit::uninit();
+
+    let info = unsafe {
+        if libc::dladdr(addr, info.as_mut_ptr()) == 0 {
+            error!("failed to register library: 0x{:x} is not in any library", addr as usize);
+            return false
+        }
+
+        info.assume_init()
+    };
+
+    let name = if info.dli_fname.is_null() {
+        String::new()
+    } else {
+        unsafe { CStr::from_ptr(info.dli_fname).to_string_lossy().into() }
+    };
+
+    let base = info.dli_fbase as usize;
+
+    let mut lock = G_LIBRARIES.lock().unwrap();
+    if lock.iter().any(|lib| lib.base == base) {
+        return true
+    }
+
+    let record = LibraryRecord { name, base, ranges: find_ranges(base) };
+    debug!("library registered: {record:?}");
+
+    lock.push(record);
+
+    true
+}
+
+pub fn registered_libraries() -> Vec<LibraryRecord> {
+    G_LIBRARIES.lock().unwrap().clone()
+}
+
+#[no_mangle]
+pub extern "C" fn zlb_register_library(addr: *const c_void) -> bool {
+    crate::panic::guard("zlb_register_library", || register_library(addr)).unwrap_or(false)
+}
+
+// base of the registered library that contains `addr`, a module may pass any of its own addresses
+pub fn library_base(addr: usize) -> Option<usize> {
+    G_LIBRARIES.lock().unwrap().iter()
+        .find(|lib| lib.ranges.iter().any(|(begin, end)| *begin <= addr && addr < *end))
+        .map(|lib| lib.base)
+}
+
+// as recorded by the loader, zero before it has shared its config
+pub fn bridge_base() -> usize {
+    unsafe { ZLB_BASE }
+}
+
+// ranges mapped by the dlopen of this bridge, including dependencies that weren't loaded before
+pub fn bridge_ranges() -> Vec<(usize, usize)> {
+    let ranges = unsafe { &*ptr::addr_of!(ZLB_RANGES) };
+    let len = unsafe { ZLB_RANGES_LEN }.min(ranges.len());
+
+    ranges[.. len].iter().map(|range| (range[0], range[1])).collect()
+}
+
+#[no_mangle]
+pub extern "C" fn zlb_library_base(addr: *const c_void) -> usize {
+    crate::panic::guard("zlb_library_base", || library_base(addr as usize).unwrap_or(0)).unwrap_or(0)
+}
+
+#[no_mangle]
+pub extern "C" fn zlb_bridge_base() -> usize {
+    bridge_base()
+}
+
+// copies up to `max` ranges as begin and end pairs into `out`, and returns how many there are in total
+///
+/// # Safety
+///
+/// `out` is either null or points to room for `max` pairs
+#[no_mangle]
+pub unsafe extern "C" fn zlb_bridge_ranges(out: *mut [usize; 2], max: usize) -> usize {
+    let ranges = bridge_ranges();
+
+    if !out.is_null() {
+        for (index, (begin, end)) in ranges.iter().take(max).enumerate() {
+            *out.add(index) = [*begin, *end];
+        }
+    }
+
+    ranges.len()
+}
@@ -0,0 +1,60 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use log::error;
+use common::debug_select;
+
+use crate::{control, PID};
+
+// what happens to the process after a panic in injected code
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum PanicPolicy {
+    // crash right away, so that the bug is noticed
+    Abort,
+    // keep the host process alive, and stop calling into the bridge
+    Disable
+}
+
+const POLICY: PanicPolicy = debug_select!(PanicPolicy::Abort, PanicPolicy::Disable);
+
+static G_DISABLED: AtomicBool = AtomicBool::new(false);
+
+// with `panic = "abort"` this is the last chance to tell anyone what happened
+pub fn install_hook() {
+    let default_hook = panic::take_hook();
+
+    panic::set_hook(Box::new(move |info| {
+        let message = format!("panic in injected code: {info}, policy: {POLICY:?}");
+
+        error!("[{}] {message}", *PID);
+        control::report_panic(&message);
+
+        default_hook(info);
+    }));
+}
+
+pub fn is_disabled() -> bool {
+    G_DISABLED.load(Ordering::Relaxed)
+}
+
+// run an extern "C" entry point without letting a panic unwind into foreign frames
+pub fn guard<R>(entry: &str, func: impl FnOnce() -> R) -> Option<R> {
+    if is_disabled() {
+        return None
+    }
+
+    match panic::catch_unwind(AssertUnwindSafe(func)) {
+        Ok(result) => Some(result),
+        Err(_) => {
+            error!("[{}] `{entry}` panicked, policy: {POLICY:?}", *PID);
+
+            if POLICY == PanicPolicy::Abort {
+                process::abort();
+            }
+
+            G_DISABLED.store(true, Ordering::Relaxed);
+            None
+        }
+    }
+}
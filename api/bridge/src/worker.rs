@@ -0,0 +1,188 @@
+use std::collections::{HashMap, VecDeque};
+use std::ffi::{c_char, c_void, CStr};
+use std::fs;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::{debug, error, warn};
+use common::lazy::Lazy;
+
+use crate::PID;
+
+// ANDROID_PRIORITY_BACKGROUND, the main thread keeps the cpu while it draws the first frame
+const WORKER_NICE: libc::c_int = 10;
+
+const LOOPER_POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+// work is run anyway once this passes, e.g. for a process that never starts a looper
+const LOOPER_TIMEOUT: Duration = Duration::from_secs(10);
+
+// where the main thread waits for messages once `Looper.loop()` is entered
+#[cfg(target_arch = "aarch64")]
+const LOOPER_SYSCALLS: &[libc::c_long] = &[libc::SYS_epoll_pwait];
+#[cfg(target_arch = "x86_64")]
+const LOOPER_SYSCALLS: &[libc::c_long] = &[libc::SYS_epoll_pwait, libc::SYS_epoll_wait];
+
+// when deferred work of a module runs, relative to ActivityThread starting on the main thread
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Ordering {
+    // as soon as specialize is done, alongside the main thread
+    AfterSpecialize,
+    // once the main thread has entered its looper, ActivityThread is attached then
+    AfterActivityThread
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+
+#[derive(Default)]
+struct Queue {
+    pending: VecDeque<(String, Job)>,
+    orderings: HashMap<String, Ordering>,
+    // jobs only start to run after specialize, those deferred before wait in `pending`
+    specialized: bool,
+    running: bool,
+    looper_started: bool
+}
+
+impl Queue {
+    fn ordering(&self, module: &str) -> Ordering {
+        self.orderings.get(module).copied().unwrap_or(Ordering::AfterActivityThread)
+    }
+
+    // the first job that may run now, jobs of a module share an ordering so they stay in order
+    fn next(&mut self) -> Option<(String, Job)> {
+        let index = self.pending.iter()
+            .position(|(module, _)| self.looper_started || self.ordering(module) == Ordering::AfterSpecialize)?;
+
+        self.pending.remove(index)
+    }
+}
+
+static G_QUEUE: Lazy<Mutex<Queue>> = Lazy::new(Mutex::default);
+
+// the same for every job of the module, including those already deferred
+pub fn set_ordering(module: &str, ordering: Ordering) {
+    debug!("[{}] deferred work of {module} runs {ordering:?}", *PID);
+    G_QUEUE.lock().unwrap().orderings.insert(module.into(), ordering);
+}
+
+// run `work` on a low priority thread after specialize, jobs of the same module run in the order deferred
+pub fn defer(module: &str, work: impl FnOnce() + Send + 'static) {
+    G_QUEUE.lock().unwrap().pending.push_back((module.into(), Box::new(work)));
+    spawn_if_idle();
+}
+
+// called by the bridge once specialize has returned
+pub(crate) fn start() {
+    G_QUEUE.lock().unwrap().specialized = true;
+    spawn_if_idle();
+}
+
+fn spawn_if_idle() {
+    let mut queue = G_QUEUE.lock().unwrap();
+
+    if !queue.specialized || queue.running || queue.pending.is_empty() {
+        return
+    }
+
+    // left unnamed, a thread of its own name would stand out in the task list of the app
+    match thread::Builder::new().spawn(run) {
+        Ok(_) => queue.running = true,
+        Err(err) => error!("[{}] failed to start worker, deferred work is dropped: {err}", *PID)
+    }
+}
+
+fn looper_entered(pid: i32) -> bool {
+    // the syscall number first, or `running`
+    fs::read_to_string(format!("/proc/self/task/{pid}/syscall"))
+        .ok()
+        .and_then(|syscall| syscall.split_whitespace().next()?.parse().ok())
+        .is_some_and(|number: libc::c_long| LOOPER_SYSCALLS.contains(&number))
+}
+
+// `ActivityThread.main()` attaches before `Looper.loop()`, so the first wait of the main thread in epoll
+// tells it has started, without a JNI call from this thread
+fn wait_for_looper() {
+    let start = Instant::now();
+
+    while !looper_entered(*PID) {
+        if start.elapsed() > LOOPER_TIMEOUT {
+            warn!("[{}] main thread hasn't entered its looper in {LOOPER_TIMEOUT:?}, running deferred work anyway", *PID);
+            break
+        }
+
+        thread::sleep(LOOPER_POLL_INTERVAL);
+    }
+
+    debug!("[{}] looper entered after {:?}", *PID, start.elapsed());
+    G_QUEUE.lock().unwrap().looper_started = true;
+}
+
+fn run() {
+    if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, WORKER_NICE) } != 0 {
+        warn!("[{}] failed to lower priority of worker: {}", *PID, std::io::Error::last_os_error());
+    }
+
+    loop {
+        let job = {
+            let mut queue = G_QUEUE.lock().unwrap();
+            let job = queue.next();
+
+            if job.is_none() && queue.pending.is_empty() {
+                queue.running = false;
+                return
+            }
+
+            job
+        };
+
+        match job {
+            Some((module, job)) => {
+                let start = Instant::now();
+                crate::panic::guard("deferred work", job);
+                debug!("[{}] deferred work of {module} took {:?}", *PID, start.elapsed());
+            }
+            // only work waiting for ActivityThread is left
+            None => wait_for_looper()
+        }
+    }
+}
+
+// `data` goes to another thread with the callback
+struct Callback(extern "C" fn(*mut c_void), *mut c_void);
+
+unsafe impl Send for Callback {}
+
+impl Callback {
+    fn call(self) {
+        (self.0)(self.1)
+    }
+}
+
+/// # Safety
+///
+/// `module` is a nul terminated string, `ordering` is 0 for after specialize and 1 for after ActivityThread
+#[no_mangle]
+pub unsafe extern "C" fn zlb_set_defer_ordering(module: *const c_char, ordering: libc::c_int) -> bool {
+    let module = CStr::from_ptr(module).to_string_lossy();
+
+    let ordering = match ordering {
+        0 => Ordering::AfterSpecialize,
+        1 => Ordering::AfterActivityThread,
+        _ => return false
+    };
+
+    crate::panic::guard("zlb_set_defer_ordering", || set_ordering(&module, ordering)).is_some()
+}
+
+/// # Safety
+///
+/// `module` is a nul terminated string, and `data` stays valid for the callback on another thread
+#[no_mangle]
+pub unsafe extern "C" fn zlb_defer(module: *const c_char, callback: extern "C" fn(*mut c_void), data: *mut c_void) {
+    let module = CStr::from_ptr(module).to_string_lossy();
+    let callback = Callback(callback, data);
+
+    crate::panic::guard("zlb_defer", || defer(&module, move || callback.call()));
+}
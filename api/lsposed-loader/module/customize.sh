@@ -1,6 +1,6 @@
-if [ "$API" -lt 31 ]; then
+if [ "$API" -lt 29 ]; then
     ui_print "! Unsupported SDK: $API"
-    abort    "! Minimal supported SDK is 31 (Android 12)"
+    abort    "! Minimal supported SDK is 29 (Android 10)"
 else
     ui_print "- Device SDK: $API"
 fi
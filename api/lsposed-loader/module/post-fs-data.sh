@@ -21,15 +21,16 @@ if [ -e "$LSPOSED/post-fs-data.sh" ]; then
     sh "$LSPOSED/post-fs-data.sh"
 fi
 
-TMPDIR=/debug_ramdisk/zloader-lsposed
+chmod +x bin/zloader
+
+# /debug_ramdisk is not there with every root implementation
+TMPDIR="$(bin/zloader staging-dir)/zloader-lsposed"
 
 mkdir -p "$TMPDIR"
 cp lib/liblsposed_loader.so "$TMPDIR"
 cp "$LSPOSED/zygisk/$(getprop ro.product.cpu.abi).so" "$TMPDIR/liblsposed.so"
 chcon -R u:object_r:system_file:s0 "$TMPDIR"
 
-chmod +x bin/zloader
-
 export ZLB_NOLOAD=1
 
-bin/zloader --filter "$TMPDIR/liblsposed_loader.so" "$TMPDIR/liblsposed_loader.so" &
+bin/zloader %ZLOADER_ARGS% --filter "$TMPDIR/liblsposed_loader.so" "$TMPDIR/liblsposed_loader.so" &
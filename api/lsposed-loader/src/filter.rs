@@ -1,5 +1,6 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::ffi::CStr;
+use std::fs;
 use std::path::PathBuf;
 use std::sync::{mpsc, Mutex};
 use std::thread;
@@ -23,6 +24,7 @@ const MANAGER_PACKAGE: &str = "org.lsposed.manager";
 const PER_USER_RANGE: libc::uid_t = 100000;
 
 const DATABASE: &str = "/data/adb/lspd/config/modules_config.db";
+const PACKAGES_LIST: &str = "/data/system/packages.list";
 
 const SQL: &str = "
 SELECT DISTINCT s.app_pkg_name, s.user_id
@@ -157,29 +159,88 @@ static G_SCOPE: Lazy<Mutex<HashSet<ScopeInfo>>> = Lazy::new(|| {
     Mutex::new(HashSet::new())
 });
 
+fn read_app_ids() -> Result<HashMap<String, libc::uid_t>> {
+    let content = fs::read_to_string(PACKAGES_LIST)?;
+
+    Ok(content.lines().filter_map(|line| {
+        let mut fields = line.split_whitespace();
+        let pkg = fields.next()?;
+        let app_id = fields.next()?.parse().ok()?;
+
+        Some((pkg.into(), app_id))
+    }).collect())
+}
+
 #[no_mangle]
 #[allow(clippy::not_unsafe_ptr_arg_deref)]
-pub extern "C" fn check_process(uid: libc::uid_t, pkg: *const c_char, _name: *const c_char) -> bool {
-    let _ = &*INIT_LOGGER;
-    let _ = &*G_SCOPE;
+pub extern "C" fn collect_uids(buffer: *mut libc::uid_t, capacity: usize) -> usize {
+    bridge::panic::guard("collect_uids", || {
+        let _ = &*INIT_LOGGER;
+        let _ = &*G_SCOPE;
+
+        let app_ids = match read_app_ids() {
+            Ok(app_ids) => app_ids,
+            Err(err) => {
+                warn!("failed to read {PACKAGES_LIST}: {err}");
+                HashMap::new()
+            }
+        };
 
-    if uid == SYSTEM_UID {
-        return true
-    }
+        let mut uids = HashSet::from([SYSTEM_UID]);
+        let lock = G_SCOPE.lock().unwrap();
 
-    if !pkg.is_null() {
-        let user = uid / PER_USER_RANGE;
-        let pkg = unsafe { CStr::from_ptr(pkg).to_str().unwrap() };
-        
-        if pkg == PARASITIC_PACKAGE || pkg == MANAGER_PACKAGE {
+        let mut users = HashSet::from([0]);
+
+        for info in lock.iter() {
+            if let Some(app_id) = app_ids.get(&info.pkg) {
+                uids.insert(info.user * PER_USER_RANGE + app_id);
+            }
+            users.insert(info.user);
+        }
+
+        for user in users {
+            for pkg in [PARASITIC_PACKAGE, MANAGER_PACKAGE] {
+                if let Some(app_id) = app_ids.get(pkg) {
+                    uids.insert(user * PER_USER_RANGE + app_id);
+                }
+            }
+        }
+
+        for (index, uid) in uids.iter().take(capacity).enumerate() {
+            unsafe {
+                *buffer.add(index) = *uid;
+            }
+        }
+
+        uids.len()
+    }).unwrap_or(0)
+}
+
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn check_process(uid: libc::uid_t, pkg: *const c_char, _name: *const c_char) -> bool {
+    bridge::panic::guard("check_process", || {
+        let _ = &*INIT_LOGGER;
+        let _ = &*G_SCOPE;
+
+        if uid == SYSTEM_UID {
             return true
         }
+
+        if !pkg.is_null() {
+            let user = uid / PER_USER_RANGE;
+            let pkg = unsafe { CStr::from_ptr(pkg).to_str().unwrap() };
         
-        let lock = G_SCOPE.lock().unwrap();
-        if lock.contains(&ScopeInfo { pkg: pkg.into(), user }) {
-            return true
+            if pkg == PARASITIC_PACKAGE || pkg == MANAGER_PACKAGE {
+                return true
+            }
+        
+            let lock = G_SCOPE.lock().unwrap();
+            if lock.contains(&ScopeInfo { pkg: pkg.into(), user }) {
+                return true
+            }
         }
-    }
 
-    false
+        false
+    }).unwrap_or(false)
 }
@@ -5,6 +5,7 @@ use std::pin::Pin;
 use std::sync::Mutex;
 use anyhow::Result;
 use log::error;
+use ::common::rootimpl;
 use ::common::zygote::SpecializeArgs;
 
 use bridge::ApiBridge;
@@ -18,14 +19,12 @@ mod abi;
 mod filter;
 
 struct ZygiskContext {
-    args: Vec<u64>,
     module: Option<Pin<Box<ZygiskModule>>>
 }
 
 impl ZygiskContext {
     fn new() -> Self {
         Self {
-            args: Vec::new(),
             module: None
         }
     }
@@ -45,7 +44,7 @@ impl ZygiskCompat {
 impl ApiBridge for ZygiskCompat {
     fn on_dlopen(&self) {
         let res : Result<()> = try {
-            let library = File::open("/debug_ramdisk/zloader-lsposed/liblsposed.so")?;
+            let library = File::open(rootimpl::staging_path("zloader-lsposed/liblsposed.so"))?;
             let mut lock = self.ctx.lock().unwrap();
             lock.module.replace(ZygiskModule::new("LSPosed", library.into())?);
         };
@@ -56,31 +55,40 @@ impl ApiBridge for ZygiskCompat {
     }
 
     fn on_specialize(&self, args: SpecializeArgs) {
-        let env = args.env();
+        let (env, is_system_server) = match args.env().and_then(|env| Ok((env, args.is_system_server()?))) {
+            Ok(args) => args,
+            Err(err) => {
+                warn!("bad specialize args, skipped: {}", err);
+                return
+            }
+        };
 
-        let mut lock = self.ctx.lock().unwrap();
+        let lock = self.ctx.lock().unwrap();
         
         if let Some(module) = &lock.module {
             module.entry(env);
             
-            if args.is_system_server() {
+            if is_system_server {
                 module.prss(&module.args_server(&args));
             } else {
                 module.pras(&module.args_app(&args));
             }
-
-            lock.args.extend(args.as_slice());
         }
     }
 
-    fn after_specialize(&self) {
+    fn after_specialize(&self, args: SpecializeArgs) {
+        let is_system_server = match args.is_system_server() {
+            Ok(is_system_server) => is_system_server,
+            Err(err) => {
+                warn!("bad specialize args, skipped: {}", err);
+                return
+            }
+        };
+
         let lock = self.ctx.lock().unwrap();
 
         if let Some(module) = &lock.module {
-            let args = &lock.args;
-            let args= SpecializeArgs::from(args.as_ptr() as *mut _);
-
-            if args.is_system_server() {
+            if is_system_server {
                 module.poss(&module.args_server(&args));
             } else {
                 module.poas(&module.args_app(&args));
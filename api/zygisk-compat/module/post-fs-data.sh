@@ -6,14 +6,15 @@ fi
 
 cd "$MODDIR" || exit
 
-TMPDIR=/debug_ramdisk/zloader-zygisk
+chmod +x bin/zloader
+chmod +x bin/zygiskd
+
+# /debug_ramdisk is not there with every root implementation
+TMPDIR="$(bin/zloader staging-dir)/zloader-zygisk"
 
 mkdir -p "$TMPDIR"
 cp lib/libzygisk_compat.so "$TMPDIR"
 chcon -R u:object_r:system_file:s0 "$TMPDIR"
 
-chmod +x bin/zloader
-chmod +x bin/zygiskd
-
-bin/zloader "$TMPDIR/libzygisk_compat.so" &
+bin/zloader %ZLOADER_ARGS% "$TMPDIR/libzygisk_compat.so" &
 bin/zygiskd --tmpdir "$TMPDIR" &
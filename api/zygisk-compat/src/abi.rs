@@ -1,8 +1,10 @@
+use std::fmt::{Display, Formatter};
 use std::marker::PhantomPinned;
 use std::ptr;
 use jni_sys::{jboolean, jint, jintArray, jlong, jobjectArray, jstring};
 use common::zygote::SpecializeArgs;
-use crate::debug;
+use crate::{debug, warn};
+use crate::options::{self, ModuleOption};
 
 #[macro_export]
 macro_rules! compat {
@@ -67,54 +69,57 @@ pub union AppSpecializeArgs {
 
 impl AppSpecializeArgs {
     pub fn new(args: &SpecializeArgs, api: libc::c_long) -> Self {
-        match api { 
-            1 ..= 2 => {
-                Self {
-                    v1: AppSpecializeArgsV1 {
-                        uid: args.uid,
-                        gid: args.gid,
-                        gids: args.gids,
-                        runtime_flags: args.runtime_flags,
-                        mount_external: args.mount_external,
-                        se_info: args.managed_se_info,
-                        nice_name: args.managed_nice_name,
-                        instruction_set: args.managed_instruction_set,
-                        app_data_dir: args.managed_app_data_dir,
-                        is_child_zygote: args.is_child_zygote,
-                        is_top_app: args.is_top_app,
-                        pkg_data_info_list: args.pkg_data_info_list,
-                        whitelisted_data_info_list: args.allowlisted_data_info_list,
-                        mount_data_dirs: args.mount_data_dirs,
-                        mount_storage_dirs: args.mount_storage_dirs,
+        // the pointers are handed to modules, which only use them before the args go away
+        unsafe {
+            match api {
+                1 ..= 2 => {
+                    Self {
+                        v1: AppSpecializeArgsV1 {
+                            uid: args.uid_ptr(),
+                            gid: args.gid_ptr(),
+                            gids: args.gids_ptr(),
+                            runtime_flags: args.runtime_flags_ptr(),
+                            mount_external: args.mount_external_ptr(),
+                            se_info: args.managed_se_info_ptr(),
+                            nice_name: args.managed_nice_name_ptr(),
+                            instruction_set: args.managed_instruction_set_ptr(),
+                            app_data_dir: args.managed_app_data_dir_ptr(),
+                            is_child_zygote: args.is_child_zygote_ptr(),
+                            is_top_app: args.is_top_app_ptr(),
+                            pkg_data_info_list: args.pkg_data_info_list_ptr(),
+                            whitelisted_data_info_list: args.allowlisted_data_info_list_ptr(),
+                            mount_data_dirs: args.mount_data_dirs_ptr(),
+                            mount_storage_dirs: args.mount_storage_dirs_ptr(),
+                        }
                     }
                 }
-            }
-            3 ..= 4 => {
-                Self {
-                    v3: {
-                        AppSpecializeArgsV3 {
-                            uid: args.uid,
-                            gid: args.gid,
-                            gids: args.gids,
-                            runtime_flags: args.runtime_flags,
-                            rlimits: args.rlimits,
-                            mount_external: args.mount_external,
-                            se_info: args.managed_se_info,
-                            nice_name: args.managed_nice_name,
-                            instruction_set: args.managed_instruction_set,
-                            app_data_dir: args.managed_app_data_dir,
-                            fds_to_ignore: ptr::null_mut(),
-                            is_child_zygote: args.is_child_zygote,
-                            is_top_app: args.is_top_app,
-                            pkg_data_info_list: args.pkg_data_info_list,
-                            whitelisted_data_info_list: args.allowlisted_data_info_list,
-                            mount_data_dirs: args.mount_data_dirs,
-                            mount_storage_dirs: args.mount_storage_dirs,
+                3 ..= 4 => {
+                    Self {
+                        v3: {
+                            AppSpecializeArgsV3 {
+                                uid: args.uid_ptr(),
+                                gid: args.gid_ptr(),
+                                gids: args.gids_ptr(),
+                                runtime_flags: args.runtime_flags_ptr(),
+                                rlimits: args.rlimits_ptr(),
+                                mount_external: args.mount_external_ptr(),
+                                se_info: args.managed_se_info_ptr(),
+                                nice_name: args.managed_nice_name_ptr(),
+                                instruction_set: args.managed_instruction_set_ptr(),
+                                app_data_dir: args.managed_app_data_dir_ptr(),
+                                fds_to_ignore: ptr::null_mut(),
+                                is_child_zygote: args.is_child_zygote_ptr(),
+                                is_top_app: args.is_top_app_ptr(),
+                                pkg_data_info_list: args.pkg_data_info_list_ptr(),
+                                whitelisted_data_info_list: args.allowlisted_data_info_list_ptr(),
+                                mount_data_dirs: args.mount_data_dirs_ptr(),
+                                mount_storage_dirs: args.mount_storage_dirs_ptr(),
+                            }
                         }
                     }
                 }
+                _ => unreachable!()
             }
-            _ => unreachable!()
         }
     }
 }
@@ -137,55 +142,105 @@ pub union ServerSpecializeArgs {
 
 impl ServerSpecializeArgs {
     pub fn new(args: &SpecializeArgs, api: libc::c_long) -> Self {
-        match api {
-            1 ..= 4 => {
-                Self {
-                    v1: ServerSpecializeArgsV1 {
-                        uid: args.uid,
-                        gid: args.gid,
-                        gids: args.gids,
-                        runtime_flags: args.runtime_flags,
-                        permitted_capabilities: args.permitted_capabilities,
-                        effective_capabilities: args.effective_capabilities,
+        // the pointers are handed to modules, which only use them before the args go away
+        unsafe {
+            match api {
+                1 ..= 4 => {
+                    Self {
+                        v1: ServerSpecializeArgsV1 {
+                            uid: args.uid_ptr(),
+                            gid: args.gid_ptr(),
+                            gids: args.gids_ptr(),
+                            runtime_flags: args.runtime_flags_ptr(),
+                            permitted_capabilities: args.permitted_capabilities_ptr(),
+                            effective_capabilities: args.effective_capabilities_ptr(),
+                        }
                     }
                 }
+                _ => unreachable!()
             }
-            _ => unreachable!()
         }
     }
 }
 
 type ModuleImpl = libc::c_void;
 
+// why a module's function table was refused
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Rejection {
+    UnsupportedVersion(libc::c_long),
+    NullImpl,
+    NullCallback(&'static str),
+    MisalignedCallback(&'static str, usize)
+}
+
+impl Display for Rejection {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Rejection::UnsupportedVersion(version) => write!(fmt, "unsupported api version: {version}"),
+            Rejection::NullImpl => write!(fmt, "module instance is null"),
+            Rejection::NullCallback(name) => write!(fmt, "callback `{name}` is null"),
+            Rejection::MisalignedCallback(name, addr) => write!(fmt, "callback `{name}` is misaligned: 0x{addr:x}")
+        }
+    }
+}
+
+// callbacks may be null in a broken table, `Option` keeps reading them sound
 #[repr(C)]
 pub struct ModuleAbi {
     pub version: libc::c_long,
     pub imp: *const ModuleImpl,
-    pub pras: fn(*const ModuleImpl, *const AppSpecializeArgs),
-    pub poas: fn(*const ModuleImpl, *const AppSpecializeArgs),
-    pub prss: fn(*const ModuleImpl, *const ServerSpecializeArgs),
-    pub poss: fn(*const ModuleImpl, *const ServerSpecializeArgs)
+    pub pras: Option<fn(*const ModuleImpl, *const AppSpecializeArgs)>,
+    pub poas: Option<fn(*const ModuleImpl, *const AppSpecializeArgs)>,
+    pub prss: Option<fn(*const ModuleImpl, *const ServerSpecializeArgs)>,
+    pub poss: Option<fn(*const ModuleImpl, *const ServerSpecializeArgs)>
 }
 
+const CALLBACK_ALIGN: usize = if cfg!(target_arch = "aarch64") { 4 } else { 1 };
+
 impl ModuleAbi {
-    fn is_valid(&self) -> bool {
-        if self.version < 1 || self.version > 4 {
-            return false
+    // callbacks every version of the table has to provide, v1 to v4 share the same layout
+    fn callbacks(&self) -> Result<[(&'static str, Option<usize>); 4], Rejection> {
+        match self.version {
+            1 ..= 4 => Ok([
+                ("preAppSpecialize", self.pras.map(|func| func as usize)),
+                ("postAppSpecialize", self.poas.map(|func| func as usize)),
+                ("preServerSpecialize", self.prss.map(|func| func as usize)),
+                ("postServerSpecialize", self.poss.map(|func| func as usize))
+            ]),
+            version => Err(Rejection::UnsupportedVersion(version))
         }
-        
+    }
+
+    pub fn validate(&self) -> Result<(), Rejection> {
+        let callbacks = self.callbacks()?;
+
         if self.imp.is_null() {
-            return false
+            return Err(Rejection::NullImpl)
         }
-        
-        self.pras as usize & self.poas as usize & self.prss as usize & self.poss as usize != 0
+
+        for (name, addr) in callbacks {
+            match addr {
+                None => return Err(Rejection::NullCallback(name)),
+                Some(addr) if addr % CALLBACK_ALIGN != 0 => return Err(Rejection::MisalignedCallback(name, addr)),
+                Some(_) => ()
+            }
+        }
+
+        Ok(())
     }
 }
 
 #[repr(C)]
 pub struct ApiAbi {
-    pub module_abi: *const ModuleAbi,
-    register_module: fn(*mut ApiAbi, *const ModuleAbi) -> bool,
+    // passed back by modules as `impl`, points to the table itself once a module registers
+    imp: *mut ApiAbi,
+    register_module: extern "C" fn(*mut ApiAbi, *const ModuleAbi) -> bool,
     api: [usize; 16],
+    // not visible to modules, they only see the fields above
+    pub module_abi: *const ModuleAbi,
+    pub rejection: Option<Rejection>,
+    pub options: Vec<ModuleOption>,
     _pin: PhantomPinned
 }
 
@@ -194,14 +249,22 @@ impl ApiAbi {
     #[allow(invalid_value)]
     pub fn new() -> Self {
         Self {
-            module_abi: ptr::null(),
+            imp: ptr::null_mut(),
             register_module: ApiAbi::register,
             api: [0usize; 16],
+            module_abi: ptr::null(),
+            rejection: None,
+            options: Vec::new(),
             _pin: PhantomPinned
         }
     }
     
-    fn register(api_abi: *mut ApiAbi, module_abi: *const ModuleAbi) -> bool {
+    // called by modules from C++
+    extern "C" fn register(api_abi: *mut ApiAbi, module_abi: *const ModuleAbi) -> bool {
+        bridge::panic::guard("register_module", || Self::register_internal(api_abi, module_abi)).unwrap_or(false)
+    }
+
+    fn register_internal(api_abi: *mut ApiAbi, module_abi: *const ModuleAbi) -> bool {
         let api = match unsafe { api_abi.as_mut() } {
             Some(abi) => abi,
             None => return false,
@@ -212,14 +275,153 @@ impl ApiAbi {
             None => return false,
         };
         
-        if !module.is_valid() {
+        if let Err(rejection) = module.validate() {
+            api.rejection = Some(rejection);
             return false
         }
         
+        api.imp = api_abi;
         api.module_abi = module;
 
+        // the table is laid out by the version the module was built against
+        if let Some(slot) = options::set_option_slot(module.version) {
+            api.api[slot] = ApiAbi::set_option as usize;
+        }
+
         debug!("register module: 0x{:x} api_version={}", module_abi as usize, module.version);
         
         true
     }
+
+    // called by modules from C++
+    extern "C" fn set_option(imp: *mut ApiAbi, option: libc::c_int) {
+        bridge::panic::guard("set_option", || Self::set_option_internal(imp, option));
+    }
+
+    fn set_option_internal(imp: *mut ApiAbi, option: libc::c_int) {
+        let api = match unsafe { imp.as_mut() } {
+            Some(abi) => abi,
+            None => return
+        };
+
+        let version = match unsafe { api.module_abi.as_ref() } {
+            Some(module) => module.version,
+            None => return
+        };
+
+        match options::translate(version, option) {
+            Some(option) => {
+                debug!("set option: {} (api_version={})", option, version);
+
+                if !api.options.contains(&option) {
+                    api.options.push(option);
+                }
+            }
+            None => {
+                warn!("unknown option {} for api version {}", option, version);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn callback(_: *const ModuleImpl, _: *const AppSpecializeArgs) {}
+
+    fn server_callback(_: *const ModuleImpl, _: *const ServerSpecializeArgs) {}
+
+    fn module(version: libc::c_long) -> ModuleAbi {
+        ModuleAbi {
+            version,
+            imp: 0x1000 as *const ModuleImpl,
+            pras: Some(callback),
+            poas: Some(callback),
+            prss: Some(server_callback),
+            poss: Some(server_callback)
+        }
+    }
+
+    #[test]
+    fn accepts_well_formed_tables() {
+        for version in 1 ..= 4 {
+            assert_eq!(module(version).validate(), Ok(()));
+        }
+    }
+
+    #[test]
+    fn rejects_unsupported_versions() {
+        for version in [-1, 0, 5, libc::c_long::MAX] {
+            assert_eq!(module(version).validate(), Err(Rejection::UnsupportedVersion(version)));
+        }
+    }
+
+    #[test]
+    fn rejects_null_impl() {
+        let table = ModuleAbi { imp: ptr::null(), ..module(4) };
+        assert_eq!(table.validate(), Err(Rejection::NullImpl));
+    }
+
+    #[test]
+    fn rejects_null_callbacks() {
+        let tables = [
+            ("preAppSpecialize", ModuleAbi { pras: None, ..module(4) }),
+            ("postAppSpecialize", ModuleAbi { poas: None, ..module(4) }),
+            ("preServerSpecialize", ModuleAbi { prss: None, ..module(4) }),
+            ("postServerSpecialize", ModuleAbi { poss: None, ..module(4) })
+        ];
+
+        for (name, table) in tables {
+            assert_eq!(table.validate(), Err(Rejection::NullCallback(name)));
+        }
+    }
+
+    #[test]
+    fn version_is_checked_first() {
+        let table = ModuleAbi { imp: ptr::null(), pras: None, ..module(0) };
+        assert_eq!(table.validate(), Err(Rejection::UnsupportedVersion(0)));
+    }
+
+    #[test]
+    #[cfg(target_arch = "aarch64")]
+    fn rejects_misaligned_callbacks() {
+        // never called, only its address is looked at
+        let misaligned = unsafe { std::mem::transmute::<usize, fn(*const ModuleImpl, *const AppSpecializeArgs)>(callback as usize + 1) };
+        let table = ModuleAbi { poas: Some(misaligned), ..module(4) };
+
+        assert_eq!(table.validate(), Err(Rejection::MisalignedCallback("postAppSpecialize", callback as usize + 1)));
+    }
+
+    #[test]
+    fn register_records_rejection() {
+        let mut api = ApiAbi::new();
+        let table = ModuleAbi { prss: None, ..module(2) };
+
+        assert!(!ApiAbi::register_internal(&mut api, &table));
+        assert_eq!(api.rejection, Some(Rejection::NullCallback("preServerSpecialize")));
+        assert!(api.module_abi.is_null());
+        assert!(api.imp.is_null());
+        assert!(api.api.iter().all(|&slot| slot == 0));
+    }
+
+    #[test]
+    fn register_rejects_null_pointers() {
+        let mut api = ApiAbi::new();
+        let table = module(4);
+
+        assert!(!ApiAbi::register_internal(&mut api, ptr::null()));
+        assert!(!ApiAbi::register_internal(ptr::null_mut(), &table));
+        assert_eq!(api.rejection, None);
+    }
+
+    #[test]
+    fn register_accepts_well_formed_table() {
+        let mut api = ApiAbi::new();
+        let table = module(4);
+
+        assert!(ApiAbi::register_internal(&mut api, &table));
+        assert_eq!(api.rejection, None);
+        assert_eq!(api.module_abi, &table as *const _);
+    }
 }
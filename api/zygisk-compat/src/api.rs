@@ -1,18 +1,28 @@
+use std::cell::Cell;
 use std::fmt::{Debug, Formatter};
 use std::mem;
+use std::io::Write;
 use std::os::fd::{AsFd, OwnedFd};
+use std::os::unix::net::UnixStream;
 use std::pin::Pin;
 
 use anyhow::Result;
+use bincode::config;
+use byteorder::{NativeEndian, WriteBytesExt};
 use fragile::Fragile;
 use jni_sys::JNIEnv;
 use common::zygote::SpecializeArgs;
 
+use crate::daemon_socket;
+use crate::common::{CallbackTiming, DaemonSocketAction};
 use crate::abi::{ApiAbi, AppSpecializeArgs, ModuleAbi, ServerSpecializeArgs};
-use crate::dlfcn::{dlopen_fd, dlsym};
+use crate::dlfcn::{dlclose, dlopen_fd, dlsym, LibraryHandle};
+use crate::options::ModuleOption;
+use crate::{debug, warn};
 
 pub struct ZygiskModule {
     id: String,
+    handle: Fragile<Cell<Option<LibraryHandle>>>,
     entry: fn(*const ApiAbi, JNIEnv),
     api: Fragile<Pin<Box<ApiAbi>>>,
 }
@@ -26,8 +36,11 @@ impl Debug for ZygiskModule {
 macro_rules! impl_callback {
     ($name: ident, $args_type: ty) => {
         pub fn $name(&self, args: $args_type) {
-            let module = self.module();
-            (module.$name)(module.imp, args);
+            if let Some(module) = self.module() {
+                if let Some(callback) = module.$name {
+                    callback(module.imp, args);
+                }
+            }
         }
     };
 }
@@ -36,17 +49,19 @@ impl ZygiskModule {
     pub fn new(name: &str, fd: OwnedFd) -> Result<Pin<Box<Self>>> {
         let handle = dlopen_fd(fd.as_fd(), libc::RTLD_NOW)?;
         let entry_fn: fn(*const ApiAbi, JNIEnv) = unsafe {
-            mem::transmute(dlsym(handle, "zygisk_module_entry")?)
+            mem::transmute(dlsym(&handle, "zygisk_module_entry")?)
         };
+
+        bridge::libs::register_library(entry_fn as *const _);
         
         Ok(Box::pin(Self {
             id: name.into(),
+            handle: Fragile::new(Cell::new(Some(handle))),
             entry: entry_fn,
             api: Fragile::new(Box::pin(ApiAbi::new()))
         }))
     }
     
-    #[allow(dead_code)]
     pub fn id(&self) -> &str {
         &self.id
     }
@@ -55,20 +70,49 @@ impl ZygiskModule {
         self.api.get()
     }
     
-    fn module(&self) -> &ModuleAbi {
-        unsafe { &*self.api().module_abi }
+    // null until the module registers itself with a valid table
+    fn module(&self) -> Option<&ModuleAbi> {
+        unsafe { self.api().module_abi.as_ref() }
+    }
+
+    fn version(&self) -> libc::c_long {
+        self.module().map_or(0, |module| module.version)
     }
     
     pub fn entry(&self, env: JNIEnv) {
         (self.entry)(self.api(), env);
+
+        if let Some(rejection) = self.api().rejection {
+            warn!("module {} rejected: {}", self.id, rejection);
+
+            if let Err(err) = report_rejection(&self.id, &rejection.to_string()) {
+                warn!("failed to report rejection of {}: {}", self.id, err);
+            }
+        }
+    }
+
+    pub fn has_option(&self, option: ModuleOption) -> bool {
+        self.api().options.contains(&option)
+    }
+
+    // requested by the module with `DLCLOSE_MODULE_LIBRARY`, no callback may be called afterwards
+    pub fn unload(&self) {
+        if let Some(handle) = self.handle.get().take() {
+            if let Err(err) = dlclose(handle) {
+                warn!("failed to unload module {}: {}", self.id, err);
+                return
+            }
+
+            debug!("module {} unloaded", self.id);
+        }
     }
 
     pub fn args_app(&self, args: &SpecializeArgs) -> AppSpecializeArgs {
-        AppSpecializeArgs::new(args, self.module().version)
+        AppSpecializeArgs::new(args, self.version())
     }
 
     pub fn args_server(&self, args: &SpecializeArgs) -> ServerSpecializeArgs {
-        ServerSpecializeArgs::new(args, self.module().version)
+        ServerSpecializeArgs::new(args, self.version())
     }
     
     impl_callback!(pras, &AppSpecializeArgs);
@@ -76,3 +120,52 @@ impl ZygiskModule {
     impl_callback!(prss, &ServerSpecializeArgs);
     impl_callback!(poss, &ServerSpecializeArgs);
 }
+
+// let the daemon know, apps only log it to their own logcat
+fn report_rejection(id: &str, reason: &str) -> Result<()> {
+    let message = format!("{id}: {reason}");
+    let mut stream = UnixStream::connect(daemon_socket())?;
+
+    stream.write_u8(DaemonSocketAction::ReportRejection.into())?;
+    stream.write_u64::<NativeEndian>(message.len() as u64)?;
+    stream.write_all(message.as_bytes())?;
+
+    Ok(())
+}
+
+// left out over the budget, the daemon keeps an audit record
+pub fn report_skipped(id: &str) -> Result<()> {
+    let mut stream = UnixStream::connect(daemon_socket())?;
+
+    stream.write_u8(DaemonSocketAction::ReportSkipped.into())?;
+    stream.write_i32::<NativeEndian>(std::process::id() as i32)?;
+    stream.write_u64::<NativeEndian>(id.len() as u64)?;
+    stream.write_all(id.as_bytes())?;
+
+    Ok(())
+}
+
+// connected before specialize, the domain of the app may not be allowed to connect to the daemon
+pub struct TimingReport {
+    stream: UnixStream
+}
+
+impl TimingReport {
+    pub fn open() -> Result<Self> {
+        let mut stream = UnixStream::connect(daemon_socket())?;
+
+        stream.write_u8(DaemonSocketAction::ReportTimings.into())?;
+        stream.write_i32::<NativeEndian>(std::process::id() as i32)?;
+
+        Ok(Self { stream })
+    }
+
+    pub fn send(mut self, timings: &[CallbackTiming]) -> Result<()> {
+        let data = bincode::encode_to_vec(timings, config::standard())?;
+
+        self.stream.write_u64::<NativeEndian>(data.len() as u64)?;
+        self.stream.write_all(&data)?;
+
+        Ok(())
+    }
+}
@@ -1,9 +1,17 @@
 use std::mem;
 
+use bincode::{Decode, Encode};
+
 #[derive(Debug)]
 #[repr(u8)]
 pub enum DaemonSocketAction {
     ReadModules,
+    // followed by length and text of the reason
+    ReportRejection,
+    // followed by pid, length and id of the module left out
+    ReportSkipped,
+    // followed by pid, then length and the encoded timings once the process is specialized
+    ReportTimings,
 }
 
 impl From<u8> for DaemonSocketAction {
@@ -17,3 +25,56 @@ impl From<DaemonSocketAction> for u8 {
         unsafe { mem::transmute(value) }
     }
 }
+
+#[derive(Debug, Clone, Encode, Decode)]
+pub struct ModuleMeta {
+    pub id: String,
+    // higher goes first, and is the last to be left out
+    pub priority: i32,
+    // size of the library, what it maps is about the same
+    pub size: u64
+}
+
+// wall time of a single callback of a module in an app process
+#[derive(Debug, Clone, Encode, Decode)]
+pub struct CallbackTiming {
+    pub module: String,
+    pub callback: String,
+    pub micros: u64
+}
+
+// what a single app process may load, no limit if unset
+#[derive(Debug, Clone, Copy, Default, Encode, Decode)]
+pub struct Budget {
+    pub max_modules: Option<u32>,
+    pub max_size: Option<u64>
+}
+
+impl Budget {
+    // modules are expected to be sorted by priority, returns how many of them fit
+    pub fn fit(&self, modules: &[ModuleMeta]) -> usize {
+        let mut total = 0u64;
+
+        for (index, module) in modules.iter().enumerate() {
+            total += module.size;
+
+            let over_count = self.max_modules.is_some_and(|max| index as u32 >= max);
+            let over_size = self.max_size.is_some_and(|max| total > max);
+
+            if over_count || over_size {
+                return index
+            }
+        }
+
+        modules.len()
+    }
+}
+
+// sent along with the fds of modules, in the same order
+#[derive(Debug, Encode, Decode)]
+pub struct ModuleList {
+    pub modules: Vec<ModuleMeta>,
+    pub budget: Budget,
+    // false while the daemon is still loading, apps started that early only get what is ready
+    pub complete: bool
+}
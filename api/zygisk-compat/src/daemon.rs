@@ -1,49 +1,90 @@
 #![feature(try_blocks)]
 
 use std::{env, fs, io};
+use std::collections::HashMap;
+use std::fmt::Write;
 use std::fs::File;
-use std::io::BufReader;
+use std::io::{BufReader, Read};
 use std::os::fd::AsRawFd;
 use std::os::unix::net::UnixListener;
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
 
-use anyhow::{Context, Result};
+use android_logger::AndroidLogger;
+use anyhow::{anyhow, Context, Result};
 use bincode::config;
 use byteorder::{NativeEndian, ReadBytesExt, WriteBytesExt};
 use clap::Parser;
-use log::{debug, error, LevelFilter};
+use log::{debug, error, warn, LevelFilter};
 use memfd::{FileSeal, Memfd, MemfdOptions};
 use sendfd::SendWithFd;
 use tokio::runtime::Runtime;
-use tokio::task;
+use tokio::task::{self, JoinSet};
+use ::common::audit;
 use ::common::debug_select;
+use ::common::features;
+use ::common::logfile;
 use ::common::utils::dump_tombstone_on_panic;
 
-use crate::common::DaemonSocketAction;
+use crate::common::{Budget, CallbackTiming, DaemonSocketAction, ModuleList, ModuleMeta};
 use crate::selinux::chcon;
 
 mod selinux;
 mod common;
 
+const MAX_REPORT_LEN: usize = 4096;
+
+// the process reports once specialized, which may take a while on a slow launch
+const TIMING_REPORT_TIMEOUT: Duration = Duration::from_secs(10);
+
+// a single callback taking this long is audited, that much is added to every launch
+const SLOW_CALLBACK: Duration = Duration::from_millis(50);
+
+// rewritten on every report, slowest modules first
+const TIMINGS_FILE: &str = "timings.txt";
+
+// optional, an integer in the module directory, modules without it have priority 0
+const PRIORITY_FILE: &str = "zygisk/priority";
+
+const MODULE_LIBRARY: &str = "zygisk/arm64-v8a.so";
+
 #[derive(Parser)]
 struct Args {
     #[clap(long)]
-    tmpdir: PathBuf
+    tmpdir: PathBuf,
+
+    /// Maximum number of modules loaded into each app, lowest priority ones are left out beyond it
+    #[clap(long)]
+    max_modules: Option<u32>,
+
+    /// Maximum total size in bytes of modules loaded into each app
+    #[clap(long)]
+    max_size: Option<u64>,
+
+    /// Write logs to a file under /data/adb/zloader/logs as well, rotated by size
+    #[clap(long)]
+    log_file: bool
 }
 
 #[derive(Debug)]
 struct Module {
-    name: String,
+    meta: ModuleMeta,
     fd: Memfd
 }
 
 impl Module {
-    fn new(name: String, fd: Memfd) -> Module {
-        Self { name, fd }
+    fn new(meta: ModuleMeta, fd: Memfd) -> Module {
+        Self { meta, fd }
     }
 }
 
+fn read_priority(module_dir: &Path) -> i32 {
+    fs::read_to_string(module_dir.join(PRIORITY_FILE)).ok()
+        .and_then(|priority| priority.trim().parse().ok())
+        .unwrap_or(0)
+}
+
 fn load_library(name: &str, lib: &PathBuf) -> Result<Memfd> {
     let options = MemfdOptions::default().allow_sealing(true);
     let mfd = options.create(name)?;
@@ -60,31 +101,114 @@ fn load_library(name: &str, lib: &PathBuf) -> Result<Memfd> {
     Ok(mfd)
 }
 
-fn load_modules() -> Result<Vec<Module>> {
+// loaded so far, each along with its position in the directory
+#[derive(Default)]
+struct Modules {
+    loaded: Vec<(usize, Module)>,
+    complete: bool
+}
+
+type SharedModules = Arc<RwLock<Modules>>;
+
+fn module_dirs() -> Result<Vec<(String, PathBuf)>> {
     let current = env::current_dir()?;
     let modules_dir = current.parent().unwrap();
 
-    let dirs = fs::read_dir(modules_dir)?;
-    let mut modules = Vec::new();
+    let dirs = fs::read_dir(modules_dir)?.flatten()
+        .filter(|dir| dir.path().join(MODULE_LIBRARY).exists() && !dir.path().join("disable").exists())
+        .map(|dir| (dir.file_name().to_string_lossy().into(), dir.path()))
+        .collect();
+
+    Ok(dirs)
+}
+
+fn load_module(module_id: String, dir: &Path) -> Result<Module> {
+    debug!("loading module `{module_id}`...");
+
+    let mfd = load_library(&module_id, &dir.join(MODULE_LIBRARY))?;
+
+    let meta = ModuleMeta {
+        priority: read_priority(dir),
+        size: mfd.as_file().metadata()?.len(),
+        id: module_id
+    };
+
+    Ok(Module::new(meta, mfd))
+}
+
+// copying libraries may take a while on a cold boot, so that is done concurrently and after the socket is up
+async fn load_modules(modules: SharedModules) {
+    let dirs = module_dirs().unwrap_or_else(|err| {
+        error!("failed to list modules: {err}");
+        Vec::new()
+    });
+
+    let mut tasks = JoinSet::new();
+
+    for (order, (module_id, dir)) in dirs.into_iter().enumerate() {
+        tasks.spawn_blocking(move || (order, load_module(module_id, &dir)));
+    }
+
+    while let Some(res) = tasks.join_next().await {
+        match res {
+            Ok((order, Ok(module))) => {
+                debug!("loaded module: {module:?}");
+
+                let mut lock = modules.write().unwrap();
+                lock.loaded.push((order, module));
+
+                // stable, modules of the same priority keep the order of the directory
+                lock.loaded.sort_by_key(|(order, module)| (-module.meta.priority, *order));
+            }
+            Ok((_, Err(err))) => error!("failed to load module: {err:#}"),
+            Err(err) => error!("module loading task failed: {err}")
+        }
+    }
+
+    let mut lock = modules.write().unwrap();
+    lock.complete = true;
+
+    debug!("all {} modules loaded", lock.loaded.len());
+}
+
+#[derive(Default)]
+struct Aggregate {
+    calls: u64,
+    total_micros: u64,
+    max_micros: u64
+}
 
-    for dir in dirs.flatten() {
-        let module_id = dir.file_name().into_string().unwrap();
+// (module, callback) to how long it took across all processes since the daemon started
+type SharedTimings = Arc<Mutex<HashMap<(String, String), Aggregate>>>;
 
-        let lib = dir.path().join("zygisk/arm64-v8a.so");
-        let disable = dir.path().join("disable");
+fn record_timings(timings: &SharedTimings, pid: i32, report: Vec<CallbackTiming>, file: &Path) -> Result<()> {
+    let mut timings = timings.lock().unwrap();
 
-        if !lib.exists() || disable.exists() {
-            continue
+    for CallbackTiming { module, callback, micros } in report {
+        if micros >= SLOW_CALLBACK.as_micros() as u64 {
+            warn!("[{pid}] module `{module}` took {}ms in `{callback}`", micros / 1000);
+            audit::record(pid, &format!("zygisk module `{module}` took {}ms in `{callback}`", micros / 1000));
         }
 
-        debug!("loading module `{module_id}`...");
+        let aggregate = timings.entry((module, callback)).or_default();
+        aggregate.calls += 1;
+        aggregate.total_micros += micros;
+        aggregate.max_micros = aggregate.max_micros.max(micros);
+    }
+
+    let mut sorted: Vec<_> = timings.iter().collect();
+    sorted.sort_by_key(|(_, aggregate)| u64::MAX - aggregate.total_micros);
 
-        let mfd = load_library(&module_id, &lib)?;
+    let mut content = String::from("# module callback calls average_us max_us total_us\n");
 
-        modules.push(Module::new(module_id, mfd));
+    for ((module, callback), aggregate) in sorted {
+        let average = aggregate.total_micros / aggregate.calls;
+        writeln!(content, "{module} {callback} {} {average} {} {}", aggregate.calls, aggregate.max_micros, aggregate.total_micros)?;
     }
 
-    Ok(modules)
+    fs::write(file, content)?;
+
+    Ok(())
 }
 
 fn create_daemon_socket<P : AsRef<Path>>(skfile: P) -> Result<UnixListener> {
@@ -98,25 +222,25 @@ fn create_daemon_socket<P : AsRef<Path>>(skfile: P) -> Result<UnixListener> {
     Ok(listener)
 }
 
-fn init_logger() {
-    android_logger::init_once(
+fn init_logger(to_file: bool) {
+    let level = features::log_level(debug_select!(LevelFilter::Trace, LevelFilter::Info));
+    let logger = AndroidLogger::new(
         android_logger::Config::default()
-            .with_max_level(debug_select!(LevelFilter::Trace, LevelFilter::Info))
+            .with_max_level(level)
             .with_tag("ZLoader-Zygisk")
     );
+
+    let _ = logfile::init("zygiskd", Box::new(logger), level, to_file);
 }
 
 fn main() -> Result<()> {
-    init_logger();
-    dump_tombstone_on_panic();
-
     let args = Args::parse();
 
-    fs::create_dir_all(&args.tmpdir).context("failed to create tmpdir")?;
+    init_logger(args.log_file);
+    dump_tombstone_on_panic();
+    features::log_effective("zygiskd");
 
-    let modules = load_modules().context("failed to load modules")?;
-    
-    debug!("loaded modules: {modules:?}");
+    fs::create_dir_all(&args.tmpdir).context("failed to create tmpdir")?;
 
     let listener = create_daemon_socket(args.tmpdir.join("daemon.sock"))
         .context("failed to create daemon socket")?;
@@ -124,29 +248,108 @@ fn main() -> Result<()> {
     let runtime = Runtime::new()?;
     let _handle = runtime.enter();
 
-    let module_ids: Arc<Vec<_>> = Arc::new(modules.iter().map(|m| m.name.clone()).collect());
-    let module_fds: Arc<Vec<_>> = Arc::new(modules.iter().map(|m| m.fd.as_raw_fd()).collect());
+    let budget = Budget { max_modules: args.max_modules, max_size: args.max_size };
+
+    let modules = SharedModules::default();
+    runtime.spawn(load_modules(Arc::clone(&modules)));
+
+    let timings = SharedTimings::default();
+    let timings_file = args.tmpdir.join(TIMINGS_FILE);
 
     for mut stream in listener.incoming().flatten() {
         let action = DaemonSocketAction::from(stream.read_u8()?);
 
-        let ids = Arc::clone(&module_ids);
-        let fds = Arc::clone(&module_fds);
+        let modules = Arc::clone(&modules);
+        let timings = Arc::clone(&timings);
+        let timings_file = timings_file.clone();
 
         task::spawn(async move {
             match action {
                 DaemonSocketAction::ReadModules => {
                     let res: Result<()> = try {
-                        let ids = bincode::encode_to_vec(&ids, config::standard())?;
+                        // whatever is ready by now, the list and the fds taken under the same lock
+                        let lock = modules.read().unwrap();
+
+                        let list = ModuleList {
+                            modules: lock.loaded.iter().map(|(_, module)| module.meta.clone()).collect(),
+                            budget,
+                            complete: lock.complete
+                        };
+                        let fds: Vec<_> = lock.loaded.iter().map(|(_, module)| module.fd.as_raw_fd()).collect();
+
+                        let list = bincode::encode_to_vec(&list, config::standard())?;
                         stream.write_u64::<NativeEndian>(fds.len() as u64)?;
-                        stream.write_u64::<NativeEndian>(ids.len() as u64)?;
-                        stream.send_with_fd(&ids, &fds)?;
+                        stream.write_u64::<NativeEndian>(list.len() as u64)?;
+                        stream.send_with_fd(&list, &fds)?;
                     };
                     
                     if let Err(err) = res {
                         error!("failed to send modules: {err}");
                     }
                 }
+                DaemonSocketAction::ReportRejection => {
+                    let res: Result<String> = try {
+                        let len = stream.read_u64::<NativeEndian>()? as usize;
+                        if len > MAX_REPORT_LEN {
+                            Err(anyhow!("rejection report too long: {len}"))?;
+                        }
+
+                        let mut message = vec![0u8; len];
+                        stream.read_exact(&mut message)?;
+                        String::from_utf8_lossy(&message).into()
+                    };
+
+                    match res {
+                        Ok(message) => error!("module rejected by zygisk api: {message}"),
+                        Err(err) => error!("failed to read rejection report: {err}")
+                    }
+                }
+                DaemonSocketAction::ReportSkipped => {
+                    let res: Result<(i32, String)> = try {
+                        let pid = stream.read_i32::<NativeEndian>()?;
+                        let len = stream.read_u64::<NativeEndian>()? as usize;
+                        if len > MAX_REPORT_LEN {
+                            Err(anyhow!("skip report too long: {len}"))?;
+                        }
+
+                        let mut id = vec![0u8; len];
+                        stream.read_exact(&mut id)?;
+                        (pid, String::from_utf8_lossy(&id).into())
+                    };
+
+                    match res {
+                        Ok((pid, id)) => {
+                            warn!("[{pid}] module `{id}` left out, over budget {budget:?}");
+                            audit::record(pid, &format!("zygisk module `{id}` left out, over budget"));
+                        }
+                        Err(err) => error!("failed to read skip report: {err}")
+                    }
+                }
+                DaemonSocketAction::ReportTimings => {
+                    let res: Result<()> = try {
+                        let pid = stream.read_i32::<NativeEndian>()?;
+
+                        // blocks until the process is specialized, or gives up if it never gets there
+                        stream.set_read_timeout(Some(TIMING_REPORT_TIMEOUT))?;
+
+                        let len = stream.read_u64::<NativeEndian>()? as usize;
+                        if len > MAX_REPORT_LEN {
+                            Err(anyhow!("timing report too long: {len}"))?;
+                        }
+
+                        let mut data = vec![0u8; len];
+                        stream.read_exact(&mut data)?;
+
+                        let report: Vec<CallbackTiming> = bincode::decode_from_slice(&data, config::standard())?.0;
+                        debug!("[{pid}] timings: {report:?}");
+
+                        record_timings(&timings, pid, report, &timings_file)?;
+                    };
+
+                    if let Err(err) = res {
+                        error!("failed to read timing report: {err}");
+                    }
+                }
             }
         });
     }
@@ -52,7 +52,7 @@ pub fn dlopen_fd(fd: BorrowedFd, flags: libc::c_int) -> Result<LibraryHandle> {
     }
 }
 
-pub fn dlsym(handle: LibraryHandle, symbol: &str) -> Result<*const c_void> {
+pub fn dlsym(handle: &LibraryHandle, symbol: &str) -> Result<*const c_void> {
     let symbol = CString::new(symbol).unwrap();
     
     unsafe {
@@ -65,3 +65,11 @@ pub fn dlsym(handle: LibraryHandle, symbol: &str) -> Result<*const c_void> {
         Ok(addr)
     }
 }
+
+pub fn dlclose(handle: LibraryHandle) -> Result<()> {
+    if unsafe { libc::dlclose(handle.0 as _) } != 0 {
+        dlerror()?;
+    }
+
+    Ok(())
+}
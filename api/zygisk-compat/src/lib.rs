@@ -2,6 +2,7 @@
 
 use std::os::fd::{FromRawFd, OwnedFd, RawFd};
 use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
 use std::pin::Pin;
 use std::sync::Mutex;
 use anyhow::Context;
@@ -10,33 +11,47 @@ use bincode::config;
 use byteorder::{NativeEndian, ReadBytesExt, WriteBytesExt};
 use log::error;
 use sendfd::RecvWithFd;
+use ::common::rootimpl;
 use ::common::zygote::SpecializeArgs;
 
 use bridge::ApiBridge;
 
-use crate::api::ZygiskModule;
-use crate::common::DaemonSocketAction;
+use crate::api::{report_skipped, TimingReport, ZygiskModule};
+use crate::common::{DaemonSocketAction, ModuleList};
+use crate::options::ModuleOption;
 
 mod api;
 mod dlfcn;
 mod logs;
 mod abi;
 mod common;
+mod options;
+mod sched;
+mod timing;
+
+fn daemon_socket() -> PathBuf {
+    rootimpl::staging_path("zloader-zygisk/daemon.sock")
+}
 
 struct ZygiskContext {
-    args: Vec<u64>,
-    modules: Vec<Pin<Box<ZygiskModule>>>
+    modules: Vec<Pin<Box<ZygiskModule>>>,
+    report: Option<TimingReport>
 }
 
 impl ZygiskContext {
     fn new() -> Self {
         Self {
-            args: Vec::new(),
-            modules: Vec::new()
+            modules: Vec::new(),
+            report: None
         }
     }
 }
 
+// every module callback goes through here
+fn invoke<R>(module: &ZygiskModule, callback: &str, func: impl FnOnce() -> R) -> R {
+    timing::measure(module.id(), callback, || sched::preserve(module.id(), callback, func))
+}
+
 
 struct ZygiskCompat {
     ctx: Mutex<ZygiskContext>
@@ -51,7 +66,7 @@ impl ZygiskCompat {
 impl ApiBridge for ZygiskCompat {
     fn on_dlopen(&self) {
         let res : Result<()> = try {
-            let mut stream = UnixStream::connect("/debug_ramdisk/zloader-zygisk/daemon.sock").context("failed to connect daemon")?;
+            let mut stream = UnixStream::connect(daemon_socket()).context("failed to connect daemon")?;
             
             stream.write_u8(DaemonSocketAction::ReadModules.into())?;
             
@@ -65,12 +80,30 @@ impl ApiBridge for ZygiskCompat {
             
             stream.recv_with_fd(&mut buffer, &mut fds)?;
             
-            let ids: Vec<String> = bincode::decode_from_slice(&buffer, config::standard())?.0;
+            let list: ModuleList = bincode::decode_from_slice(&buffer, config::standard())?.0;
+            let fit = list.budget.fit(&list.modules);
+
+            if !list.complete {
+                warn!("daemon is still loading modules, only {} of them are ready", list.modules.len());
+            }
             
             let mut modules = Vec::new();
 
-            for (id, fd) in ids.into_iter().zip(fds) {
-                modules.push(ZygiskModule::new(&id, unsafe { OwnedFd::from_raw_fd(fd) })?);
+            // sorted by priority, whatever is beyond the budget is left out
+            for (index, (meta, fd)) in list.modules.into_iter().zip(fds).enumerate() {
+                let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+                if index >= fit {
+                    warn!("module {} left out, over budget {:?}", meta.id, list.budget);
+
+                    if let Err(err) = report_skipped(&meta.id) {
+                        warn!("failed to report skipped module: {}", err);
+                    }
+
+                    continue
+                }
+
+                modules.push(ZygiskModule::new(&meta.id, fd)?);
             }
             
             debug!("modules: {:?}", modules);
@@ -85,52 +118,82 @@ impl ApiBridge for ZygiskCompat {
     }
 
     fn on_specialize(&self, args: SpecializeArgs) {
-        let env = args.env();
+        let (env, is_system_server) = match args.env().and_then(|env| Ok((env, args.is_system_server()?))) {
+            Ok(args) => args,
+            Err(err) => {
+                warn!("bad specialize args, modules skipped: {}", err);
+                return
+            }
+        };
 
         let mut lock = self.ctx.lock().unwrap();
         let modules = &lock.modules;
 
         for module in modules {
             debug!("call `onLoad` for module: {}", module.id());
-            module.entry(env);
+            invoke(module, "onLoad", || module.entry(env));
         }
 
-        if args.is_system_server() {
+        if is_system_server {
             for module in modules {
                 debug!("call `preServerSpecialize` for module: {}", module.id());
                 let args = module.args_server(&args);
-                module.prss(&args);
+                invoke(module, "preServerSpecialize", || module.prss(&args));
             }
         } else {
             for module in modules {
                 debug!("call `preAppSpecialize` for module: {}", module.id());
                 let args = module.args_app(&args);
-                module.pras(&args);
+                invoke(module, "preAppSpecialize", || module.pras(&args));
             }
         }
 
-        lock.args.extend(args.as_slice());
+        if !lock.modules.is_empty() {
+            lock.report = TimingReport::open().map_err(|err| warn!("failed to report timings: {}", err)).ok();
+        }
     }
 
-    fn after_specialize(&self) {
-        let lock = self.ctx.lock().unwrap();
+    fn after_specialize(&self, args: SpecializeArgs) {
+        let is_system_server = match args.is_system_server() {
+            Ok(is_system_server) => is_system_server,
+            Err(err) => {
+                warn!("bad specialize args, modules skipped: {}", err);
+                return
+            }
+        };
 
-        let args = &lock.args;
-        let args= SpecializeArgs::from(args.as_ptr() as *mut _);
+        let mut lock = self.ctx.lock().unwrap();
 
         let modules = &lock.modules;
         
-        if args.is_system_server() {
+        if is_system_server {
             for module in modules {
                 debug!("call `postServerSpecialize` for module: {}", module.id());
                 let args = module.args_server(&args);
-                module.poss(&args);
+                invoke(module, "postServerSpecialize", || module.poss(&args));
             }
         } else {
             for module in modules {
                 debug!("call `postAppSpecialize` for module: {}", module.id());
                 let args = module.args_app(&args);
-                module.poas(&args);
+                invoke(module, "postAppSpecialize", || module.poas(&args));
+            }
+        }
+
+        for module in modules {
+            // umount is decided by the loader before specialize, it's too late to honour it here
+            if module.has_option(ModuleOption::ForceDenylistUnmount) {
+                debug!("module {} requested {}, ignored", module.id(), ModuleOption::ForceDenylistUnmount);
+            }
+
+            if module.has_option(ModuleOption::DlcloseModuleLibrary) {
+                module.unload();
+            }
+        }
+
+        if let Some(report) = lock.report.take() {
+            if let Err(err) = report.send(&timing::take()) {
+                warn!("failed to report timings: {}", err);
             }
         }
     }
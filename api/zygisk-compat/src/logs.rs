@@ -18,3 +18,11 @@ macro_rules! debug {
         log::debug!(concat!("[{}] ", $fmt), *crate::logs::PID, $( $args ),*);
     };
 }
+
+#[macro_export]
+#[allow(clippy::crate_in_macro_def)]
+macro_rules! warn {
+    ($fmt: literal $( ,$args: expr )*) => {
+        log::warn!(concat!("[{}] ", $fmt), *crate::logs::PID, $( $args ),*);
+    };
+}
@@ -0,0 +1,108 @@
+use std::fmt::{Display, Formatter};
+
+// `zygisk::Option` as understood by the compat layer, independent of the api version a module was built against
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ModuleOption {
+    ForceDenylistUnmount,
+    DlcloseModuleLibrary
+}
+
+impl Display for ModuleOption {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ModuleOption::ForceDenylistUnmount => write!(fmt, "FORCE_DENYLIST_UNMOUNT"),
+            ModuleOption::DlcloseModuleLibrary => write!(fmt, "DLCLOSE_MODULE_LIBRARY")
+        }
+    }
+}
+
+// raw values of `zygisk::Option` in the header of each api version, indexed by the value a module passes
+const OPTIONS_V1: &[ModuleOption] = &[ModuleOption::ForceDenylistUnmount, ModuleOption::DlcloseModuleLibrary];
+const OPTIONS_V2: &[ModuleOption] = &[ModuleOption::ForceDenylistUnmount, ModuleOption::DlcloseModuleLibrary];
+const OPTIONS_V3: &[ModuleOption] = &[ModuleOption::ForceDenylistUnmount, ModuleOption::DlcloseModuleLibrary];
+const OPTIONS_V4: &[ModuleOption] = &[ModuleOption::ForceDenylistUnmount, ModuleOption::DlcloseModuleLibrary];
+
+fn options_of(version: libc::c_long) -> &'static [ModuleOption] {
+    match version {
+        1 => OPTIONS_V1,
+        2 => OPTIONS_V2,
+        3 => OPTIONS_V3,
+        4 => OPTIONS_V4,
+        _ => &[]
+    }
+}
+
+// translate a raw option with the numbering of the module's api version, unknown values are dropped
+pub fn translate(version: libc::c_long, raw: libc::c_int) -> Option<ModuleOption> {
+    usize::try_from(raw).ok().and_then(|index| options_of(version).get(index).copied())
+}
+
+// index of `setOption` in the api table after `registerModule`, `pltHookExclude` is gone since v4
+pub fn set_option_slot(version: libc::c_long) -> Option<usize> {
+    match version {
+        1 ..= 3 => Some(5),
+        4 => Some(4),
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use super::ModuleOption::*;
+
+    #[test]
+    fn set_option_slot_per_version() {
+        let cases = [
+            (-1, None),
+            (0, None),
+            (1, Some(5)),
+            (2, Some(5)),
+            (3, Some(5)),
+            (4, Some(4)),
+            (5, None),
+            (libc::c_long::MAX, None)
+        ];
+
+        for (version, slot) in cases {
+            assert_eq!(set_option_slot(version), slot, "api version {version}");
+        }
+    }
+
+    #[test]
+    fn translate_per_version() {
+        let cases = [
+            (1, 0, Some(ForceDenylistUnmount)),
+            (1, 1, Some(DlcloseModuleLibrary)),
+            (2, 0, Some(ForceDenylistUnmount)),
+            (2, 1, Some(DlcloseModuleLibrary)),
+            (3, 0, Some(ForceDenylistUnmount)),
+            (3, 1, Some(DlcloseModuleLibrary)),
+            (4, 0, Some(ForceDenylistUnmount)),
+            (4, 1, Some(DlcloseModuleLibrary))
+        ];
+
+        for (version, raw, option) in cases {
+            assert_eq!(translate(version, raw), option, "api version {version}, option {raw}");
+        }
+    }
+
+    #[test]
+    fn translate_out_of_range() {
+        let cases = [
+            (1, 2),
+            (4, 2),
+            (4, -1),
+            (4, libc::c_int::MAX),
+            (4, libc::c_int::MIN),
+            (0, 0),
+            (5, 0),
+            (-1, 1)
+        ];
+
+        for (version, raw) in cases {
+            assert_eq!(translate(version, raw), None, "api version {version}, option {raw}");
+        }
+    }
+}
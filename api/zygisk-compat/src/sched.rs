@@ -0,0 +1,92 @@
+use std::mem;
+
+use log::error;
+
+// scheduling attributes of the specializing thread, which modules are not supposed to leave changed
+#[derive(PartialEq, Eq)]
+struct SchedState {
+    policy: libc::c_int,
+    priority: libc::c_int,
+    nice: libc::c_int,
+    affinity: Vec<usize>
+}
+
+impl SchedState {
+    fn capture() -> Option<Self> {
+        unsafe {
+            let tid = libc::gettid();
+
+            let policy = libc::sched_getscheduler(tid);
+            if policy < 0 {
+                return None
+            }
+
+            let mut param: libc::sched_param = mem::zeroed();
+            if libc::sched_getparam(tid, &mut param) < 0 {
+                return None
+            }
+
+            // -1 is a valid nice value, so errno tells failures apart
+            *libc::__errno() = 0;
+            let nice = libc::getpriority(libc::PRIO_PROCESS, tid as _);
+            if nice == -1 && *libc::__errno() != 0 {
+                return None
+            }
+
+            let mut set: libc::cpu_set_t = mem::zeroed();
+            if libc::sched_getaffinity(tid, mem::size_of::<libc::cpu_set_t>(), &mut set) < 0 {
+                return None
+            }
+
+            let affinity = (0 .. libc::CPU_SETSIZE as usize).filter(|cpu| libc::CPU_ISSET(*cpu, &set)).collect();
+
+            Some(Self { policy, priority: param.sched_priority, nice, affinity })
+        }
+    }
+
+    fn restore(&self) {
+        unsafe {
+            let tid = libc::gettid();
+
+            let param = libc::sched_param { sched_priority: self.priority };
+            if libc::sched_setscheduler(tid, self.policy, &param) < 0 {
+                error!("failed to restore scheduler policy: {}", std::io::Error::last_os_error());
+            }
+
+            if libc::setpriority(libc::PRIO_PROCESS, tid as _, self.nice) < 0 {
+                error!("failed to restore nice value: {}", std::io::Error::last_os_error());
+            }
+
+            let mut set: libc::cpu_set_t = mem::zeroed();
+            for cpu in &self.affinity {
+                libc::CPU_SET(*cpu, &mut set);
+            }
+
+            if libc::sched_setaffinity(tid, mem::size_of::<libc::cpu_set_t>(), &set) < 0 {
+                error!("failed to restore cpu affinity: {}", std::io::Error::last_os_error());
+            }
+        }
+    }
+
+    fn describe(&self) -> String {
+        format!("policy={} priority={} nice={} cpus={:?}", self.policy, self.priority, self.nice, self.affinity)
+    }
+}
+
+// run a module callback, and undo any change it made to scheduling attributes of the current thread
+pub fn preserve<R>(module: &str, callback: &str, func: impl FnOnce() -> R) -> R {
+    let before = SchedState::capture();
+    let result = func();
+
+    if let (Some(before), Some(after)) = (before, SchedState::capture()) {
+        if before != after {
+            warn!(
+                "module {} altered scheduling in `{}`: {} -> {}, restoring",
+                module, callback, before.describe(), after.describe()
+            );
+            before.restore();
+        }
+    }
+
+    result
+}
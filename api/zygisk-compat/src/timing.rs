@@ -0,0 +1,22 @@
+use std::mem;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use crate::common::CallbackTiming;
+
+// callbacks measured in this process so far, sent to the daemon after specialize
+static TIMINGS: Mutex<Vec<CallbackTiming>> = Mutex::new(Vec::new());
+
+pub fn measure<R>(module: &str, callback: &str, func: impl FnOnce() -> R) -> R {
+    let start = Instant::now();
+    let result = func();
+    let micros = start.elapsed().as_micros() as u64;
+
+    TIMINGS.lock().unwrap().push(CallbackTiming { module: module.into(), callback: callback.into(), micros });
+
+    result
+}
+
+pub fn take() -> Vec<CallbackTiming> {
+    mem::take(&mut TIMINGS.lock().unwrap())
+}
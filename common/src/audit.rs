@@ -0,0 +1,28 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::warn;
+
+pub const AUDIT_LOG: &str = "/data/adb/zloader/audit.log";
+
+// the log is started over beyond this, so that it can't fill up /data
+const MAX_AUDIT_LOG_SIZE: u64 = 1 << 20;
+
+// things that went wrong inside a target or were left out on purpose, kept apart from logcat
+pub fn record(pid: i32, message: &str) {
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs());
+
+    let res = (|| {
+        if fs::metadata(AUDIT_LOG).is_ok_and(|metadata| metadata.len() > MAX_AUDIT_LOG_SIZE) {
+            fs::remove_file(AUDIT_LOG)?;
+        }
+
+        let mut file = OpenOptions::new().create(true).append(true).open(AUDIT_LOG)?;
+        file.write_all(format!("{timestamp} [{pid}] {message}\n").as_bytes())
+    })();
+
+    if let Err(err) = res {
+        warn!("[{pid}] failed to write audit log: {err}");
+    }
+}
@@ -0,0 +1,108 @@
+use std::env;
+use std::fmt::{Display, Formatter};
+use std::sync::OnceLock;
+
+use log::{debug, LevelFilter};
+
+use crate::properties::getprop;
+
+#[derive(Debug, Clone, Copy)]
+enum Source {
+    Env(&'static str),
+    Property(&'static str)
+}
+
+impl Display for Source {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Source::Env(name) => write!(fmt, "env {name}"),
+            Source::Property(name) => write!(fmt, "prop {name}")
+        }
+    }
+}
+
+// a toggle read from the environment or a system property, once per process, so it can't change halfway through
+pub struct Toggle {
+    source: Source,
+    value: OnceLock<Option<String>>
+}
+
+impl Toggle {
+    const fn env(name: &'static str) -> Self {
+        Self { source: Source::Env(name), value: OnceLock::new() }
+    }
+
+    const fn property(name: &'static str) -> Self {
+        Self { source: Source::Property(name), value: OnceLock::new() }
+    }
+
+    // none if unset, an empty property counts as unset
+    pub fn raw(&self) -> Option<&str> {
+        let value = self.value.get_or_init(|| match self.source {
+            Source::Env(name) => env::var(name).ok(),
+            Source::Property(name) => Some(getprop(name)).filter(|value| !value.is_empty())
+        });
+
+        value.as_deref()
+    }
+
+    pub fn is_set(&self) -> bool {
+        self.raw().is_some()
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        matches!(self.raw(), Some("1" | "true"))
+    }
+}
+
+impl Display for Toggle {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(fmt, "{}", self.source)
+    }
+}
+
+// skips the bridge constructor, for processes that only need to dlopen it
+pub static NOLOAD: Toggle = Toggle::env("ZLB_NOLOAD");
+
+// set by ksud for the scripts and daemons it starts
+pub static KERNELSU: Toggle = Toggle::env("KSU");
+
+// only a level such as `debug` is understood, not per-module directives
+pub static RUST_LOG: Toggle = Toggle::env("RUST_LOG");
+
+pub static SAFE_MODE: Toggle = Toggle::property("persist.zloader.safe_mode");
+
+// path of the library whose SpecializeCommon is probed, for running against the mock zygote off Android
+pub static RUNTIME_LIBRARY: Toggle = Toggle::env("ZLOADER_RUNTIME_LIBRARY");
+
+const TOGGLES: &[&Toggle] = &[&NOLOAD, &KERNELSU, &RUST_LOG, &SAFE_MODE, &RUNTIME_LIBRARY];
+
+pub fn noload() -> bool {
+    NOLOAD.is_set()
+}
+
+pub fn kernelsu() -> bool {
+    KERNELSU.is_set()
+}
+
+pub fn safe_mode() -> bool {
+    SAFE_MODE.is_enabled()
+}
+
+// `RUST_LOG` overrides the default level of each component
+pub fn log_level(default: LevelFilter) -> LevelFilter {
+    RUST_LOG.raw().and_then(|level| level.parse().ok()).unwrap_or(default)
+}
+
+// every toggle and its effective value, one per line
+pub fn dump() -> String {
+    TOGGLES.iter()
+        .map(|toggle| format!("{toggle}: {}\n", toggle.raw().unwrap_or("<unset>")))
+        .collect()
+}
+
+pub fn log_effective(component: &str) {
+    for line in dump().lines() {
+        debug!("{component} feature {line}");
+    }
+}
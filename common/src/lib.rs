@@ -2,3 +2,25 @@ pub mod zygote;
 pub mod properties;
 pub mod utils;
 pub mod lazy;
+pub mod process;
+pub mod audit;
+pub mod logfile;
+pub mod rootimpl;
+pub mod features;
+
+use std::path::PathBuf;
+
+// bumped whenever the globals shared between the loader and bridges, or what they mean, change
+pub const BRIDGE_ABI_VERSION: u32 = 1;
+
+// high half of `ZLB_ABI`, so that garbage isn't taken for a version
+pub const BRIDGE_ABI_MAGIC: u32 = 0x5a4c4241;
+
+pub const fn bridge_abi() -> u64 {
+    (BRIDGE_ABI_MAGIC as u64) << 32 | BRIDGE_ABI_VERSION as u64
+}
+
+// control socket of the loader daemon
+pub fn control_socket() -> PathBuf {
+    rootimpl::staging_path("zloader/control.sock")
+}
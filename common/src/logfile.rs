@@ -0,0 +1,174 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::{LevelFilter, Log, Metadata, Record, SetLoggerError};
+
+pub const LOG_ROOT: &str = "/data/adb/zloader/logs";
+
+// a session is rotated into `.old` beyond this, so that it holds at most twice as much
+const MAX_LOG_SIZE: u64 = 1 << 20;
+
+// sessions of each binary kept on disk, older ones are removed on start
+const MAX_SESSIONS: usize = 5;
+
+struct LogFile {
+    path: PathBuf,
+    file: File,
+    size: u64
+}
+
+impl LogFile {
+    fn open(path: PathBuf) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+
+        Ok(Self { path, file, size })
+    }
+
+    fn write(&mut self, line: &str) -> std::io::Result<()> {
+        if self.size + line.len() as u64 > MAX_LOG_SIZE {
+            let mut rotated = self.path.clone().into_os_string();
+            rotated.push(".old");
+
+            fs::rename(&self.path, rotated)?;
+            *self = Self::open(self.path.clone())?;
+        }
+
+        self.file.write_all(line.as_bytes())?;
+        self.size += line.len() as u64;
+
+        Ok(())
+    }
+}
+
+// logcat rolls over quickly during boot, records are written to a file of the session as well
+pub struct FileLogger {
+    inner: Box<dyn Log>,
+    file: Mutex<Option<LogFile>>
+}
+
+impl Log for FileLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        self.inner.log(record);
+
+        if !self.inner.enabled(record.metadata()) {
+            return
+        }
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        let line = format!(
+            "{}.{:03} {} {} {}: {}\n",
+            now.as_secs(), now.subsec_millis(), std::process::id(), record.level(), record.target(), record.args()
+        );
+
+        let mut lock = self.file.lock().unwrap();
+
+        // give up on the file rather than on logging
+        if let Some(file) = lock.as_mut() {
+            if file.write(&line).is_err() {
+                *lock = None;
+            }
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+
+        if let Some(file) = self.file.lock().unwrap().as_mut() {
+            let _ = file.file.flush();
+        }
+    }
+}
+
+fn session_files(name: &str) -> Vec<PathBuf> {
+    let mut files: Vec<_> = fs::read_dir(LOG_ROOT).into_iter().flatten().flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            let file_name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+            file_name.starts_with(&format!("{name}-")) && file_name.ends_with(".log")
+        })
+        .collect();
+
+    // named after the start time, so that the name sorts them
+    files.sort();
+    files
+}
+
+fn new_session(name: &str) -> std::io::Result<LogFile> {
+    fs::create_dir_all(LOG_ROOT)?;
+
+    let mut files = session_files(name);
+
+    while files.len() >= MAX_SESSIONS {
+        let oldest = files.remove(0);
+        let mut rotated = oldest.clone().into_os_string();
+        rotated.push(".old");
+
+        let _ = fs::remove_file(&oldest);
+        let _ = fs::remove_file(rotated);
+    }
+
+    let start = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs());
+    LogFile::open(Path::new(LOG_ROOT).join(format!("{name}-{start}.log")))
+}
+
+// install `inner` as the logger, teeing into a new session under `LOG_ROOT` if `to_file` is set
+pub fn init(name: &str, inner: Box<dyn Log>, level: LevelFilter, to_file: bool) -> Result<(), SetLoggerError> {
+    let file = if to_file {
+        new_session(name).map_err(|err| eprintln!("failed to create log file: {err}")).ok()
+    } else {
+        None
+    };
+
+    log::set_boxed_logger(Box::new(FileLogger { inner, file: Mutex::new(file) }))?;
+    log::set_max_level(level);
+
+    Ok(())
+}
+
+// the last `count` sessions of every binary, the rotated part of a session goes first
+pub fn sessions(count: usize) -> Vec<PathBuf> {
+    let mut files: Vec<_> = fs::read_dir(LOG_ROOT).into_iter().flatten().flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "log"))
+        .collect();
+
+    let start_of = |path: &PathBuf| -> u64 {
+        path.file_stem().and_then(|stem| stem.to_str())
+            .and_then(|stem| stem.rsplit('-').next())
+            .and_then(|start| start.parse().ok())
+            .unwrap_or(0)
+    };
+
+    files.sort_by_key(|path| std::cmp::Reverse(start_of(path)));
+    files.truncate(count);
+    files.reverse();
+
+    files.into_iter()
+        .flat_map(|path| {
+            let mut rotated = path.clone().into_os_string();
+            rotated.push(".old");
+            [PathBuf::from(rotated), path]
+        })
+        .filter(|path| path.exists())
+        .collect()
+}
+
+// concatenate the last `count` sessions, for bug reports
+pub fn dump(count: usize) -> String {
+    let mut output = String::new();
+
+    for path in sessions(count) {
+        output += &format!("==> {} <==\n", path.display());
+        output += &fs::read_to_string(&path).unwrap_or_else(|err| format!("failed to read: {err}\n"));
+    }
+
+    output
+}
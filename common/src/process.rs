@@ -0,0 +1,68 @@
+use std::ffi::c_char;
+
+// instruction set of the zygote itself, as named by the runtime (`dalvik.vm.isa.*`)
+pub const NATIVE_INSTRUCTION_SET: &str = if cfg!(target_arch = "aarch64") {
+    "arm64"
+} else if cfg!(target_arch = "x86_64") {
+    "x86_64"
+} else if cfg!(target_arch = "arm") {
+    "arm"
+} else {
+    "x86"
+};
+
+// snapshot of a specializing process passed to `check_process_ex` of filters,
+// strings are null when the runtime didn't provide them
+#[repr(C)]
+#[derive(Debug)]
+pub struct ProcessInfo {
+    pub uid: libc::uid_t,
+    pub package: *const c_char,
+    pub name: *const c_char,
+    pub instruction_set: *const c_char,
+    // app code runs through a native bridge (e.g. Houdini), its own libraries are not native to the process
+    pub native_bridge: bool,
+    // what follows `:` in the process name (e.g. `isolated` or `webview`), null for the main process of a package
+    pub component: *const c_char
+}
+
+pub fn is_native_bridge(instruction_set: Option<&str>) -> bool {
+    instruction_set.is_some_and(|isa| isa != NATIVE_INSTRUCTION_SET)
+}
+
+pub fn component(name: &str) -> Option<&str> {
+    name.split_once(':').map(|(_, component)| component)
+}
+
+// match a process name against a glob, `*` matches any run of characters and `?` a single one
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+
+    let (mut p, mut n) = (0, 0);
+    // position of the last `*` and where it started matching, to backtrack to
+    let mut star = None;
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                star = Some((p, n));
+                p += 1;
+            }
+            Some(ch) if *ch == '?' || *ch == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match star {
+                Some((star_p, star_n)) => {
+                    p = star_p + 1;
+                    n = star_n + 1;
+                    star = Some((star_p, star_n + 1));
+                }
+                None => return false
+            }
+        }
+    }
+
+    pattern[p ..].iter().all(|ch| *ch == '*')
+}
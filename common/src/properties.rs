@@ -2,6 +2,7 @@ use std::ffi::{CStr, CString};
 
 extern "C" {
     fn __system_property_get(name: *const libc::c_char, value: *mut libc::c_char) -> u32;
+    fn __system_property_set(name: *const libc::c_char, value: *const libc::c_char) -> libc::c_int;
 }
 
 pub fn getprop(name: &str) -> String {
@@ -15,3 +16,10 @@ pub fn getprop(name: &str) -> String {
     
     prop.to_string_lossy().into()
 }
+
+pub fn setprop(name: &str, value: &str) -> bool {
+    let name = CString::new(name).unwrap();
+    let value = CString::new(value).unwrap();
+
+    unsafe { __system_property_set(name.as_ptr(), value.as_ptr()) == 0 }
+}
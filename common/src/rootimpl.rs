@@ -0,0 +1,75 @@
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+// the root implementation zloader is installed with, which decides where files are staged
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RootImpl {
+    Magisk,
+    KernelSU,
+    APatch,
+    Unknown
+}
+
+impl Display for RootImpl {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            RootImpl::Magisk => "Magisk",
+            RootImpl::KernelSU => "KernelSU",
+            RootImpl::APatch => "APatch",
+            RootImpl::Unknown => "unknown"
+        };
+
+        write!(fmt, "{name}")
+    }
+}
+
+impl RootImpl {
+    // only works for root, app processes can't see into /data/adb
+    pub fn detect() -> Self {
+        if Path::new("/data/adb/ksud").exists() {
+            RootImpl::KernelSU
+        } else if Path::new("/data/adb/apd").exists() {
+            RootImpl::APatch
+        } else if Path::new("/data/adb/magisk").exists() {
+            RootImpl::Magisk
+        } else {
+            RootImpl::Unknown
+        }
+    }
+
+    // tmpfs directories it keeps its files in, the first one present is used
+    fn staging_candidates(self) -> &'static [&'static str] {
+        match self {
+            RootImpl::Magisk | RootImpl::Unknown => &["/debug_ramdisk", "/sbin"],
+            // the same order as ksud picks its own tmp dir in
+            RootImpl::KernelSU => &["/debug_ramdisk", "/patch_hw", "/oem", "/root", "/sbin"],
+            RootImpl::APatch => &["/debug_ramdisk", "/dev"]
+        }
+    }
+
+    pub fn staging_dir(self) -> PathBuf {
+        let candidates = self.staging_candidates();
+
+        candidates.iter()
+            .map(PathBuf::from)
+            .find(|dir| dir.is_dir())
+            .unwrap_or_else(|| PathBuf::from(candidates[0]))
+    }
+}
+
+static STAGING_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+// for processes that can't detect the root implementation, bridges are told by the loader instead
+pub fn set_staging_dir(dir: PathBuf) -> bool {
+    STAGING_DIR.set(dir).is_ok()
+}
+
+pub fn staging_dir() -> &'static Path {
+    STAGING_DIR.get_or_init(|| RootImpl::detect().staging_dir())
+}
+
+// where the files of a component are staged, such as `zloader-zygisk`
+pub fn staging_path(name: &str) -> PathBuf {
+    staging_dir().join(name)
+}
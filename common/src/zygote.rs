@@ -1,5 +1,7 @@
-use std::{mem, ptr, slice};
-use jni_sys::{jint, jintArray, jlong, JNIEnv, jobjectArray, jstring};
+use std::{fmt, mem, ptr, slice};
+use std::error::Error;
+use std::ffi::{c_char, CString};
+use jni_sys::{jboolean, jint, jintArray, jlong, jsize, JNIEnv, JNINativeInterface__1_6, jobjectArray, jstring};
 use crate::lazy::Lazy;
 use crate::properties::getprop;
 
@@ -7,32 +9,267 @@ static SDK_VERSION: Lazy<i32> = Lazy::new(|| {
     getprop("ro.build.version.sdk").parse().unwrap()
 });
 
+// why an argument can't be read, an SDK or offset mismatch shows up as one of these instead of a crash
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ArgError {
+    UnsupportedSdk(i32),
+    LengthMismatch { expected: usize, actual: usize },
+    Unavailable(&'static str),
+    Null(&'static str),
+    Missing(&'static str),
+    Invalid(&'static str),
+    Jni(&'static str)
+}
+
+impl fmt::Display for ArgError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArgError::UnsupportedSdk(sdk) => write!(fmt, "unsupported SDK version: {sdk}"),
+            ArgError::LengthMismatch { expected, actual } => write!(
+                fmt, "the layout expects {expected} arguments, but the loader verified {actual}"
+            ),
+            ArgError::Unavailable(name) => write!(fmt, "`{name}` is not available on SDK {}", *SDK_VERSION),
+            ArgError::Null(name) => write!(fmt, "`{name}` is null"),
+            ArgError::Missing(name) => write!(fmt, "`{name}` is not found in the signature"),
+            ArgError::Invalid(name) => write!(fmt, "the value for `{name}` can't be passed to java"),
+            ArgError::Jni(name) => write!(fmt, "`{name}` threw an exception")
+        }
+    }
+}
+
+impl Error for ArgError {}
+
+// parameter types of SpecializeCommon, as far as telling its arguments apart needs
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ArgKind {
+    Env,
+    UInt,
+    Int,
+    Long,
+    Bool,
+    IntArray,
+    ObjectArray,
+    String,
+    Other
+}
+
+// every argument `SpecializeArgs` knows of, in the order SpecializeCommon takes them, and whether it may be missing
+const ARG_FIELDS: [(&str, ArgKind, bool); 22] = [
+    ("env", ArgKind::Env, false),
+    ("uid", ArgKind::UInt, false),
+    ("gid", ArgKind::UInt, false),
+    ("gids", ArgKind::IntArray, false),
+    ("runtime_flags", ArgKind::Int, false),
+    ("rlimits", ArgKind::ObjectArray, false),
+    ("permitted_capabilities", ArgKind::Long, false),
+    ("effective_capabilities", ArgKind::Long, false),
+    ("bounding_capabilities", ArgKind::Long, true),
+    ("mount_external", ArgKind::Int, false),
+    ("managed_se_info", ArgKind::String, false),
+    ("managed_nice_name", ArgKind::String, false),
+    ("is_system_server", ArgKind::Bool, false),
+    ("is_child_zygote", ArgKind::Bool, false),
+    ("managed_instruction_set", ArgKind::String, false),
+    ("managed_app_data_dir", ArgKind::String, false),
+    ("is_top_app", ArgKind::Bool, true),
+    ("pkg_data_info_list", ArgKind::ObjectArray, true),
+    ("allowlisted_data_info_list", ArgKind::ObjectArray, true),
+    ("mount_data_dirs", ArgKind::Bool, true),
+    ("mount_storage_dirs", ArgKind::Bool, true),
+    ("mount_sysprop_overrides", ArgKind::Bool, true)
+];
+
+// signatures assumed when the loader doesn't tell the actual one
+const SDK_29_SIGNATURE: &[ArgKind] = &[
+    ArgKind::Env, ArgKind::UInt, ArgKind::UInt, ArgKind::IntArray, ArgKind::Int, ArgKind::ObjectArray,
+    ArgKind::Long, ArgKind::Long, ArgKind::Int, ArgKind::String, ArgKind::String, ArgKind::Bool, ArgKind::Bool,
+    ArgKind::String, ArgKind::String
+];
+
+// SDK 30 added `is_top_app`, the data info lists and the mount flags, which SDK 31 kept as is
+const SDK_31_SIGNATURE: &[ArgKind] = &[
+    ArgKind::Env, ArgKind::UInt, ArgKind::UInt, ArgKind::IntArray, ArgKind::Int, ArgKind::ObjectArray,
+    ArgKind::Long, ArgKind::Long, ArgKind::Int, ArgKind::String, ArgKind::String, ArgKind::Bool, ArgKind::Bool,
+    ArgKind::String, ArgKind::String, ArgKind::Bool, ArgKind::ObjectArray, ArgKind::ObjectArray, ArgKind::Bool,
+    ArgKind::Bool
+];
+
+const SDK_35_SIGNATURE: &[ArgKind] = &[
+    ArgKind::Env, ArgKind::UInt, ArgKind::UInt, ArgKind::IntArray, ArgKind::Int, ArgKind::ObjectArray,
+    ArgKind::Long, ArgKind::Long, ArgKind::Long, ArgKind::Int, ArgKind::String, ArgKind::String, ArgKind::Bool,
+    ArgKind::Bool, ArgKind::String, ArgKind::String, ArgKind::Bool, ArgKind::ObjectArray, ArgKind::ObjectArray,
+    ArgKind::Bool, ArgKind::Bool, ArgKind::Bool
+];
+
+// where each field of `SpecializeArgs` is in the arguments, shared with the bridge as is
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ArgLayout {
+    // argument count of the signature, 0 if the layout is unknown
+    count: u8,
+    // index + 1 of each field in `ARG_FIELDS`, 0 if the signature doesn't have it
+    slots: [u8; ARG_FIELDS.len()]
+}
+
+impl ArgLayout {
+    pub const UNKNOWN: Self = Self { count: 0, slots: [0; ARG_FIELDS.len()] };
+
+    // greedy matching is enough, as optional arguments never come right before another of the same kind,
+    // unknown trailing arguments of vendor backports are left alone
+    pub fn derive(signature: &[ArgKind]) -> Result<Self, ArgError> {
+        let mut slots = [0; ARG_FIELDS.len()];
+        let mut next = 0;
+
+        for (slot, (name, kind, optional)) in slots.iter_mut().zip(ARG_FIELDS) {
+            if signature.get(next) == Some(&kind) {
+                next += 1;
+                *slot = next as u8;
+            } else if !optional {
+                return Err(ArgError::Missing(name))
+            }
+        }
+
+        Ok(Self { count: signature.len() as u8, slots })
+    }
+
+    pub fn for_sdk(sdk: i32) -> Result<Self, ArgError> {
+        match sdk {
+            29 => Self::derive(SDK_29_SIGNATURE),
+            30 ..= 34 => Self::derive(SDK_31_SIGNATURE),
+            35 => Self::derive(SDK_35_SIGNATURE),
+            sdk => Err(ArgError::UnsupportedSdk(sdk))
+        }
+    }
+
+    // the layout itself if known, or the one of the SDK of this device
+    pub fn or_sdk(self) -> Self {
+        if self.is_known() {
+            return self
+        }
+
+        Self::for_sdk(*SDK_VERSION).unwrap_or(Self::UNKNOWN)
+    }
+
+    pub fn is_known(&self) -> bool {
+        self.count != 0
+    }
+
+    pub fn count(&self) -> usize {
+        self.count as usize
+    }
+
+    fn slot(&self, field: usize) -> Option<usize> {
+        self.slots[field].checked_sub(1).map(usize::from)
+    }
+}
+
+impl fmt::Display for ArgLayout {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fields: Vec<_> = ARG_FIELDS.iter().enumerate()
+            .filter_map(|(field, (name, _, _))| Some(format!("{name}={}", self.slot(field)?)))
+            .collect();
+
+        write!(fmt, "{} arguments: {}", self.count, fields.join(", "))
+    }
+}
+
+// the env of the thread in SpecializeCommon, functions are looked up by their offset in the table, the same as
+// the loader does for a remote env; new references are local to the native frame SpecializeCommon runs in
+struct Jni(*mut JNIEnv);
+
+impl Jni {
+    unsafe fn function<F>(&self, offset: usize) -> F {
+        let table = *self.0 as *const u8;
+        mem::transmute_copy(&*(table.add(offset) as *const usize))
+    }
+
+    fn check(&self, name: &'static str) -> Result<(), ArgError> {
+        unsafe {
+            let exception_check: unsafe extern "system" fn(*mut JNIEnv) -> jboolean =
+                self.function(mem::offset_of!(JNINativeInterface__1_6, ExceptionCheck));
+
+            if !exception_check(self.0) {
+                return Ok(())
+            }
+
+            let exception_clear: unsafe extern "system" fn(*mut JNIEnv) =
+                self.function(mem::offset_of!(JNINativeInterface__1_6, ExceptionClear));
+
+            exception_clear(self.0);
+        }
+
+        Err(ArgError::Jni(name))
+    }
+
+    fn new_int_array(&self, values: &[jint]) -> Result<jintArray, ArgError> {
+        let len = jsize::try_from(values.len()).map_err(|_| ArgError::Invalid("gids"))?;
+
+        unsafe {
+            let new_int_array: unsafe extern "system" fn(*mut JNIEnv, jsize) -> jintArray =
+                self.function(mem::offset_of!(JNINativeInterface__1_6, NewIntArray));
+            let set_int_array_region: unsafe extern "system" fn(*mut JNIEnv, jintArray, jsize, jsize, *const jint) =
+                self.function(mem::offset_of!(JNINativeInterface__1_6, SetIntArrayRegion));
+
+            let array = new_int_array(self.0, len);
+            self.check("NewIntArray")?;
+
+            set_int_array_region(self.0, array, 0, len, values.as_ptr());
+            self.check("SetIntArrayRegion")?;
+
+            Ok(array)
+        }
+    }
+
+    // modified UTF-8 only differs from UTF-8 in nul and supplementary characters
+    fn new_string(&self, value: &str, name: &'static str) -> Result<jstring, ArgError> {
+        if value.chars().any(|c| c as u32 > 0xffff) {
+            return Err(ArgError::Invalid(name))
+        }
+
+        let value = CString::new(value).map_err(|_| ArgError::Invalid(name))?;
+
+        unsafe {
+            let new_string_utf: unsafe extern "system" fn(*mut JNIEnv, *const c_char) -> jstring =
+                self.function(mem::offset_of!(JNINativeInterface__1_6, NewStringUTF));
+
+            let string = new_string_utf(self.0, value.as_ptr());
+            self.check("NewStringUTF")?;
+
+            Ok(string)
+        }
+    }
+}
+
+// a field is null if the layout doesn't have it, which the accessors report rather than dereference
 #[repr(C)]
 #[derive(Debug, Clone)]
 pub struct SpecializeArgs {
     ptr: *const u64,
-    pub env: *mut JNIEnv,
-    pub uid: *mut jint,
-    pub gid: *mut jint,
-    pub gids: *mut jintArray,
-    pub runtime_flags: *mut jint,
-    pub rlimits: *mut jobjectArray,
-    pub permitted_capabilities: *mut jlong,
-    pub effective_capabilities: *mut jlong,
-    pub bounding_capabilities: *mut jlong,
-    pub mount_external: *mut jint,
-    pub managed_se_info: *mut jstring,
-    pub managed_nice_name: *mut jstring,
-    pub is_system_server: *mut bool,
-    pub is_child_zygote: *mut bool,
-    pub managed_instruction_set: *mut jstring,
-    pub managed_app_data_dir: *mut jstring,
-    pub is_top_app: *mut bool,
-    pub pkg_data_info_list: *mut jobjectArray,
-    pub allowlisted_data_info_list: *mut jobjectArray,
-    pub mount_data_dirs: *mut bool,
-    pub mount_storage_dirs: *mut bool,
-    pub mount_sysprop_overrides: *mut bool,
+    // argument count verified by the loader, 0 if unknown
+    len: usize,
+    layout: ArgLayout,
+    env: *mut JNIEnv,
+    uid: *mut jint,
+    gid: *mut jint,
+    gids: *mut jintArray,
+    runtime_flags: *mut jint,
+    rlimits: *mut jobjectArray,
+    permitted_capabilities: *mut jlong,
+    effective_capabilities: *mut jlong,
+    bounding_capabilities: *mut jlong,
+    mount_external: *mut jint,
+    managed_se_info: *mut jstring,
+    managed_nice_name: *mut jstring,
+    is_system_server: *mut bool,
+    is_child_zygote: *mut bool,
+    managed_instruction_set: *mut jstring,
+    managed_app_data_dir: *mut jstring,
+    is_top_app: *mut bool,
+    pkg_data_info_list: *mut jobjectArray,
+    allowlisted_data_info_list: *mut jobjectArray,
+    mount_data_dirs: *mut bool,
+    mount_storage_dirs: *mut bool,
+    mount_sysprop_overrides: *mut bool,
 }
 
 impl Default for SpecializeArgs {
@@ -43,71 +280,219 @@ impl Default for SpecializeArgs {
     }
 }
 
-impl From<*mut u64> for SpecializeArgs {
-    #[allow(clippy::not_unsafe_ptr_arg_deref)]
-    fn from(value: *mut u64) -> Self {
+// a checked getter for the value of each field, and an unsafe one for where it is, which the compat layers
+// hand on to modules as is
+macro_rules! accessors {
+    ($( $field: ident, $raw: ident: $kind: ident $ty: ty; )*) => {
+        $(
+            pub fn $field(&self) -> Result<$ty, ArgError> {
+                accessors!(@read $kind self, $field)
+            }
+
+            /// # Safety
+            ///
+            /// the pointer is null if the layout doesn't have the field, and is only valid as long as the
+            /// memory the args were made from
+            pub unsafe fn $raw(&self) -> *mut $ty {
+                self.$field
+            }
+        )*
+    };
+    (@read value $self: ident, $field: ident) => {
+        $self.read($self.$field, stringify!($field))
+    };
+    // the other bytes of the slot aren't cleared by the caller, so only the lowest one is looked at
+    (@read flag $self: ident, $field: ident) => {
+        $self.read($self.$field as *mut u8, stringify!($field)).map(|flag| flag != 0)
+    };
+}
+
+impl SpecializeArgs {
+    /// # Safety
+    ///
+    /// `value` points to `len` arguments, or to as many as the layout counts if `len` is 0, which stay valid
+    /// and are only written through these args while they are used
+    pub unsafe fn with_layout(value: *mut u64, len: usize, layout: ArgLayout) -> Self {
         macro_rules! arg {
-            ($( $min: literal, $idx: literal );*) => {
-                $(
-                    if *SDK_VERSION >= $min {
-                        value.offset($idx) as _
-                    } else
-                )* {
-                    ptr::null_mut()
+            ($field: literal) => {
+                match layout.slot($field) {
+                    Some(index) if !value.is_null() => value.add(index) as _,
+                    _ => ptr::null_mut()
                 }
             };
         }
 
-        unsafe {
-            Self {
-                ptr: value,
-                env: arg!(31, 0),
-                uid: arg!(31, 1),
-                gid: arg!(31, 2),
-                gids: arg!(31, 3),
-                runtime_flags: arg!(31, 4),
-                rlimits: arg!(31, 5),
-                permitted_capabilities: arg!(31, 6),
-                effective_capabilities: arg!(31, 7),
-                bounding_capabilities: arg!(35, 8),
-                mount_external: arg!(35, 9; 31, 8),
-                managed_se_info: arg!(35, 10; 31, 9),
-                managed_nice_name: arg!(35, 11; 31, 10),
-                is_system_server: arg!(35, 12; 31, 11),
-                is_child_zygote: arg!(35, 13; 31, 12),
-                managed_instruction_set: arg!(35, 14; 31, 13),
-                managed_app_data_dir: arg!(35, 15; 31, 14),
-                is_top_app: arg!(35, 16; 31, 15),
-                pkg_data_info_list: arg!(35, 17; 31, 16),
-                allowlisted_data_info_list: arg!(35, 18; 31, 17),
-                mount_data_dirs: arg!(35, 19; 31, 18),
-                mount_storage_dirs: arg!(35, 20; 31, 19),
-                mount_sysprop_overrides: arg!(35, 21),
-            }
+        Self {
+            ptr: value,
+            len,
+            layout,
+            env: arg!(0),
+            uid: arg!(1),
+            gid: arg!(2),
+            gids: arg!(3),
+            runtime_flags: arg!(4),
+            rlimits: arg!(5),
+            permitted_capabilities: arg!(6),
+            effective_capabilities: arg!(7),
+            bounding_capabilities: arg!(8),
+            mount_external: arg!(9),
+            managed_se_info: arg!(10),
+            managed_nice_name: arg!(11),
+            is_system_server: arg!(12),
+            is_child_zygote: arg!(13),
+            managed_instruction_set: arg!(14),
+            managed_app_data_dir: arg!(15),
+            is_top_app: arg!(16),
+            pkg_data_info_list: arg!(17),
+            allowlisted_data_info_list: arg!(18),
+            mount_data_dirs: arg!(19),
+            mount_storage_dirs: arg!(20),
+            mount_sysprop_overrides: arg!(21),
         }
     }
-}
 
-impl SpecializeArgs {
-    pub fn as_slice(&self) -> &[u64] {
-        unsafe {
-            match *SDK_VERSION {
-                31 ..= 34 => {
-                    slice::from_raw_parts(self.ptr, 20)
-                }
-                35 => {
-                    slice::from_raw_parts(self.ptr, 22)
-                }
-                _ => panic!("unsupported SDK version: {}", *SDK_VERSION)
-            }
+    // the layout comes from the signature or a supported SDK, and is only trusted if the loader agrees on the count
+    pub fn arg_count(&self) -> Result<usize, ArgError> {
+        if self.ptr.is_null() {
+            return Err(ArgError::Null("args"))
+        }
+
+        if !self.layout.is_known() {
+            return Err(ArgError::UnsupportedSdk(*SDK_VERSION))
+        }
+
+        let expected = self.layout.count();
+
+        match self.len {
+            0 => Ok(expected),
+            len if len == expected => Ok(len),
+            len => Err(ArgError::LengthMismatch { expected, actual: len })
+        }
+    }
+
+    pub fn as_slice(&self) -> Result<&[u64], ArgError> {
+        let len = self.arg_count()?;
+        unsafe { Ok(slice::from_raw_parts(self.ptr, len)) }
+    }
+
+    // a slot is only read once the layout is trusted, so that a wrong one can't reach past the args
+    fn read<T: Copy>(&self, field: *mut T, name: &'static str) -> Result<T, ArgError> {
+        self.arg_count()?;
+
+        if field.is_null() {
+            return Err(ArgError::Unavailable(name))
+        }
+
+        unsafe { Ok(*field) }
+    }
+
+    fn write<T: Copy>(&mut self, field: *mut T, name: &'static str, value: T) -> Result<(), ArgError> {
+        self.arg_count()?;
+
+        if field.is_null() {
+            return Err(ArgError::Unavailable(name))
         }
+
+        unsafe { *field = value }
+
+        Ok(())
+    }
+
+    fn jni(&self) -> Result<Jni, ArgError> {
+        Ok(Jni(self.env()? as *mut JNIEnv))
+    }
+
+    pub fn env(&self) -> Result<JNIEnv, ArgError> {
+        let env = self.read(self.env, "env")?;
+
+        if env.is_null() {
+            return Err(ArgError::Null("env"))
+        }
+
+        Ok(env)
+    }
+
+    /// # Safety
+    ///
+    /// see `with_layout`, the pointer is valid as long as the memory the args were made from
+    pub unsafe fn env_ptr(&self) -> *mut JNIEnv {
+        self.env
+    }
+
+    accessors! {
+        uid, uid_ptr: value jint;
+        gid, gid_ptr: value jint;
+        gids, gids_ptr: value jintArray;
+        runtime_flags, runtime_flags_ptr: value jint;
+        rlimits, rlimits_ptr: value jobjectArray;
+        permitted_capabilities, permitted_capabilities_ptr: value jlong;
+        effective_capabilities, effective_capabilities_ptr: value jlong;
+        bounding_capabilities, bounding_capabilities_ptr: value jlong;
+        mount_external, mount_external_ptr: value jint;
+        managed_se_info, managed_se_info_ptr: value jstring;
+        managed_nice_name, managed_nice_name_ptr: value jstring;
+        is_system_server, is_system_server_ptr: flag bool;
+        is_child_zygote, is_child_zygote_ptr: flag bool;
+        managed_instruction_set, managed_instruction_set_ptr: value jstring;
+        managed_app_data_dir, managed_app_data_dir_ptr: value jstring;
+        is_top_app, is_top_app_ptr: flag bool;
+        pkg_data_info_list, pkg_data_info_list_ptr: value jobjectArray;
+        allowlisted_data_info_list, allowlisted_data_info_list_ptr: value jobjectArray;
+        mount_data_dirs, mount_data_dirs_ptr: flag bool;
+        mount_storage_dirs, mount_storage_dirs_ptr: flag bool;
+        mount_sysprop_overrides, mount_sysprop_overrides_ptr: flag bool;
+    }
+
+    // setters for what modules commonly change, only valid before SpecializeCommon has read the args,
+    // that is in pre specialize
+    pub fn set_uid(&mut self, uid: jint) -> Result<(), ArgError> {
+        self.write(self.uid, "uid", uid)
+    }
+
+    pub fn set_gid(&mut self, gid: jint) -> Result<(), ArgError> {
+        self.write(self.gid, "gid", gid)
+    }
+
+    // the array belongs to java, so a new one replaces it rather than being written into
+    pub fn set_gids(&mut self, gids: &[jint]) -> Result<(), ArgError> {
+        let array = self.jni()?.new_int_array(gids)?;
+        self.write(self.gids, "gids", array)
+    }
+
+    pub fn add_runtime_flags(&mut self, flags: jint) -> Result<(), ArgError> {
+        let current = self.runtime_flags()?;
+        self.write(self.runtime_flags, "runtime_flags", current | flags)
+    }
+
+    pub fn remove_runtime_flags(&mut self, flags: jint) -> Result<(), ArgError> {
+        let current = self.runtime_flags()?;
+        self.write(self.runtime_flags, "runtime_flags", current & !flags)
+    }
+
+    pub fn set_mount_external(&mut self, mount_external: jint) -> Result<(), ArgError> {
+        self.write(self.mount_external, "mount_external", mount_external)
+    }
+
+    pub fn set_se_info(&mut self, se_info: &str) -> Result<(), ArgError> {
+        let string = self.jni()?.new_string(se_info, "managed_se_info")?;
+        self.write(self.managed_se_info, "managed_se_info", string)
+    }
+
+    pub fn set_nice_name(&mut self, nice_name: &str) -> Result<(), ArgError> {
+        let string = self.jni()?.new_string(nice_name, "managed_nice_name")?;
+        self.write(self.managed_nice_name, "managed_nice_name", string)
+    }
+
+    pub fn set_app_data_dir(&mut self, app_data_dir: &str) -> Result<(), ArgError> {
+        let string = self.jni()?.new_string(app_data_dir, "managed_app_data_dir")?;
+        self.write(self.managed_app_data_dir, "managed_app_data_dir", string)
     }
 
-    pub fn env(&self) -> JNIEnv {
-        unsafe { *self.env }
+    pub fn set_mount_data_dirs(&mut self, mount_data_dirs: bool) -> Result<(), ArgError> {
+        self.write(self.mount_data_dirs, "mount_data_dirs", mount_data_dirs)
     }
 
-    pub fn is_system_server(&self) -> bool {
-        unsafe { *self.is_system_server }
+    pub fn set_mount_storage_dirs(&mut self, mount_storage_dirs: bool) -> Result<(), ArgError> {
+        self.write(self.mount_storage_dirs, "mount_storage_dirs", mount_storage_dirs)
     }
 }
@@ -1,12 +1,83 @@
 #![no_std]
 
-#[derive(Debug)]
-#[repr(C)]
+pub mod protocol;
+
+// candidate symbols of SpecializeCommon probed at once, each one gets its own uprobe program
+pub const MAX_UPROBE_TARGETS: usize = 4;
+
+#[derive(Debug, Copy, Clone)]
 pub enum EbpfEvent {
-    ZygoteStarted(i32),
+    ZygoteStarted(i32, ZygoteFlavor),
     ZygoteForked(i32),
-    ZygoteCrashed(i32),
+    ZygoteCrashed(i32, ZygoteFlavor),
     RequireUprobeAttach(i32),
-    RequireInject(i32, usize),
+    // pid, return address, where the return address was found, and index of the probed candidate
+    RequireInject(i32, usize, ReturnAddressSource, usize),
     RequireUmount(i32),
+    UprobeSkipped(i32),
+    RequirePostSpecialize(i32),
+    TrackingFailed(i32),
+}
+
+// context of the task that triggered the event
+#[derive(Debug, Copy, Clone)]
+#[repr(C)]
+pub struct EventMeta {
+    pub uid: u32,
+    pub comm: [u8; 16],
+    pub timestamp: u64
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[repr(u32)]
+pub enum ReturnAddressSource {
+    Stack,
+    LinkRegister,
+    FramePointer
+}
+
+impl ReturnAddressSource {
+    pub fn from_raw(value: u64) -> Option<Self> {
+        match value {
+            0 => Some(ReturnAddressSource::Stack),
+            1 => Some(ReturnAddressSource::LinkRegister),
+            2 => Some(ReturnAddressSource::FramePointer),
+            _ => None
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[repr(u32)]
+pub enum ZygoteFlavor {
+    Zygote64,
+    Zygote32
+}
+
+impl ZygoteFlavor {
+    pub fn from_raw(value: u64) -> Option<Self> {
+        match value {
+            0 => Some(ZygoteFlavor::Zygote64),
+            1 => Some(ZygoteFlavor::Zygote32),
+            _ => None
+        }
+    }
+}
+
+// state of a zygote child, stored in the pinned `ZYGOTE_CHILDREN` map
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[repr(u32)]
+pub enum ProcessState {
+    WaitForAttach,
+    WaitForUmount
+}
+
+// a child runs as root until SpecializeCommon, so its uid only decides at the uprobe whether it is stopped again
+// for injection; before that every child is stopped for the attach, except while `Nothing` is in scope
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[repr(u32)]
+pub enum UidFilter {
+    Disabled,
+    Enabled,
+    Nothing
 }
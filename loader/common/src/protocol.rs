@@ -0,0 +1,147 @@
+use core::fmt::{Display, Formatter};
+use core::mem::{offset_of, size_of};
+
+use crate::{EbpfEvent, EventMeta, MAX_UPROBE_TARGETS, ReturnAddressSource, ZygoteFlavor};
+
+pub const MESSAGE_MAGIC: u32 = u32::from_le_bytes(*b"ZLEV");
+
+// bump on every change to `EbpfMessage` or the tags below
+pub const PROTOCOL_VERSION: u32 = 4;
+
+const TAG_ZYGOTE_STARTED: u32 = 0;
+const TAG_ZYGOTE_FORKED: u32 = 1;
+const TAG_ZYGOTE_CRASHED: u32 = 2;
+const TAG_REQUIRE_UPROBE_ATTACH: u32 = 3;
+const TAG_REQUIRE_INJECT: u32 = 4;
+const TAG_REQUIRE_UMOUNT: u32 = 5;
+const TAG_UPROBE_SKIPPED: u32 = 6;
+const TAG_REQUIRE_POST_SPECIALIZE: u32 = 7;
+const TAG_TRACKING_FAILED: u32 = 8;
+
+// wire format of the event channel, only ever written by eBPF and parsed by `decode`
+#[derive(Debug, Copy, Clone)]
+#[repr(C)]
+pub struct EbpfMessage {
+    pub magic: u32,
+    pub version: u32,
+    pub tag: u32,
+    pub pid: i32,
+    pub args: [u64; 2],
+    pub meta: EventMeta
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum DecodeError {
+    Truncated(usize),
+    BadMagic(u32),
+    VersionMismatch(u32),
+    UnknownTag(u32),
+    BadValue(u32)
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            DecodeError::Truncated(len) => write!(fmt, "truncated message: {len} bytes, expected {}", size_of::<EbpfMessage>()),
+            DecodeError::BadMagic(magic) => write!(fmt, "bad magic: 0x{magic:x}"),
+            DecodeError::VersionMismatch(version) => write!(fmt, "protocol version mismatch: got {version}, expected {PROTOCOL_VERSION}"),
+            DecodeError::UnknownTag(tag) => write!(fmt, "unknown event tag: {tag}"),
+            DecodeError::BadValue(tag) => write!(fmt, "bad payload for event tag: {tag}")
+        }
+    }
+}
+
+impl EbpfMessage {
+    pub fn new(event: EbpfEvent, meta: EventMeta) -> Self {
+        let (tag, pid, args) = match event {
+            EbpfEvent::ZygoteStarted(pid, flavor) => (TAG_ZYGOTE_STARTED, pid, [flavor as u64, 0]),
+            EbpfEvent::ZygoteForked(pid) => (TAG_ZYGOTE_FORKED, pid, [0, 0]),
+            EbpfEvent::ZygoteCrashed(pid, flavor) => (TAG_ZYGOTE_CRASHED, pid, [flavor as u64, 0]),
+            EbpfEvent::RequireUprobeAttach(pid) => (TAG_REQUIRE_UPROBE_ATTACH, pid, [0, 0]),
+            EbpfEvent::RequireInject(pid, lr, source, target) => {
+                (TAG_REQUIRE_INJECT, pid, [lr as u64, source as u64 | (target as u64) << 32])
+            }
+            EbpfEvent::RequireUmount(pid) => (TAG_REQUIRE_UMOUNT, pid, [0, 0]),
+            EbpfEvent::UprobeSkipped(pid) => (TAG_UPROBE_SKIPPED, pid, [0, 0]),
+            EbpfEvent::RequirePostSpecialize(pid) => (TAG_REQUIRE_POST_SPECIALIZE, pid, [0, 0]),
+            EbpfEvent::TrackingFailed(pid) => (TAG_TRACKING_FAILED, pid, [0, 0]),
+        };
+
+        Self { magic: MESSAGE_MAGIC, version: PROTOCOL_VERSION, tag, pid, args, meta }
+    }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    let mut buffer = [0u8; 4];
+    buffer.copy_from_slice(&data[offset .. offset + 4]);
+    u32::from_ne_bytes(buffer)
+}
+
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    let mut buffer = [0u8; 8];
+    buffer.copy_from_slice(&data[offset .. offset + 8]);
+    u64::from_ne_bytes(buffer)
+}
+
+pub fn decode(data: &[u8]) -> Result<(EbpfEvent, EventMeta), DecodeError> {
+    if data.len() < size_of::<EbpfMessage>() {
+        return Err(DecodeError::Truncated(data.len()))
+    }
+
+    let magic = read_u32(data, offset_of!(EbpfMessage, magic));
+    if magic != MESSAGE_MAGIC {
+        return Err(DecodeError::BadMagic(magic))
+    }
+
+    let version = read_u32(data, offset_of!(EbpfMessage, version));
+    if version != PROTOCOL_VERSION {
+        return Err(DecodeError::VersionMismatch(version))
+    }
+
+    let tag = read_u32(data, offset_of!(EbpfMessage, tag));
+    let pid = read_u32(data, offset_of!(EbpfMessage, pid)) as i32;
+    let args = offset_of!(EbpfMessage, args);
+    let (arg0, arg1) = (read_u64(data, args), read_u64(data, args + 8));
+
+    let event = match tag {
+        TAG_ZYGOTE_STARTED => {
+            let flavor = ZygoteFlavor::from_raw(arg0).ok_or(DecodeError::BadValue(tag))?;
+            EbpfEvent::ZygoteStarted(pid, flavor)
+        }
+        TAG_ZYGOTE_FORKED => EbpfEvent::ZygoteForked(pid),
+        TAG_ZYGOTE_CRASHED => {
+            let flavor = ZygoteFlavor::from_raw(arg0).ok_or(DecodeError::BadValue(tag))?;
+            EbpfEvent::ZygoteCrashed(pid, flavor)
+        }
+        TAG_REQUIRE_UPROBE_ATTACH => EbpfEvent::RequireUprobeAttach(pid),
+        TAG_REQUIRE_INJECT => {
+            let source = ReturnAddressSource::from_raw(arg1 & 0xFFFFFFFF).ok_or(DecodeError::BadValue(tag))?;
+            let target = (arg1 >> 32) as usize;
+
+            if target >= MAX_UPROBE_TARGETS {
+                return Err(DecodeError::BadValue(tag))
+            }
+
+            EbpfEvent::RequireInject(pid, arg0 as usize, source, target)
+        }
+        TAG_REQUIRE_UMOUNT => EbpfEvent::RequireUmount(pid),
+        TAG_UPROBE_SKIPPED => EbpfEvent::UprobeSkipped(pid),
+        TAG_REQUIRE_POST_SPECIALIZE => EbpfEvent::RequirePostSpecialize(pid),
+        TAG_TRACKING_FAILED => EbpfEvent::TrackingFailed(pid),
+        _ => return Err(DecodeError::UnknownTag(tag))
+    };
+
+    let meta = offset_of!(EbpfMessage, meta);
+    let comm = meta + offset_of!(EventMeta, comm);
+
+    let mut meta_comm = [0u8; 16];
+    meta_comm.copy_from_slice(&data[comm .. comm + 16]);
+
+    let meta = EventMeta {
+        uid: read_u32(data, meta + offset_of!(EventMeta, uid)),
+        comm: meta_comm,
+        timestamp: read_u64(data, meta + offset_of!(EventMeta, timestamp))
+    };
+
+    Ok((event, meta))
+}
@@ -2,35 +2,72 @@
 #![no_main]
 
 use core::cmp;
+use core::sync::atomic::{AtomicI64, Ordering};
 
 use aya_ebpf::{EbpfContext, helpers};
 use aya_ebpf::bindings::{BPF_ANY, BPF_EXIST};
-use aya_ebpf::macros::{map, tracepoint, uprobe};
-use aya_ebpf::maps::{Array, HashMap, RingBuf};
-use aya_ebpf::programs::{ProbeContext, TracePointContext};
+use aya_ebpf::macros::{map, raw_tracepoint, tracepoint, uprobe, uretprobe};
+use aya_ebpf::maps::{Array, HashMap, LruHashMap, PerCpuArray, RingBuf};
+use aya_ebpf::programs::{ProbeContext, RawTracePointContext, RetProbeContext, TracePointContext};
 use aya_log_ebpf::{debug, error};
 use seq_macro::seq;
 
-use ebpf_common::EbpfEvent;
+use ebpf_common::{EbpfEvent, EventMeta, ProcessState, ReturnAddressSource, UidFilter, ZygoteFlavor};
+use ebpf_common::protocol::{EbpfMessage, PROTOCOL_VERSION};
 
-const ZYGOTE_NAME: &[u8] = b"zygote64";
+const ZYGOTE64_NAME: &[u8] = b"zygote64";
+const ZYGOTE32_NAME: &[u8] = b"zygote\0";
 const IS_DEBUG: bool = cfg!(is_debug);
 
-#[repr(u32)]
-#[derive(Eq, PartialEq)]
-enum ProcessState {
-    WaitForAttach,
-    WaitForUmount
-}
+// may be resized by userspace before loading
+#[map]
+static mut EVENT_CHANNEL: RingBuf = RingBuf::with_byte_size(0x10000, 0);
+
+#[map]
+static mut DROPPED_EVENTS: PerCpuArray<u64> = PerCpuArray::with_max_entries(1, 0);
+
+// pinned maps survive a crash of userspace, so that a restarted monitor can pick up where it left off
+// both flavors may run at once, and a restarting zygote may overlap with the dying one
+#[map]
+static mut ZYGOTES: HashMap<i32, ZygoteFlavor> = HashMap::pinned(8, 0);
+
+// least recently forked children are evicted on fork churn, capacity may be changed by userspace
+#[map]
+static mut ZYGOTE_CHILDREN: LruHashMap<i32, ProcessState> = LruHashMap::pinned(512, 0);
+
+// number of children in `WaitForAttach`, a cheap array lookup that lets almost every syscall bail out early;
+// it may only overcount (e.g. on LRU eviction), which costs a hash lookup but never misses a child
+#[map]
+static mut WAITING_FOR_ATTACH: Array<i64> = Array::pinned(1, 0);
+
+#[map]
+static mut CHILD_ZYGOTES: HashMap<i32, u8> = HashMap::pinned(64, 0);
+
+#[map]
+static mut UID_FILTER: Array<u32> = Array::with_max_entries(1, 0);
+
+#[map]
+static mut TARGET_UIDS: HashMap<u32, u8> = HashMap::with_max_entries(4096, 0);
+
+// uid a child specializes to, seen by the uprobe before it unshares, while it still runs as root
+#[map]
+static mut SPECIALIZE_UIDS: LruHashMap<i32, u32> = LruHashMap::with_max_entries(512, 0);
 
+// a process repeatedly hitting a matching path can't crowd zygote events out of the ring buffer;
+// a burst of tokens per pid, refilled over time, least recently seen pids are evicted
 #[map]
-static mut EVENT_CHANNEL: RingBuf = RingBuf::with_byte_size(0x1000, 0);
+static mut RATE_LIMITS: LruHashMap<i32, TokenBucket> = LruHashMap::with_max_entries(1024, 0);
 
 #[map]
-static mut ZYGOTE_PID: Array<i32> = Array::with_max_entries(1, 0);
+static mut RATE_LIMITED: PerCpuArray<u64> = PerCpuArray::with_max_entries(1, 0);
 
+// start and end of the executable mappings of the library SpecializeCommon is called from, set by userspace
+// for the current zygote as its children share the mappings; all zero while unknown
 #[map]
-static mut ZYGOTE_CHILDREN: HashMap<i32, ProcessState> = HashMap::with_max_entries(512, 0);
+static mut CALLER_TEXT: Array<[u64; 2]> = Array::with_max_entries(1, 0);
+
+const RATE_LIMIT_BURST: u64 = 16;
+const RATE_LIMIT_INTERVAL_NS: u64 = 100_000_000;  // a token every 100ms
 
 
 #[macro_export]
@@ -82,17 +119,33 @@ fn strcmp16(str1: &[u8], str2: &[u8]) -> bool {
     is_same
 }
 
+// read by userspace from the object file to reject a mismatched build at startup
+#[no_mangle]
+#[used]
+static ZLOADER_PROTOCOL_VERSION: u32 = PROTOCOL_VERSION;
 
 #[inline(always)]
-fn emit<T: 'static>(value: T) -> bool {
+fn emit(event: EbpfEvent) -> bool {
+    let meta = EventMeta {
+        uid: (helpers::bpf_get_current_uid_gid() & 0xFFFFFFFF) as u32,
+        comm: helpers::bpf_get_current_comm().unwrap_or([0; 16]),
+        timestamp: unsafe { helpers::bpf_ktime_get_ns() }
+    };
+
     unsafe {
-        let entry = EVENT_CHANNEL.reserve::<T>(0);
+        let entry = EVENT_CHANNEL.reserve::<EbpfMessage>(0);
         let mut entry = match entry {
             Some(entry) => entry,
-            None => return false
+            None => {
+                if let Some(dropped) = DROPPED_EVENTS.get_ptr_mut(0) {
+                    *dropped += 1;
+                }
+
+                return false
+            }
         };
 
-        entry.write(value);
+        entry.write(EbpfMessage::new(event, meta));
         entry.submit(0);
     }
 
@@ -100,6 +153,51 @@ fn emit<T: 'static>(value: T) -> bool {
 }
 
 
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct TokenBucket {
+    tokens: u64,
+    last_refill: u64
+}
+
+// take a token for a stop or an emit on behalf of the pid, false once it is out of them
+#[inline(always)]
+fn take_token(pid: i32) -> bool {
+    let now = unsafe { helpers::bpf_ktime_get_ns() };
+
+    unsafe {
+        let bucket = match RATE_LIMITS.get_ptr_mut(&pid) {
+            Some(bucket) => &mut *bucket,
+            None => {
+                let bucket = TokenBucket { tokens: RATE_LIMIT_BURST - 1, last_refill: now };
+                let _ = RATE_LIMITS.insert(&pid, &bucket, BPF_ANY as _);
+
+                return true
+            }
+        };
+
+        // races between cpus only lose or gain a token
+        let refill = now.saturating_sub(bucket.last_refill) / RATE_LIMIT_INTERVAL_NS;
+
+        if refill > 0 {
+            bucket.tokens = cmp::min(bucket.tokens + refill, RATE_LIMIT_BURST);
+            bucket.last_refill = now;
+        }
+
+        if bucket.tokens == 0 {
+            if let Some(limited) = RATE_LIMITED.get_ptr_mut(0) {
+                *limited += 1;
+            }
+
+            return false
+        }
+
+        bucket.tokens -= 1;
+    }
+
+    true
+}
+
 #[inline(always)]
 fn is_root() -> bool {
     helpers::bpf_get_current_uid_gid() & 0xFFFFFFFF == 0
@@ -111,16 +209,63 @@ fn current_pid() -> i32 {
 }
 
 #[inline(always)]
-fn stop_current() {
+fn uid_filter() -> u32 {
     unsafe {
-        helpers::bpf_send_signal_thread(19 /* SIGSTOP */);
+        match UID_FILTER.get(0) {
+            Some(mode) => *mode,
+            None => UidFilter::Disabled as u32
+        }
     }
 }
 
 #[inline(always)]
-fn resume_current() {
+fn is_target_uid(uid: u32) -> bool {
+    match uid_filter() {
+        mode if mode == UidFilter::Disabled as u32 => true,
+        mode if mode == UidFilter::Nothing as u32 => false,
+        _ => unsafe { TARGET_UIDS.get(&uid).is_some() }
+    }
+}
+
+#[inline(always)]
+fn count_waiting_for_attach(delta: i64) {
     unsafe {
-        helpers::bpf_send_signal_thread(18 /* SIGCONT */);
+        if let Some(ptr) = WAITING_FOR_ATTACH.get_ptr_mut(0) {
+            AtomicI64::from_ptr(ptr).fetch_add(delta, Ordering::Relaxed);
+        }
+    }
+}
+
+#[inline(always)]
+fn any_waiting_for_attach() -> bool {
+    unsafe { WAITING_FOR_ATTACH.get(0).is_some_and(|count| *count > 0) }
+}
+
+// patched by userspace before loading, the verifier prunes the helper calls below on kernels without it
+#[no_mangle]
+static SEND_SIGNAL: u8 = 1;
+
+#[inline(always)]
+fn can_send_signal() -> bool {
+    unsafe { core::ptr::read_volatile(&SEND_SIGNAL) != 0 }
+}
+
+// without the helper, userspace stops the process as soon as it receives the event
+#[inline(always)]
+fn stop_current() {
+    if can_send_signal() {
+        unsafe {
+            helpers::bpf_send_signal_thread(19 /* SIGSTOP */);
+        }
+    }
+}
+
+#[inline(always)]
+fn resume_current() {
+    if can_send_signal() {
+        unsafe {
+            helpers::bpf_send_signal_thread(18 /* SIGCONT */);
+        }
     }
 }
 
@@ -172,22 +317,31 @@ pub fn handle_task_task_rename(ctx: TracePointContext) -> u32 {
 
     let event: &TaskRenameEvent = ctx.as_event();
 
-    if strcmp16(&event.new_comm, ZYGOTE_NAME) {
-        if IS_DEBUG {
-            debug!(&ctx, "zygote (re)started: {}", event.pid);
-        }
+    let flavor = if strcmp16(&event.new_comm, ZYGOTE64_NAME) {
+        ZygoteFlavor::Zygote64
+    } else if strcmp16(&event.new_comm, ZYGOTE32_NAME) {
+        ZygoteFlavor::Zygote32
+    } else {
+        return 0
+    };
 
-        if !emit(EbpfEvent::ZygoteStarted(event.pid)) && IS_DEBUG {
-            error!(&ctx, "failed to notify zygote start");
-        }
+    // zygote renames itself once, any root process renaming itself over and over is not one
+    if !take_token(event.pid) {
+        return 0
+    }
 
-        unsafe {
-            if let Some(ptr) = ZYGOTE_PID.get_ptr_mut(0) {
-                *ptr = event.pid;
-            }
-        }
+    if IS_DEBUG {
+        debug!(&ctx, "zygote (re)started: {} (flavor={})", event.pid, flavor as u32);
+    }
 
-        return 0
+    if !emit(EbpfEvent::ZygoteStarted(event.pid, flavor)) && IS_DEBUG {
+        error!(&ctx, "failed to notify zygote start");
+    }
+
+    unsafe {
+        if ZYGOTES.insert(&event.pid, &flavor, BPF_ANY as _).is_err() && IS_DEBUG {
+            error!(&ctx, "failed to track zygote {}", event.pid);
+        }
     }
 
     0
@@ -204,13 +358,20 @@ struct NewTaskEvent {
 
 #[tracepoint]
 pub fn handle_task_task_newtask(ctx: TracePointContext) -> u32 {
-    if !is_root() {
+    let current_pid = current_pid();
+
+    // child zygotes (app_zygote, webview_zygote) don't run as root
+    let flavor = if is_root() { unsafe { ZYGOTES.get(&current_pid) }.copied() } else { None };
+
+    // children of zygote32 are never injected, and the compat syscall numbers differ on x86,
+    // so tracking them would only leave them waiting for attach until they exit
+    if flavor == Some(ZygoteFlavor::Zygote32) {
         return 0
     }
 
-    let current_pid = current_pid();
+    let is_zygote = flavor.is_some();
 
-    if unsafe { ZYGOTE_PID.get(0) } != Some(&current_pid) {
+    if !is_zygote && unsafe { CHILD_ZYGOTES.get(&current_pid) }.is_none() {
         return 0
     }
 
@@ -226,13 +387,20 @@ pub fn handle_task_task_newtask(ctx: TracePointContext) -> u32 {
         debug!(&ctx, "zygote forked: {} -> {} (clone_flags={:x})", current_pid, child_pid, event.clone_flags);
     }
 
-    if !emit(EbpfEvent::ZygoteForked(event.pid)) && IS_DEBUG {
+    // charged to the child, zygote itself may fork in bursts on boot
+    if take_token(event.pid) && !emit(EbpfEvent::ZygoteForked(event.pid)) && IS_DEBUG {
         error!(&ctx, "failed to notify zygote fork");
     }
 
     unsafe {
-        if ZYGOTE_CHILDREN.insert(&child_pid, &ProcessState::WaitForAttach, BPF_ANY as _).is_err() && IS_DEBUG {
-            error!(&ctx, "failed to mark process {} as unattached", child_pid);
+        if ZYGOTE_CHILDREN.insert(&child_pid, &ProcessState::WaitForAttach, BPF_ANY as _).is_err() {
+            if IS_DEBUG {
+                error!(&ctx, "failed to mark process {} as unattached", child_pid);
+            }
+
+            emit(EbpfEvent::TrackingFailed(child_pid));
+        } else {
+            count_waiting_for_attach(1);
         }
     }
 
@@ -254,39 +422,98 @@ pub fn handle_sched_sched_process_exit(ctx: TracePointContext) -> u32 {
     let pid = event.pid;
     
     unsafe {
-        if ZYGOTE_PID.get(0) == Some(&pid) {
+        if let Some(flavor) = ZYGOTES.get(&pid).copied() {
             if IS_DEBUG {
                 debug!(&ctx, "zygote crashed ({})", pid);
             }
 
-            if !emit(EbpfEvent::ZygoteCrashed(pid)) && IS_DEBUG {
+            if !emit(EbpfEvent::ZygoteCrashed(pid, flavor)) && IS_DEBUG {
                 error!(&ctx, "failed to notify zygote crashed");
             }
+
+            let _ = ZYGOTES.remove(&pid);
+        }
+
+        if ZYGOTE_CHILDREN.get(&pid) == Some(&ProcessState::WaitForAttach) && ZYGOTE_CHILDREN.remove(&pid).is_ok() {
+            count_waiting_for_attach(-1);
         }
 
         let _ = ZYGOTE_CHILDREN.remove(&pid);
+        let _ = CHILD_ZYGOTES.remove(&pid);
+        let _ = SPECIALIZE_UIDS.remove(&pid);
     }
     
     0
 }
 
 
+// raw tracepoints only get `struct pt_regs *` of the syscall, the parts needed are read from it
+#[cfg(ebpf_target_arch = "x86_64")]
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct KernelPtRegs {
+    _r15_to_rbp: [u64; 5],
+    _rbx: u64,
+    _r11_to_r8: [u64; 4],
+    _rax: u64,
+    _rcx: u64,
+    _rdx: u64,
+    _rsi: u64,
+    rdi: u64,
+    orig_rax: u64
+}
+
+#[cfg(ebpf_target_arch = "aarch64")]
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct KernelPtRegs {
+    regs: [u64; 31],
+    _sp: u64,
+    _pc: u64,
+    _pstate: u64,
+    orig_x0: u64,
+    syscallno: i32
+}
+
+// return syscall number and first argument
+#[inline(always)]
+fn read_syscall_regs(regs: *const KernelPtRegs) -> Option<(i64, u64)> {
+    let regs = unsafe { helpers::bpf_probe_read_kernel(regs).ok()? };
+
+    Some(arch_select!(
+        (regs.orig_rax as i64, regs.rdi),
+        (regs.syscallno as i64, regs.orig_x0)
+    ))
+}
+
+// only the syscall number, a single field is cheaper to read than the whole regs
+#[inline(always)]
+fn read_syscall_id(regs: *const KernelPtRegs) -> Option<i64> {
+    unsafe {
+        arch_select!(
+            helpers::bpf_probe_read_kernel(core::ptr::addr_of!((*regs).orig_rax)).ok().map(|id| id as i64),
+            helpers::bpf_probe_read_kernel(core::ptr::addr_of!((*regs).syscallno)).ok().map(|id| id as i64)
+        )
+    }
+}
+
+const SYS_RT_SIGPROCMASK: i64 = arch_select!(14, 135);
+const SYS_UNSHARE: i64 = arch_select!(272, 97);
+
 #[repr(C)]
 struct SyscallEnterEvent {
     id: i64,
     args: [u64; 6]
 }
 
-#[tracepoint]
-pub fn handle_raw_syscalls_sys_enter(ctx: TracePointContext) -> u32 {
-    let event: &SyscallEnterEvent = ctx.as_event();
-
-    if event.id != arch_select!(14, 135) /* rt_sigprocmask */ || event.args[0] != 1 /* SIG_UNBLOCK */ {
+#[inline(always)]
+fn on_sys_enter<C: EbpfContext>(ctx: &C, id: i64, arg0: u64) -> u32 {
+    if id != SYS_RT_SIGPROCMASK || arg0 != 1 /* SIG_UNBLOCK */ {
         return 0;
     }
 
-    if !is_root() {
-        return 0
+    if !any_waiting_for_attach() {
+        return 0;
     }
 
     #[cfg(ebpf_target_arch = "aarch64")]
@@ -294,22 +521,36 @@ pub fn handle_raw_syscalls_sys_enter(ctx: TracePointContext) -> u32 {
         return 0;
     }
 
+    // no root check here, children of child zygotes are forked by non-root processes
     let current_pid = current_pid();
 
     unsafe {
         if ZYGOTE_CHILDREN.get(&current_pid) == Some(&ProcessState::WaitForAttach) {
             if IS_DEBUG {
-                debug!(&ctx, "post zygote fork: {}", current_pid);
+                debug!(ctx, "post zygote fork: {}", current_pid);
             }
 
             if ZYGOTE_CHILDREN.insert(&current_pid, &ProcessState::WaitForUmount, BPF_EXIST as _).is_err() {
-                error!(&ctx, "failed to update process state");
+                error!(ctx, "failed to update process state");
+            } else {
+                count_waiting_for_attach(-1);
+            }
+
+            // the child still runs as root here, so its uid is unknown until specialize and out of scope uids are
+            // only let go at the uprobe; learning it before fork would take a uprobe in zygote that every child
+            // inherits, so the stop is only skipped when no uid is in scope at all
+            if uid_filter() == UidFilter::Nothing as u32 || !take_token(current_pid) {
+                return 0
             }
 
             stop_current();
 
-            if !emit(EbpfEvent::RequireUprobeAttach(current_pid)) && IS_DEBUG {
-                error!(&ctx, "failed to require uprobe attach");
+            if !emit(EbpfEvent::RequireUprobeAttach(current_pid)) {
+                if IS_DEBUG {
+                    error!(ctx, "failed to require uprobe attach");
+                }
+
+                // the event is lost, nobody would resume it otherwise
                 resume_current();
             }
         }
@@ -319,24 +560,41 @@ pub fn handle_raw_syscalls_sys_enter(ctx: TracePointContext) -> u32 {
 }
 
 
+#[tracepoint]
+pub fn handle_raw_syscalls_sys_enter(ctx: TracePointContext) -> u32 {
+    let event: &SyscallEnterEvent = ctx.as_event();
+    on_sys_enter(&ctx, event.id, event.args[0])
+}
+
+#[raw_tracepoint(tracepoint = "sys_enter")]
+pub fn handle_raw_sys_enter(ctx: RawTracePointContext) -> u32 {
+    let args = ctx.as_ptr() as *const [u64; 2];
+    let (regs, id) = unsafe { ((*args)[0] as *const KernelPtRegs, (*args)[1] as i64) };
+
+    // runs on every syscall system-wide, the regs are only read once nothing cheaper rules it out
+    if id != SYS_RT_SIGPROCMASK || !any_waiting_for_attach() {
+        return 0
+    }
+
+    match read_syscall_regs(regs) {
+        Some((_, arg0)) => on_sys_enter(&ctx, id, arg0),
+        None => 0
+    }
+}
+
+
 #[repr(C)]
 struct SyscallExitEvent {
     id: i64,
     return_value: u64
 }
 
-#[tracepoint]
-pub fn handle_raw_syscalls_sys_exit(ctx: TracePointContext) -> u32 {
-    let event: &SyscallExitEvent = ctx.as_event();
-    
-    if event.id != arch_select!(272, 97) /* unshare */ || event.return_value != 0 {
+#[inline(always)]
+fn on_sys_exit<C: EbpfContext>(ctx: &C, id: i64, return_value: u64) -> u32 {
+    if id != SYS_UNSHARE || return_value != 0 {
         return 0;
     }
 
-    if !is_root() {
-        return 0
-    }
-
     #[cfg(ebpf_target_arch = "aarch64")]
     if is_32_bit() {
         return 0;
@@ -347,13 +605,21 @@ pub fn handle_raw_syscalls_sys_exit(ctx: TracePointContext) -> u32 {
     unsafe {
         if ZYGOTE_CHILDREN.get(&current_pid) == Some(&ProcessState::WaitForUmount) {
             if IS_DEBUG {
-                debug!(&ctx, "process unshare: {}", current_pid);
+                debug!(ctx, "process unshare: {}", current_pid);
+            }
+
+            if !take_token(current_pid) {
+                let _ = ZYGOTE_CHILDREN.remove(&current_pid);
+                return 0
             }
 
             stop_current();
 
-            if !emit(EbpfEvent::RequireUmount(current_pid)) && IS_DEBUG {
-                error!(&ctx, "failed to require umount");
+            if !emit(EbpfEvent::RequireUmount(current_pid)) {
+                if IS_DEBUG {
+                    error!(ctx, "failed to require umount");
+                }
+
                 resume_current();
             }
             
@@ -365,39 +631,157 @@ pub fn handle_raw_syscalls_sys_exit(ctx: TracePointContext) -> u32 {
 }
 
 
-#[uprobe]
-pub fn handle_specialize_common(ctx: ProbeContext) -> u32 {
-    #[inline(always)]
-    fn try_run(ctx: &ProbeContext) -> Option<()> {
-        let current_pid = current_pid();
+#[tracepoint]
+pub fn handle_raw_syscalls_sys_exit(ctx: TracePointContext) -> u32 {
+    let event: &SyscallExitEvent = ctx.as_event();
+    on_sys_exit(&ctx, event.id, event.return_value)
+}
 
-        let uid: u64 = ctx.arg(1)?;
-        let gid: u64 = ctx.arg(2)?;
+#[raw_tracepoint(tracepoint = "sys_exit")]
+pub fn handle_raw_sys_exit(ctx: RawTracePointContext) -> u32 {
+    let args = ctx.as_ptr() as *const [u64; 2];
+    let (regs, return_value) = unsafe { ((*args)[0] as *const KernelPtRegs, (*args)[1]) };
 
-        #[cfg(ebpf_target_arch = "x86_64")]
-        let lr = unsafe {
-            let sp = (*ctx.regs).rsp as *const usize;
-            helpers::bpf_probe_read_user(sp).ok()?
-        };
+    // runs on every syscall system-wide, the syscall number is only read for a child about to unshare
+    if return_value != 0 || unsafe { ZYGOTE_CHILDREN.get(&current_pid()) } != Some(&ProcessState::WaitForUmount) {
+        return 0
+    }
+
+    match read_syscall_id(regs) {
+        Some(id) => on_sys_exit(&ctx, id, return_value),
+        None => 0
+    }
+}
 
-        #[cfg(ebpf_target_arch = "aarch64")]
-        let lr = unsafe { (*ctx.regs).regs[30] as usize };
+#[inline(always)]
+fn is_user_address(addr: usize) -> bool {
+    addr >= 0x1000 && addr < 0x0000_8000_0000_0000
+}
 
-        if IS_DEBUG {
-            debug!(ctx, "zygote specialize ({}): uid={} gid={}", current_pid, uid, gid);
+// a saved frame pointer or the return address of another frame is a user address too, but not into the caller
+#[inline(always)]
+fn is_caller_address(addr: usize) -> bool {
+    match unsafe { CALLER_TEXT.get(0) } {
+        Some([start, end]) if *end != 0 => addr as u64 >= *start && (addr as u64) < *end,
+        _ => is_user_address(addr)
+    }
+}
+
+// read the saved return address through the frame pointer, in case the probe fires after the prologue
+#[inline(always)]
+fn frame_return_address(fp: usize) -> Option<usize> {
+    if !is_user_address(fp) {
+        return None
+    }
+
+    let lr: usize = unsafe { helpers::bpf_probe_read_user((fp + 8) as *const usize).ok()? };
+
+    if is_caller_address(lr) { Some(lr) } else { None }
+}
+
+// at the entry `*rsp` is the return address, after `push %rbp` it is the saved rbp and `rbp + 8` is
+#[cfg(ebpf_target_arch = "x86_64")]
+#[inline(always)]
+fn return_address(ctx: &ProbeContext) -> Option<(usize, ReturnAddressSource)> {
+    let (sp, fp) = unsafe { ((*ctx.regs).rsp as usize, (*ctx.regs).rbp as usize) };
+
+    if let Ok(lr) = unsafe { helpers::bpf_probe_read_user(sp as *const usize) } {
+        if is_caller_address(lr) {
+            return Some((lr, ReturnAddressSource::Stack))
+        }
+    }
+
+    frame_return_address(fp).map(|lr| (lr, ReturnAddressSource::FramePointer))
+}
+
+#[cfg(ebpf_target_arch = "aarch64")]
+#[inline(always)]
+fn return_address(ctx: &ProbeContext) -> Option<(usize, ReturnAddressSource)> {
+    let (lr, fp) = unsafe { ((*ctx.regs).regs[30] as usize, (*ctx.regs).regs[29] as usize) };
+
+    if is_caller_address(lr) {
+        return Some((lr, ReturnAddressSource::LinkRegister))
+    }
+
+    frame_return_address(fp).map(|lr| (lr, ReturnAddressSource::FramePointer))
+}
+
+#[inline(always)]
+fn on_specialize_common(ctx: &ProbeContext, target: usize) -> Option<()> {
+    let current_pid = current_pid();
+
+    let uid: u64 = ctx.arg(1)?;
+    let gid: u64 = ctx.arg(2)?;
+
+    let (lr, source) = return_address(ctx)?;
+
+    if IS_DEBUG {
+        debug!(ctx, "zygote specialize ({}): uid={} gid={} target={}", current_pid, uid, gid, target);
+    }
+
+    // out of scope uids too, umount is decided for every child
+    unsafe {
+        let _ = SPECIALIZE_UIDS.insert(&current_pid, &(uid as u32), BPF_ANY as _);
+    }
+
+    if !is_target_uid(uid as u32) {
+        if !emit(EbpfEvent::UprobeSkipped(current_pid)) && IS_DEBUG {
+            error!(ctx, "failed to notify uprobe skipped");
         }
 
-        stop_current();
+        return Some(())
+    }
+
+    if !take_token(current_pid) {
+        return Some(())
+    }
 
-        if !emit(EbpfEvent::RequireInject(current_pid, lr)) && IS_DEBUG {
+    stop_current();
+
+    if !emit(EbpfEvent::RequireInject(current_pid, lr, source, target)) {
+        if IS_DEBUG {
             error!(ctx, "failed to require inject");
-            resume_current();
         }
 
-        Some(())
+        resume_current();
+    }
+
+    Some(())
+}
+
+// one program per candidate symbol, so that the event tells which signature applies;
+// keep the range in sync with `MAX_UPROBE_TARGETS`
+seq!(N in 0 .. 4 {
+    #[uprobe]
+    pub fn handle_specialize_common_~N(ctx: ProbeContext) -> u32 {
+        let _ = on_specialize_common(&ctx, N);
+
+        0
     }
+});
 
-    let _ = try_run(&ctx);
+
+#[uretprobe]
+pub fn handle_specialize_common_ret(ctx: RetProbeContext) -> u32 {
+    let current_pid = current_pid();
+
+    if IS_DEBUG {
+        debug!(&ctx, "zygote specialized: {}", current_pid);
+    }
+
+    if !take_token(current_pid) {
+        return 0
+    }
+
+    stop_current();
+
+    if !emit(EbpfEvent::RequirePostSpecialize(current_pid)) {
+        if IS_DEBUG {
+            error!(&ctx, "failed to require post specialize");
+        }
+
+        resume_current();
+    }
 
     0
 }
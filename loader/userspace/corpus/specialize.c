@@ -0,0 +1,19 @@
+// source of the libspecialize fixtures, the AOSP 14 SpecializeCommon at file offset 0x210 and address 0x10210
+//
+// gcc -shared -fPIC -nostdlib -O2 -Wl,--build-id=none -Wl,-z,noseparate-code -Wl,-Ttext-segment=0x10000 \
+//     -o libspecialize.so specialize.c
+//
+// libspecialize-stripped.so only has it in `.gnu_debugdata`, the way Android strips its libraries:
+//
+// objcopy --only-keep-debug libspecialize.so debug
+// objcopy -S --remove-section .gdb_index --remove-section .comment --keep-symbol=<mangled name> debug mini
+// xz --check=crc32 mini
+// strip --strip-all -o libspecialize-stripped.so libspecialize.so
+// objcopy --add-section .gnu_debugdata=mini.xz libspecialize-stripped.so
+
+int padding(int x) { return x * 3 + 1; }
+
+__attribute__((used, noinline)) static void specialize(void)
+    __asm__("_ZN12_GLOBAL__N_116SpecializeCommonEP7_JNIEnvjjP10_jintArrayiP13_jobjectArraylliP8_jstringS7_bbS7_S7_bS5_S5_bbb");
+
+__attribute__((used, noinline)) static void specialize(void) {}
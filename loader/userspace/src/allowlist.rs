@@ -0,0 +1,79 @@
+use std::collections::HashSet;
+use std::time::Duration;
+
+use anyhow::Result;
+use aya::maps::{Array, HashMap, MapData};
+use libloading::Symbol;
+use log::{debug, error, info};
+use nix::libc;
+use tokio::time;
+
+use ebpf_common::UidFilter;
+
+const SYNC_INTERVAL: Duration = Duration::from_secs(5);
+const MAX_TARGET_UIDS: usize = 4096;
+
+pub type CollectUidsFn = Symbol<'static, extern "C" fn(*mut libc::uid_t, usize) -> usize>;
+
+pub struct UidAllowlist {
+    mode: Array<MapData, u32>,
+    uids: HashMap<MapData, u32, u8>,
+    current: HashSet<u32>
+}
+
+impl UidAllowlist {
+    pub fn new(mode: Array<MapData, u32>, uids: HashMap<MapData, u32, u8>) -> Self {
+        Self { mode, uids, current: HashSet::new() }
+    }
+
+    fn set_mode(&mut self, mode: UidFilter) -> Result<()> {
+        self.mode.set(0, mode as u32, 0)?;
+        Ok(())
+    }
+
+    fn sync(&mut self, uids: HashSet<u32>) -> Result<()> {
+        for uid in self.current.difference(&uids) {
+            self.uids.remove(uid)?;
+        }
+
+        for uid in uids.difference(&self.current) {
+            self.uids.insert(uid, 1, 0)?;
+        }
+
+        let mode = if uids.is_empty() { UidFilter::Nothing } else { UidFilter::Enabled };
+
+        if uids != self.current {
+            info!("target uids updated: {} in scope", uids.len());
+            debug!("target uids: {uids:?}");
+        }
+
+        self.current = uids;
+        self.set_mode(mode)
+    }
+
+    pub async fn serve(mut self, collect: CollectUidsFn) {
+        let mut buffer = vec![0 as libc::uid_t; MAX_TARGET_UIDS];
+        let mut interval = time::interval(SYNC_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            let count = collect(buffer.as_mut_ptr(), buffer.len());
+            if count > buffer.len() {
+                error!("too many target uids ({count}), disabling uid filter");
+
+                if let Err(err) = self.set_mode(UidFilter::Disabled) {
+                    error!("failed to disable uid filter: {err}");
+                }
+
+                continue
+            }
+
+            let uids = buffer[.. count].iter().copied().collect();
+
+            if let Err(err) = self.sync(uids) {
+                error!("failed to sync target uids: {err}");
+            }
+        }
+    }
+}
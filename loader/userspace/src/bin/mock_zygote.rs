@@ -0,0 +1,129 @@
+// mimics just enough of zygote64 for the eBPF programs and the monitor to go through a whole launch on plain Linux:
+// renames itself, forks, unblocks SIGCHLD in the child, and calls a SpecializeCommon of its own which unshares;
+//
+//   ZLOADER_RUNTIME_LIBRARY=target/debug/mock-zygote zloader ...
+//   mock-zygote --children 4
+//
+// both need root, and the debug profile keeps the symbol table the uprobe is resolved from;
+// injection itself expects bionic, so on glibc it fails after the uprobe and the process is resumed
+
+use std::ffi::c_void;
+use std::hint::black_box;
+use std::process;
+use std::ptr;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use clap::Parser;
+use nix::libc;
+use nix::sys::signal::{SigmaskHow, Signal, SigSet, sigprocmask};
+use nix::sys::wait::waitpid;
+use nix::unistd::{fork, ForkResult, Uid};
+
+// the first uid of apps
+const FIRST_APPLICATION_UID: u32 = 10000;
+
+#[derive(Parser, Debug)]
+struct Args {
+    /// Number of children to fork
+    #[clap(long, default_value_t = 1)]
+    children: u32,
+
+    /// Uid passed to SpecializeCommon of the first child, incremented for each one after
+    #[clap(long, default_value_t = FIRST_APPLICATION_UID)]
+    uid: u32,
+
+    /// Milliseconds to wait after the rename, for the monitor to pick up the new zygote
+    #[clap(long, default_value_t = 500)]
+    delay: u64,
+
+    /// Milliseconds between forks
+    #[clap(long, default_value_t = 100)]
+    interval: u64
+}
+
+// the signature of SDK 31, so that argument counting and layout derivation go through the usual path
+#[allow(clippy::too_many_arguments)]
+#[inline(never)]
+#[export_name = "_ZN12_GLOBAL__N_116SpecializeCommonEP7_JNIEnvjjP10_jintArrayiP13_jobjectArraylliP8_jstringS7_bbS7_S7_bS5_S5_bb"]
+pub extern "C" fn specialize_common(
+    env: *mut c_void,
+    uid: u32,
+    gid: u32,
+    gids: *mut c_void,
+    runtime_flags: i32,
+    rlimits: *mut c_void,
+    permitted_capabilities: i64,
+    effective_capabilities: i64,
+    mount_external: i32,
+    se_info: *mut c_void,
+    nice_name: *mut c_void,
+    is_system_server: bool,
+    is_child_zygote: bool,
+    instruction_set: *mut c_void,
+    app_data_dir: *mut c_void,
+    is_top_app: bool,
+    pkg_data_info_list: *mut c_void,
+    allowlisted_data_info_list: *mut c_void,
+    mount_data_dirs: bool,
+    mount_storage_dirs: bool
+) {
+    black_box((env, uid, gid, gids, runtime_flags, rlimits, permitted_capabilities, effective_capabilities, mount_external));
+    black_box((se_info, nice_name, is_system_server, is_child_zygote, instruction_set, app_data_dir, is_top_app));
+    black_box((pkg_data_info_list, allowlisted_data_info_list, mount_data_dirs, mount_storage_dirs));
+
+    // MountEmulatedStorage, where module files are unmounted
+    if unsafe { libc::unshare(libc::CLONE_NEWNS) } != 0 {
+        eprintln!("[{}] unshare failed: {}", process::id(), std::io::Error::last_os_error());
+    }
+}
+
+fn child(uid: u32) -> ! {
+    // right after fork, zygote unblocks SIGCHLD, which is where the child is stopped for the uprobe
+    let mut mask = SigSet::empty();
+    mask.add(Signal::SIGCHLD);
+
+    if let Err(err) = sigprocmask(SigmaskHow::SIG_UNBLOCK, Some(&mask), None) {
+        eprintln!("[{}] sigprocmask failed: {err}", process::id());
+    }
+
+    let null = ptr::null_mut();
+    specialize_common(null, uid, uid, null, 0, null, 0, 0, 0, null, null, false, false, null, null, false, null, null, false, false);
+
+    println!("[{}] specialized to uid {uid}", process::id());
+    process::exit(0)
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+
+    if !Uid::current().is_root() {
+        bail!("zygote is only tracked when it runs as root");
+    }
+
+    // what task_rename is watched for
+    if unsafe { libc::prctl(libc::PR_SET_NAME, c"zygote64".as_ptr()) } != 0 {
+        bail!("failed to rename: {}", std::io::Error::last_os_error());
+    }
+
+    println!("[{}] running as zygote64", process::id());
+    thread::sleep(Duration::from_millis(args.delay));
+
+    let mut children = Vec::new();
+
+    for index in 0 .. args.children {
+        match unsafe { fork()? } {
+            ForkResult::Child => child(args.uid + index),
+            ForkResult::Parent { child } => children.push(child)
+        }
+
+        thread::sleep(Duration::from_millis(args.interval));
+    }
+
+    for child in children {
+        println!("[{child}] {:?}", waitpid(child, None)?);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,156 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context, Result};
+use nix::sys::utsname::uname;
+
+use common::{audit, features, logfile, rootimpl};
+use common::properties::getprop;
+
+use crate::config::Config;
+use crate::{doctor, restrictions};
+
+const BUNDLE_ROOT: &str = "/data/adb/zloader";
+
+// sessions of log files included, only there if logging to files is enabled
+const BUNDLE_LOG_SESSIONS: usize = 3;
+const REPORT_ROOT: &str = "/data/adb/zloader/reports";
+const SELINUX_ENFORCE: &str = "/sys/fs/selinux/enforce";
+
+const AUDIT_ENTRIES: usize = 200;
+const BUILD_PROPERTIES: &[&str] = &[
+    "ro.build.fingerprint",
+    "ro.build.version.sdk",
+    "ro.product.cpu.abilist",
+    "ro.dalvik.vm.native.bridge"
+];
+
+fn command_output(program: &str, args: &[&str]) -> Option<String> {
+    let output = Command::new(program).args(args).output().ok()?;
+    output.status.success().then(|| String::from_utf8_lossy(&output.stdout).trim().into())
+}
+
+fn system_info() -> String {
+    let mut info = String::new();
+
+    if let Ok(kernel) = uname() {
+        info += &format!(
+            "kernel: {} {} {}\n",
+            kernel.release().to_string_lossy(), kernel.version().to_string_lossy(), kernel.machine().to_string_lossy()
+        );
+    }
+
+    let selinux = match fs::read_to_string(SELINUX_ENFORCE).map(|mode| mode.trim().to_string()) {
+        Ok(mode) if mode == "1" => "enforcing".into(),
+        Ok(mode) if mode == "0" => "permissive".into(),
+        Ok(mode) => format!("unknown ({mode})"),
+        Err(err) => format!("unavailable ({err})")
+    };
+    info += &format!("selinux: {selinux}\n");
+
+    for name in BUILD_PROPERTIES {
+        info += &format!("{name}: {}\n", getprop(name));
+    }
+
+    info += &format!("staging: {}\n", rootimpl::staging_dir().display());
+    info += &features::dump();
+    info + &format!("root: {}\n", root_implementation())
+}
+
+fn root_implementation() -> String {
+    if Path::new("/data/adb/ksud").exists() {
+        let version = command_output("/data/adb/ksud", &["-V"]).unwrap_or_else(|| "unknown".into());
+        return format!("KernelSU ({version})")
+    }
+
+    if Path::new("/data/adb/apd").exists() {
+        let version = command_output("/data/adb/apd", &["-V"]).unwrap_or_else(|| "unknown".into());
+        return format!("APatch ({version})")
+    }
+
+    match (command_output("magisk", &["-v"]), command_output("magisk", &["-V"])) {
+        (Some(name), Some(code)) => format!("Magisk ({name}, {code})"),
+        _ => "unknown".into()
+    }
+}
+
+// only fields known to the parser make it into the bundle, comments and unknown keys are dropped
+fn sanitized_config(config: Option<&str>) -> String {
+    let config = match config {
+        Some(path) => Config::load(path),
+        None => return "no config file, defaults in use\n".into()
+    };
+
+    match config {
+        Ok(config) => format!("{config:#?}\n"),
+        Err(err) => format!("failed to load config: {err:#}\n")
+    }
+}
+
+fn verbose_reports() -> String {
+    let entries = match fs::read_dir(REPORT_ROOT) {
+        Ok(entries) => entries,
+        Err(err) => return format!("{REPORT_ROOT}: {err}\n")
+    };
+
+    let mut names: Vec<_> = entries.flatten()
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .collect();
+    names.sort();
+
+    names.join("\n") + "\n"
+}
+
+// collect everything an issue report usually asks for into a single tarball, and return its path
+pub fn create(config: Option<&str>, output: Option<&str>) -> Result<PathBuf> {
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let name = format!("issue-{timestamp}");
+    let dir = PathBuf::from(BUNDLE_ROOT).join(&name);
+
+    fs::create_dir_all(&dir).context(format!("failed to create {dir:?}"))?;
+
+    let doctor = match doctor::render() {
+        Ok((output, _)) => output,
+        Err(err) => format!("doctor failed: {err:#}\n")
+    };
+
+    let audit = match restrictions::audit_entries(AUDIT_ENTRIES) {
+        Ok(entries) => entries.join("\n") + "\n",
+        Err(err) => format!("{err:#}\n")
+    };
+
+    let files = [
+        ("doctor.txt", doctor),
+        ("system.txt", system_info()),
+        ("audit.txt", audit),
+        ("injection-audit.txt", fs::read_to_string(audit::AUDIT_LOG).unwrap_or_default()),
+        ("config.txt", sanitized_config(config)),
+        ("reports.txt", verbose_reports()),
+        ("logs.txt", logfile::dump(BUNDLE_LOG_SESSIONS))
+    ];
+
+    for (file, content) in files {
+        fs::write(dir.join(file), content).context(format!("failed to write {file}"))?;
+    }
+
+    let tarball = match output {
+        Some(output) => PathBuf::from(output),
+        None => PathBuf::from(BUNDLE_ROOT).join(format!("{name}.tar.gz"))
+    };
+
+    let status = Command::new("tar")
+        .arg("-czf").arg(&tarball)
+        .arg("-C").arg(BUNDLE_ROOT)
+        .arg(&name)
+        .status()?;
+
+    let _ = fs::remove_dir_all(&dir);
+
+    if !status.success() {
+        bail!("failed to pack {tarball:?}: {status}");
+    }
+
+    Ok(tarball)
+}
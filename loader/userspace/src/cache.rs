@@ -0,0 +1,159 @@
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::hash::{Hash, Hasher};
+use std::io::{BufRead, BufReader, Write};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock, RwLock};
+
+use anyhow::{bail, Result};
+use log::{debug, warn};
+use nix::libc;
+
+use crate::runtime::LibraryIdentity;
+use crate::symbols::{self, SymbolIndex};
+
+const CACHE_FILE: &str = "/data/adb/zloader/symbols.cache";
+const SHARDS: usize = 16;
+
+// (device, inode, size, mtime) of a library, cheap to read for every lookup
+type FileKey = (u64, u64, u64, i64);
+
+
+// symbol offsets keyed by build-id, shared by all injection tasks and persisted across restarts
+pub struct SymbolCache {
+    identities: RwLock<HashMap<FileKey, String>>,
+    shards: [RwLock<HashMap<(String, String), usize>>; SHARDS],
+    // every symbol of the libraries missed on so far, along with the identity they were parsed from
+    tables: RwLock<HashMap<PathBuf, (String, Arc<SymbolIndex>)>>
+}
+
+// released when the file is closed
+fn lock(file: &File, operation: libc::c_int) -> Result<()> {
+    if unsafe { libc::flock(file.as_raw_fd(), operation) } != 0 {
+        bail!("failed to lock {CACHE_FILE}: {}", std::io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+static CACHE: LazyLock<SymbolCache> = LazyLock::new(|| {
+    let cache = SymbolCache::new();
+
+    if let Err(err) = cache.load(CACHE_FILE) {
+        debug!("symbol cache not loaded: {err}");
+    }
+
+    cache
+});
+
+impl SymbolCache {
+    fn new() -> Self {
+        Self {
+            identities: RwLock::default(),
+            shards: Default::default(),
+            tables: RwLock::default()
+        }
+    }
+
+    fn shard(&self, key: &(String, String)) -> &RwLock<HashMap<(String, String), usize>> {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+
+        &self.shards[hasher.finish() as usize % SHARDS]
+    }
+
+    fn load(&self, path: &str) -> Result<()> {
+        let file = File::open(path)?;
+        lock(&file, libc::LOCK_SH)?;
+
+        for line in BufReader::new(&file).lines() {
+            let line = line?;
+            let mut fields = line.split_whitespace();
+
+            if let (Some(identity), Some(name), Some(offset)) = (fields.next(), fields.next(), fields.next()) {
+                if let Ok(offset) = usize::from_str_radix(offset, 16) {
+                    let key = (identity.to_string(), name.to_string());
+                    self.shard(&key).write().unwrap().insert(key, offset);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // other daemons (e.g. a restarted one) may append at the same time
+    fn write_back(identity: &str, name: &str, offset: usize) -> Result<()> {
+        let mut file = OpenOptions::new().create(true).append(true).open(CACHE_FILE)?;
+        lock(&file, libc::LOCK_EX)?;
+
+        file.write_all(format!("{identity} {name} {offset:x}\n").as_bytes())?;
+
+        Ok(())
+    }
+
+    fn identity(&self, library: &Path) -> Result<String> {
+        let metadata = library.metadata()?;
+        let key = (metadata.dev(), metadata.ino(), metadata.size(), metadata.mtime());
+
+        if let Some(identity) = self.identities.read().unwrap().get(&key) {
+            return Ok(identity.clone())
+        }
+
+        let identity = LibraryIdentity::read(library)?.to_string().replace(' ', "");
+        self.identities.write().unwrap().insert(key, identity.clone());
+
+        Ok(identity)
+    }
+
+    // parsed once per identity, so that other symbols of the same library don't read it again,
+    // the table of a file that has been replaced since is dropped
+    fn table(&self, library: &Path, identity: &str) -> Result<Arc<SymbolIndex>> {
+        if let Some((known, table)) = self.tables.read().unwrap().get(library) {
+            if known == identity {
+                return Ok(Arc::clone(table))
+            }
+        }
+
+        let table = Arc::new(SymbolIndex::open(library)?);
+        self.tables.write().unwrap().insert(library.into(), (identity.into(), Arc::clone(&table)));
+
+        debug!("{} symbols of {library:?} parsed", table.count());
+
+        Ok(table)
+    }
+
+    fn resolve(&self, library: &Path, name: &str) -> Result<usize> {
+        let identity = match self.identity(library) {
+            Ok(identity) => identity,
+            Err(err) => {
+                debug!("can't identify {library:?}, symbol cache bypassed: {err}");
+                return symbols::resolve(library, name)
+            }
+        };
+
+        let key = (identity, name.to_string());
+        let shard = self.shard(&key);
+
+        if let Some(offset) = shard.read().unwrap().get(&key) {
+            return Ok(*offset)
+        }
+
+        // resolved outside the lock, a concurrent miss only costs a duplicate parse
+        let offset = self.table(library, &key.0)?.resolve(name)?;
+
+        if shard.write().unwrap().insert(key.clone(), offset).is_none() {
+            if let Err(err) = Self::write_back(&key.0, name, offset) {
+                warn!("failed to write symbol cache: {err}");
+            }
+        }
+
+        Ok(offset)
+    }
+}
+
+pub fn resolve<P: AsRef<Path>>(library: P, name: &str) -> Result<usize> {
+    CACHE.resolve(library.as_ref(), name)
+}
@@ -0,0 +1,301 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::{fs, mem};
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProcessCategory {
+    App,
+    SystemServer,
+    ChildZygote
+}
+
+impl Display for ProcessCategory {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProcessCategory::App => write!(fmt, "app"),
+            ProcessCategory::SystemServer => write!(fmt, "system_server"),
+            ProcessCategory::ChildZygote => write!(fmt, "child_zygote")
+        }
+    }
+}
+
+// degradation steps taken one by one, each time zygote is found in a bootloop
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BootloopAction {
+    DisableInjection,
+    DisableUprobe,
+    Exit
+}
+
+impl BootloopAction {
+    // value reported through the state property once the action is taken
+    pub fn state(&self) -> &'static str {
+        match self {
+            BootloopAction::DisableInjection => "injection_disabled",
+            BootloopAction::DisableUprobe => "uprobe_disabled",
+            BootloopAction::Exit => "exited"
+        }
+    }
+}
+
+impl Display for BootloopAction {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BootloopAction::DisableInjection => write!(fmt, "disable_injection"),
+            BootloopAction::DisableUprobe => write!(fmt, "disable_uprobe"),
+            BootloopAction::Exit => write!(fmt, "exit")
+        }
+    }
+}
+
+// what to do with processes running app code through a native bridge (e.g. Houdini on x86 devices)
+#[derive(Debug, Default, Copy, Clone, Eq, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NativeBridgePolicy {
+    #[default]
+    Inject,
+    Skip
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BootloopConfig {
+    pub actions: Vec<BootloopAction>
+}
+
+impl Default for BootloopConfig {
+    fn default() -> Self {
+        Self {
+            actions: vec![BootloopAction::DisableInjection, BootloopAction::DisableUprobe, BootloopAction::Exit]
+        }
+    }
+}
+
+// where SpecializeCommon is in a library with its symbol stripped, tried only when none of the symbols resolve
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SpecializeSignature {
+    pub name: String,
+    // hex bytes of its prologue, such as `fd 7b ?? a9`, which must match exactly once
+    pub pattern: String,
+    pub args_count: usize,
+    // SDK versions it applies to, inclusive
+    #[serde(default)]
+    pub min_sdk: Option<i32>,
+    #[serde(default)]
+    pub max_sdk: Option<i32>
+}
+
+impl SpecializeSignature {
+    pub fn applies_to(&self, sdk: i32) -> bool {
+        !self.min_sdk.is_some_and(|min| sdk < min) && !self.max_sdk.is_some_and(|max| sdk > max)
+    }
+}
+
+// an extra library injected besides the bridges, such as a profiler agent, without specialize hooks of its own
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Payload {
+    pub library: String,
+
+    // called without arguments right after dlopen
+    #[serde(default)]
+    pub entry: Option<String>,
+
+    // packages it is injected into, every injected process if empty
+    #[serde(default)]
+    pub packages: Vec<String>,
+
+    // file names of other payloads that must be loaded before it
+    #[serde(default)]
+    pub after: Vec<String>,
+
+    // fail the whole injection if it can't be loaded, rather than going on without it
+    #[serde(default)]
+    pub required: bool
+}
+
+impl Payload {
+    pub fn name(&self) -> &str {
+        Path::new(&self.library).file_name().and_then(|name| name.to_str()).unwrap_or(&self.library)
+    }
+
+    pub fn applies_to(&self, package: Option<&str>) -> bool {
+        self.packages.is_empty() || package.is_some_and(|package| self.packages.iter().any(|name| name == package))
+    }
+}
+
+// dependencies first, otherwise in the order they are listed
+fn sort_payloads(payloads: Vec<Payload>) -> Result<Vec<Payload>> {
+    for payload in &payloads {
+        if let Some(after) = payload.after.iter().find(|after| !payloads.iter().any(|other| other.name() == after.as_str())) {
+            bail!("payload {} is to be loaded after {after}, which is not a payload", payload.library);
+        }
+    }
+
+    let mut pending = payloads;
+    let mut sorted: Vec<Payload> = Vec::new();
+
+    while !pending.is_empty() {
+        let ready = pending.iter()
+            .position(|payload| payload.after.iter().all(|after| sorted.iter().any(|done| done.name() == after.as_str())))
+            .context(format!("payloads depend on each other in a cycle: {:?}", pending.iter().map(Payload::name).collect::<Vec<_>>()))?;
+
+        sorted.push(pending.remove(ready));
+    }
+
+    Ok(sorted)
+}
+
+// detached debug files for libraries stripped of SpecializeCommon, looked up by build-id
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DebugSymbolsConfig {
+    // symbol packages laid out as `.build-id/ab/cdef.debug`, besides /data/adb/zloader/symbols
+    #[serde(default)]
+    pub directories: Vec<String>,
+
+    // debuginfod servers over plain http, such as `http://debuginfod.example.com`
+    #[serde(default)]
+    pub debuginfod: Vec<String>
+}
+
+// module files to unmount for apps on the denylist, a mount matches a rule if every field that is set matches
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UmountRule {
+    // such as `magisk` or `KSU`
+    #[serde(default)]
+    pub source: Option<String>,
+
+    // any of them, or any type if empty
+    #[serde(default)]
+    pub fs_types: Vec<String>,
+
+    // prefix of where it is mounted
+    #[serde(default)]
+    pub mount_point: Option<String>,
+
+    // prefix of the path within its filesystem, for bind mounts out of the modules dir
+    #[serde(default)]
+    pub root: Option<String>,
+
+    // shares the source of what is mounted there, such as the loop device of the modules image,
+    // that mount itself doesn't match
+    #[serde(default)]
+    pub source_of: Option<String>,
+
+    // matching mounts are left alone, whatever other rules say
+    #[serde(default)]
+    pub keep: bool
+}
+
+impl UmountRule {
+    // a rule without any of them would match every mount
+    fn has_matcher(&self) -> bool {
+        self.source.is_some() || !self.fs_types.is_empty() || self.mount_point.is_some()
+            || self.root.is_some() || self.source_of.is_some()
+    }
+}
+
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UmountConfig {
+    // applied along with the built-in rules of the root implementation, or instead of them if set
+    #[serde(default)]
+    pub replace_defaults: bool,
+
+    #[serde(default)]
+    pub rules: Vec<UmountRule>
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub bridges: HashMap<ProcessCategory, Vec<String>>,
+
+    #[serde(default)]
+    pub bootloop: BootloopConfig,
+
+    #[serde(default)]
+    pub native_bridge: NativeBridgePolicy,
+
+    // symbol prefixes of SpecializeCommon and its vendor variants, the stock one is used if empty
+    #[serde(default)]
+    pub specialize_symbols: Vec<String>,
+
+    // prologue patterns for libraries with SpecializeCommon stripped entirely
+    #[serde(default)]
+    pub specialize_signatures: Vec<SpecializeSignature>,
+
+    #[serde(default)]
+    pub debug_symbols: DebugSymbolsConfig,
+
+    #[serde(default)]
+    pub umount: UmountConfig,
+
+    // extra libraries, loaded after the bridges in dependency order
+    #[serde(default)]
+    pub payloads: Vec<Payload>,
+
+    // packages injected only once they come to foreground, skipping pre specialize hooks
+    #[serde(default)]
+    pub deferred_packages: Vec<String>,
+
+    // defer any app that is already in a background scheduling group when it specializes,
+    // such as one started for a broadcast receiver
+    #[serde(default)]
+    pub defer_background: bool,
+
+    // globs on full process names, e.g. `*:isolated*` or `com.android.chrome:webview`, never injected
+    #[serde(default)]
+    pub blocked_processes: Vec<String>
+}
+
+impl Config {
+    pub fn load<P : AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let content = fs::read_to_string(path)
+            .context(format!("failed to read config file {}", path.display()))?;
+
+        let mut config: Config = toml::from_str(&content)
+            .context(format!("failed to parse config file {}", path.display()))?;
+
+        // a library is only loaded once, its hooks would be called twice
+        for (category, bridges) in &config.bridges {
+            if let Some(bridge) = bridges.iter().enumerate().find_map(|(i, bridge)| bridges[.. i].contains(bridge).then_some(bridge)) {
+                bail!("bridge {bridge} is listed twice for `{category}`");
+            }
+        }
+
+        if let Some(payload) = config.payloads.iter().enumerate().find_map(|(i, payload)| {
+            config.payloads[.. i].iter().any(|other| other.name() == payload.name()).then_some(payload)
+        }) {
+            bail!("payload {} is listed twice", payload.name());
+        }
+
+        config.payloads = sort_payloads(mem::take(&mut config.payloads))?;
+
+        if let Some(index) = config.umount.rules.iter().position(|rule| !rule.has_matcher()) {
+            bail!("umount rule #{} matches every mount", index + 1);
+        }
+
+        Ok(config)
+    }
+
+    // fill categories missing from the config file with the default bridges
+    pub fn with_default_bridges(mut self, bridges: &[String]) -> Self {
+        for category in [ProcessCategory::App, ProcessCategory::SystemServer, ProcessCategory::ChildZygote] {
+            self.bridges.entry(category).or_insert_with(|| bridges.to_vec());
+        }
+
+        self
+    }
+}
@@ -0,0 +1,88 @@
+use std::cell::RefCell;
+use std::fmt::{Display, Formatter};
+
+use anyhow::Error;
+
+// what an injection task is working on, filled in as it goes
+#[derive(Clone, Debug)]
+pub struct TaskContext {
+    pub pid: i32,
+    pub uid: Option<u32>,
+    pub package: Option<String>,
+    pub stage: &'static str
+}
+
+impl Display for TaskContext {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(fmt, "[{}]", self.pid)?;
+
+        if let Some(uid) = self.uid {
+            write!(fmt, " uid={uid}")?;
+        }
+
+        if let Some(package) = &self.package {
+            write!(fmt, " package={package}")?;
+        }
+
+        write!(fmt, " stage={}", self.stage)
+    }
+}
+
+// an error that left a task, together with where the task was at the time
+#[derive(Debug)]
+pub struct TaskError {
+    pub context: TaskContext,
+    pub source: Error
+}
+
+impl Display for TaskError {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(fmt, "{}: {:#}", self.context, self.source)
+    }
+}
+
+// a task runs on a single thread from start to end, injection never awaits in between
+thread_local! {
+    static CURRENT: RefCell<Option<TaskContext>> = const { RefCell::new(None) };
+}
+
+fn update(func: impl FnOnce(&mut TaskContext)) {
+    CURRENT.with_borrow_mut(|current| {
+        if let Some(context) = current {
+            func(context);
+        }
+    });
+}
+
+pub fn set_uid(uid: u32) {
+    update(|context| context.uid = Some(uid));
+}
+
+pub fn set_package(package: &str) {
+    update(|context| context.package = Some(package.into()));
+}
+
+pub fn set_stage(stage: &'static str) {
+    update(|context| context.stage = stage);
+}
+
+// attach the context of the running task to an error that is logged before the task ends
+pub fn wrap(pid: i32, source: Error) -> TaskError {
+    let context = CURRENT.with_borrow(|current| current.clone()).unwrap_or(TaskContext {
+        pid,
+        uid: None,
+        package: None,
+        stage: "unknown"
+    });
+
+    TaskError { context, source }
+}
+
+// call at task spawn, so that whatever bubbles out tells which process it belonged to
+pub fn run<T>(pid: i32, stage: &'static str, func: impl FnOnce() -> anyhow::Result<T>) -> Result<T, TaskError> {
+    let previous = CURRENT.replace(Some(TaskContext { pid, uid: None, package: None, stage }));
+    let res = func();
+    let context = CURRENT.replace(previous).expect("task context disappeared");
+
+    res.map_err(|source| TaskError { context, source })
+}
@@ -0,0 +1,215 @@
+use std::{fs, process};
+use std::io::{BufRead, BufReader as StdBufReader, Write};
+use std::os::unix::net::UnixStream as StdUnixStream;
+
+use anyhow::{bail, Context, Result};
+use log::{debug, error, info, warn};
+use procfs::process::Process;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task;
+use common::control_socket;
+
+use crate::heartbeat::Heartbeats;
+use crate::report::VerboseTargets;
+use crate::status::DaemonStatus;
+
+// the event loop reloads the config and the denylist and replies with the outcome
+pub type ReloadRequest = oneshot::Sender<String>;
+
+#[derive(Clone)]
+pub struct ControlContext {
+    pub verbose: VerboseTargets,
+    pub status: DaemonStatus,
+    pub heartbeats: Heartbeats,
+    pub reload: mpsc::Sender<ReloadRequest>
+}
+
+// who is on the other end, as told by the kernel rather than by the peer
+#[derive(Debug, Copy, Clone)]
+struct Peer {
+    uid: u32,
+    pid: i32
+}
+
+impl Peer {
+    fn is_root(&self) -> bool {
+        self.uid == 0
+    }
+
+    // the umount helper is forked by the daemon and runs as root, anything else may be an app
+    fn is_helper(&self) -> bool {
+        self.is_root() && Process::new(self.pid)
+            .and_then(|process| process.stat())
+            .is_ok_and(|stat| stat.ppid as u32 == process::id())
+    }
+}
+
+// line based commands, the socket is reachable by apps so only root may send any but the bridge ones:
+//   status               zygote pid, injected and failed counts, whether injection is paused
+//   stats                counters of events and injections as a JSON object
+//   pause                skip injection until resumed, zygote children are still tracked
+//   resume               undo pause
+//   reload               read the config file and the denylist again, the filter library stays loaded
+//   verbose <package>    collect a verbose report on the next launch of the package
+//   quiet <package>      drop a pending verbose mark
+//   list                 list pending verbose marks
+//   panic <text>         sent by the bridge before a panic takes down the injected process
+//   heartbeat            sent by the bridge once it is initialized
+//   umount <pid> <unmounted> <failed> [<errno>...]
+//                        sent by the umount helper after unmounting module files for a child, with an errno
+//                        for each mount left
+async fn execute(command: &str, peer: Peer, ctx: &ControlContext) -> String {
+    let mut words = command.split_whitespace();
+
+    // the bridge runs in the injected process, so what it reports is about the peer rather than a pid it names
+    match words.next() {
+        Some("heartbeat") => {
+            ctx.heartbeats.received(peer.pid);
+            return "ok".into()
+        }
+        Some("panic") => {
+            let message: Vec<_> = words.collect();
+            error!("[{}] bridge reported: {}", peer.pid, message.join(" "));
+            return "ok".into()
+        }
+        Some("umount") if !peer.is_helper() => {
+            warn!("umount report from pid {} (uid {}) rejected", peer.pid, peer.uid);
+            return "error: permission denied".into()
+        }
+        _ if !peer.is_root() => {
+            warn!("control command from pid {} (uid {}) rejected: {command}", peer.pid, peer.uid);
+            return "error: permission denied".into()
+        }
+        _ => ()
+    }
+
+    let mut words = command.split_whitespace();
+
+    match (words.next(), words.next()) {
+        (Some("status"), None) => ctx.status.to_string(),
+        (Some("stats"), None) => ctx.status.stats(),
+        (Some("pause"), None) => {
+            if ctx.status.set_paused(true) {
+                info!("injection paused");
+            }
+            "ok".into()
+        }
+        (Some("resume"), None) => {
+            if ctx.status.set_paused(false) {
+                info!("injection resumed");
+            }
+            "ok".into()
+        }
+        (Some("reload"), None) => {
+            let (tx, rx) = oneshot::channel();
+
+            if ctx.reload.send(tx).await.is_err() {
+                return "error: daemon is shutting down".into()
+            }
+
+            rx.await.unwrap_or_else(|_| "error: reload dropped".into())
+        }
+        (Some("verbose"), Some(package)) => {
+            ctx.verbose.mark(package);
+            info!("verbose injection requested for {package}");
+            "ok".into()
+        }
+        (Some("quiet"), Some(package)) => {
+            if ctx.verbose.unmark(package) { "ok".into() } else { format!("error: {package} is not marked") }
+        }
+        (Some("list"), None) => ctx.verbose.marked().join(" "),
+        (Some("umount"), Some(pid)) => {
+            let counts: Vec<_> = words.by_ref().take(2).map(str::parse::<u64>).collect();
+
+            let [Ok(unmounted), Ok(failed)] = counts[..] else {
+                return format!("error: invalid umount report: {command}")
+            };
+
+            let errors: Vec<_> = words.collect();
+            ctx.status.umounted(unmounted, failed);
+
+            if failed > 0 {
+                warn!("[{pid}] {unmounted} unmounted, {failed} left: {}", errors.join(" "));
+            } else {
+                debug!("[{pid}] {unmounted} unmounted");
+            }
+
+            "ok".into()
+        }
+        _ => format!("error: unknown command: {command}")
+    }
+}
+
+async fn handle_client(stream: UnixStream, ctx: ControlContext) -> Result<()> {
+    let cred = stream.peer_cred()?;
+    let peer = Peer { uid: cred.uid(), pid: cred.pid().context("peer pid is unknown")? };
+
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+
+    while let Some(line) = lines.next_line().await? {
+        debug!("control command from {peer:?}: {line}");
+
+        let reply = execute(&line, peer, &ctx).await;
+        writer.write_all(format!("{reply}\n").as_bytes()).await?;
+    }
+
+    Ok(())
+}
+
+pub async fn serve(ctx: ControlContext) {
+    let res: Result<()> = try {
+        let path = control_socket();
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let _ = fs::remove_file(&path);
+        let listener = UnixListener::bind(&path)?;
+
+        loop {
+            let (stream, _) = listener.accept().await?;
+            let ctx = ctx.clone();
+
+            task::spawn(async move {
+                if let Err(err) = handle_client(stream, ctx).await {
+                    error!("control client error: {err}");
+                }
+            });
+        }
+    };
+
+    if let Err(err) = res {
+        error!("control socket is not available: {err}");
+    }
+}
+
+pub fn connect() -> Result<StdUnixStream> {
+    let path = control_socket();
+
+    StdUnixStream::connect(&path).context(format!("failed to connect to {}, is the daemon running?", path.display()))
+}
+
+// send a single command over a connection and wait for the reply
+pub fn exchange(mut stream: StdUnixStream, command: &str) -> Result<String> {
+    stream.write_all(format!("{command}\n").as_bytes())?;
+
+    let mut reply = String::new();
+    StdBufReader::new(stream).read_line(&mut reply)?;
+
+    let reply = reply.trim_end();
+
+    if let Some(err) = reply.strip_prefix("error: ") {
+        bail!("{err}");
+    }
+
+    Ok(reply.into())
+}
+
+// send a single command to a running daemon, used by `zloader ctl`
+pub fn request(command: &str) -> Result<String> {
+    exchange(connect()?, command)
+}
@@ -0,0 +1,98 @@
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use log::{debug, info, warn};
+
+use crate::config::DebugSymbolsConfig;
+
+// always searched, and where files from debuginfod are kept
+pub const SYMBOLS_ROOT: &str = "/data/adb/zloader/symbols";
+
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_DEBUG_FILE_SIZE: usize = 256 << 20;
+
+fn hex(build_id: &[u8]) -> String {
+    build_id.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+// `.build-id/ab/cdef....debug`, the layout gdb and symbol packages use
+fn build_id_path(root: &Path, build_id: &str) -> PathBuf {
+    root.join(".build-id").join(&build_id[.. 2]).join(format!("{}.debug", &build_id[2 ..]))
+}
+
+// plain http only, no TLS implementation is linked in
+fn fetch(url: &str) -> Result<Vec<u8>> {
+    let rest = url.strip_prefix("http://").context(format!("only http:// is supported: {url}"))?;
+    let (host, path) = match rest.split_once('/') {
+        Some((host, path)) => (host, format!("/{path}")),
+        None => (rest, "/".into())
+    };
+
+    let addr = if host.contains(':') { host.to_string() } else { format!("{host}:80") };
+    let addr = addr.to_socket_addrs()?.next().context(format!("failed to resolve {host}"))?;
+
+    let mut stream = TcpStream::connect_timeout(&addr, FETCH_TIMEOUT)?;
+    stream.set_read_timeout(Some(FETCH_TIMEOUT))?;
+    stream.set_write_timeout(Some(FETCH_TIMEOUT))?;
+
+    // HTTP/1.0 rules out chunked responses
+    write!(stream, "GET {path} HTTP/1.0\r\nHost: {host}\r\nUser-Agent: zloader\r\nConnection: close\r\n\r\n")?;
+
+    let mut response = Vec::new();
+    stream.take(MAX_DEBUG_FILE_SIZE as u64 + 4096).read_to_end(&mut response)?;
+
+    let split = response.windows(4).position(|window| window == b"\r\n\r\n").context("malformed http response")?;
+    let head = String::from_utf8_lossy(&response[.. split]);
+    let status = head.lines().next().and_then(|line| line.split_whitespace().nth(1)).unwrap_or_default();
+
+    if status != "200" {
+        bail!("{url}: http {status}");
+    }
+
+    Ok(response.split_off(split + 4))
+}
+
+fn fetch_from_debuginfod(server: &str, build_id: &str) -> Result<PathBuf> {
+    let url = format!("{}/buildid/{build_id}/debuginfo", server.trim_end_matches('/'));
+    let data = fetch(&url)?;
+
+    let path = build_id_path(Path::new(SYMBOLS_ROOT), build_id);
+    fs::create_dir_all(path.parent().unwrap())?;
+    fs::write(&path, data).context(format!("failed to save {}", path.display()))?;
+
+    info!("debug file of {build_id} downloaded from {server}");
+
+    Ok(path)
+}
+
+// a detached debug file for the build-id, from local symbol packages first, then from debuginfod
+pub fn find_debug_file(build_id: &[u8], config: &DebugSymbolsConfig) -> Option<PathBuf> {
+    let build_id = hex(build_id);
+
+    if build_id.len() < 4 {
+        return None
+    }
+
+    let local = Some(SYMBOLS_ROOT).into_iter()
+        .chain(config.directories.iter().map(String::as_str))
+        .map(|root| build_id_path(Path::new(root), &build_id))
+        .find(|path| path.is_file());
+
+    if let Some(path) = local {
+        debug!("debug file of {build_id} found at {}", path.display());
+        return Some(path)
+    }
+
+    for server in &config.debuginfod {
+        match fetch_from_debuginfod(server, &build_id) {
+            Ok(path) => return Some(path),
+            Err(err) => warn!("failed to fetch debug file of {build_id} from {server}: {err}")
+        }
+    }
+
+    None
+}
@@ -0,0 +1,130 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::{debug, error, info};
+use procfs::process::Process;
+use tokio::{task, time};
+
+use crate::{context, loader};
+use crate::loader::TraceOptions;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+// `ProcessList.FOREGROUND_APP_ADJ`, given to the app of the resumed activity
+const FOREGROUND_APP_ADJ: i16 = 0;
+
+// cpuset and cpuctl (or schedtune on older kernels) groups of processes the user doesn't see
+const BACKGROUND_GROUPS: &[&str] = &["/background", "/system-background", "/restricted"];
+const SCHED_CONTROLLERS: &[&str] = &["cpuset", "cpu", "schedtune"];
+
+struct Pending {
+    package: String,
+    bridges: Vec<String>,
+    // tells a reused pid apart
+    start_time: u64
+}
+
+// processes of packages configured for deferral, injected late once they come to foreground
+#[derive(Clone)]
+pub struct DeferredInjections {
+    packages: Arc<HashSet<String>>,
+    background: bool,
+    pending: Arc<Mutex<HashMap<i32, Pending>>>
+}
+
+impl DeferredInjections {
+    pub fn new(packages: Vec<String>, background: bool) -> Self {
+        Self {
+            packages: Arc::new(packages.into_iter().collect()),
+            background,
+            pending: Arc::default()
+        }
+    }
+
+    fn start_time(pid: i32) -> Option<u64> {
+        Some(Process::new(pid).ok()?.stat().ok()?.starttime)
+    }
+
+    // the activity manager may have moved it already while it is stopped, a process still in the group
+    // of zygote is taken as foreground; cgroup v2 has no scheduling groups, so nothing is background there
+    fn is_background(pid: i32) -> bool {
+        let cgroups = match Process::new(pid).and_then(|process| process.cgroups()) {
+            Ok(cgroups) => cgroups,
+            Err(_) => return false
+        };
+
+        cgroups.into_iter().any(|cgroup| {
+            cgroup.controllers.iter().any(|controller| SCHED_CONTROLLERS.contains(&controller.as_str()))
+                && BACKGROUND_GROUPS.contains(&cgroup.pathname.as_str())
+        })
+    }
+
+    // return false if the package is injected right away
+    pub fn defer(&self, pid: i32, package: &str, bridges: &[String]) -> bool {
+        if !self.packages.contains(package) && !(self.background && Self::is_background(pid)) {
+            return false
+        }
+
+        let start_time = match Self::start_time(pid) {
+            Some(start_time) => start_time,
+            None => return false
+        };
+
+        self.pending.lock().unwrap().insert(pid, Pending { package: package.into(), bridges: bridges.to_vec(), start_time });
+
+        true
+    }
+
+    // remove processes that came to foreground or exited, return the former
+    fn take_foreground(&self) -> Vec<(i32, Pending)> {
+        let mut pending = self.pending.lock().unwrap();
+        let mut done = Vec::new();
+
+        for (pid, entry) in pending.iter() {
+            let process = match Process::new(*pid) {
+                Ok(process) if Self::start_time(*pid) == Some(entry.start_time) => process,
+                _ => {
+                    debug!("[{pid}] {} exited before coming to foreground", entry.package);
+                    done.push((*pid, false));
+                    continue
+                }
+            };
+
+            if process.oom_score_adj().is_ok_and(|adj| adj == FOREGROUND_APP_ADJ) {
+                done.push((*pid, true));
+            }
+        }
+
+        done.into_iter()
+            .filter_map(|(pid, foreground)| pending.remove(&pid).filter(|_| foreground).map(|entry| (pid, entry)))
+            .collect()
+    }
+
+    pub async fn serve(self, trace: TraceOptions) {
+        if self.packages.is_empty() && !self.background {
+            return
+        }
+
+        let mut interval = time::interval(POLL_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            for (pid, entry) in self.take_foreground() {
+                info!("[{pid}] {} came to foreground, injecting", entry.package);
+
+                task::spawn_blocking(move || {
+                    let res = context::run(pid, "attach", || {
+                        context::set_package(&entry.package);
+                        loader::late_inject(pid, &entry.bridges, trace)
+                    });
+
+                    if let Err(err) = res {
+                        error!("failed to inject late {err}");
+                    }
+                });
+            }
+        }
+    }
+}
@@ -0,0 +1,248 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io::ErrorKind;
+use std::process::Command;
+use std::sync::{LazyLock, Mutex};
+use std::thread;
+
+use anyhow::{bail, Result};
+use log::{debug, error, warn};
+use nix::libc;
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify};
+
+use common::rootimpl::RootImpl;
+
+use crate::packages::{self, PER_USER_RANGE};
+
+// package names and uids, one per line, for excluding apps without a root manager
+const DENYLIST_FILE: &str = "/data/adb/zloader/denylist";
+
+// the kernel only answers a prctl with this option, and writes it back to `reply` when it does
+const KERNEL_SU_OPTION: u32 = 0xDEADBEEF;
+const CMD_UID_SHOULD_UMOUNT: libc::c_ulong = 13;
+
+// both files are replaced or written through a journal, so their directories are watched instead
+const WATCHED: &[(&str, &str)] = &[("/data/adb", "magisk.db"), ("/data/adb/zloader", "denylist")];
+
+#[derive(Default)]
+struct OwnDenylist {
+    packages: HashSet<String>,
+    uids: HashSet<u32>
+}
+
+impl OwnDenylist {
+    // a uid below the per-user range stands for the app in every user
+    fn contains(&self, uid: u32, packages: &[String]) -> bool {
+        self.uids.contains(&uid) || self.uids.contains(&(uid % PER_USER_RANGE))
+            || packages.iter().any(|package| self.packages.contains(package))
+    }
+}
+
+// read on first use, and again after the file behind it changes
+#[derive(Default)]
+struct Cached {
+    magisk: Option<HashSet<String>>,
+    own: Option<OwnDenylist>
+}
+
+static ROOT_IMPL: LazyLock<RootImpl> = LazyLock::new(RootImpl::detect);
+
+static CACHE: LazyLock<Mutex<Cached>> = LazyLock::new(|| {
+    if let Err(err) = thread::Builder::new().name("denylist".into()).spawn(watch) {
+        error!("failed to watch denylist: {err}, changes won't be picked up");
+    }
+
+    Mutex::default()
+});
+
+fn invalidate(name: &str) {
+    let mut cache = CACHE.lock().unwrap();
+
+    if name.starts_with("magisk.db") {
+        cache.magisk = None;
+    } else if name.starts_with("denylist") {
+        cache.own = None;
+    }
+}
+
+// both lists are read again on the next check, for changes the watcher may have missed
+pub fn reload() {
+    let mut cache = CACHE.lock().unwrap();
+
+    cache.magisk = None;
+    cache.own = None;
+}
+
+fn watch() {
+    let res: Result<()> = try {
+        let inotify = Inotify::init(InitFlags::IN_CLOEXEC)?;
+        let flags = AddWatchFlags::IN_CLOSE_WRITE | AddWatchFlags::IN_MOVED_TO | AddWatchFlags::IN_CREATE
+            | AddWatchFlags::IN_MODIFY | AddWatchFlags::IN_DELETE;
+
+        for (dir, _) in WATCHED {
+            // such as /data/adb/zloader before the first boot with the module
+            if let Err(err) = inotify.add_watch(*dir, flags) {
+                warn!("failed to watch {dir}: {err}, changes in it won't be picked up");
+            }
+        }
+
+        loop {
+            for event in inotify.read_events()? {
+                let Some(name) = event.name.as_ref().and_then(|name| name.to_str()) else {
+                    continue
+                };
+
+                if WATCHED.iter().any(|(_, file)| name.starts_with(file)) {
+                    debug!("denylist invalidated by {name}");
+                    invalidate(name);
+                }
+            }
+        }
+    };
+
+    if let Err(err) = res {
+        error!("denylist watcher exited: {err}");
+    }
+}
+
+fn read_magisk_denylist() -> Result<HashSet<String>> {
+    // exits with 0 only while the denylist is enforced, nothing is hidden otherwise
+    if !Command::new("magisk").args(["--denylist", "status"]).output()?.status.success() {
+        debug!("magisk denylist is not enforced");
+        return Ok(HashSet::new())
+    }
+
+    let output = Command::new("magisk").args(["--denylist", "ls"]).output()?;
+
+    if !output.status.success() {
+        bail!("magisk --denylist ls exited with {}", output.status);
+    }
+
+    // `<package>|<process>`, once for every process of a package
+    let denylist: HashSet<_> = String::from_utf8_lossy(&output.stdout).lines()
+        .filter_map(|line| line.split('|').next())
+        .map(str::trim)
+        .filter(|package| !package.is_empty())
+        .map(String::from)
+        .collect();
+
+    debug!("magisk denylist: {denylist:?}");
+
+    Ok(denylist)
+}
+
+// `#` starts a comment
+fn read_own_denylist() -> Result<OwnDenylist> {
+    let content = match fs::read_to_string(DENYLIST_FILE) {
+        Ok(content) => content,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(OwnDenylist::default()),
+        Err(err) => return Err(err.into())
+    };
+
+    let mut denylist = OwnDenylist::default();
+
+    for entry in content.lines().map(|line| line.split('#').next().unwrap_or_default().trim()).filter(|entry| !entry.is_empty()) {
+        match entry.parse() {
+            Ok(uid) => denylist.uids.insert(uid),
+            Err(_) => denylist.packages.insert(entry.into())
+        };
+    }
+
+    debug!("denylist of zloader: {} packages, {} uids", denylist.packages.len(), denylist.uids.len());
+
+    Ok(denylist)
+}
+
+impl Cached {
+    fn magisk(&mut self) -> Result<&HashSet<String>> {
+        if self.magisk.is_none() {
+            self.magisk = Some(read_magisk_denylist()?);
+        }
+
+        Ok(self.magisk.as_ref().unwrap())
+    }
+
+    fn own(&mut self) -> Result<&OwnDenylist> {
+        if self.own.is_none() {
+            self.own = Some(read_own_denylist()?);
+        }
+
+        Ok(self.own.as_ref().unwrap())
+    }
+
+    // the list of zloader, merged with the one of Magisk; isolated and app zygote uids don't map to a package,
+    // so only a uid entry matches them
+    fn denied(&mut self, uid: u32, packages: &[String]) -> Result<bool> {
+        if self.own()?.contains(uid, packages) {
+            return Ok(true)
+        }
+
+        if *ROOT_IMPL != RootImpl::Magisk {
+            return Ok(false)
+        }
+
+        let magisk = self.magisk()?;
+
+        Ok(packages.iter().any(|package| magisk.contains(package)))
+    }
+}
+
+// the "umount modules" flag of the app profile, or the default profile for apps without one;
+// the kernel keeps the profiles ksud loaded, so it's asked rather than the storage of ksud
+fn check_kernelsu(uid: u32) -> Result<bool> {
+    let mut should_umount = false;
+    let mut reply = 0u32;
+
+    unsafe {
+        libc::prctl(
+            KERNEL_SU_OPTION as libc::c_int,
+            CMD_UID_SHOULD_UMOUNT,
+            uid as libc::c_ulong,
+            &mut should_umount as *mut bool as libc::c_ulong,
+            &mut reply as *mut u32 as libc::c_ulong
+        );
+    }
+
+    if reply != KERNEL_SU_OPTION {
+        bail!("no answer from the kernel, KernelSU may be too old");
+    }
+
+    Ok(should_umount)
+}
+
+// whether module files should be unmounted for a child specializing to the uid, which belongs to the packages
+pub fn check(uid: u32, packages: &[String]) -> bool {
+    let result: Result<bool> = try {
+        let mut cache = CACHE.lock().unwrap();
+
+        match *ROOT_IMPL {
+            RootImpl::Magisk => cache.denied(uid, packages)?,
+            RootImpl::KernelSU => cache.denied(uid, packages)? || check_kernelsu(uid)?,
+            // module files are hidden from every app, as before the denylist was consulted
+            _ => true
+        }
+    };
+
+    result.unwrap_or_else(|err| {
+        warn!("failed to check denylist for uid {uid}: {err}, unmounting anyway");
+        true
+    })
+}
+
+// whether injection should be skipped, the package is the one of the process if it is known;
+// the "umount modules" flag of KernelSU is on for most apps, so it is no reason to skip
+pub fn is_denied(uid: u32, package: Option<&str>) -> bool {
+    let result: Result<bool> = try {
+        let packages = match package {
+            Some(package) => vec![package.to_string()],
+            None => packages::packages_of(uid)?
+        };
+
+        CACHE.lock().unwrap().denied(uid, &packages)?
+    };
+
+    result.unwrap_or_else(|err| {
+        warn!("failed to check denylist for uid {uid}: {err}, injecting anyway");
+        false
+    })
+}
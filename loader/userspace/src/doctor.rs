@@ -0,0 +1,150 @@
+use std::fs;
+use std::path::Path;
+
+use anyhow::{bail, Result};
+use nix::sys::utsname::uname;
+
+use crate::features;
+use crate::restrictions;
+
+const TRACING_ROOTS: &[&str] = &["/sys/kernel/tracing", "/sys/kernel/debug/tracing"];
+const TRACEPOINTS: &[&str] = &[
+    "task/task_rename",
+    "task/task_newtask",
+    "sched/sched_process_exit",
+    "raw_syscalls/sys_enter",
+    "raw_syscalls/sys_exit"
+];
+
+const UPROBE_PMU: &str = "/sys/bus/event_source/devices/uprobe/type";
+const KERNEL_BTF: &str = "/sys/kernel/btf/vmlinux";
+
+struct Check {
+    name: String,
+    // failures of optional checks only cost performance or a fallback path
+    required: bool,
+    result: Result<String, String>
+}
+
+impl Check {
+    fn new(name: impl Into<String>, required: bool, result: Result<String, String>) -> Self {
+        Self { name: name.into(), required, result }
+    }
+
+    fn status(&self) -> &'static str {
+        match (&self.result, self.required) {
+            (Ok(_), _) => "PASS",
+            (Err(_), true) => "FAIL",
+            (Err(_), false) => "WARN"
+        }
+    }
+}
+
+fn probe<E: ToString>(res: Result<(), E>, detail: &str) -> Result<String, String> {
+    res.map(|_| detail.into()).map_err(|err| err.to_string())
+}
+
+fn check_helper(name: &str, func: i32, required: bool) -> Check {
+    Check::new(format!("helper {name}"), required, probe(features::helper_supported(func), "available"))
+}
+
+fn check_tracepoint(tracepoint: &str) -> Check {
+    let found = TRACING_ROOTS.iter()
+        .map(|root| Path::new(root).join("events").join(tracepoint).join("id"))
+        .find(|path| path.exists());
+
+    let result = match found {
+        Some(path) => Ok(path.display().to_string()),
+        None => Err(format!("not found under {TRACING_ROOTS:?}, is tracefs mounted?"))
+    };
+
+    Check::new(format!("tracepoint {tracepoint}"), true, result)
+}
+
+fn check_uprobe() -> Check {
+    let result = match fs::read_to_string(UPROBE_PMU) {
+        Ok(pmu) => Ok(format!("perf event type {}", pmu.trim())),
+        Err(err) => Err(format!("{UPROBE_PMU}: {err}, kernel is built without CONFIG_UPROBE_EVENTS"))
+    };
+
+    Check::new("uprobe", true, result)
+}
+
+fn check_btf() -> Check {
+    let result = match fs::metadata(KERNEL_BTF) {
+        Ok(metadata) => Ok(format!("{} bytes", metadata.len())),
+        Err(err) => Err(format!("{KERNEL_BTF}: {err}"))
+    };
+
+    Check::new("kernel btf", false, result)
+}
+
+fn check_ptrace() -> Check {
+    let result = match restrictions::yama_ptrace_scope() {
+        Some(3) => Err(restrictions::PtraceRestriction::YamaNoAttach.to_string()),
+        Some(scope) => Ok(format!("yama ptrace_scope {scope}")),
+        None => Ok("yama is not enabled".into())
+    };
+
+    Check::new("ptrace", true, result)
+}
+
+fn run_checks() -> Vec<Check> {
+    let mut checks = vec![
+        Check::new(
+            "ring buffer",
+            true,
+            probe(features::map_supported(features::BPF_MAP_TYPE_RINGBUF, rustix::param::page_size() as u32), "available")
+        ),
+        Check::new(
+            "raw tracepoint programs",
+            false,
+            probe(features::program_supported(features::BPF_PROG_TYPE_RAW_TRACEPOINT), "available, syscall hooks are cheaper")
+        ),
+        check_helper("bpf_probe_read_user", features::BPF_FUNC_PROBE_READ_USER, true),
+        check_helper("bpf_probe_read_kernel", features::BPF_FUNC_PROBE_READ_KERNEL, true),
+        check_helper("bpf_get_current_task", features::BPF_FUNC_GET_CURRENT_TASK, true),
+        // processes are stopped from userspace without it, which may race the injection
+        check_helper("bpf_send_signal_thread", features::BPF_FUNC_SEND_SIGNAL_THREAD, false),
+        check_uprobe()
+    ];
+
+    checks.extend(TRACEPOINTS.iter().map(|tracepoint| check_tracepoint(tracepoint)));
+    checks.push(check_btf());
+    checks.push(check_ptrace());
+
+    checks
+}
+
+// render the report along with the number of required checks that failed
+pub fn render() -> Result<(String, usize)> {
+    let kernel = uname()?;
+    let mut output = format!("kernel: {} {}\n", kernel.release().to_string_lossy(), kernel.machine().to_string_lossy());
+
+    let checks = run_checks();
+
+    for check in &checks {
+        let detail = match &check.result {
+            Ok(detail) => detail,
+            Err(reason) => reason
+        };
+
+        output += &format!("[{}] {}: {detail}\n", check.status(), check.name);
+    }
+
+    let failed = checks.iter().filter(|check| check.required && check.result.is_err()).count();
+
+    Ok((output, failed))
+}
+
+// print what the running kernel lacks for injection, fail if anything required is missing
+pub fn main() -> Result<()> {
+    let (output, failed) = render()?;
+    print!("{output}");
+
+    if failed != 0 {
+        bail!("{failed} required checks failed");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,175 @@
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::sync::Arc;
+use std::sync::mpsc as std_mpsc;
+use std::thread;
+
+use anyhow::Result;
+use aya::maps::{MapData, RingBuf};
+use log::{debug, error, warn};
+use nix::libc;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+
+use ebpf_common::{EbpfEvent, EventMeta};
+use ebpf_common::protocol;
+
+use crate::resumer::{Resumer, Resumes};
+use crate::signals;
+use crate::status::DaemonStatus;
+
+// above the injection tasks and the apps being started, far below what the kernel runs its own threads at
+const DRAIN_PRIORITY: libc::c_int = 10;
+
+// events on their way to the event loop, a stopped child beyond that is let go rather than kept waiting
+const QUEUE_CAPACITY: usize = 256;
+
+pub type Event = (EbpfEvent, EventMeta);
+
+// wakes the drain thread out of poll when there is something to resume
+pub struct Wakeup(OwnedFd);
+
+impl Wakeup {
+    fn new() -> io::Result<Self> {
+        let fd = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC | libc::EFD_NONBLOCK) };
+
+        if fd < 0 {
+            return Err(io::Error::last_os_error())
+        }
+
+        Ok(Self(unsafe { OwnedFd::from_raw_fd(fd) }))
+    }
+
+    pub fn notify(&self) {
+        let count: u64 = 1;
+        unsafe { libc::write(self.0.as_raw_fd(), &count as *const _ as *const libc::c_void, 8) };
+    }
+
+    fn clear(&self) {
+        let mut count: u64 = 0;
+        unsafe { libc::read(self.0.as_raw_fd(), &mut count as *mut _ as *mut libc::c_void, 8) };
+    }
+}
+
+// events after which the process is expected to be stopped
+fn stopping_pid(event: &EbpfEvent) -> Option<i32> {
+    match event {
+        EbpfEvent::RequireUprobeAttach(pid)
+        | EbpfEvent::RequireInject(pid, _, _, _)
+        | EbpfEvent::RequirePostSpecialize(pid)
+        | EbpfEvent::RequireUmount(pid) => Some(*pid),
+        _ => None
+    }
+}
+
+// empties the ring buffer and signals stopped children on a thread of its own, so that injection tasks
+// keeping the runtime busy can't hold up an app being started
+struct Drain {
+    ring: RingBuf<MapData>,
+    tx: mpsc::Sender<Event>,
+    resumes: Resumes,
+    wakeup: Arc<Wakeup>,
+    stop_in_userspace: bool,
+    status: DaemonStatus
+}
+
+impl Drain {
+    fn dispatch(&mut self, event: EbpfEvent, meta: EventMeta) {
+        let Some(pid) = stopping_pid(&event) else {
+            // rare and never stopping anything, such as a zygote start, which must not be lost
+            let _ = self.tx.blocking_send((event, meta));
+            return
+        };
+
+        // waiting for it to land is left to whatever handles the child next
+        if self.stop_in_userspace {
+            signals::stop_process(pid);
+        }
+
+        // a uprobe left attached is picked up by the missed hook check
+        if let Err(TrySendError::Full((event, _))) = self.tx.try_send((event, meta)) {
+            warn!("[{pid}] event queue is full, resumed without handling {event:?}");
+            self.status.shed();
+            self.resumes.resume(pid);
+        }
+    }
+
+    fn drain(&mut self) {
+        loop {
+            // decoded right away, the entry holds its place in the ring buffer
+            let decoded = match self.ring.next() {
+                Some(entry) => protocol::decode(&entry),
+                None => break
+            };
+
+            match decoded {
+                Ok((event, meta)) => self.dispatch(event, meta),
+                Err(err) => error!("error while handling event: {err}")
+            }
+        }
+    }
+
+    fn wait(&self) -> io::Result<()> {
+        let timeout = match self.resumes.next_retry() {
+            Some(delay) => delay.as_millis().clamp(1, libc::c_int::MAX as u128) as libc::c_int,
+            None => -1
+        };
+
+        let mut fds = [
+            libc::pollfd { fd: self.ring.as_raw_fd(), events: libc::POLLIN, revents: 0 },
+            libc::pollfd { fd: self.wakeup.0.as_raw_fd(), events: libc::POLLIN, revents: 0 }
+        ];
+
+        if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as _, timeout) } < 0 {
+            let err = io::Error::last_os_error();
+
+            if err.kind() != io::ErrorKind::Interrupted {
+                return Err(err)
+            }
+        }
+
+        self.wakeup.clear();
+        Ok(())
+    }
+
+    fn run(mut self) {
+        let param = libc::sched_param { sched_priority: DRAIN_PRIORITY };
+
+        if unsafe { libc::sched_setscheduler(0, libc::SCHED_FIFO, &param) } != 0 {
+            warn!("failed to make the drain thread SCHED_FIFO: {}", io::Error::last_os_error());
+        }
+
+        // the event loop is gone on shutdown, which resumes what is left by itself
+        while !self.tx.is_closed() {
+            self.drain();
+            self.resumes.run();
+
+            if let Err(err) = self.wait() {
+                error!("drain thread exited: {err}");
+                return
+            }
+        }
+
+        debug!("drain thread exited");
+    }
+}
+
+// the event loop receives what is drained, and resumes children through the returned resumer
+pub fn spawn(ring: RingBuf<MapData>, stop_in_userspace: bool, status: DaemonStatus) -> Result<(mpsc::Receiver<Event>, Resumer)> {
+    let (tx, rx) = mpsc::channel(QUEUE_CAPACITY);
+    let (resume_tx, resume_rx) = std_mpsc::channel();
+    let wakeup = Arc::new(Wakeup::new()?);
+
+    let drain = Drain {
+        ring,
+        tx,
+        resumes: Resumes::new(resume_rx, status.clone()),
+        wakeup: Arc::clone(&wakeup),
+        stop_in_userspace,
+        status
+    };
+
+    thread::Builder::new().name("drain".into()).spawn(move || drain.run())?;
+
+    Ok((rx, Resumer::new(resume_tx, wakeup)))
+}
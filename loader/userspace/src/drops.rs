@@ -0,0 +1,118 @@
+use std::collections::HashSet;
+use std::time::Duration;
+
+use anyhow::Result;
+use aya::maps::{MapData, PerCpuArray};
+use log::{debug, error, info, warn};
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+use procfs::process::{all_processes, Process};
+use tokio::time;
+
+use crate::status::DaemonStatus;
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+// counts events that didn't fit into the ring buffer, and resumes the processes they left stopped;
+// events held back by the per-pid rate limit stop nothing, they are only counted
+pub struct DropMonitor {
+    dropped: PerCpuArray<MapData, u64>,
+    limited: PerCpuArray<MapData, u64>,
+    status: DaemonStatus,
+    total: u64,
+    limited_total: u64,
+    suspects: HashSet<i32>
+}
+
+impl DropMonitor {
+    pub fn new(dropped: PerCpuArray<MapData, u64>, limited: PerCpuArray<MapData, u64>, status: DaemonStatus) -> Self {
+        Self { dropped, limited, status, total: 0, limited_total: 0, suspects: HashSet::new() }
+    }
+
+    fn read_total(counter: &PerCpuArray<MapData, u64>) -> Result<u64> {
+        Ok(counter.get(&0, 0)?.iter().sum())
+    }
+
+    fn check_limited(&mut self) -> Result<()> {
+        let total = Self::read_total(&self.limited)?;
+
+        if total > self.limited_total {
+            warn!("{} events rate limited, some process keeps hitting the probes", total - self.limited_total);
+            self.limited_total = total;
+            self.status.set_rate_limited(total);
+        }
+
+        Ok(())
+    }
+
+    // zygote children stopped by SIGSTOP (not by ptrace), their parent is either zygote64 or a child zygote
+    fn stopped_children() -> Result<HashSet<i32>> {
+        let mut pids = HashSet::new();
+
+        for process in all_processes()?.flatten() {
+            let stat = match process.stat() {
+                Ok(stat) => stat,
+                Err(_) => continue
+            };
+
+            if stat.state != 'T' {
+                continue
+            }
+
+            let parent = Process::new(stat.ppid).and_then(|parent| parent.stat());
+            if parent.is_ok_and(|parent| parent.comm.contains("zygote")) {
+                pids.insert(stat.pid);
+            }
+        }
+
+        Ok(pids)
+    }
+
+    fn check(&mut self) -> Result<()> {
+        self.check_limited()?;
+
+        let total = Self::read_total(&self.dropped)?;
+
+        if total > self.total {
+            warn!("{} events dropped, ring buffer is full", total - self.total);
+            self.total = total;
+            self.status.set_dropped(total);
+
+            // events may still be in flight, only resume those which stay stopped till the next check
+            self.suspects = Self::stopped_children()?;
+            debug!("possibly stranded processes: {:?}", self.suspects);
+
+            return Ok(())
+        }
+
+        if self.suspects.is_empty() {
+            return Ok(())
+        }
+
+        let stopped = Self::stopped_children()?;
+
+        for pid in self.suspects.intersection(&stopped) {
+            info!("[{pid}] resuming stranded process");
+
+            if let Err(err) = kill(Pid::from_raw(*pid), Signal::SIGCONT) {
+                error!("[{pid}] failed to resume stranded process: {err}");
+            }
+        }
+
+        self.suspects.clear();
+
+        Ok(())
+    }
+
+    pub async fn serve(mut self) {
+        let mut interval = time::interval(CHECK_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            if let Err(err) = self.check() {
+                error!("failed to check dropped events: {err}");
+            }
+        }
+    }
+}
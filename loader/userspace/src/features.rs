@@ -0,0 +1,128 @@
+use std::io;
+use std::mem::size_of;
+
+use nix::libc;
+
+const BPF_MAP_CREATE: libc::c_long = 0;
+const BPF_PROG_LOAD: libc::c_long = 5;
+
+pub const BPF_MAP_TYPE_RINGBUF: u32 = 27;
+
+pub const BPF_PROG_TYPE_TRACEPOINT: u32 = 5;
+pub const BPF_PROG_TYPE_RAW_TRACEPOINT: u32 = 17;
+
+pub const BPF_FUNC_GET_CURRENT_TASK: i32 = 35;
+pub const BPF_FUNC_PROBE_READ_USER: i32 = 112;
+pub const BPF_FUNC_PROBE_READ_KERNEL: i32 = 113;
+pub const BPF_FUNC_SEND_SIGNAL_THREAD: i32 = 117;
+
+#[repr(C)]
+struct BpfInsn {
+    code: u8,
+    regs: u8,
+    off: i16,
+    imm: i32
+}
+
+// the leading fields of `union bpf_attr` used by BPF_MAP_CREATE, padded to cover the rest with zeros
+#[repr(C)]
+struct MapCreateAttr {
+    map_type: u32,
+    key_size: u32,
+    value_size: u32,
+    max_entries: u32,
+    _padding: [u8; 112]
+}
+
+// the leading fields of `union bpf_attr` used by BPF_PROG_LOAD, padded to cover the rest with zeros
+#[repr(C)]
+struct ProgLoadAttr {
+    prog_type: u32,
+    insn_cnt: u32,
+    insns: u64,
+    license: u64,
+    log_level: u32,
+    log_size: u32,
+    log_buf: u64,
+    kern_version: u32,
+    _padding: [u8; 84]
+}
+
+fn bpf<T>(cmd: libc::c_long, attr: &T) -> io::Result<()> {
+    let fd = unsafe {
+        libc::syscall(libc::SYS_bpf, cmd, attr as *const T, size_of::<T>())
+    };
+
+    if fd < 0 {
+        return Err(io::Error::last_os_error())
+    }
+
+    unsafe {
+        libc::close(fd as _);
+    }
+
+    Ok(())
+}
+
+fn load_program(prog_type: u32, insns: &[BpfInsn], log: &mut [u8]) -> io::Result<()> {
+    let license = c"GPL";
+
+    let attr = ProgLoadAttr {
+        prog_type,
+        insn_cnt: insns.len() as u32,
+        insns: insns.as_ptr() as u64,
+        license: license.as_ptr() as u64,
+        log_level: if log.is_empty() { 0 } else { 1 },
+        log_size: log.len() as u32,
+        log_buf: log.as_mut_ptr() as u64,
+        kern_version: 0,
+        _padding: [0; 84]
+    };
+
+    bpf(BPF_PROG_LOAD, &attr)
+}
+
+// load `return 0;`, which only the program type itself can make the kernel reject
+pub fn program_supported(prog_type: u32) -> io::Result<()> {
+    load_program(prog_type, &[
+        BpfInsn { code: 0xb7 /* mov64 imm */, regs: 0, off: 0, imm: 0 },
+        BpfInsn { code: 0x95 /* exit */, regs: 0, off: 0, imm: 0 }
+    ], &mut [])
+}
+
+// load `helper(0, 0, 0); return 0;` and see whether the verifier knows the helper, like bpftool does;
+// the arguments may well be rejected, only a complaint about the call itself means it's missing
+pub fn helper_supported(func: i32) -> io::Result<()> {
+    let mut log = vec![0u8; 4096];
+
+    let res = load_program(BPF_PROG_TYPE_TRACEPOINT, &[
+        BpfInsn { code: 0xb7 /* mov64 imm */, regs: 1, off: 0, imm: 0 },
+        BpfInsn { code: 0xb7 /* mov64 imm */, regs: 2, off: 0, imm: 0 },
+        BpfInsn { code: 0xb7 /* mov64 imm */, regs: 3, off: 0, imm: 0 },
+        BpfInsn { code: 0x85 /* call */, regs: 0, off: 0, imm: func },
+        BpfInsn { code: 0xb7 /* mov64 imm */, regs: 0, off: 0, imm: 0 },
+        BpfInsn { code: 0x95 /* exit */, regs: 0, off: 0, imm: 0 }
+    ], &mut log);
+
+    let log = String::from_utf8_lossy(&log);
+
+    match res {
+        Err(_) if log.contains("invalid func") || log.contains("unknown func") => {
+            Err(io::Error::new(io::ErrorKind::Unsupported, format!("helper #{func} is unknown to the kernel")))
+        }
+        Err(err) if err.raw_os_error() == Some(libc::EPERM) => Err(err),
+        _ => Ok(())
+    }
+}
+
+pub fn map_supported(map_type: u32, max_entries: u32) -> io::Result<()> {
+    let attr = MapCreateAttr {
+        map_type,
+        key_size: 0,
+        value_size: 0,
+        max_entries,
+        _padding: [0; 112]
+    };
+
+    bpf(BPF_MAP_CREATE, &attr)
+}
@@ -0,0 +1,103 @@
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::Result;
+use log::{debug, error};
+
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+enum Freezer {
+    // cgroup v2, `cgroup.freeze` holds 0 or 1
+    Unified(PathBuf),
+    // cgroup v1, `freezer.state` holds THAWED, FREEZING or FROZEN
+    Legacy(PathBuf)
+}
+
+impl Freezer {
+    fn find(pid: i32) -> Result<Option<Self>> {
+        let cgroups = fs::read_to_string(format!("/proc/{pid}/cgroup"))?;
+
+        for line in cgroups.lines() {
+            let mut fields = line.splitn(3, ':');
+            let (_, controllers, path) = match (fields.next(), fields.next(), fields.next()) {
+                (Some(id), Some(controllers), Some(path)) => (id, controllers, path.trim_start_matches('/')),
+                _ => continue
+            };
+
+            if controllers.split(',').any(|name| name == "freezer") {
+                let file = PathBuf::from(CGROUP_ROOT).join("freezer").join(path).join("freezer.state");
+                if file.exists() {
+                    return Ok(Some(Freezer::Legacy(file)))
+                }
+            }
+
+            if controllers.is_empty() {
+                let file = PathBuf::from(CGROUP_ROOT).join(path).join("cgroup.freeze");
+                if file.exists() {
+                    return Ok(Some(Freezer::Unified(file)))
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn file(&self) -> &PathBuf {
+        match self {
+            Freezer::Unified(file) | Freezer::Legacy(file) => file
+        }
+    }
+
+    fn is_frozen(&self) -> Result<bool> {
+        let state = fs::read_to_string(self.file())?;
+
+        Ok(match self {
+            Freezer::Unified(_) => state.trim() == "1",
+            Freezer::Legacy(_) => state.trim() != "THAWED"
+        })
+    }
+
+    fn set_frozen(&self, frozen: bool) -> Result<()> {
+        let state = match (self, frozen) {
+            (Freezer::Unified(_), true) => "1",
+            (Freezer::Unified(_), false) => "0",
+            (Freezer::Legacy(_), true) => "FROZEN",
+            (Freezer::Legacy(_), false) => "THAWED"
+        };
+
+        fs::write(self.file(), state)?;
+
+        Ok(())
+    }
+}
+
+// keeps the cgroup of a target thawed during injection, and freezes it again when dropped
+pub struct ThawGuard {
+    pid: i32,
+    freezer: Freezer
+}
+
+impl Drop for ThawGuard {
+    fn drop(&mut self) {
+        match self.freezer.set_frozen(true) {
+            Ok(_) => debug!("[{}] cgroup refrozen: {:?}", self.pid, self.freezer.file()),
+            Err(err) => error!("[{}] failed to refreeze cgroup: {err}", self.pid)
+        }
+    }
+}
+
+pub fn thaw(pid: i32) -> Result<Option<ThawGuard>> {
+    let freezer = match Freezer::find(pid)? {
+        Some(freezer) => freezer,
+        None => return Ok(None)
+    };
+
+    if !freezer.is_frozen()? {
+        return Ok(None)
+    }
+
+    freezer.set_frozen(false)?;
+    debug!("[{pid}] cgroup thawed for injection: {:?}", freezer.file());
+
+    Ok(Some(ThawGuard { pid, freezer }))
+}
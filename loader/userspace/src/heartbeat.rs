@@ -0,0 +1,81 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use log::warn;
+use tokio::time;
+
+use crate::status::DaemonStatus;
+
+// the bridge reports right after on_dlopen, which runs in pre specialize
+const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(5);
+const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Default)]
+struct Pending {
+    // the heartbeat comes while the loader is still in pre specialize, so before the injection is done
+    received: HashMap<i32, Instant>,
+    expected: HashMap<i32, (Option<String>, Instant)>
+}
+
+// injected processes that never heard from their bridge, such as one whose constructor failed
+#[derive(Clone)]
+pub struct Heartbeats {
+    pending: Arc<Mutex<Pending>>,
+    status: DaemonStatus
+}
+
+impl Heartbeats {
+    pub fn new(status: DaemonStatus) -> Self {
+        Self { pending: Arc::default(), status }
+    }
+
+    pub fn expect(&self, pid: i32, package: Option<String>) {
+        let mut pending = self.pending.lock().unwrap();
+
+        if pending.received.remove(&pid).is_none() {
+            pending.expected.insert(pid, (package, Instant::now()));
+        }
+    }
+
+    pub fn received(&self, pid: i32) {
+        let mut pending = self.pending.lock().unwrap();
+
+        if pending.expected.remove(&pid).is_none() {
+            pending.received.insert(pid, Instant::now());
+        }
+    }
+
+    fn sweep(&self) {
+        let mut pending = self.pending.lock().unwrap();
+
+        // heartbeats of injections that failed after pre specialize are never claimed
+        pending.received.retain(|_, time| time.elapsed() < HEARTBEAT_TIMEOUT);
+
+        pending.expected.retain(|pid, (package, time)| {
+            if time.elapsed() < HEARTBEAT_TIMEOUT {
+                return true
+            }
+
+            match package {
+                Some(package) => {
+                    warn!("[{pid}] no heartbeat from the bridge of {package}, it may run half-injected");
+                    self.status.half_injected(package);
+                }
+                None => warn!("[{pid}] no heartbeat from the bridge, it may run half-injected")
+            }
+
+            self.status.heartbeat_missed();
+            false
+        });
+    }
+
+    pub async fn serve(self) {
+        let mut interval = time::interval(SWEEP_INTERVAL);
+
+        loop {
+            interval.tick().await;
+            self.sweep();
+        }
+    }
+}
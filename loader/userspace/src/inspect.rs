@@ -0,0 +1,238 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use clap::Subcommand;
+use procfs::process::{MemoryMap, Process};
+
+use crate::loader::{self, LIBRARY_SEARCH_PATHS};
+use crate::runtime;
+use crate::symbols::{self, ArgCounter};
+
+// every variant seen so far shares it, whatever follows is the signature
+const SPECIALIZE_PREFIX: &str = "_ZN12_GLOBAL__N_116SpecializeCommon";
+
+const CORPUS: &str = include_str!("../corpus/specialize_common.txt");
+
+#[derive(Subcommand, Debug)]
+pub enum InspectTarget {
+    /// Print the memory maps of a process, and where each library is loaded
+    Maps {
+        pid: i32
+    },
+
+    /// Resolve symbols the way injection does, and where they are in a process if given
+    Symbol {
+        /// Path or file name of the library, file names are looked up in the process or the system
+        library: String,
+
+        #[clap(required = true)]
+        names: Vec<String>,
+
+        #[clap(long)]
+        pid: Option<i32>
+    },
+
+    /// Check argument counting against the corpus of known SpecializeCommon symbols, and whether the one of a library is known
+    Corpus {
+        /// Corpus file to use instead of the built-in one
+        #[clap(long)]
+        file: Option<String>,
+
+        /// libandroid_runtime.so to resolve, such as one pulled from a ROM, defaults to the one of this device
+        #[clap(long)]
+        library: Option<String>
+    }
+}
+
+fn read_maps(pid: i32) -> Result<Vec<MemoryMap>> {
+    let mut maps: Vec<_> = Process::new(pid)?.maps().context(format!("failed to read maps of {pid}"))?.into_iter().collect();
+    maps.sort_by_key(|map| map.address.0);
+
+    Ok(maps)
+}
+
+fn maps(pid: i32) -> Result<()> {
+    let maps = read_maps(pid)?;
+
+    for map in &maps {
+        println!(
+            "{:x}-{:x} {} {:08x} {:?}",
+            map.address.0, map.address.1, map.perms.as_str(), map.offset, map.pathname
+        );
+    }
+
+    let mut modules: Vec<_> = loader::modules_of(&maps).into_iter().collect();
+    modules.sort_by_key(|(_, (_, base))| *base);
+
+    println!();
+
+    for (name, (path, base)) in modules {
+        println!("{base:x} {name} ({})", path.display());
+    }
+
+    Ok(())
+}
+
+fn symbol(library: &str, names: &[String], pid: Option<i32>) -> Result<()> {
+    let module = match pid {
+        Some(pid) => loader::modules_of(&read_maps(pid)?).remove(library),
+        None => None
+    };
+
+    let path = match &module {
+        Some((path, _)) => path.clone(),
+        None if library.contains('/') => PathBuf::from(library),
+        None => LIBRARY_SEARCH_PATHS.iter()
+            .map(|dir| Path::new(dir).join(library))
+            .find(|path| path.exists())
+            .context(format!("{library} is not found in {}", LIBRARY_SEARCH_PATHS.join(", ")))?
+    };
+
+    let names: Vec<_> = names.iter().map(String::as_str).collect();
+    let offsets = symbols::resolve_many(&path, &names)?;
+
+    for (name, offset) in names.iter().zip(offsets) {
+        println!("{name}: {} +0x{offset:x}", path.display());
+
+        if let (Some(pid), Some((_, base))) = (pid, &module) {
+            println!("{name}: 0x{:x} in {pid}", base + offset);
+        }
+    }
+
+    if let (Some(pid), None) = (pid, &module) {
+        println!("{library} is not loaded in {pid}");
+    }
+
+    Ok(())
+}
+
+// `<expected arg count> <mangled name> [# where it was seen]`, blank lines and comments skipped
+fn parse_corpus(corpus: &str) -> Result<Vec<(usize, String)>> {
+    corpus.lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let (count, name) = line.split_once(char::is_whitespace).context(format!("malformed corpus line: {line}"))?;
+            Ok((count.parse().context(format!("invalid count in corpus line: {line}"))?, name.trim().into()))
+        })
+        .collect()
+}
+
+fn corpus(file: Option<&str>, library: Option<&str>) -> Result<()> {
+    let corpus = match file {
+        Some(file) => parse_corpus(&fs::read_to_string(file).context(format!("failed to read {file}"))?)?,
+        None => parse_corpus(CORPUS)?
+    };
+
+    let mut failed = 0;
+
+    for (expected, name) in &corpus {
+        let result = ArgCounter::count(name).and_then(|count| Ok((count, symbols::arg_layout(name)?)));
+
+        match result {
+            Ok((count, layout)) if count == *expected && layout.count() == count => (),
+            Ok((count, layout)) => {
+                println!("FAIL {name}: {count} arguments, expected {expected}, layout of {layout}");
+                failed += 1;
+            }
+            Err(err) => {
+                println!("FAIL {name}: {err}");
+                failed += 1;
+            }
+        }
+    }
+
+    println!("{} of {} corpus entries counted as expected", corpus.len() - failed, corpus.len());
+
+    // goes through `.gnu_debugdata` as well when the library is stripped
+    let library = library.unwrap_or(runtime::runtime_library());
+    let (name, offset) = symbols::resolve_for_uprobe(library, SPECIALIZE_PREFIX)?;
+    let count = ArgCounter::count(&name)?;
+
+    println!("{library}: {name} at +0x{offset:x} with {count} arguments");
+
+    match symbols::arg_layout(&name) {
+        Ok(layout) => println!("layout: {layout}"),
+        Err(err) => println!("layout: {err}, the one of the SDK is assumed")
+    }
+
+    match corpus.iter().find(|(_, known)| *known == name) {
+        Some((expected, _)) if *expected != count => println!("known, but the corpus expects {expected} arguments"),
+        Some(_) => println!("known to the corpus"),
+        None => println!("not in the corpus yet, consider reporting it along with the ROM")
+    }
+
+    if failed != 0 {
+        bail!("{failed} corpus entries failed");
+    }
+
+    Ok(())
+}
+
+pub fn main(target: &InspectTarget) -> Result<()> {
+    match target {
+        InspectTarget::Maps { pid } => maps(*pid),
+        InspectTarget::Symbol { library, names, pid } => symbol(library, names, *pid),
+        InspectTarget::Corpus { file, library } => corpus(file.as_deref(), library.as_deref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/corpus");
+
+    // built from corpus/specialize.c
+    const FIXTURE_OFFSET: u64 = 0x210;
+
+    fn corpus() -> Vec<(usize, String)> {
+        parse_corpus(CORPUS).unwrap()
+    }
+
+    #[test]
+    fn corpus_is_counted() {
+        let corpus = corpus();
+        assert!(!corpus.is_empty());
+
+        for (expected, name) in &corpus {
+            assert!(name.starts_with(SPECIALIZE_PREFIX), "{name}");
+            assert_eq!(ArgCounter::count(name).unwrap(), *expected, "{name}");
+            assert_eq!(symbols::arg_layout(name).unwrap().count(), *expected, "{name}");
+        }
+    }
+
+    #[test]
+    fn malformed_corpus_lines() {
+        assert!(parse_corpus("15").is_err());
+        assert!(parse_corpus("many _ZN12_GLOBAL__N_116SpecializeCommonEv").is_err());
+        assert_eq!(parse_corpus("# only a comment\n\n").unwrap(), vec![]);
+    }
+
+    fn check_fixture(library: &str) {
+        let (name, offset) = symbols::resolve_for_uprobe(format!("{FIXTURES}/{library}"), SPECIALIZE_PREFIX).unwrap();
+
+        assert_eq!(offset, FIXTURE_OFFSET, "{library}");
+
+        match corpus().iter().find(|(_, known)| *known == name) {
+            Some((expected, _)) => assert_eq!(ArgCounter::count(&name).unwrap(), *expected, "{library}"),
+            None => panic!("{library}: {name} is not in the corpus")
+        }
+    }
+
+    #[test]
+    fn resolve_fixture() {
+        check_fixture("libspecialize.so");
+    }
+
+    #[test]
+    fn resolve_fixture_from_debugdata() {
+        check_fixture("libspecialize-stripped.so");
+    }
+
+    #[test]
+    fn resolve_missing_prefix() {
+        assert!(symbols::resolve_for_uprobe(format!("{FIXTURES}/libspecialize.so"), "_ZN12_GLOBAL__N_116NotThere").is_err());
+    }
+}
@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use log::{info, warn};
+use nix::time::{clock_gettime, ClockId};
+
+const LATENCY_FILE: &str = "/data/adb/zloader/latency.csv";
+
+// timelines of processes that never finish (e.g. skipped ones) are dropped beyond this
+const MAX_TIMELINES: usize = 256;
+
+// stages of an injection in the order they happen
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Stage {
+    Forked,
+    Stopped,
+    UprobeAttached,
+    UprobeHit,
+    PtraceAttached,
+    DlopenDone,
+    Resumed
+}
+
+impl Stage {
+    const ALL: [Stage; 7] = [
+        Stage::Forked,
+        Stage::Stopped,
+        Stage::UprobeAttached,
+        Stage::UprobeHit,
+        Stage::PtraceAttached,
+        Stage::DlopenDone,
+        Stage::Resumed
+    ];
+}
+
+impl Display for Stage {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Stage::Forked => write!(fmt, "forked"),
+            Stage::Stopped => write!(fmt, "stopped"),
+            Stage::UprobeAttached => write!(fmt, "uprobe_attached"),
+            Stage::UprobeHit => write!(fmt, "uprobe_hit"),
+            Stage::PtraceAttached => write!(fmt, "ptrace_attached"),
+            Stage::DlopenDone => write!(fmt, "dlopen_done"),
+            Stage::Resumed => write!(fmt, "resumed")
+        }
+    }
+}
+
+// nanoseconds on CLOCK_MONOTONIC, the clock `bpf_ktime_get_ns` reads as well
+pub fn now() -> u64 {
+    clock_gettime(ClockId::CLOCK_MONOTONIC)
+        .map(|ts| ts.tv_sec() as u64 * 1_000_000_000 + ts.tv_nsec() as u64)
+        .unwrap_or(0)
+}
+
+// per process timestamps of each stage, does nothing unless enabled
+#[derive(Clone, Default)]
+pub struct LatencyTracker {
+    timelines: Option<Arc<Mutex<HashMap<i32, Vec<(Stage, u64)>>>>>
+}
+
+impl LatencyTracker {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            timelines: enabled.then(Default::default)
+        }
+    }
+
+    pub fn record(&self, pid: i32, stage: Stage, timestamp: u64) {
+        let timelines = match &self.timelines {
+            Some(timelines) => timelines,
+            None => return
+        };
+
+        let timeline = {
+            let mut timelines = timelines.lock().unwrap();
+
+            if timelines.len() >= MAX_TIMELINES && !timelines.contains_key(&pid) {
+                if let Some(oldest) = timelines.iter().min_by_key(|(_, timeline)| timeline[0].1).map(|(pid, _)| *pid) {
+                    timelines.remove(&oldest);
+                }
+            }
+
+            timelines.entry(pid).or_default().push((stage, timestamp));
+
+            if stage != Stage::Resumed {
+                return
+            }
+
+            timelines.remove(&pid).unwrap()
+        };
+
+        Self::finish(pid, timeline);
+    }
+
+    pub fn record_now(&self, pid: i32, stage: Stage) {
+        if self.timelines.is_some() {
+            self.record(pid, stage, now());
+        }
+    }
+
+    // drop the timeline of a process that won't be injected
+    pub fn discard(&self, pid: i32) {
+        if let Some(timelines) = &self.timelines {
+            timelines.lock().unwrap().remove(&pid);
+        }
+    }
+
+    fn finish(pid: i32, mut timeline: Vec<(Stage, u64)>) {
+        timeline.sort();
+
+        let start = timeline[0].1;
+        let breakdown: Vec<_> = timeline.windows(2)
+            .map(|pair| format!("{} +{:?}", pair[1].0, Duration::from_nanos(pair[1].1.saturating_sub(pair[0].1))))
+            .collect();
+
+        let total = Duration::from_nanos(timeline[timeline.len() - 1].1.saturating_sub(start));
+        info!("[{pid}] injection latency {total:?}: {}", breakdown.join(", "));
+
+        // one column per stage, relative to the first one, empty if the stage was not seen
+        let columns: Vec<_> = Stage::ALL.iter()
+            .map(|stage| {
+                timeline.iter()
+                    .find(|(seen, _)| seen == stage)
+                    .map(|(_, timestamp)| (timestamp.saturating_sub(start) / 1000).to_string())
+                    .unwrap_or_default()
+            })
+            .collect();
+
+        if let Err(err) = Self::export(&format!("{pid},{}\n", columns.join(","))) {
+            warn!("failed to export latency of {pid}: {err}");
+        }
+    }
+
+    fn export(line: &str) -> std::io::Result<()> {
+        let mut file = OpenOptions::new().create(true).append(true).open(LATENCY_FILE)?;
+
+        if file.metadata()?.len() == 0 {
+            let header: Vec<_> = Stage::ALL.iter().map(|stage| format!("{stage}_us")).collect();
+            file.write_all(format!("pid,{}\n", header.join(",")).as_bytes())?;
+        }
+
+        file.write_all(line.as_bytes())
+    }
+}
@@ -1,37 +1,141 @@
-use std::collections::HashMap;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
 use std::ffi::{c_char, CString};
-use std::io::IoSlice;
+use std::os::unix::ffi::OsStrExt;
+use std::io::{IoSlice, IoSliceMut};
 use std::{mem, process, ptr};
 use std::mem::MaybeUninit;
-use std::path::PathBuf;
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 use anyhow::{anyhow, bail, Context, Result};
 use jni_sys::JNINativeInterface__1_6;
 use libloading::Symbol;
-use log::{debug, error, info};
+use tracing::{debug, debug_span, error, info, instrument, warn, Span};
 use nix::errno::Errno;
 use nix::libc;
 
-#[cfg(target_arch = "aarch64")]
 use nix::libc::iovec;
-
 use nix::libc::user_regs_struct;
 use nix::sys::ptrace;
 use nix::sys::signal::{kill, Signal};
-use nix::sys::uio::{process_vm_writev, RemoteIoVec};
+use nix::sys::uio::{process_vm_readv, process_vm_writev, RemoteIoVec};
 use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
 use nix::unistd::Pid;
-use procfs::process::{MemoryMap, MMapPath, Process};
-use common::zygote::SpecializeArgs;
-use crate::{arch_select, symbols};
+use procfs::process::{MemoryMap, MMapPath, MMPermissions, Process};
+use common::{audit, rootimpl};
+use common::process::{self as process_info, ProcessInfo};
+use common::zygote::{ArgLayout, SpecializeArgs};
+use crate::{arch_select, cache, denylist, freezer, prologue, restrictions, symbols};
+use crate::config::{NativeBridgePolicy, Payload, ProcessCategory};
+use crate::deferred::DeferredInjections;
+use crate::freezer::ThawGuard;
+use crate::heartbeat::Heartbeats;
+use crate::latency::{LatencyTracker, Stage};
+use crate::report::{Report, VerboseTargets};
+use crate::status::DaemonStatus;
+use crate::context;
+use crate::zygotes::ChildZygotes;
 use crate::loader::args::Arg;
+use crate::outcome::ResultNotifier;
+
+// keep in sync with `ZLB_ARGS`, `ZLB_STAGING_DIR` and `ZLB_RANGES` in the bridge
+const ZLB_MAX_ARGS: usize = 32;
+const ZLB_STAGING_DIR_SIZE: usize = 256;
+const ZLB_MAX_RANGES: usize = 32;
+
+const VERBOSE_STEPS: usize = 256;
+
+const UNMAP_UPROBES_ATTEMPTS: usize = 3;
+
+// hardened kernels may come without CONFIG_CROSS_MEMORY_ATTACH, remembered once seen
+static VM_READV_UNAVAILABLE: AtomicBool = AtomicBool::new(false);
+
+// `si_code` of SIGTRAP from a hardware breakpoint or watchpoint
+const TRAP_HWBKPT: i32 = 4;
+
+// traps not caused by a remote call passed on to the tracee, before the call is given up
+const MAX_FORWARDED_TRAPS: usize = 16;
+
+// instructions single stepped after a stray trap, in the hope of reaching the return address
+const RECOVERY_STEPS: usize = 64;
+
+// `int3` and `brk #0`, and how far pc is past it once trapped
+const BREAKPOINT: u64 = arch_select!(0xcc, 0xd420_0000);
+const BREAKPOINT_MASK: u64 = arch_select!(0xff, 0xffff_ffff);
+const BREAKPOINT_PC_OFFSET: usize = arch_select!(1, 0);
+
+const SCRATCH_SIZE: usize = 0x1000;
+const PAGE_SIZE: usize = 0x1000;
+
+// divides the page size, so that a chunk never crosses into a page that may not be mapped
+const STRING_CHUNK: usize = 0x100;
+
+// a corrupted pointer must not keep the target frozen while pages of garbage are read,
+// nothing read from zygote args or dlerror comes close to either
+const MAX_STRING_LENGTH: usize = 0x2000;
+const STRING_READ_TIMEOUT: Duration = Duration::from_millis(100);
+
+// grown to fit if the buffers of a single remote call are larger
+const BUFFERS_SIZE: usize = 0x4000;
+
+const NT_PRFPREG: i32 = 2;
+#[cfg(target_arch = "aarch64")]
+const NT_ARM_PAC_MASK: i32 = 0x406;
+#[cfg(target_arch = "aarch64")]
+const NT_ARM_PACA_KEYS: i32 = 0x407;
+
+// large enough for `user_fpsimd_struct` and the fxsave area
+const MAX_REGSET_SIZE: usize = 1024;
+
+const LINKER_NAMESPACES: &[&str] = &["default", "sphal", "vndk", "vndk_product", "rs"];
+pub const LIBRARY_SEARCH_PATHS: &[&str] = &["/system/lib64", "/system_ext/lib64", "/apex/com.android.runtime/lib64/bionic"];
 
 pub type FilterFn<'a> = Symbol<'a, extern "C" fn(libc::uid_t, *const c_char, *const c_char) -> bool>;
+pub type FilterExFn<'a> = Symbol<'a, extern "C" fn(*const ProcessInfo) -> bool>;
+
+// `check_process_ex` is preferred when the filter exports it
+#[derive(Clone)]
+pub enum Filter<'a> {
+    Simple(FilterFn<'a>),
+    Snapshot(FilterExFn<'a>)
+}
+
+// how processes are traced, the same for every injection of a daemon
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TraceOptions {
+    // read back and verify every write into the target
+    pub strict: bool,
+    // detach and exit on SIGSTOP, so that a debugger can take over
+    pub debug_detach: bool,
+    // run a remote call again with a breakpoint as return address, if it returned somewhere else
+    pub breakpoint_retry: bool,
+    // return from remote calls into a breakpoint on a scratch page, rather than faulting at the base of libc
+    pub breakpoint_completion: bool
+}
 
 pub struct BridgeConfig<'a> {
-    pub library: String,
-    pub filter_fn: Option<FilterFn<'a>>,
+    pub bridges: Arc<HashMap<ProcessCategory, Vec<String>>>,
+    pub payloads: Arc<Vec<Payload>>,
+    pub filter_fn: Option<Filter<'a>>,
+    pub result_fn: Option<ResultNotifier>,
+    pub blocked_processes: Arc<Vec<String>>,
+    pub native_bridge: NativeBridgePolicy,
     pub args_count: usize,
+    pub args_layout: ArgLayout,
     pub return_addr: usize,
+    pub trace: TraceOptions,
+    pub uretprobe: bool,
+    pub child_zygotes: ChildZygotes,
+    pub verbose: VerboseTargets,
+    pub latency: LatencyTracker,
+    pub deferred: DeferredInjections,
+    pub status: DaemonStatus,
+    pub heartbeats: Heartbeats,
 }
 
 #[derive(Debug, Clone)]
@@ -137,48 +241,157 @@ impl Registers {
     }
 }
 
+// state besides general-purpose registers that code run by a remote call may leave changed
+#[derive(Debug, Clone)]
+struct ExtraRegisters {
+    fp: Vec<u8>,
+    // only readable with CONFIG_CHECKPOINT_RESTORE, none without pointer authentication as well
+    #[cfg(target_arch = "aarch64")]
+    pac_keys: Option<Vec<u8>>
+}
+
+// where a remote call stopped instead of at its return address
+struct CallFault {
+    pc: usize,
+    sp: usize,
+    location: String,
+    siginfo: String
+}
+
+impl Display for CallFault {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(fmt, "pc=0x{:x} ({}) sp=0x{:x} {}", self.pc, self.location, self.sp, self.siginfo)
+    }
+}
+
+fn locate(maps: &[MemoryMap], pc: u64) -> String {
+    maps.iter()
+        .find(|map| map.address.0 <= pc && pc < map.address.1)
+        .map(|map| format!("{:?}+0x{:x}", map.pathname, pc - map.address.0 + map.offset))
+        .unwrap_or_default()
+}
 
 struct Tracee {
-    pid: Pid
+    pid: Pid,
+    options: TraceOptions,
+    // other threads, seized and held in a ptrace stop while the main thread is traced
+    siblings: RefCell<Vec<Pid>>
 }
 
 impl Tracee {
-    fn new(pid: i32) -> Self {
-        Self { pid: Pid::from_raw(pid) }
+    fn new(pid: i32, options: TraceOptions) -> Self {
+        Self { pid: Pid::from_raw(pid), options, siblings: RefCell::new(Vec::new()) }
     }
 
-    fn attach(&self) -> Result<()> {
-        ptrace::attach(self.pid)?;
-
-        loop {
-            waitpid(self.pid, Some(WaitPidFlag::__WALL))?;
+    fn seize(&self) -> Result<()> {
+        if let Err(err) = ptrace::seize(self.pid, ptrace::Options::empty()) {
+            if err != Errno::EPERM {
+                bail!(err);
+            }
 
-            if ptrace::getsiginfo(self.pid) != Err(Errno::EINVAL) {
-                break
+            // only reported, sepolicy is left for the user to change
+            for restriction in restrictions::diagnose_ptrace(self.pid.as_raw()) {
+                error!("[{}] {restriction}", self.pid);
             }
 
-            ptrace::cont(self.pid, None)?;
+            bail!("[{}] ptrace attach not permitted", self.pid);
         }
 
+        Ok(())
+    }
+
+    // not wrapped by nix, the tracee stays in group-stop but reports the next event
+    fn listen(&self) -> Result<()> {
+        Errno::result(unsafe {
+            libc::ptrace(libc::PTRACE_LISTEN, self.pid.as_raw(), 0, 0)
+        })?;
+
+        Ok(())
+    }
+
+    // the child is stopped by eBPF, but that SIGSTOP may still be pending when we get here, so every
+    // stop is told apart by what reports it rather than assuming which one comes first
+    #[instrument(name = "attach", skip_all)]
+    fn attach(&self) -> Result<()> {
+        self.attach_thread()?;
+        self.stop_siblings()?;
+
+        Ok(())
+    }
+
+    // only the main thread, left in a ptrace-stop
+    fn attach_thread(&self) -> Result<()> {
+        self.seize()?;
+
+        // end the stop of eBPF now, the tracee is held by ptrace-stops from here on
         kill(self.pid, Signal::SIGCONT)?;
-        ptrace::cont(self.pid, None)?;
-        waitpid(self.pid, Some(WaitPidFlag::__WALL))?;
+        ptrace::interrupt(self.pid)?;
+
+        loop {
+            match waitpid(self.pid, Some(WaitPidFlag::__WALL))? {
+                // our interrupt, ready for remote calls
+                WaitStatus::PtraceEvent(_, Signal::SIGTRAP, libc::PTRACE_EVENT_STOP) => break,
+                // group-stop of a SIGSTOP that came after our SIGCONT, wait for another one inside it
+                WaitStatus::PtraceEvent(_, signal, libc::PTRACE_EVENT_STOP) => {
+                    debug!("[{}] group-stop by {signal} while attaching", self.pid);
+                    kill(self.pid, Signal::SIGCONT)?;
+                    self.listen()?;
+                }
+                // job control has already taken effect as the signal was sent, delivering it changes nothing
+                WaitStatus::Stopped(_, Signal::SIGSTOP | Signal::SIGCONT) => ptrace::cont(self.pid, None)?,
+                WaitStatus::Stopped(_, signal) => ptrace::cont(self.pid, signal)?,
+                WaitStatus::Exited(..) | WaitStatus::Signaled(..) => bail!("[{}] exited while attaching", self.pid),
+                status => bail!("[{}] unexpected stop while attaching: {status:?}", self.pid)
+            }
+        }
 
         Ok(())
     }
 
-    #[cfg(target_arch = "x86_64")]
-    fn regs(&self) -> Result<Registers> {
-        let mut regs: MaybeUninit<user_regs_struct> = MaybeUninit::uninit();
+    // some OEM zygotes start helper threads before specialize, a remote call mustn't race them
+    fn stop_siblings(&self) -> Result<()> {
+        let mut siblings = self.siblings.borrow_mut();
 
-        Errno::result(unsafe {
-            libc::ptrace(libc::PTRACE_GETREGS, self.pid.as_raw(), 0, regs.as_mut_ptr())
-        })?;
+        // threads may be started meanwhile, until a pass finds nothing new
+        loop {
+            let tasks: Vec<_> = Process::new(self.pid.as_raw())?.tasks()?
+                .flatten()
+                .map(|task| Pid::from_raw(task.tid))
+                .filter(|tid| *tid != self.pid && !siblings.contains(tid))
+                .collect();
 
-        Ok(Registers::new(unsafe { regs.assume_init() }))
+            if tasks.is_empty() {
+                break
+            }
+
+            for tid in tasks {
+                match ptrace::seize(tid, ptrace::Options::empty()) {
+                    Ok(_) => siblings.push(tid),
+                    Err(Errno::ESRCH) => continue,
+                    Err(err) => bail!("[{}] failed to seize thread {tid}: {err}", self.pid)
+                }
+
+                match ptrace::interrupt(tid) {
+                    Ok(_) => (),
+                    Err(Errno::ESRCH) => continue,
+                    Err(err) => bail!("[{}] failed to interrupt thread {tid}: {err}", self.pid)
+                }
+
+                // the thread may exit rather than stop, which is just as good
+                if let WaitStatus::Exited(..) | WaitStatus::Signaled(..) = waitpid(tid, Some(WaitPidFlag::__WALL))? {
+                    siblings.retain(|sibling| *sibling != tid);
+                }
+            }
+        }
+
+        if !siblings.is_empty() {
+            debug!("[{}] {} sibling threads stopped", self.pid, siblings.len());
+        }
+
+        Ok(())
     }
 
-    #[cfg(target_arch = "aarch64")]
+    // PTRACE_GETREGS doesn't exist on aarch64, GETREGSET works on both
     fn regs(&self) -> Result<Registers> {
         let mut regs: MaybeUninit<user_regs_struct> = MaybeUninit::uninit();
         let iov = iovec {
@@ -216,6 +429,85 @@ impl Tracee {
         Ok(())
     }
 
+    fn regset(&self, note: i32) -> Result<Vec<u8>, Errno> {
+        let mut buffer = vec![0u8; MAX_REGSET_SIZE];
+        let mut iov = iovec {
+            iov_base: buffer.as_mut_ptr() as _,
+            iov_len: buffer.len()
+        };
+
+        Errno::result(unsafe {
+            libc::ptrace(libc::PTRACE_GETREGSET, self.pid.as_raw(), note, &mut iov as *mut _)
+        })?;
+
+        // the kernel shrinks it to the actual size
+        buffer.truncate(iov.iov_len);
+
+        Ok(buffer)
+    }
+
+    fn set_regset(&self, note: i32, data: &[u8]) -> Result<(), Errno> {
+        let iov = iovec {
+            iov_base: data.as_ptr() as *mut _,
+            iov_len: data.len()
+        };
+
+        Errno::result(unsafe {
+            libc::ptrace(libc::PTRACE_SETREGSET, self.pid.as_raw(), note, &iov as *const _)
+        })?;
+
+        Ok(())
+    }
+
+    // data and instruction masks of signed pointers, none if pointer authentication is not in use
+    #[cfg(target_arch = "aarch64")]
+    fn pac_mask(&self) -> Option<(u64, u64)> {
+        let mask = self.regset(NT_ARM_PAC_MASK).ok()?;
+
+        Some((
+            u64::from_ne_bytes(mask.get(0 .. 8)?.try_into().ok()?),
+            u64::from_ne_bytes(mask.get(8 .. 16)?.try_into().ok()?)
+        ))
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    fn pac_keys(&self) -> Option<Vec<u8>> {
+        let (data_mask, insn_mask) = self.pac_mask()?;
+
+        match self.regset(NT_ARM_PACA_KEYS) {
+            Ok(keys) => Some(keys),
+            Err(err) => {
+                debug!("[{}] pac mask {data_mask:x}/{insn_mask:x}, keys not readable: {err}", self.pid);
+                None
+            }
+        }
+    }
+
+    fn extra_regs(&self) -> Result<ExtraRegisters> {
+        let fp = self.regset(NT_PRFPREG).context(format!("[{}] failed to read fp registers", self.pid))?;
+
+        Ok(ExtraRegisters {
+            fp,
+            #[cfg(target_arch = "aarch64")]
+            pac_keys: self.pac_keys()
+        })
+    }
+
+    fn set_extra_regs(&self, regs: &ExtraRegisters) -> Result<()> {
+        self.set_regset(NT_PRFPREG, &regs.fp).context(format!("[{}] failed to restore fp registers", self.pid))?;
+
+        #[cfg(target_arch = "aarch64")]
+        if let Some(keys) = &regs.pac_keys {
+            self.set_regset(NT_ARM_PACA_KEYS, keys).context(format!("[{}] failed to restore pac keys", self.pid))?;
+        }
+
+        if self.options.strict && self.regset(NT_PRFPREG)? != regs.fp {
+            bail!("[{}] fp register verification failed", self.pid);
+        }
+
+        Ok(())
+    }
+
     fn peek(&self, addr: usize) -> Result<u64> {
         Ok(ptrace::read(self.pid, addr as _)? as u64)
     }
@@ -225,31 +517,47 @@ impl Tracee {
             ptrace::write(self.pid, addr as _, value as *mut _)?
         }
 
+        if self.options.strict {
+            let actual = self.peek(addr)?;
+            if actual != value {
+                bail!("[{}] write verification failed at 0x{addr:x}: expected 0x{value:x}, got 0x{actual:x}", self.pid);
+            }
+        }
+
         Ok(())
     }
 
-    #[cfg(target_arch = "x86_64")]
-    fn arg(&self, regs: &Registers, n: usize) -> Result<u64> {
-        let arg = if n < 6 {
-            regs.arg(n)
-        } else {
-            let n = n - 6;
-            self.peek(regs.sp() + 8 * n + 8 /* call */)?
-        };
+    // compare the registers that matter for resuming, some others may be normalized by the kernel
+    fn verify_regs(&self, expected: &Registers) -> Result<()> {
+        let actual = self.regs()?;
+
+        if actual.pc() != expected.pc() || actual.sp() != expected.sp() {
+            bail!(
+                "[{}] register verification failed: expected pc=0x{:x} sp=0x{:x}, got pc=0x{:x} sp=0x{:x}",
+                self.pid, expected.pc(), expected.sp(), actual.pc(), actual.sp()
+            );
+        }
+
+        for i in 0 .. arch_select!(6, 8) {
+            if actual.arg(i) != expected.arg(i) {
+                bail!("[{}] register verification failed: arg{i} expected 0x{:x}, got 0x{:x}", self.pid, expected.arg(i), actual.arg(i));
+            }
+        }
 
-        Ok(arg)
+        Ok(())
     }
 
-    #[cfg(target_arch = "aarch64")]
-    fn arg(&self, regs: &Registers, n: usize) -> Result<u64> {
-        let arg = if n < 8 {
-            regs.arg(n)
-        } else {
-            let n = n - 8;
-            self.peek(regs.sp() + 8 * n)?
-        };
+    // those beyond registers are read from the stack at once
+    fn args(&self, regs: &Registers, count: usize) -> Result<Vec<u64>> {
+        let args_on_regs = arch_select!(6, 8);
+        let mut args: Vec<_> = (0 .. count.min(args_on_regs)).map(|n| regs.arg(n)).collect();
+
+        if count > args_on_regs {
+            let stack = regs.sp() + arch_select!(8 /* call */, 0);
+            args.extend(self.read_words(stack, count - args_on_regs)?);
+        }
 
-        Ok(arg)
+        Ok(args)
     }
 
     fn set_arg(&self, regs: &mut Registers, n: usize, value: u64) -> Result<()> {
@@ -279,16 +587,63 @@ impl Tracee {
         Ok(())
     }
 
-    fn alloc(&self, regs: &mut Registers, data: &[u8]) -> Result<usize> {
-        let new_sp = (regs.sp() - data.len()) & !0x7;
+    // only right at the entry of a function, before the prologue touches sp or lr
+    #[cfg(target_arch = "x86_64")]
+    fn return_addr(&self, regs: &Registers) -> Result<usize> {
+        Ok(self.peek(regs.sp())? as usize)
+    }
+
+    #[cfg(target_arch = "aarch64")]
+    fn return_addr(&self, regs: &Registers) -> Result<usize> {
+        Ok(regs.0.regs[30] as usize)
+    }
+
+    fn read(&self, addr: usize, len: usize) -> Result<Vec<u8>> {
+        if !VM_READV_UNAVAILABLE.load(Ordering::Relaxed) {
+            let mut buffer = vec![0u8; len];
+            let remote_iov = RemoteIoVec { base: addr, len };
+
+            match process_vm_readv(self.pid, &mut [IoSliceMut::new(&mut buffer)], &[remote_iov]) {
+                Ok(read) if read == len => return Ok(buffer),
+                Ok(read) => bail!("[{}] short read at 0x{addr:x}: {read} of {len} bytes", self.pid),
+                Err(err @ (Errno::ENOSYS | Errno::EPERM)) => {
+                    warn!("[{}] process_vm_readv is not available ({err}), falling back to ptrace", self.pid);
+                    VM_READV_UNAVAILABLE.store(true, Ordering::Relaxed);
+                }
+                Err(err) => bail!("[{}] failed to read 0x{len:x} bytes at 0x{addr:x}: {err}", self.pid)
+            }
+        }
+
+        self.read_by_peek(addr, len)
+    }
+
+    // a word at an aligned address never crosses into another page
+    fn read_by_peek(&self, addr: usize, len: usize) -> Result<Vec<u8>> {
+        let start = addr & !7;
+        let mut buffer = Vec::with_capacity(len + 16);
+
+        for word in (start .. addr + len).step_by(8) {
+            buffer.extend(self.peek(word)?.to_ne_bytes());
+        }
+
+        Ok(buffer[addr - start ..][.. len].to_vec())
+    }
+
+    fn read_words(&self, addr: usize, count: usize) -> Result<Vec<u64>> {
+        let data = self.read(addr, count * 8)?;
+        Ok(data.chunks_exact(8).map(|chunk| u64::from_ne_bytes(chunk.try_into().unwrap())).collect())
+    }
 
+    fn write(&self, addr: usize, data: &[u8]) -> Result<()> {
         let local_iov = IoSlice::new(data);
-        let remote_iov = RemoteIoVec { base: new_sp, len: data.len() };
+        let remote_iov = RemoteIoVec { base: addr, len: data.len() };
         process_vm_writev(self.pid, &[local_iov], &[remote_iov])?;
 
-        regs.set_sp(new_sp);
+        if self.options.strict && self.read(addr, data.len())? != data {
+            bail!("[{}] write verification failed at 0x{addr:x} ({} bytes)", self.pid, data.len());
+        }
 
-        Ok(new_sp)
+        Ok(())
     }
 
     fn wait(&self) -> Result<WaitStatus> {
@@ -303,8 +658,8 @@ impl Tracee {
                         return Ok(status)
                     }
 
-                    #[cfg(debug_assertions)]
-                    if let WaitStatus::Stopped(_, Signal::SIGSTOP) = status {
+                    // leave the process stopped for a debugger, and get out of its way
+                    if self.options.debug_detach && matches!(status, WaitStatus::Stopped(_, Signal::SIGSTOP)) {
                         info!("[{}] detach for debug", self.pid);
                         let _ = ptrace::detach(self.pid, Signal::SIGSTOP);
                         process::exit(0);
@@ -326,6 +681,31 @@ impl Tracee {
         }
     }
 
+    // single step `count` instructions, and describe where each of them was
+    fn step_trace(&self, count: usize, maps: &[MemoryMap]) -> Result<Vec<String>> {
+        let mut steps = Vec::new();
+
+        for _ in 0 .. count {
+            ptrace::step(self.pid, None)?;
+
+            match waitpid(self.pid, Some(WaitPidFlag::__WALL))? {
+                WaitStatus::Stopped(_, Signal::SIGTRAP) => (),
+                status => {
+                    steps.push(format!("stopped: {status:?}"));
+                    break
+                }
+            }
+
+            let regs = self.regs()?;
+            let pc = regs.pc() as u64;
+            let location = locate(maps, pc);
+
+            steps.push(format!("pc=0x{pc:x} sp=0x{:x} {location}", regs.sp()));
+        }
+
+        Ok(steps)
+    }
+
     // single step for debug
     #[allow(dead_code)]
     fn debug_call(&self) -> Result<()> {
@@ -372,42 +752,137 @@ impl Tracee {
         Ok(())
     }
 
-    fn call(&self, regs: &Registers, func: usize, args: &[u64], return_addr: usize) -> Result<u64> {
-        let retval: Result<u64> = try {
-            let mut regs = regs.clone();
-            
-            let args_on_regs = arch_select!(6, 8);
-            let remain = args.len().saturating_sub(args_on_regs);
-            
-            regs.set_sp(regs.sp() - remain * 8);
+    fn fault(&self, regs: &Registers) -> CallFault {
+        let location = Process::new(self.pid.as_raw())
+            .and_then(|process| process.maps())
+            .map(|maps| locate(&maps.into_iter().collect::<Vec<_>>(), regs.pc() as u64))
+            .unwrap_or_default();
 
-            // align to 16 bytes
-            regs.set_sp(regs.sp() & !0xF);
-            
-            // pass arguments
-            for (i, arg) in args.iter().copied().enumerate() {
-                self.set_arg(&mut regs, i, arg)?;
-            }
+        let siginfo = match ptrace::getsiginfo(self.pid) {
+            Ok(info) => format!("signo={} code={} addr=0x{:x}", info.si_signo, info.si_code, unsafe { info.si_addr() } as usize),
+            Err(err) => format!("siginfo unavailable: {err}")
+        };
 
-            regs.set_pc(func);  // jump to func
+        CallFault { pc: regs.pc(), sp: regs.sp(), location, siginfo }
+    }
 
-            self.set_return_addr(&mut regs, return_addr, true)?;
+    // hardware breakpoints and watchpoints of self-debugging apps, or a SIGTRAP sent by someone
+    fn is_foreign_trap(&self) -> bool {
+        ptrace::getsiginfo(self.pid).is_ok_and(|info| info.si_code == TRAP_HWBKPT || info.si_code <= 0)
+    }
 
-            // all ready, run!
-            self.set_regs(&regs)?;
-            ptrace::cont(self.pid, None)?;
+    // a stray trap, e.g. a breakpoint left behind, may only be a detour on the way back
+    fn recover(&self, expected_pc: usize) -> Result<bool> {
+        for _ in 0 .. RECOVERY_STEPS {
+            ptrace::step(self.pid, None)?;
             self.wait()?;
 
-            // check return address
+            if self.regs()?.pc() == expected_pc {
+                return Ok(true)
+            }
+        }
+
+        Ok(false)
+    }
+
+    // the inner result is the fault if the call didn't come back to `expected_pc`
+    fn run_call(
+        &self, regs: &Registers, func: usize, args: &[u64], return_addr: usize, expected_pc: usize
+    ) -> Result<Result<u64, CallFault>> {
+        let mut regs = regs.clone();
+
+        let args_on_regs = arch_select!(6, 8);
+        let remain = args.len().saturating_sub(args_on_regs);
+
+        regs.set_sp(regs.sp() - remain * 8);
+
+        // align to 16 bytes
+        regs.set_sp(regs.sp() & !0xF);
+
+        // pass arguments
+        for (i, arg) in args.iter().copied().enumerate() {
+            self.set_arg(&mut regs, i, arg)?;
+        }
+
+        regs.set_pc(func);  // jump to func
+
+        self.set_return_addr(&mut regs, return_addr, true)?;
+
+        // all ready, run!
+        self.set_regs(&regs)?;
+        ptrace::cont(self.pid, None)?;
+        let mut status = self.wait()?;
+
+        // check return address
+        regs = self.regs()?;
+
+        // the app may handle it by itself, it would have got the trap without us as well
+        for _ in 0 .. MAX_FORWARDED_TRAPS {
+            if regs.pc() == expected_pc || !matches!(status, WaitStatus::Stopped(_, Signal::SIGTRAP)) || !self.is_foreign_trap() {
+                break
+            }
+
+            debug!("[{}] trap of its own during remote call at 0x{:x}, forwarded", self.pid, regs.pc());
+            ptrace::cont(self.pid, Signal::SIGTRAP)?;
+            status = self.wait()?;
             regs = self.regs()?;
-            let current_pc = regs.pc();
+        }
+
+        if regs.pc() == expected_pc {
+            return Ok(Ok(regs.return_value()))
+        }
+
+        let fault = self.fault(&regs);
 
-            if current_pc != return_addr {
-                error!("[{}] wrong return address: 0x{:x}", self.pid, current_pc);
-                bail!("wrong return address");
+        if let WaitStatus::Stopped(_, Signal::SIGTRAP) = status {
+            if self.recover(expected_pc)? {
+                warn!("[{}] stray trap during remote call, recovered by single step: {fault}", self.pid);
+                return Ok(Ok(self.regs()?.return_value()))
             }
+        }
+
+        Ok(Err(fault))
+    }
+
+    // return into a breakpoint placed where the tracee is stopped, restored right after
+    fn run_call_with_breakpoint(&self, regs: &Registers, func: usize, args: &[u64]) -> Result<Result<u64, CallFault>> {
+        let addr = regs.pc();
+        let original = self.peek(addr)?;
+
+        self.poke(addr, (original & !BREAKPOINT_MASK) | BREAKPOINT)?;
+        let res = self.run_call(regs, func, args, addr, addr + BREAKPOINT_PC_OFFSET);
+        self.poke(addr, original)?;
+
+        res
+    }
+
+    fn call(&self, regs: &Registers, func: usize, args: &[u64], return_addr: usize) -> Result<u64> {
+        self.call_until(regs, func, args, return_addr, return_addr)
+    }
 
-            regs.return_value()
+    // `expected_pc` is where the tracee stops once the call returned into `return_addr`
+    fn call_until(&self, regs: &Registers, func: usize, args: &[u64], return_addr: usize, expected_pc: usize) -> Result<u64> {
+        let retval: Result<u64> = try {
+            match self.run_call(regs, func, args, return_addr, expected_pc)? {
+                Ok(retval) => retval,
+                // the function is run again, only enabled for those who'd rather risk that than a failed injection
+                Err(fault) if self.options.breakpoint_retry => {
+                    warn!("[{}] wrong return address, retrying with a breakpoint: {fault}", self.pid);
+                    self.set_regs(regs)?;
+
+                    match self.run_call_with_breakpoint(regs, func, args)? {
+                        Ok(retval) => retval,
+                        Err(fault) => {
+                            error!("[{}] wrong return address with a breakpoint: {fault}", self.pid);
+                            bail!("wrong return address");
+                        }
+                    }
+                }
+                Err(fault) => {
+                    error!("[{}] wrong return address: {fault}", self.pid);
+                    bail!("wrong return address");
+                }
+            }
         };
 
         // restore regs
@@ -424,6 +899,13 @@ impl Drop for Tracee {
         if let Err(err) = ptrace::detach(self.pid, None) {
            error!("[{}] failed to detach: {}", self.pid, err);
         }
+
+        for tid in self.siblings.get_mut().drain(..) {
+            match ptrace::detach(tid, None) {
+                Ok(_) | Err(Errno::ESRCH) => (),
+                Err(err) => error!("[{}] failed to detach thread {tid}: {err}", self.pid)
+            }
+        }
     }
 }
 
@@ -516,10 +998,38 @@ impl<T : Into<Vec<u8>>> ToUnixString for T {
 }
 
 
+// file name of every mapped library, with its path and where its first mapping starts
+pub fn modules_of(maps: &[MemoryMap]) -> HashMap<String, (PathBuf, usize)> {
+    let mut modules = HashMap::new();
+
+    maps.iter().for_each(|map| {
+        if let MMapPath::Path(p) = &map.pathname {
+            if let Some(filename) = p.file_name() {
+                let filename = filename.to_string_lossy().into();
+
+                if modules.contains_key(&filename) {
+                    return
+                }
+
+                modules.insert(
+                    filename,
+                    (p.clone(), map.address.0 as usize)
+                );
+            }
+        }
+    });
+
+    modules
+}
+
 struct TraceeWrapper<'a> {
     tracee: &'a Tracee,
     maps: Vec<MemoryMap>,
-    modules: HashMap<String, (PathBuf, usize)>
+    modules: HashMap<String, (PathBuf, usize)>,
+    // page holding the breakpoint remote calls return into, with `breakpoint_completion`
+    scratch: Cell<Option<usize>>,
+    // mapping that buffers passed to remote calls are written to, as address and size
+    buffers: Cell<Option<(usize, usize)>>
 }
 
 impl<'a> TraceeWrapper<'a> {
@@ -527,7 +1037,9 @@ impl<'a> TraceeWrapper<'a> {
         let mut instance = Self {
             tracee,
             maps: Vec::new(),
-            modules: HashMap::new()
+            modules: HashMap::new(),
+            scratch: Cell::new(None),
+            buffers: Cell::new(None)
         };
 
         instance.update_maps()?;
@@ -545,23 +1057,7 @@ impl<'a> TraceeWrapper<'a> {
         self.maps = proc.maps()?.into_iter().collect();
         self.maps.sort_by_key(|map| map.address.0);
 
-        self.modules.clear();
-        self.maps.iter().for_each(|map| {
-            if let MMapPath::Path(p) = &map.pathname {
-                if let Some(filename) = p.file_name() {
-                    let filename = filename.to_string_lossy().into();
-
-                    if self.modules.contains_key(&filename) {
-                        return
-                    }
-
-                    self.modules.insert(
-                        filename,
-                        (p.clone(), map.address.0 as usize)
-                    );
-                }
-            }
-        });
+        self.modules = modules_of(&self.maps);
 
         Ok(())
     }
@@ -571,60 +1067,240 @@ impl<'a> TraceeWrapper<'a> {
 
         let tracee = self.tracee;
         let backup = tracee.regs()?;
+        let extra = tracee.extra_regs()?;
 
         let res: Result<u64> = try {
-            let mut regs = backup.clone();
+            let regs = backup.clone();
             let mut real_args = Vec::new();
 
+            // buffers are only used during the call, so each call starts over at the beginning of the mapping
+            let align = |len: usize| (len + 7) & !7;
+            let total = args.iter().map(|arg| if let Arg::Slice(data) = arg { align(data.len()) } else { 0 }).sum();
+            let mut buffer = if total > 0 { self.buffers(total)? } else { 0 };
+
             for arg in args {
                 real_args.push(match arg {
                     Arg::Numeric(arg) => *arg,
-                    Arg::Slice(data) => tracee.alloc(&mut regs, data.as_slice())? as u64
+                    Arg::Slice(data) => {
+                        let addr = buffer;
+                        tracee.write(addr, data)?;
+                        buffer += align(data.len());
+                        addr as u64
+                    }
                 });
             }
 
-            let return_addr = return_addr.unwrap_or(self.find_module("libc.so")?.1);
-            tracee.call(&regs, func, &real_args, return_addr)?
+            // an explicit return address is kept, the callee may care who called it
+            match return_addr {
+                Some(return_addr) => tracee.call(&regs, func, &real_args, return_addr)?,
+                None if tracee.options.breakpoint_completion => {
+                    let scratch = self.scratch()?;
+                    tracee.call_until(&regs, func, &real_args, scratch, scratch + BREAKPOINT_PC_OFFSET)?
+                }
+                None => tracee.call(&regs, func, &real_args, self.find_module("libc.so")?.1)?
+            }
         };
 
         tracee.set_regs(&backup)?;
+        tracee.set_extra_regs(&extra)?;
 
         res
     }
-    
-    fn read_string(&self, addr: usize) -> Result<String> {
-        let mut buffer: Vec<u8> = Vec::new();
-        let mut ptr = addr;
 
-        loop {
-            let end = self.tracee.peek(ptr)?
-                .to_le_bytes()
-                .iter()
-                .copied()
-                .any(|ch| {
-                    let skip = ch == 0;
-                    if !skip {
-                        buffer.push(ch);
-                    }
-                    skip
-                });
+    // calls to map and unmap the scratch page return into a breakpoint where the tracee is stopped instead
+    fn call_at_pc(&self, func: usize, args: &[u64]) -> Result<u64> {
+        let tracee = self.tracee;
+        let backup = tracee.regs()?;
+        let extra = tracee.extra_regs()?;
 
-            if end {
-                break
-            }
+        let res = tracee.run_call_with_breakpoint(&backup, func, args);
+        tracee.set_regs(&backup)?;
+        tracee.set_extra_regs(&extra)?;
 
-            ptr += 8;
+        match res? {
+            Ok(retval) => Ok(retval),
+            Err(fault) => bail!("[{}] breakpoint missed: {fault}", self.pid())
         }
+    }
 
-        Ok(String::from_utf8(buffer)?)
+    fn mmap(&self, len: usize, prot: i32) -> Result<usize> {
+        let mmap_addr = self.find_symbol_addr("libc.so", "mmap")?;
+        let addr = self.call_at_pc(mmap_addr, &[
+            0,
+            len as u64,
+            prot as u64,
+            (libc::MAP_PRIVATE | libc::MAP_ANONYMOUS) as u64,
+            -1i64 as u64,
+            0
+        ])? as usize;
+
+        if addr as *mut libc::c_void == libc::MAP_FAILED {
+            bail!("[{}] remote mmap of 0x{len:x} bytes failed", self.pid());
+        }
+
+        Ok(addr)
     }
 
-    //noinspection RsUnresolvedPath
-    fn read_jstring(&self, jnienv: usize, jstring: usize) -> Result<String> {
+    fn munmap(&self, addr: usize, len: usize) -> Result<()> {
+        let munmap_addr = self.find_symbol_addr("libc.so", "munmap")?;
+
+        if self.call_at_pc(munmap_addr, &[addr as u64, len as u64])? != 0 {
+            bail!("[{}] remote munmap at 0x{addr:x} failed", self.pid());
+        }
+
+        Ok(())
+    }
+
+    fn mprotect(&self, addr: usize, len: usize, prot: i32) -> Result<()> {
+        let mprotect_addr = self.find_symbol_addr("libc.so", "mprotect")?;
+
+        if self.call_at_pc(mprotect_addr, &[addr as u64, len as u64, prot as u64])? != 0 {
+            bail!("[{}] remote mprotect at 0x{addr:x} failed", self.pid());
+        }
+
+        Ok(())
+    }
+
+    // current protection of each mapping overlapping the pages of `range`, clipped to them
+    fn protections(&self, range: &Range<usize>) -> Result<Vec<(Range<usize>, i32)>> {
+        let begin = range.start & !(PAGE_SIZE - 1);
+        let end = range.end.next_multiple_of(PAGE_SIZE);
+
+        let mut regions: Vec<_> = Process::new(self.pid().as_raw())?.maps()?.into_iter()
+            .filter(|map| (map.address.0 as usize) < end && begin < map.address.1 as usize)
+            .map(|map| {
+                let prot = [
+                    (MMPermissions::READ, libc::PROT_READ),
+                    (MMPermissions::WRITE, libc::PROT_WRITE),
+                    (MMPermissions::EXECUTE, libc::PROT_EXEC)
+                ].into_iter().filter(|(perm, _)| map.perms.contains(*perm)).fold(0, |prot, (_, bit)| prot | bit);
+
+                ((map.address.0 as usize).max(begin) .. (map.address.1 as usize).min(end), prot)
+            })
+            .collect();
+
+        regions.sort_by_key(|(region, _)| region.start);
+
+        if regions.iter().map(|(region, _)| region.len()).sum::<usize>() != end - begin {
+            bail!("[{}] 0x{begin:x}-0x{end:x} is not entirely mapped", self.pid());
+        }
+
+        Ok(regions)
+    }
+
+    // make `range` writable for `func`, then put back and verify the protections it had
+    #[allow(dead_code)]
+    fn with_writable<T>(&self, range: Range<usize>, func: impl FnOnce(&Tracee) -> Result<T>) -> Result<T> {
+        let regions = self.protections(&range)?;
+        let changed: Vec<_> = regions.iter().filter(|(_, prot)| prot & libc::PROT_WRITE == 0).cloned().collect();
+
+        for (region, prot) in &changed {
+            self.mprotect(region.start, region.len(), prot | libc::PROT_WRITE)?;
+        }
+
+        let res = func(self.tracee);
+
+        for (region, prot) in &changed {
+            self.mprotect(region.start, region.len(), *prot)?;
+        }
+
+        // a region left writable behind would be easy to spot, mappings may have been split or merged meanwhile
+        let restored = self.protections(&range)?;
+        let mismatch = restored.iter().any(|(region, prot)| {
+            regions.iter().any(|(original, original_prot)| {
+                original.start < region.end && region.start < original.end && original_prot != prot
+            })
+        });
+
+        if mismatch {
+            bail!("[{}] protections of 0x{:x}-0x{:x} not restored", self.pid(), range.start, range.end);
+        }
+
+        res
+    }
+
+    fn scratch(&self) -> Result<usize> {
+        if let Some(scratch) = self.scratch.get() {
+            return Ok(scratch)
+        }
+
+        let page = self.mmap(SCRATCH_SIZE, libc::PROT_READ | libc::PROT_EXEC)?;
+
+        // ptrace writes through the missing write permission
+        self.tracee.poke(page, BREAKPOINT)?;
+        self.scratch.set(Some(page));
+
+        debug!("[{}] scratch page for remote calls at 0x{page:x}", self.pid());
+
+        Ok(page)
+    }
+
+    // writing below sp could clobber the red zone or run into the guard page, buffers get a mapping instead
+    fn buffers(&self, len: usize) -> Result<usize> {
+        match self.buffers.take() {
+            Some((addr, size)) if size >= len => {
+                self.buffers.set(Some((addr, size)));
+                return Ok(addr)
+            }
+            Some((addr, size)) => self.munmap(addr, size)?,
+            None => ()
+        }
+
+        let size = len.next_multiple_of(PAGE_SIZE).max(BUFFERS_SIZE);
+        let addr = self.mmap(size, libc::PROT_READ | libc::PROT_WRITE)?;
+        self.buffers.set(Some((addr, size)));
+
+        debug!("[{}] buffers for remote calls at 0x{addr:x}, 0x{size:x} bytes", self.pid());
+
+        Ok(addr)
+    }
+    
+    fn read_string(&self, addr: usize) -> Result<String> {
+        if addr == 0 {
+            bail!("[{}] null string pointer", self.pid());
+        }
+
+        let mut buffer: Vec<u8> = Vec::new();
+        let mut ptr = addr;
+        let start = Instant::now();
+
+        loop {
+            if buffer.len() >= MAX_STRING_LENGTH {
+                bail!("[{}] string at 0x{addr:x} is longer than {MAX_STRING_LENGTH} bytes", self.pid());
+            }
+
+            if start.elapsed() > STRING_READ_TIMEOUT {
+                bail!("[{}] reading string at 0x{addr:x} timed out after {} bytes", self.pid(), buffer.len());
+            }
+
+            let chunk = self.tracee.read(ptr, STRING_CHUNK - ptr % STRING_CHUNK)?;
+
+            match chunk.iter().position(|ch| *ch == 0) {
+                Some(end) => {
+                    buffer.extend_from_slice(&chunk[.. end]);
+                    break
+                }
+                None => buffer.extend_from_slice(&chunk)
+            }
+
+            ptr += chunk.len();
+        }
+
+        Ok(String::from_utf8(buffer)?)
+    }
+
+    //noinspection RsUnresolvedPath
+    fn read_jstring(&self, jnienv: usize, jstring: usize) -> Result<String> {
         let tracee = self.tracee;
         let functions = tracee.peek(jnienv)? as usize;
-        let alloc = tracee.peek(functions + mem::offset_of!(JNINativeInterface__1_6, GetStringUTFChars))? as usize;
-        let release = tracee.peek(functions + mem::offset_of!(JNINativeInterface__1_6, ReleaseStringUTFChars))? as usize;
+
+        // both from a single read of the function table
+        let alloc_offset = mem::offset_of!(JNINativeInterface__1_6, GetStringUTFChars);
+        let release_offset = mem::offset_of!(JNINativeInterface__1_6, ReleaseStringUTFChars);
+        let table = tracee.read_words(functions + alloc_offset, (release_offset - alloc_offset) / 8 + 1)?;
+
+        let alloc = table[0] as usize;
+        let release = table[table.len() - 1] as usize;
 
         let ptr = self.call(alloc, args!(jnienv, jstring, 0u64), None)? as usize;
         let result = self.read_string(ptr);
@@ -640,69 +1316,167 @@ impl<'a> TraceeWrapper<'a> {
 
     fn find_symbol_addr(&self, lib: &str, func: &str) -> Result<usize> {
         let (lib, base) = self.find_module(lib)?;
-        let offset = symbols::resolve(lib, func)?;  // Todo: cache results?
+        let offset = cache::resolve(lib, func)?;
 
         Ok(base + offset)
     }
 }
 
-// return true to inject, or false to skip
-fn check_process(wrapper: &TraceeWrapper, args: &[u64], filter: Option<&FilterFn>) -> Result<bool> {
-    let args = SpecializeArgs::from(args.as_ptr() as *mut _);
+// the scratch page would give injection away if left behind
+impl Drop for TraceeWrapper<'_> {
+    fn drop(&mut self) {
+        let mappings = [self.scratch.take().map(|page| (page, SCRATCH_SIZE)), self.buffers.take()];
 
-    let jnienv = unsafe { *(args.env as *const usize) };
-    let process_name = unsafe { *(args.managed_nice_name as *const usize) };
-    let app_data_dir = unsafe { *(args.managed_app_data_dir as *const usize) };
+        for (addr, size) in mappings.into_iter().flatten() {
+            if let Err(err) = self.munmap(addr, size) {
+                warn!("[{}] failed to unmap scratch memory at 0x{addr:x}: {err}", self.pid());
+            }
+        }
+    }
+}
 
-    let uid = unsafe { *(args.uid as *const libc::uid_t) };
-    debug!("[{}] uid={uid}", wrapper.pid());
+#[derive(Debug, Default)]
+struct ProcessSnapshot {
+    uid: libc::uid_t,
+    package: Option<String>,
+    name: Option<String>,
+    instruction_set: Option<String>,
+    native_bridge: bool
+}
 
-    let package_name: Option<String> = if app_data_dir != 0 {
-        let dir = wrapper.read_jstring(jnienv, app_data_dir)?;
-        if let Some(index) = dir.rfind('/') {
-            let package_name = &dir[index + 1 ..];
-            Some(package_name.into())
-        } else {
-            None
-        }
-    } else {
-        None
-    };
-    debug!("[{}] package_name={package_name:?}", wrapper.pid());
+impl ProcessSnapshot {
+    fn read(wrapper: &TraceeWrapper, args: &[u64], layout: ArgLayout) -> Result<Self> {
+        // a copy of the args, values are addresses in the target and only read from it
+        let args = unsafe { SpecializeArgs::with_layout(args.as_ptr() as *mut _, args.len(), layout.or_sdk()) };
 
-    let process_name: Option<String> = if process_name != 0 {
-        let name = wrapper.read_jstring(jnienv, process_name)?;
-        Some(name)
-    } else {
-        None
-    };
-    debug!("[{}] process_name={package_name:?}", wrapper.pid());
-    
-    if let Some(filter) = filter {
-        let pkg = package_name.as_ref().map(|pkg| CString::new(pkg.clone()).unwrap());
-        let pkg = match &pkg {
-            None => ptr::null(),
-            Some(pkg) => pkg.as_ptr()
-        };
-        
-        let name = process_name.map(|name| CString::new(name.clone()).unwrap());
-        let name = match &name {
-            None => ptr::null(),
-            Some(name) => name.as_ptr()
+        let jnienv = args.env()? as usize;
+        let read_jstring = |jstring: jni_sys::jstring| -> Result<Option<String>> {
+            if jstring.is_null() {
+                return Ok(None)
+            }
+
+            wrapper.read_jstring(jnienv, jstring as usize).map(Some)
         };
-        
-        return if filter(uid, pkg, name) {
-            Ok(true)
-        } else {
-            Ok(false) 
+
+        let uid = args.uid()? as libc::uid_t;
+        debug!("[{}] uid={uid}", wrapper.pid());
+        context::set_uid(uid);
+
+        let package = read_jstring(args.managed_app_data_dir()?)?
+            .and_then(|dir| dir.rfind('/').map(|index| dir[index + 1 ..].to_string()));
+        debug!("[{}] package_name={package:?}", wrapper.pid());
+
+        // passed on to filters and used in file names of reports, garbage here means the args are off
+        if let Some(package) = package.as_deref().filter(|package| !is_package_name(package)) {
+            bail!("[{}] implausible package name {package:?}, args may be misread", wrapper.pid());
+        }
+
+        if let Some(package) = &package {
+            Span::current().record("package", package.as_str());
+            context::set_package(package);
+        }
+
+        let name = read_jstring(args.managed_nice_name()?)?;
+        debug!("[{}] process_name={name:?}", wrapper.pid());
+
+        let instruction_set = read_jstring(args.managed_instruction_set()?)?;
+
+        if let Some(string) = [&name, &instruction_set].into_iter().flatten().find(|string| !is_printable(string)) {
+            bail!("[{}] implausible string {string:?} in args, args may be misread", wrapper.pid());
+        }
+
+        let native_bridge = process_info::is_native_bridge(instruction_set.as_deref());
+        debug!("[{}] instruction_set={instruction_set:?}, native_bridge={native_bridge}", wrapper.pid());
+
+        Ok(Self { uid, package, name, instruction_set, native_bridge })
+    }
+
+    fn check(&self, filter: &Filter) -> bool {
+        let to_cstring = |str: &Option<String>| str.as_ref().map(|str| CString::new(str.as_str()).unwrap());
+        let as_ptr = |str: &Option<CString>| str.as_ref().map_or(ptr::null(), |str| str.as_ptr());
+
+        let package = to_cstring(&self.package);
+        let name = to_cstring(&self.name);
+        let instruction_set = to_cstring(&self.instruction_set);
+        let component = to_cstring(&self.name.as_deref().and_then(process_info::component).map(String::from));
+
+        match filter {
+            Filter::Simple(filter) => filter(self.uid, as_ptr(&package), as_ptr(&name)),
+            Filter::Snapshot(filter) => {
+                let info = ProcessInfo {
+                    uid: self.uid,
+                    package: as_ptr(&package),
+                    name: as_ptr(&name),
+                    instruction_set: as_ptr(&instruction_set),
+                    native_bridge: self.native_bridge,
+                    component: as_ptr(&component)
+                };
+
+                filter(&info)
+            }
         }
     }
+}
 
-    Ok(true)
+// letters, digits, `_` and `.`, as allowed by PackageParser, with some room for what vendors make of it
+fn is_package_name(package: &str) -> bool {
+    !package.is_empty() && package.chars().all(|ch| ch.is_ascii_alphanumeric() || matches!(ch, '_' | '.' | '-'))
 }
 
-// dlopen api bridge, and return address of pre & post specialize hook
-fn remote_dlopen(wrapper: &mut TraceeWrapper, bridge: &str) -> Result<()> {
+fn is_printable(string: &str) -> bool {
+    !string.chars().any(char::is_control)
+}
+
+// return true to inject, or false to skip, along with the uid and package name
+fn check_process(wrapper: &TraceeWrapper, args: &[u64], config: &BridgeConfig) -> Result<(bool, libc::uid_t, Option<String>)> {
+    let snapshot = ProcessSnapshot::read(wrapper, args, config.args_layout)?;
+
+    if snapshot.native_bridge && config.native_bridge == NativeBridgePolicy::Skip {
+        debug!("[{}] runs through native bridge, skipped by policy", wrapper.pid());
+        return Ok((false, snapshot.uid, snapshot.package))
+    }
+
+    let blocked = snapshot.name.as_deref().and_then(|name| {
+        config.blocked_processes.iter().find(|pattern| process_info::glob_match(pattern, name))
+    });
+
+    if let Some(pattern) = blocked {
+        debug!("[{}] process name matches `{pattern}`, skipped", wrapper.pid());
+        return Ok((false, snapshot.uid, snapshot.package))
+    }
+
+    if denylist::is_denied(snapshot.uid, snapshot.package.as_deref()) {
+        debug!("[{}] on the denylist, skipped", wrapper.pid());
+        return Ok((false, snapshot.uid, snapshot.package))
+    }
+
+    let inject = match &config.filter_fn {
+        Some(filter) => snapshot.check(filter),
+        None => true
+    };
+
+    Ok((inject, snapshot.uid, snapshot.package))
+}
+
+fn process_category(args: &[u64], layout: ArgLayout) -> ProcessCategory {
+    let args = unsafe { SpecializeArgs::with_layout(args.as_ptr() as *mut _, args.len(), layout.or_sdk()) };
+
+    // only unreadable with a layout that doesn't fit, which fails reading the snapshot later anyway
+    let is_system_server = args.is_system_server().unwrap_or(false);
+    let is_child_zygote = args.is_child_zygote().unwrap_or(false);
+
+    if is_system_server {
+        ProcessCategory::SystemServer
+    } else if is_child_zygote {
+        ProcessCategory::ChildZygote
+    } else {
+        ProcessCategory::App
+    }
+}
+
+// dlopen api bridge, return the ranges it newly mapped
+#[instrument(skip(wrapper))]
+fn remote_dlopen(wrapper: &mut TraceeWrapper, bridge: &str) -> Result<Vec<(usize, usize)>> {
     debug!("remote dlopen: {bridge}");
     
     let libc_base = wrapper.find_module("libc.so")?.1;
@@ -710,108 +1484,320 @@ fn remote_dlopen(wrapper: &mut TraceeWrapper, bridge: &str) -> Result<()> {
     let dlopen_addr = wrapper.find_symbol_addr("libdl.so", "dlopen")?;
     let dlerror_addr = wrapper.find_symbol_addr("libdl.so", "dlerror")?;
 
-    fn dlerror(wrapper: &TraceeWrapper, func: usize) -> Result<()> {
+    fn dlerror(wrapper: &TraceeWrapper, func: usize, bridge: &str) -> Result<()> {
         let error = wrapper.call(func, &[], None)?;
         let error = wrapper.read_string(error as _)?;
 
-        Err(anyhow!(error))
+        let notes = linker_diagnostics(wrapper, bridge);
+        if notes.is_empty() {
+            return Err(anyhow!(error))
+        }
+
+        Err(anyhow!("{error}\n{}", notes.join("\n")))
     }
 
+    let mapped_before: HashSet<_> = wrapper.maps.iter().map(|map| map.address).collect();
     let handle = wrapper.call(dlopen_addr, args!(bridge.unix(), libc::RTLD_LAZY), Some(libc_base))?;
 
     if handle == 0 {
-        dlerror(wrapper, dlerror_addr)?;
+        dlerror(wrapper, dlerror_addr, bridge)?;
     }
 
     // update maps after dlopen
     wrapper.update_maps()?;
 
+    // the bridge along with whatever it depends on that wasn't loaded yet
+    let loaded: Vec<_> = wrapper.maps.iter()
+        .filter(|map| matches!(map.pathname, MMapPath::Path(_)) && !mapped_before.contains(&map.address))
+        .map(|map| (map.address.0 as usize, map.address.1 as usize))
+        .collect();
+
+    Ok(loaded)
+}
+
+fn check_abi(wrapper: &TraceeWrapper, library: &str) -> Result<()> {
+    let abi = wrapper.find_symbol_addr(library, "ZLB_ABI")
+        .context(format!("{library} predates the abi handshake, reinstall the module"))?;
+    let abi = wrapper.tracee.peek(abi)?;
+
+    if abi >> 32 != common::BRIDGE_ABI_MAGIC as u64 {
+        bail!("{library} exports a malformed abi: 0x{abi:x}");
+    }
+
+    if abi as u32 != common::BRIDGE_ABI_VERSION {
+        bail!(
+            "abi of {library} is {}, but the loader speaks {}, the module may be partially updated",
+            abi as u32, common::BRIDGE_ABI_VERSION
+        );
+    }
+
     Ok(())
 }
 
-fn unmap_uprobes(wrapper: &TraceeWrapper) -> Result<()> {
-    let uprobes_range = wrapper.maps.iter().find_map(|map| {
-        if map.pathname == MMapPath::Other("uprobes".into()) {
-            Some(map.address)
-        } else {
-            None
+// fill in what the bridge can't find out by itself, before any callback
+fn share_config(wrapper: &TraceeWrapper, bridge: &str, loaded: &[(usize, usize)]) -> Result<()> {
+    let tracee = wrapper.tracee;
+    let library = library_name(bridge);
+
+    check_abi(wrapper, &library)?;
+
+    // the bridge loads its modules from there
+    let staging_dir = wrapper.find_symbol_addr(&library, "ZLB_STAGING_DIR")?;
+    let mut data = rootimpl::staging_dir().as_os_str().as_bytes().to_vec();
+
+    if data.len() >= ZLB_STAGING_DIR_SIZE {
+        bail!("staging dir too long for the bridge: {}", rootimpl::staging_dir().display());
+    }
+
+    data.push(0);
+    tracee.write(staging_dir, &data)?;
+
+    // for relocation math of modules
+    let base = wrapper.find_module(&library)?.1;
+    tracee.poke(wrapper.find_symbol_addr(&library, "ZLB_BASE")?, base as u64)?;
+
+    if loaded.len() > ZLB_MAX_RANGES {
+        warn!("[{}] {} ranges mapped by dlopen of {library}, only {ZLB_MAX_RANGES} are shared", wrapper.pid(), loaded.len());
+    }
+
+    let loaded = &loaded[.. loaded.len().min(ZLB_MAX_RANGES)];
+    let data: Vec<u8> = loaded.iter().flat_map(|(begin, end)| [*begin as u64, *end as u64]).flat_map(u64::to_ne_bytes).collect();
+
+    tracee.write(wrapper.find_symbol_addr(&library, "ZLB_RANGES")?, &data)?;
+    tracee.poke(wrapper.find_symbol_addr(&library, "ZLB_RANGES_LEN")?, loaded.len() as u64)?;
+
+    debug!("[{}] {library} loaded at 0x{base:x}, {} new ranges", wrapper.pid(), loaded.len());
+
+    Ok(())
+}
+
+// collect what usually explains a failed dlopen: linker environment, linker namespaces and missing dependencies
+fn linker_diagnostics(wrapper: &TraceeWrapper, bridge: &str) -> Vec<String> {
+    let mut notes = Vec::new();
+
+    let environ = fs::read(format!("/proc/{}/environ", wrapper.pid())).unwrap_or_default();
+    let linker_env: Vec<_> = environ.split(|ch| *ch == 0)
+        .map(String::from_utf8_lossy)
+        .filter(|var| var.starts_with("LD_"))
+        .collect();
+
+    if !linker_env.is_empty() {
+        notes.push(format!("linker environment: {}", linker_env.join(" ")));
+    }
+
+    // only available since Android 10
+    match wrapper.find_symbol_addr("libdl.so", "android_get_exported_namespace") {
+        Ok(func) => {
+            let namespaces: Vec<_> = LINKER_NAMESPACES.iter()
+                .filter(|&&name| wrapper.call(func, args!(name.unix()), None).is_ok_and(|ns| ns != 0))
+                .collect();
+
+            notes.push(format!("exported linker namespaces: {namespaces:?}"));
+        }
+        Err(_) => notes.push("exported linker namespaces: unknown".into())
+    }
+
+    let needed = match symbols::needed_libraries(bridge) {
+        Ok(needed) => needed,
+        Err(err) => {
+            notes.push(format!("failed to read dependencies of {bridge}: {err}"));
+            return notes
+        }
+    };
+
+    let bridge_dir = Path::new(bridge).parent().unwrap_or(Path::new("/"));
+
+    let missing: Vec<_> = needed.iter()
+        .filter(|lib| !wrapper.modules.contains_key(lib.as_str()))
+        .filter(|lib| {
+            !LIBRARY_SEARCH_PATHS.iter().map(Path::new).chain([bridge_dir])
+                .any(|dir| dir.join(lib).exists())
+        })
+        .collect();
+
+    if !missing.is_empty() {
+        notes.push(format!("dependencies neither loaded nor found: {missing:?}"));
+    }
+
+    notes
+}
+
+fn uprobes_regions(maps: &[MemoryMap]) -> Vec<(u64, u64)> {
+    maps.iter()
+        .filter(|map| map.pathname == MMapPath::Other("uprobes".into()))
+        .map(|map| map.address)
+        .collect()
+}
+
+// a leftover `[uprobes]` mapping gives injection away, so check maps again until none is left
+#[instrument(skip_all)]
+fn unmap_uprobes(wrapper: &mut TraceeWrapper) -> Result<()> {
+    let pid = wrapper.pid();
+
+    for _ in 0 .. UNMAP_UPROBES_ATTEMPTS {
+        let regions = uprobes_regions(&wrapper.maps);
+
+        if regions.is_empty() {
+            return Ok(())
         }
-    });
 
-    if let Some((begin, end)) = uprobes_range {
         let munmap_addr = wrapper.find_symbol_addr("libc.so", "munmap")?;
-        let res = wrapper.call(munmap_addr, args!(begin, end - begin), None)?;
-        
-        if res == 0 {
-            debug!("[{}] unmapped uprobes: {begin:x}-{end:x}", wrapper.pid());
-        } else {
-            error!("[{}] failed to unmap uprobes: {begin:x}-{end:x}", wrapper.pid());
+
+        for (begin, end) in regions {
+            let res = wrapper.call(munmap_addr, args!(begin, end - begin), None)?;
+
+            if res == 0 {
+                debug!("[{pid}] unmapped uprobes: {begin:x}-{end:x}");
+            } else {
+                warn!("[{pid}] failed to unmap uprobes: {begin:x}-{end:x}");
+            }
         }
+
+        wrapper.update_maps()?;
     }
-    
+
+    let leftover = uprobes_regions(&wrapper.maps);
+
+    if !leftover.is_empty() {
+        let regions: Vec<_> = leftover.iter().map(|(begin, end)| format!("{begin:x}-{end:x}")).collect();
+        let message = format!("[uprobes] still mapped after {UNMAP_UPROBES_ATTEMPTS} attempts: {}", regions.join(" "));
+
+        error!("[{pid}] {message}");
+        audit::record(pid.as_raw(), &message);
+    }
+
     Ok(())
 }
 
-fn load_bridge(tracee: &Tracee, config: &BridgeConfig) -> Result<()> {
-    let mut regs = tracee.regs()?;
+// run the probed instruction again on resume, so that it sees the replaced return address
+fn revert_prologue(regs: &mut Registers, prologue: &prologue::Prologue) {
+    regs.set_pc(regs.pc() - prologue.len);
+    regs.set_sp(regs.sp() + prologue.stack);
+}
+
+// `attempt` is set to the uid and package once injection is actually attempted
+fn load_bridge(tracee: &Tracee, config: &BridgeConfig, attempt: &mut Option<(libc::uid_t, Option<String>)>) -> Result<()> {
+    let probe_regs = tracee.regs()?;
+    let mut regs = probe_regs.clone();
+
+    // compilers differ in what SpecializeCommon starts with: CET and BTI landing pads, PAC or a plain prologue
+    let prologue = prologue::decode(tracee.peek(probe_regs.pc() - 8)?)
+        .context(format!("[{}] failed to decode the probed instruction", tracee.pid))?;
+
+    debug!("[{}] probed instruction: `{}`", tracee.pid, prologue.name);
 
     if cfg!(target_arch = "x86_64") {
-        // revert `push %rbp`
-        regs.set_pc(regs.pc() - 1);   
-        regs.set_sp(regs.sp() + 8);  
+        revert_prologue(&mut regs, &prologue);
+    }
+
+    // the uretprobe shares the probed instruction, re-executing it would hijack the return address twice
+    if !config.uretprobe {
+        tracee.set_regs(&regs)?;
     }
-    
-    tracee.set_regs(&regs)?;
 
     // check process
     let mut wrapper = TraceeWrapper::new(tracee)?;
-    
-    unmap_uprobes(&wrapper)?;
+
+    // the uretprobe returns through `[uprobes]`, unmap it after specialize instead
+    if !config.uretprobe {
+        unmap_uprobes(&mut wrapper)?;
+    }
 
     // retrieve args
-    let mut args = Vec::new();
+    context::set_stage("check");
+    let args = tracee.args(&regs, config.args_count)?;
+    
+    let category = process_category(&args, config.args_layout);
 
-    for i in 0 .. config.args_count {
-        args.push(tracee.arg(&regs, i)?);
+    if category == ProcessCategory::ChildZygote {
+        config.child_zygotes.promote(tracee.pid.as_raw())?;
     }
-    
-    if !check_process(&wrapper, &args, config.filter_fn.as_ref())? {
+
+    let (inject, uid, package_name) = debug_span!("check_process").in_scope(|| {
+        check_process(&wrapper, &args, config)
+    })?;
+
+    let report = package_name.as_deref().and_then(|package| config.verbose.take(package, tracee.pid.as_raw()));
+
+    if let Some(report) = &report {
+        report.write("args.txt", &format_args_dump(&args));
+        report.write_maps("maps-before.txt", &wrapper.maps);
+    }
+
+    if !inject {
         debug!("[{}] skipped.", tracee.pid);
+
+        if let Some(report) = &report {
+            report.write("result.txt", "skipped by filter\n");
+        }
+
+        return Ok(())
+    }
+
+    let bridges = match config.bridges.get(&category).filter(|bridges| !bridges.is_empty()) {
+        Some(bridges) => bridges,
+        None => {
+            debug!("[{}] no bridge for {category}, skipped.", tracee.pid);
+
+            if let Some(report) = &report {
+                report.write("result.txt", &format!("no bridge for {category}\n"));
+            }
+
+            return Ok(())
+        }
+    };
+
+    if package_name.as_deref().is_some_and(|package| config.deferred.defer(tracee.pid.as_raw(), package, bridges)) {
+        debug!("[{}] deferred until it comes to foreground", tracee.pid);
+
+        if let Some(report) = &report {
+            report.write("result.txt", "deferred\n");
+        }
+
         return Ok(())
     }
     
-    if cfg!(target_arch = "aarch64") {
-        // revert `paciasp`
-        regs.set_pc(regs.pc() - 4);
+    if cfg!(target_arch = "aarch64") && !config.uretprobe {
+        revert_prologue(&mut regs, &prologue);
     }
     
-    tracee.set_regs(&regs)?;
-
-    let args_data = unsafe {
-        std::slice::from_raw_parts(args.as_ptr() as *const u8, args.len() * 8)
-    };
+    if !config.uretprobe {
+        tracee.set_regs(&regs)?;
+    }
 
     // do inject
     debug!("[{}] injecting...", tracee.pid);
+    *attempt = Some((uid, package_name.clone()));
 
-    remote_dlopen(&mut wrapper, &config.library)?;
+    if args.len() > ZLB_MAX_ARGS {
+        bail!("[{}] too many args for bridge: {}", tracee.pid, args.len());
+    }
 
-    let library = PathBuf::from(&config.library);
-    let library = library.file_name().unwrap().to_str().unwrap();
+    context::set_stage("dlopen");
+    let plan = InjectionPlan::new(bridges, &config.payloads, package_name.as_deref());
+    let libraries = plan.execute(&mut wrapper, report.as_ref())?;
 
-    let callback_before = wrapper.find_symbol_addr(library, "ZLB_CALLBACK_PRE")?;
-    let callback_before = tracee.peek(callback_before)? as usize;
+    config.latency.record_now(tracee.pid.as_raw(), Stage::DlopenDone);
 
-    let trampoline = wrapper.find_symbol_addr(library, "ZLB_TRAMPOLINE")?;
-    let trampoline = tracee.peek(trampoline)? as usize;
+    if let Some(report) = &report {
+        report.write_maps("maps-after.txt", &wrapper.maps);
+    }
 
-    let real_return_addr = wrapper.find_symbol_addr(library, "ZLB_RETURN_ADDRESS")?;
-    tracee.poke(real_return_addr, config.return_addr as u64)?;
+    // bridges see the args as altered by those before them
+    context::set_stage("pre_specialize");
+    let mut args = args;
 
-    // call pre specialize hook
-    wrapper.call(callback_before, args!(args_data, args.len()), None)?;
+    for library in &libraries {
+        args = call_pre_specialize(&wrapper, library, &args, config.args_layout)?;
+    }
+
+    if let Some(report) = &report {
+        report.write("args-after-pre.txt", &format_args_dump(&args));
+    }
 
     // skip return address (*)
+    context::set_stage("resume");
+
     if cfg!(target_arch = "x86_64") {
         regs.set_sp(regs.sp() + 0x8);
     }
@@ -826,25 +1812,393 @@ fn load_bridge(tracee: &Tracee, config: &BridgeConfig) -> Result<()> {
         regs.set_sp(regs.sp() - 0x8);
     }
 
+    let resume_regs = if config.uretprobe {
+        // resume right after the probed instruction, post specialize hook is called on uretprobe
+        let mut resume_regs = regs.clone();
+        resume_regs.set_pc(probe_regs.pc());
+        resume_regs.set_sp(probe_regs.sp());
+        resume_regs
+    } else {
+        // SpecializeCommon returns into the trampoline of the first bridge, each trampoline
+        // returns into the one of the next bridge, and the last one to the real caller
+        let mut return_addr = config.return_addr;
+
+        for library in libraries.iter().rev() {
+            let real_return_addr = wrapper.find_symbol_addr(library, "ZLB_RETURN_ADDRESS")?;
+            tracee.poke(real_return_addr, return_addr as u64)?;
+
+            let trampoline = wrapper.find_symbol_addr(library, "ZLB_TRAMPOLINE")?;
+            return_addr = tracee.peek(trampoline)? as usize;
+        }
+
+        tracee.set_return_addr(&mut regs, return_addr, false)?;
+        regs.clone()
+    };
+
     // call SpecializeCommon
     debug!("[{}] resuming to SpecializeCommon...", tracee.pid);
-    tracee.set_return_addr(&mut regs, trampoline, false)?;
-    tracee.set_regs(&regs)?;
+    tracee.set_regs(&resume_regs)?;
+
+    if config.trace.strict {
+        tracee.verify_regs(&resume_regs)?;
+
+        // register args are read back from the tracee, stack ones from where they were written
+        let mut actual_regs = tracee.regs()?;
+        actual_regs.set_sp(regs.sp());
+
+        let actual_args = tracee.args(&actual_regs, args.len())?;
+
+        for (i, (arg, actual)) in args.iter().copied().zip(actual_args).enumerate() {
+            if actual != arg {
+                bail!("[{}] arg{i} verification failed: expected 0x{arg:x}, got 0x{actual:x}", tracee.pid);
+            }
+        }
+
+        debug!("[{}] all writes verified", tracee.pid);
+    }
+
+    // the critical window: from the resume point into SpecializeCommon
+    if let Some(report) = &report {
+        report.write("steps.txt", &tracee.step_trace(VERBOSE_STEPS, &wrapper.maps)?.join("\n"));
+        report.write("result.txt", "injected\n");
+    }
+
+    config.status.injected();
 
     Ok(())
 }
 
+// one library of an injection
+struct PlanStep<'a> {
+    library: &'a str,
+    // bridges get the shared config and specialize hooks, payloads only their entry
+    bridge: bool,
+    entry: Option<&'a str>,
+    required: bool,
+    after: &'a [String]
+}
+
+// what gets loaded into a process: the bridges, then the payloads for its package in dependency order
+struct InjectionPlan<'a> {
+    steps: Vec<PlanStep<'a>>
+}
+
+impl<'a> InjectionPlan<'a> {
+    fn new(bridges: &'a [String], payloads: &'a [Payload], package: Option<&str>) -> Self {
+        let bridges = bridges.iter().map(|bridge| PlanStep {
+            library: bridge,
+            bridge: true,
+            entry: None,
+            required: true,
+            after: &[]
+        });
+
+        // already sorted when the config is loaded
+        let payloads = payloads.iter().filter(|payload| payload.applies_to(package)).map(|payload| PlanStep {
+            library: &payload.library,
+            bridge: false,
+            entry: payload.entry.as_deref(),
+            required: payload.required,
+            after: &payload.after
+        });
+
+        Self { steps: bridges.chain(payloads).collect() }
+    }
+
+    fn run_step(wrapper: &mut TraceeWrapper, step: &PlanStep) -> Result<()> {
+        let loaded = remote_dlopen(wrapper, step.library)?;
+
+        if step.bridge {
+            share_config(wrapper, step.library, &loaded)?;
+        }
+
+        if let Some(entry) = step.entry {
+            let entry = wrapper.find_symbol_addr(&library_name(step.library), entry)?;
+            debug_span!("payload_entry", library = step.library).in_scope(|| wrapper.call(entry, &[], None))?;
+        }
+
+        Ok(())
+    }
+
+    // a payload that fails is left out along with whatever depends on it, unless it is required,
+    // return file names of the bridges loaded
+    fn execute(&self, wrapper: &mut TraceeWrapper, report: Option<&Report>) -> Result<Vec<String>> {
+        let mut bridges = Vec::new();
+        let mut loaded = HashSet::new();
+        let mut skipped = String::new();
+
+        for step in &self.steps {
+            let name = library_name(step.library);
+
+            let result = match step.after.iter().find(|after| !loaded.contains(after.as_str())) {
+                Some(after) => Err(anyhow!("{after} is not loaded")),
+                None => Self::run_step(wrapper, step)
+            };
+
+            match result {
+                Ok(()) if step.bridge => bridges.push(name),
+                Ok(()) => {
+                    debug!("[{}] payload {name} loaded", wrapper.pid());
+                    loaded.insert(name);
+                }
+                Err(err) if step.required => return Err(err.context(format!("failed to load {}", step.library))),
+                Err(err) => {
+                    warn!("[{}] payload {} skipped: {err:#}", wrapper.pid(), step.library);
+                    skipped += &format!("{}: {err:#}\n", step.library);
+                }
+            }
+        }
+
+        if let Some(report) = report.filter(|_| !skipped.is_empty()) {
+            report.write("payloads-skipped.txt", &skipped);
+        }
+
+        Ok(bridges)
+    }
+}
 
+fn library_name(bridge: &str) -> String {
+    Path::new(bridge).file_name().map_or(bridge.into(), |name| name.to_string_lossy().into())
+}
+
+// args are shared with the bridge for both pre and post specialize, and may be altered by modules
+fn call_pre_specialize(wrapper: &TraceeWrapper, library: &str, args: &[u64], layout: ArgLayout) -> Result<Vec<u64>> {
+    let tracee = wrapper.tracee;
+
+    let callback_before = wrapper.find_symbol_addr(library, "ZLB_CALLBACK_PRE")?;
+    let callback_before = tracee.peek(callback_before)? as usize;
+
+    let shared_args = wrapper.find_symbol_addr(library, "ZLB_ARGS")?;
+    let shared_args_len = wrapper.find_symbol_addr(library, "ZLB_ARGS_LEN")?;
+    let shared_args_layout = wrapper.find_symbol_addr(library, "ZLB_ARGS_LAYOUT")?;
+
+    let args_data = unsafe {
+        std::slice::from_raw_parts(args.as_ptr() as *const u8, args.len() * 8)
+    };
+
+    let layout_data = unsafe {
+        std::slice::from_raw_parts(&layout as *const ArgLayout as *const u8, mem::size_of::<ArgLayout>())
+    };
+
+    tracee.write(shared_args, args_data)?;
+    tracee.poke(shared_args_len, args.len() as u64)?;
+    // left unknown when the signature couldn't be parsed, the bridge assumes the layout of the SDK then
+    tracee.write(shared_args_layout, layout_data)?;
+
+    debug_span!("pre_specialize", library).in_scope(|| wrapper.call(callback_before, &[], None))?;
+
+    tracee.read_words(shared_args, args.len())
+}
+
+fn call_post_specialize(wrapper: &TraceeWrapper, library: &str) -> Result<()> {
+    let callback_after = wrapper.find_symbol_addr(library, "ZLB_CALLBACK_POST")?;
+    let callback_after = wrapper.tracee.peek(callback_after)? as usize;
+
+    debug!("[{}] calling post specialize hook of {library}...", wrapper.pid());
+    debug_span!("post_specialize", library).in_scope(|| wrapper.call(callback_after, &[], None))?;
+
+    Ok(())
+}
+
+fn format_args_dump(args: &[u64]) -> String {
+    args.iter().enumerate().map(|(i, arg)| format!("arg{i} = 0x{arg:x}\n")).collect()
+}
+
+
+// called on uretprobe of SpecializeCommon, only used when the trampoline is disabled
+#[instrument(name = "post_inject", skip(config))]
+pub fn handle_post_specialize(pid: i32, config: &BridgeConfig) -> Result<()> {
+    let _thaw = thaw_for_injection(pid);
+    let tracee = Tracee::new(pid, config.trace);
+    tracee.attach()?;
+
+    context::set_stage("unmap");
+    let mut wrapper = TraceeWrapper::new(&tracee)?;
+    unmap_uprobes(&mut wrapper)?;
+
+    // the category isn't known any more, the bridges loaded are whichever of its list made it into maps
+    let mut libraries: Vec<String> = Vec::new();
+
+    for bridge in config.bridges.values().flatten() {
+        let library = library_name(bridge);
+
+        if wrapper.modules.contains_key(&library) && !libraries.contains(&library) {
+            libraries.push(library);
+        }
+    }
+
+    if libraries.is_empty() {
+        debug!("[{pid}] bridge not loaded, nothing to do after specialize");
+        return Ok(())
+    }
+
+    context::set_stage("post_specialize");
+
+    for library in &libraries {
+        call_post_specialize(&wrapper, library)?;
+    }
+
+    Ok(())
+}
+
+// where a function at a file offset of the library is mapped in the process
+pub fn entry_addr(maps: &[MemoryMap], library: &str, offset: u64) -> Option<usize> {
+    maps.iter()
+        .filter(|map| map.pathname == MMapPath::Path(library.into()) && map.perms.contains(MMPermissions::EXECUTE))
+        .find(|map| (map.offset .. map.offset + (map.address.1 - map.address.0)).contains(&offset))
+        .map(|map| (map.address.0 + offset - map.offset) as usize)
+}
+
+// stop a child at the entry of SpecializeCommon by breakpoints in its own memory instead of uprobes, so the kernel
+// maps no `[uprobes]` into it; the child is left as a uprobe would leave it, stopped by SIGSTOP after the first
+// instruction, and the index of the candidate hit is returned with the return address
+#[instrument(name = "breakpoint", skip(library, offsets, trace))]
+pub fn run_to_specialize(pid: i32, library: &str, offsets: &[u64], trace: TraceOptions) -> Result<(usize, usize)> {
+    let tracee = Tracee::new(pid, trace);
+
+    // helper threads are left running, none of them calls SpecializeCommon
+    tracee.attach_thread()?;
+
+    let maps: Vec<_> = Process::new(pid)?.maps()?.into_iter().collect();
+    let mut originals = Vec::new();
+
+    let res: Result<(usize, usize)> = try {
+        for offset in offsets {
+            let addr = entry_addr(&maps, library, *offset).context(format!("[{pid}] 0x{offset:x} of {library} is not mapped"))?;
+            let original = tracee.peek(addr)?;
+
+            tracee.poke(addr, (original & !BREAKPOINT_MASK) | BREAKPOINT)?;
+            originals.push((addr, original));
+        }
+
+        ptrace::cont(tracee.pid, None)?;
+
+        let (index, mut regs) = loop {
+            match waitpid(tracee.pid, Some(WaitPidFlag::__WALL))? {
+                WaitStatus::Stopped(_, Signal::SIGTRAP) => {
+                    let regs = tracee.regs()?;
+                    let hit = originals.iter().position(|(addr, _)| regs.pc() == addr + BREAKPOINT_PC_OFFSET);
+
+                    match hit {
+                        Some(index) if !tracee.is_foreign_trap() => break (index, regs),
+                        _ => ptrace::cont(tracee.pid, Signal::SIGTRAP)?
+                    }
+                }
+                // stopped by someone else meanwhile, it is stopped again anyway once the breakpoint is hit
+                WaitStatus::PtraceEvent(_, _, libc::PTRACE_EVENT_STOP) => ptrace::cont(tracee.pid, None)?,
+                WaitStatus::Stopped(_, Signal::SIGSTOP) => ptrace::cont(tracee.pid, None)?,
+                WaitStatus::Stopped(_, signal) => ptrace::cont(tracee.pid, signal)?,
+                WaitStatus::Exited(..) | WaitStatus::Signaled(..) => bail!("[{pid}] exited before SpecializeCommon"),
+                status => bail!("[{pid}] unexpected stop before SpecializeCommon: {status:?}")
+            }
+        };
+
+        let return_addr = tracee.return_addr(&regs)?;
+
+        // put back in reverse, in case two of them share a word
+        for (addr, original) in originals.drain(..).rev() {
+            tracee.poke(addr, original)?;
+        }
+
+        // run the original first instruction, where a uprobe would have stopped
+        regs.set_pc(regs.pc() - BREAKPOINT_PC_OFFSET);
+        tracee.set_regs(&regs)?;
+        ptrace::step(tracee.pid, None)?;
+
+        match waitpid(tracee.pid, Some(WaitPidFlag::__WALL))? {
+            WaitStatus::Stopped(_, Signal::SIGTRAP) => (),
+            status => bail!("[{pid}] unexpected stop stepping over the breakpoint: {status:?}")
+        }
+
+        debug!("[{pid}] breakpoint #{index} hit, return address 0x{return_addr:x}");
+
+        (index, return_addr)
+    };
+
+    // a breakpoint left behind would kill the child with SIGTRAP once it is detached
+    for (addr, original) in originals.into_iter().rev() {
+        if let Err(err) = tracee.poke(addr, original) {
+            error!("[{pid}] failed to remove breakpoint at 0x{addr:x}: {err}");
+        }
+    }
+
+    if res.is_ok() {
+        // delivered as soon as it is detached, so that the injection attaches to a stopped child as usual
+        kill(tracee.pid, Signal::SIGSTOP)?;
+    }
+
+    res
+}
+
+// inject a process that has already specialized, only the post specialize hook is called
+#[instrument(name = "late_inject")]
+pub fn late_inject(pid: i32, bridges: &[String], trace: TraceOptions) -> Result<()> {
+    let _thaw = thaw_for_injection(pid);
+    let tracee = Tracee::new(pid, trace);
+    tracee.attach()?;
+
+    context::set_stage("dlopen");
+    let mut wrapper = TraceeWrapper::new(&tracee)?;
+
+    for bridge in bridges {
+        let loaded = remote_dlopen(&mut wrapper, bridge)?;
+        share_config(&wrapper, bridge, &loaded)?;
+    }
+
+    context::set_stage("post_specialize");
+
+    for bridge in bridges {
+        call_post_specialize(&wrapper, &library_name(bridge))?;
+    }
+
+    Ok(())
+}
+
+// some OEM configurations freeze background-forked processes right after SIGCONT
+fn thaw_for_injection(pid: i32) -> Option<ThawGuard> {
+    freezer::thaw(pid).unwrap_or_else(|err| {
+        warn!("[{pid}] failed to check freezer state: {err}");
+        None
+    })
+}
+
+#[instrument(name = "inject", skip(config), fields(package = tracing::field::Empty))]
 pub fn handle_proc(pid: i32, config: &BridgeConfig) -> Result<()> {
-    let tracee = Tracee::new(pid);
+    let start = Instant::now();
+    let _thaw = thaw_for_injection(pid);
+    let tracee = Tracee::new(pid, config.trace);
     tracee.attach()?;
+    config.latency.record_now(pid, Stage::PtraceAttached);
 
     let backup = tracee.regs()?;
+    context::set_stage("load");
 
     // restore context if anything error
-    if let Err(err) = load_bridge(&tracee, config) {
+    let mut attempt = None;
+    let result = load_bridge(&tracee, config, &mut attempt);
+    let success = result.is_ok();
+
+    if let Err(err) = result {
+        config.status.failed();
+        error!("error occurred while tracing process {}", context::wrap(pid, err));
+        context::set_stage("restore");
         tracee.set_regs(&backup)?;
-        error!("error occurred while tracing process {}: {}", pid, err);
+    }
+
+    // detaching resumes the process
+    drop(tracee);
+    config.latency.record_now(pid, Stage::Resumed);
+    config.status.attempted(start.elapsed());
+
+    // skipped processes are not attempts, the filter has already seen them
+    if let Some((uid, package)) = attempt {
+        if success {
+            config.heartbeats.expect(pid, package.clone());
+        }
+
+        if let Some(notifier) = &config.result_fn {
+            notifier.notify(uid, package, success);
+        }
     }
 
     Ok(())
@@ -1,41 +1,227 @@
 #![feature(try_blocks)]
 #![feature(duration_constructors)]
 
-use anyhow::Result;
-use clap::Parser;
+use android_logger::AndroidLogger;
+use anyhow::{Context, Result};
+use clap::{Parser, Subcommand};
 use log::LevelFilter;
+use tracing_chrome::{ChromeLayerBuilder, FlushGuard};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
 use common::debug_select;
+use common::features;
+use common::logfile;
+use common::rootimpl;
 use common::utils::dump_tombstone_on_panic;
 
+mod allowlist;
+mod bundle;
+mod cache;
+mod config;
+mod context;
+mod deferred;
+mod denylist;
+mod control;
+mod debuginfo;
+mod doctor;
+mod drain;
+mod drops;
+mod features;
+mod freezer;
+mod heartbeat;
+mod inspect;
+mod latency;
 mod macros;
+mod missed;
 mod monitor;
+mod outcome;
+mod packages;
+mod prologue;
+mod recovery;
+mod report;
+mod restrictions;
+mod resumer;
+mod runtime;
+mod safemode;
+mod signals;
+mod status;
+mod zygotes;
+mod zygote_mode;
 mod symbols;
+mod umount;
 mod loader;
 
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Probe the running kernel for everything injection relies on, and report what is missing
+    Doctor,
+
+    /// Pack diagnostics into a tarball that can be attached to an issue
+    Report {
+        /// Config file in use, included without comments or unknown keys
+        #[clap(short, long)]
+        config: Option<String>,
+
+        /// Where to write the tarball, defaults to /data/adb/zloader
+        #[clap(short, long)]
+        output: Option<String>
+    },
+
+    /// Check maps and symbol resolution of this device without running the whole pipeline
+    Inspect {
+        #[clap(subcommand)]
+        target: inspect::InspectTarget
+    },
+
+    /// Print the tmpfs directory files are staged in, as the root implementation in use keeps it
+    StagingDir,
+
+    /// Send a command to the running daemon: status, stats, pause, resume, reload, verbose <package>, quiet <package> or list,
+    /// or print the last sessions of log files with log-dump [count]
+    Ctl {
+        #[clap(required = true)]
+        command: Vec<String>
+    }
+}
+
 #[derive(Parser, Debug)]
+#[clap(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Args {
-    #[clap(index = 1)]
-    bridge: String,
+    #[clap(subcommand)]
+    command: Option<Command>,
+
+    /// Bridge libraries, loaded in the given order
+    #[clap(index = 1, required = true)]
+    bridges: Vec<String>,
     
     #[clap(short, long)]
-    filter: Option<String>
+    filter: Option<String>,
+
+    /// Config file, categories it leaves out fall back to the bridges given on the command line
+    #[clap(short, long)]
+    config: Option<String>,
+
+    /// Skip injection of processes whose uid is not reported by the filter's `collect_uids` right at the uprobe,
+    /// children are still stopped for the uprobe to be attached unless no uid is in scope at all
+    #[clap(long, requires = "filter")]
+    uid_allowlist: bool,
+
+    /// Read back and verify every write into the target process
+    #[clap(long)]
+    strict: bool,
+
+    /// Detach from a process that gets SIGSTOP while being injected and exit, so that a debugger can take over
+    #[clap(long)]
+    debug_detach: bool,
+
+    /// Run a remote call again with a breakpoint as return address when it comes back somewhere else,
+    /// the function called runs twice then
+    #[clap(long)]
+    breakpoint_retry: bool,
+
+    /// Detect the end of remote calls by a breakpoint on a scratch page, for ROMs where returning into
+    /// the base of libc doesn't fault as expected, e.g. with BTI or PAC
+    #[clap(long)]
+    breakpoint_completion: bool,
+
+    /// Call the post specialize hook from a uretprobe instead of hijacking the return address
+    #[clap(long)]
+    uretprobe: bool,
+
+    /// Stop children at SpecializeCommon by a breakpoint written into their memory while ptrace-attached,
+    /// instead of a uprobe, so that no `[uprobes]` mapping is ever created in them
+    #[clap(long, conflicts_with = "uretprobe")]
+    inline_breakpoint: bool,
+
+    /// Size of the event ring buffer in bytes, must be a power of two and at least one page
+    #[clap(long, default_value_t = 0x10000)]
+    ring_buffer_size: u32,
+
+    /// Number of zygote children tracked at once, least recently forked ones are evicted beyond it
+    #[clap(long, default_value_t = 512)]
+    children_capacity: u32,
+
+    /// Record injection stages as a Chrome trace, which can be opened with Perfetto
+    #[clap(long)]
+    trace_file: Option<String>,
+
+    /// Log how long each stage of an injection takes, and append it to /data/adb/zloader/latency.csv
+    #[clap(long)]
+    latency: bool,
+
+    /// Write logs to a file under /data/adb/zloader/logs as well, rotated by size
+    #[clap(long)]
+    log_file: bool
 }
 
-fn init_logger() {
-    android_logger::init_once(
+// sessions printed by `zloader ctl log-dump` without a count
+const DEFAULT_DUMP_SESSIONS: usize = 3;
+
+fn init_logger(to_file: bool) {
+    let level = features::log_level(debug_select!(LevelFilter::Trace, LevelFilter::Info));
+    let logger = AndroidLogger::new(
         android_logger::Config::default()
-            .with_max_level(debug_select!(LevelFilter::Trace, LevelFilter::Info))
+            .with_max_level(level)
             .with_tag("ZLoader-Core")
     );
+
+    let _ = logfile::init("zloader", Box::new(logger), level, to_file);
+}
+
+// events always reach the logger through `log-always`, the trace file is only written on request
+fn init_tracing(trace_file: Option<&str>) -> Option<FlushGuard> {
+    let trace_file = trace_file?;
+
+    let (layer, guard) = ChromeLayerBuilder::new()
+        .file(trace_file)
+        .include_args(true)
+        .build();
+
+    tracing_subscriber::registry().with(layer).init();
+
+    Some(guard)
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
-    init_logger();
+    let args = Args::parse();
+
+    init_logger(args.log_file);
     dump_tombstone_on_panic();
+    features::log_effective("zloader");
 
-    let args = Args::parse();
-    monitor::main(&args.bridge, args.filter.as_deref()).await?;
+    match &args.command {
+        Some(Command::Doctor) => return doctor::main(),
+        Some(Command::Inspect { target }) => return inspect::main(target),
+        Some(Command::StagingDir) => {
+            println!("{}", rootimpl::staging_dir().display());
+            return Ok(())
+        }
+        Some(Command::Report { config, output }) => {
+            let tarball = bundle::create(config.as_deref(), output.as_deref())?;
+            println!("report written to {}", tarball.display());
+
+            return Ok(())
+        }
+        // log files are read right here, the daemon may not even be running
+        Some(Command::Ctl { command }) if command[0] == "log-dump" => {
+            let count = match command.get(1) {
+                Some(count) => count.parse().context(format!("invalid count: {count}"))?,
+                None => DEFAULT_DUMP_SESSIONS
+            };
+
+            print!("{}", logfile::dump(count));
+            return Ok(())
+        }
+        Some(Command::Ctl { command }) => {
+            println!("{}", control::request(&command.join(" "))?);
+            return Ok(())
+        }
+        None => ()
+    }
+
+    let _trace = init_tracing(args.trace_file.as_deref());
 
-    Ok(())
+    // returns on SIGTERM and SIGINT as well, so that the trace file gets flushed
+    monitor::main(&args).await
 }
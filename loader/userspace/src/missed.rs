@@ -0,0 +1,47 @@
+use std::time::{Duration, Instant};
+
+use procfs::process::Process;
+use tokio::time;
+
+// how often uprobes still attached are checked for children that went past SpecializeCommon
+pub const CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+// SIGSTOP from eBPF is only delivered once the child returns to userspace
+const STOP_TIMEOUT: Duration = Duration::from_millis(20);
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+pub enum ChildState {
+    // stopped or not yet at SpecializeCommon, the uprobe may still hit
+    Pending,
+    // changed its uid, which only happens inside SpecializeCommon
+    Specialized(u32),
+    Gone
+}
+
+fn is_stopped(pid: i32) -> bool {
+    Process::new(pid).and_then(|process| process.stat()).is_ok_and(|stat| stat.state == 'T')
+}
+
+// give a just forked child a moment to actually stop, true if it did
+pub async fn wait_stopped(pid: i32) -> bool {
+    let start = Instant::now();
+
+    while start.elapsed() < STOP_TIMEOUT {
+        if is_stopped(pid) {
+            return true
+        }
+
+        time::sleep(STOP_POLL_INTERVAL).await;
+    }
+
+    is_stopped(pid)
+}
+
+// zygote children run as root until they specialize, and a hit uprobe stops them before that
+pub fn child_state(pid: i32) -> ChildState {
+    match Process::new(pid).and_then(|process| process.status()) {
+        Ok(status) if status.ruid != 0 => ChildState::Specialized(status.ruid),
+        Ok(_) => ChildState::Pending,
+        Err(_) => ChildState::Gone
+    }
+}
@@ -1,61 +1,141 @@
-use std::{env, mem, process};
-use std::collections::{HashMap, VecDeque};
-use std::ffi::{c_char, CString};
-use std::fs::File;
+use std::{cmp, process};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fs;
+use std::os::unix::fs::FileExt;
 use std::mem::size_of;
-use std::os::fd::{AsFd, OwnedFd};
-use std::path::PathBuf;
-use std::time::{Duration, Instant};
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use anyhow::{bail, Context, Result};
-use aya::{Ebpf, include_bytes_aligned};
-use aya::maps::RingBuf;
-use aya::programs::{TracePoint, UProbe};
+use aya::{Ebpf, EbpfLoader, include_bytes_aligned};
+use aya::maps::{Array, HashMap as BpfHashMap, MapData, PerCpuArray, RingBuf};
+use aya::programs::{RawTracePoint, TracePoint, UProbe};
+use aya::programs::raw_trace_point::RawTracePointLinkId;
 use aya::programs::trace_point::TracePointLinkId;
+use aya::programs::uprobe::UProbeLinkId;
 use aya_log::EbpfLogger;
-use libloading::{Library, Symbol};
-use log::{debug, error, info, warn};
+use libloading::Library;
 use nix::errno::Errno;
 use nix::libc;
 use nix::libc::RLIM_INFINITY;
 use nix::sys::resource::{Resource, setrlimit};
 use nix::sys::signal::{kill, Signal};
+use nix::time::{clock_gettime, ClockId};
 use nix::unistd::Pid;
-use procfs::process::{MountInfo, Process};
-use rustix::path::Arg;
-use rustix::thread;
-use tokio::io::unix::AsyncFd;
-use tokio::task;
+use object::{Object, ObjectSection, ObjectSymbol, SymbolSection};
+use procfs::process::{all_processes, MMapPath, MMPermissions, Process};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::mpsc;
+use tokio::{task, time};
+use tracing::{debug, error, info, warn};
 
-use ebpf_common::EbpfEvent;
+use common::{audit, features, properties};
+use common::zygote::ArgLayout;
+use ebpf_common::{EbpfEvent, EventMeta, ProcessState, ZygoteFlavor};
+use ebpf_common::protocol::PROTOCOL_VERSION;
 
-use crate::{loader, symbols};
-use crate::loader::BridgeConfig;
-use crate::symbols::ArgCounter;
+use crate::{arch_select, Args, context, control, denylist, drain, loader, missed, packages, recovery, restrictions, safemode, signals, symbols, umount};
+use crate::control::{ControlContext, ReloadRequest};
+use crate::allowlist::{CollectUidsFn, UidAllowlist};
+use crate::config::{BootloopAction, Config};
+use crate::deferred::DeferredInjections;
+use crate::drops::DropMonitor;
+use crate::heartbeat::Heartbeats;
+use crate::latency::{LatencyTracker, Stage};
+use crate::zygotes::ChildZygotes;
+use crate::zygote_mode::ZygoteMode;
+use crate::loader::{BridgeConfig, Filter, TraceOptions};
+use crate::report::VerboseTargets;
+use crate::missed::ChildState;
+use crate::outcome::ResultNotifier;
+use crate::runtime::{Fallbacks, UprobeTarget};
+use crate::status::DaemonStatus;
 
 const BOOTLOOP_DETECT_DURATION: Duration = Duration::from_mins(5);
 const BOOTLOOP_DETECT_THRESHOLD: usize = 3;
 
+const STATE_PROPERTY: &str = "debug.zloader.state";
+
+// crash timestamps survive daemon restarts and reboots, otherwise a restart would reset the detection
+const CRASH_HISTORY: &str = "/data/adb/zloader/crashes";
+
 struct BootloopTracker {
     duration: Duration,
     threshold: usize,
-    queue: VecDeque<Instant>
+    queue: VecDeque<SystemTime>,
+    actions: Vec<BootloopAction>,
+    taken: usize,
+    safe_mode: bool,
+    args_mismatch: bool
 }
 
 impl BootloopTracker {
-    fn new(duration: Duration, threshold: usize) -> Self {
+    fn new(duration: Duration, threshold: usize, actions: Vec<BootloopAction>) -> Self {
         Self {
             duration,
             threshold,
-            queue: VecDeque::new()
+            queue: Self::load_history(),
+            actions,
+            taken: 0,
+            safe_mode: false,
+            args_mismatch: false
+        }
+    }
+
+    // observe only, as if injection and uprobes were already disabled
+    fn enter_safe_mode(&mut self) {
+        self.safe_mode = true;
+    }
+
+    // same as safe mode, but lifted once a runtime update brings matching args
+    fn set_args_mismatch(&mut self, mismatch: bool) {
+        self.args_mismatch = mismatch;
+    }
+
+    fn is_taken(&self, action: BootloopAction) -> bool {
+        if (self.safe_mode || self.args_mismatch) && action != BootloopAction::Exit {
+            return true
+        }
+
+        self.actions[.. self.taken].contains(&action)
+    }
+
+    // state to report once an args mismatch is lifted
+    fn state(&self) -> &'static str {
+        if self.safe_mode {
+            return "safe_mode"
+        }
+
+        match self.taken {
+            0 => "running",
+            taken => self.actions[taken - 1].state()
+        }
+    }
+
+    // return the next degradation step once zygote crashed too many times
+    fn zygote_crashed(&mut self) -> Option<BootloopAction> {
+        if !self.crashed_too_often() {
+            return None
         }
+
+        // every step gets a fresh window, so that it can prove whether it helped
+        self.queue.clear();
+        self.save_history();
+
+        let action = self.actions.get(self.taken).copied()?;
+        self.taken += 1;
+
+        Some(action)
     }
 
-    fn zygote_crashed(&mut self) -> bool {
-        let now = Instant::now();
+    fn crashed_too_often(&mut self) -> bool {
+        let now = SystemTime::now();
 
         while let Some(time) = self.queue.front() {
-            if *time + self.duration <= now {
+            // also drop entries from the future, the clock may have been set back
+            if *time + self.duration <= now || *time > now {
                 self.queue.pop_front();
             } else {
                 break;
@@ -63,8 +143,115 @@ impl BootloopTracker {
         }
 
         self.queue.push_back(now);
+        self.save_history();
+
         self.queue.len() >= self.threshold
     }
+
+    fn load_history() -> VecDeque<SystemTime> {
+        let content = match fs::read_to_string(CRASH_HISTORY) {
+            Ok(content) => content,
+            Err(_) => return VecDeque::new()
+        };
+
+        let mut queue: VecDeque<_> = content.lines()
+            .filter_map(|line| line.trim().parse().ok())
+            .map(|secs| UNIX_EPOCH + Duration::from_secs(secs))
+            .collect();
+
+        queue.make_contiguous().sort();
+        debug!("{} zygote crashes loaded from history", queue.len());
+
+        queue
+    }
+
+    fn save_history(&self) {
+        let content: String = self.queue.iter()
+            .filter_map(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|since| format!("{}\n", since.as_secs()))
+            .collect();
+
+        if let Err(err) = fs::write(CRASH_HISTORY, content) {
+            warn!("failed to save crash history: {err}");
+        }
+    }
+}
+
+// zygote may restart several times during boot, every (re)start begins a new generation
+// so that stale events and in-flight work of a dead zygote can be told apart
+struct ZygoteGeneration {
+    pid: Option<i32>,
+    generation: Arc<AtomicU64>
+}
+
+#[derive(Clone)]
+struct GenerationToken {
+    generation: u64,
+    current: Arc<AtomicU64>
+}
+
+impl GenerationToken {
+    fn is_stale(&self) -> bool {
+        self.current.load(Ordering::SeqCst) != self.generation
+    }
+}
+
+impl ZygoteGeneration {
+    fn new(pid: Option<i32>) -> Self {
+        Self {
+            pid,
+            generation: Arc::new(AtomicU64::new(0))
+        }
+    }
+
+    fn current(&self) -> u64 {
+        self.generation.load(Ordering::SeqCst)
+    }
+
+    fn token(&self) -> GenerationToken {
+        GenerationToken {
+            generation: self.current(),
+            current: Arc::clone(&self.generation)
+        }
+    }
+
+    // return false if the event is a duplicate of the current generation
+    fn started(&mut self, pid: i32) -> bool {
+        if self.pid == Some(pid) {
+            return false
+        }
+
+        self.pid = Some(pid);
+        self.generation.fetch_add(1, Ordering::SeqCst);
+
+        true
+    }
+
+    // return false if the event belongs to a generation that is already gone
+    fn crashed(&mut self, pid: i32) -> bool {
+        if self.pid != Some(pid) {
+            return false
+        }
+
+        self.pid = None;
+        self.generation.fetch_add(1, Ordering::SeqCst);
+
+        true
+    }
+}
+
+fn comm_string(meta: &EventMeta) -> String {
+    let len = meta.comm.iter().position(|ch| *ch == 0).unwrap_or(meta.comm.len());
+    String::from_utf8_lossy(&meta.comm[.. len]).into()
+}
+
+// event timestamps come from `bpf_ktime_get_ns`, which shares the clock with CLOCK_MONOTONIC
+fn event_latency(meta: &EventMeta) -> Duration {
+    let now = clock_gettime(ClockId::CLOCK_MONOTONIC)
+        .map(|ts| ts.tv_sec() as u64 * 1_000_000_000 + ts.tv_nsec() as u64)
+        .unwrap_or(meta.timestamp);
+
+    Duration::from_nanos(now.saturating_sub(meta.timestamp))
 }
 
 fn bump_rlimit() {
@@ -73,7 +260,7 @@ fn bump_rlimit() {
     }
 }
 
-fn load_ebpf() -> Result<Ebpf> {
+fn load_ebpf(ring_buffer_size: u32, children_capacity: u32, send_signal: bool) -> Result<Ebpf> {
     let program_data = include_bytes_aligned!(
         concat!(
             env!("PROJECT_ROOT"), 
@@ -84,8 +271,44 @@ fn load_ebpf() -> Result<Ebpf> {
             "/zloader-ebpf"
         )
     );
+
+    let version = protocol_version(program_data).context("failed to read protocol version of ebpf program")?;
+    if version != PROTOCOL_VERSION {
+        bail!("ebpf program speaks protocol version {version}, but userspace expects {PROTOCOL_VERSION}");
+    }
     
-    Ok(Ebpf::load(program_data)?)
+    if !ring_buffer_size.is_power_of_two() || ring_buffer_size < rustix::param::page_size() as u32 {
+        bail!("ring buffer size must be a power of two and at least one page: {ring_buffer_size}");
+    }
+
+    let ebpf = EbpfLoader::new()
+        .set_max_entries("EVENT_CHANNEL", ring_buffer_size)
+        .set_max_entries("ZYGOTE_CHILDREN", children_capacity)
+        .set_global("SEND_SIGNAL", &(send_signal as u8), true)
+        .map_pin_path(recovery::pin_path()?)
+        .load(program_data)?;
+
+    Ok(ebpf)
+}
+
+fn protocol_version(program_data: &[u8]) -> Result<u32> {
+    let file = object::File::parse(program_data)?;
+
+    let symbol = file.symbols()
+        .find(|symbol| symbol.name() == Ok("ZLOADER_PROTOCOL_VERSION"))
+        .context("symbol ZLOADER_PROTOCOL_VERSION not found")?;
+
+    let section = match symbol.section() {
+        SymbolSection::Section(index) => file.section_by_index(index)?,
+        _ => bail!("symbol ZLOADER_PROTOCOL_VERSION is not defined in any section")
+    };
+
+    // symbols in a relocatable object are relative to their sections
+    let offset = (symbol.address() - section.address()) as usize;
+    let data = section.data()?;
+    let bytes = data.get(offset .. offset + size_of::<u32>()).context("symbol ZLOADER_PROTOCOL_VERSION out of range")?;
+
+    Ok(u32::from_ne_bytes(bytes.try_into()?))
 }
 
 fn attach_tracepoint(bpf: &mut Ebpf, category: &str, name: &str) -> Result<TracePointLinkId> {
@@ -98,6 +321,225 @@ fn attach_tracepoint(bpf: &mut Ebpf, category: &str, name: &str) -> Result<Trace
         .context(format!("failed to attach tracepoint: {category}/{name}"))
 }
 
+fn attach_raw_tracepoint(bpf: &mut Ebpf, name: &str) -> Result<RawTracePointLinkId> {
+    let program_name = &format!("handle_raw_{name}");
+    let program: &mut RawTracePoint = bpf.program_mut(program_name).unwrap().try_into()?;
+
+    program.load().context(format!("failed to load program {program_name}"))?;
+
+    program.attach(name)
+        .context(format!("failed to attach raw tracepoint: {name}"))
+}
+
+// raw tracepoints skip building the trace event on every syscall, use them where the kernel allows
+fn attach_syscall_hooks(bpf: &mut Ebpf) -> Result<()> {
+    let res: Result<()> = try {
+        attach_raw_tracepoint(bpf, "sys_enter")?;
+        attach_raw_tracepoint(bpf, "sys_exit")?;
+    };
+
+    match res {
+        Ok(_) => {
+            info!("syscall hooks attached as raw tracepoints");
+            return Ok(())
+        }
+        Err(err) => warn!("raw tracepoints are not available, falling back: {err:#}")
+    }
+
+    // a half attached pair would fire twice for the same syscall
+    for name in ["handle_raw_sys_enter", "handle_raw_sys_exit"] {
+        if let Some(program) = bpf.program_mut(name) {
+            let _ = program.unload();
+        }
+    }
+
+    attach_tracepoint(bpf, "raw_syscalls", "sys_enter")?;
+    attach_tracepoint(bpf, "raw_syscalls", "sys_exit")?;
+
+    Ok(())
+}
+
+fn find_running_zygotes() -> Result<Vec<(i32, ZygoteFlavor)>> {
+    let mut zygotes = Vec::new();
+
+    for proc in all_processes()?.flatten() {
+        let stat = match proc.stat() {
+            Ok(stat) => stat,
+            Err(_) => continue
+        };
+
+        let flavor = match stat.comm.as_str() {
+            "zygote64" => ZygoteFlavor::Zygote64,
+            "zygote" => ZygoteFlavor::Zygote32,
+            _ => continue
+        };
+
+        if stat.ppid == 1 && proc.uid()? == 0 {
+            zygotes.push((stat.pid, flavor));
+        }
+    }
+
+    Ok(zygotes)
+}
+
+// return pid of the running 64-bit zygote, which is the only one to be injected
+fn seed_zygote_pids(ebpf: &mut Ebpf) -> Result<Option<i32>> {
+    let zygotes = find_running_zygotes()?;
+
+    if zygotes.is_empty() {
+        debug!("no running zygote found, waiting for it to start");
+        return Ok(None)
+    }
+
+    let mut map: BpfHashMap<_, i32, u32> = BpfHashMap::try_from(ebpf.map_mut("ZYGOTES").expect("failed to find zygotes map"))?;
+
+    for (pid, flavor) in &zygotes {
+        map.insert(pid, *flavor as u32, 0)?;
+        info!("found running zygote: {pid} ({flavor:?})");
+    }
+
+    Ok(zygotes.iter().find(|(_, flavor)| *flavor == ZygoteFlavor::Zygote64).map(|(pid, _)| *pid))
+}
+
+// links with the index of the program they belong to
+type ProbeLinks = Vec<(usize, UProbeLinkId)>;
+
+// attach to every candidate, uprobes have a program per candidate while the uretprobe shares one
+fn attach_candidates(programs: &mut [&mut UProbe], target: &UprobeTarget, pid: i32) -> Result<ProbeLinks> {
+    let mut links = Vec::new();
+
+    for (id, candidate) in target.candidates.iter().enumerate() {
+        let index = cmp::min(id, programs.len() - 1);
+
+        match programs[index].attach(None, candidate.func_addr, target.library, Some(pid)) {
+            Ok(link_id) => links.push((index, link_id)),
+            Err(err) => {
+                // those attached so far would stay with the program, probing the child, until it is dropped
+                if let Err(err) = detach_candidates(programs, links) {
+                    warn!("[{pid}] failed to detach the candidates attached so far: {err}");
+                }
+
+                bail!(err);
+            }
+        }
+    }
+
+    Ok(links)
+}
+
+// what a uprobe puts at the probed address, `int3` and `brk #0x5`
+const UPROBE_INSN: u32 = arch_select!(0xcc, 0xd420_00a0);
+const UPROBE_INSN_MASK: u32 = arch_select!(0xff, 0xffff_ffff);
+
+// every candidate must have its breakpoint in the child, a child resumed with one missing may go past unprobed;
+// a uretprobe shares the breakpoint of the uprobe at the same address, so this covers both
+fn verify_links(target: &UprobeTarget, pid: i32) -> Result<()> {
+    let maps: Vec<_> = Process::new(pid)?.maps()?.into_iter().collect();
+    let mem = fs::File::open(format!("/proc/{pid}/mem"))?;
+
+    for (id, candidate) in target.candidates.iter().enumerate() {
+        let addr = loader::entry_addr(&maps, target.library, candidate.func_addr)
+            .context(format!("[{pid}] candidate #{id} is not mapped"))?;
+
+        let mut insn = [0u8; 4];
+        mem.read_exact_at(&mut insn, addr as u64)?;
+
+        if u32::from_le_bytes(insn) & UPROBE_INSN_MASK != UPROBE_INSN {
+            bail!("[{pid}] no breakpoint of candidate #{id} at 0x{addr:x}");
+        }
+    }
+
+    Ok(())
+}
+
+fn detach_candidates(programs: &mut [&mut UProbe], links: ProbeLinks) -> Result<()> {
+    for (index, link_id) in links {
+        programs[index].detach(link_id)?;
+    }
+
+    Ok(())
+}
+
+fn detach_stale(programs: &mut [&mut UProbe], attached_procs: &mut HashMap<i32, (u64, ProbeLinks)>, generation: u64) {
+    let stale: Vec<i32> = attached_procs.iter()
+        .filter(|(_, (gen, _))| *gen != generation)
+        .map(|(pid, _)| *pid)
+        .collect();
+
+    for pid in stale {
+        if let Some((_, links)) = attached_procs.remove(&pid) {
+            match detach_candidates(programs, links) {
+                Ok(_) => debug!("[{pid}] uprobe of stale zygote generation detached"),
+                Err(err) => warn!("[{pid}] failed to detach uprobe of stale zygote generation: {err}")
+            }
+        }
+    }
+}
+
+// children still probed after they specialized have gone past SpecializeCommon without a hit
+fn detect_missed(programs: &mut [&mut UProbe], attached_procs: &mut HashMap<i32, (u64, ProbeLinks)>, status: &DaemonStatus) {
+    let done: Vec<_> = attached_procs.keys()
+        .filter_map(|pid| match missed::child_state(*pid) {
+            ChildState::Pending => None,
+            state => Some((*pid, state))
+        })
+        .collect();
+
+    for (pid, state) in done {
+        if let ChildState::Specialized(uid) = state {
+            let message = format!("specialized to uid {uid} without hitting the uprobe, injection missed");
+
+            warn!("[{pid}] {message}");
+            audit::record(pid, &message);
+            status.missed();
+        }
+
+        if let Some((_, links)) = attached_procs.remove(&pid) {
+            if let Err(err) = detach_candidates(programs, links) {
+                warn!("[{pid}] failed to detach uprobe: {err}");
+            }
+        }
+    }
+}
+
+// leave nothing probed or stopped behind, nobody may be around to resume the processes later
+fn shutdown(
+    uprobes: &mut [&mut UProbe],
+    uretprobes: &mut [&mut UProbe],
+    attached_procs: &mut HashMap<i32, (u64, ProbeLinks)>,
+    attached_retprobes: &mut HashMap<i32, (u64, ProbeLinks)>,
+    children: &BpfHashMap<MapData, i32, u32>
+) {
+    let mut resume = HashSet::new();
+
+    for (programs, attached) in [(uprobes, attached_procs), (uretprobes, attached_retprobes)] {
+        for (pid, (_, links)) in attached.drain() {
+            if let Err(err) = detach_candidates(programs, links) {
+                warn!("[{pid}] failed to detach uprobe: {err}");
+            }
+
+            resume.insert(pid);
+        }
+    }
+
+    // children stopped for attaching or umount that haven't been handled yet
+    for (pid, state) in children.iter().flatten() {
+        let waiting = state == ProcessState::WaitForAttach as u32 || state == ProcessState::WaitForUmount as u32;
+
+        if waiting && Process::new(pid).and_then(|process| process.stat()).is_ok_and(|stat| stat.state == 'T') {
+            resume.insert(pid);
+        }
+    }
+
+    for pid in resume {
+        match kill(Pid::from_raw(pid), Signal::SIGCONT) {
+            Ok(_) => debug!("[{pid}] resumed on shutdown"),
+            Err(Errno::ESRCH) => (),
+            Err(err) => warn!("[{pid}] failed to resume on shutdown: {err}")
+        }
+    }
+}
+
 fn fork_daemon(func: impl Fn()) {
     unsafe {
         let p = libc::fork();
@@ -119,99 +561,130 @@ fn fork_daemon(func: impl Fn()) {
     }
 }
 
-fn umount_module_files(pid: i32) {
-    fn filter_mounts_kernelsu(mounts: Vec<MountInfo>) -> Vec<PathBuf> {
-        let module_dir = PathBuf::from("/data/adb/modules");
-        
-        let mut mp = Vec::new();
-        let mut loop_dev = None;
-
-        for info in &mounts {
-            if info.mount_point == module_dir {
-                if let Some(source) = &info.mount_source {
-                    loop_dev.replace(source.to_owned());
-                    debug!("found KernelSU loop device: {}", source);
-                    continue
-                }
-            }
+// start and end of the executable mappings of the library, which children of the zygote share
+fn caller_text(pid: i32, library: &str) -> Result<[u64; 2]> {
+    let text: Vec<_> = Process::new(pid)?.maps()?.into_iter()
+        .filter(|map| map.perms.contains(MMPermissions::EXECUTE))
+        .filter(|map| matches!(&map.pathname, MMapPath::Path(path) if path.as_os_str() == library))
+        .map(|map| map.address)
+        .collect();
 
-            if info.mount_point.starts_with("/data/adb") {
-                mp.push(info.mount_point.clone());
-                continue
-            }
+    let start = text.iter().map(|(start, _)| *start).min().context(format!("{library} is not mapped executable"))?;
+    let end = text.iter().map(|(_, end)| *end).max().unwrap_or(start);
 
-            if info.mount_source == Some("KSU".into()) && (info.fs_type == "overlay" || info.fs_type == "tmpfs") {
-                mp.push(info.mount_point.clone());
-                continue
-            }
-        }
+    Ok([start, end])
+}
 
-        for info in mounts {
-            if info.mount_source == loop_dev && info.mount_point != module_dir {
-                mp.push(info.mount_point);
-            }
-        }
+// return addresses the uprobe reads are only taken if they point into it, any user address is while unknown
+fn update_caller_text(map: &mut Array<MapData, [u64; 2]>, pid: i32, library: &str) {
+    let range = caller_text(pid, library).unwrap_or_else(|err| {
+        warn!("failed to locate {library} in zygote {pid}, return addresses are not checked: {err}");
+        [0, 0]
+    });
 
-        mp
+    if let Err(err) = map.set(0, range, 0) {
+        warn!("failed to update caller text: {err}");
     }
+}
 
-    fn filter_mounts_magisk(mounts: Vec<MountInfo>) -> Vec<PathBuf> {
-        let mut mp = Vec::new();
+// an unknown mode is taken as the default one, which is what the pipeline assumed before it was read
+fn read_zygote_mode(pid: i32) -> ZygoteMode {
+    match ZygoteMode::read(pid) {
+        Ok(mode) => {
+            info!("zygote {pid} started with {mode}");
+            mode
+        }
+        Err(err) => {
+            warn!("failed to read how zygote {pid} was started: {err}");
+            ZygoteMode::default()
+        }
+    }
+}
 
-        for info in mounts {
-            if let Some(source) = &info.mount_source {
-                if source == "magisk" || source == "worker" {
-                    mp.push(info.mount_point);
-                    continue
-                }
-            }
+fn load_config(args: &Args) -> Result<Config> {
+    let config = match &args.config {
+        Some(path) => Config::load(path)?,
+        None => Config::default()
+    };
 
-            if info.root.starts_with("/adb/modules") {
-                mp.push(info.mount_point);
-                continue
-            }
-        }
+    if args.bridges.is_empty() {
+        bail!("no bridge given");
+    }
+
+    Ok(config.with_default_bridges(&args.bridges))
+}
 
-        mp
+// argument counts SpecializeCommon has had on each SDK, with room for one backported from the next
+fn expected_arg_counts(sdk: i32) -> Option<RangeInclusive<usize>> {
+    match sdk {
+        29 => Some(15 ..= 16),
+        30 ..= 33 => Some(20 ..= 21),
+        34 => Some(20 ..= 22),
+        35 => Some(21 ..= 22),
+        _ => None
     }
+}
 
-    let res: Result<()> = try {
-        let link: OwnedFd = File::open(format!("/proc/{}/ns/mnt", pid))?.into();
-        debug!("switching into mount namespace: {pid}");
-        thread::move_into_link_name_space(link.as_fd(), None)?;
+// ArgCounter only counts commas, a wrong count would hand the bridge garbage instead of its args
+fn validate_arg_counts(target: &UprobeTarget) -> Result<()> {
+    let sdk = properties::getprop("ro.build.version.sdk").parse().unwrap_or(0);
 
-        let proc = Process::myself()?;
+    let Some(expected) = expected_arg_counts(sdk) else {
+        warn!("no expected argument count for SDK {sdk}, counts are not validated");
+        return Ok(())
+    };
 
-        let mounts: Vec<MountInfo> = proc.mountinfo()?.into_iter().collect();
-        let mounts = if env::var("KSU").is_ok() {
-            filter_mounts_kernelsu(mounts)
-        } else {
-            filter_mounts_magisk(mounts)
-        };
-        
-        debug!("[{pid}] found {} files to umount", mounts.len());
+    let mut mismatches = Vec::new();
 
-        for mount in mounts.into_iter().rev() {
-            let mp = CString::new(mount.to_string_lossy().to_string())?;
-            debug!("[{pid}] umount: {}", mp.as_str()?);
+    for (id, candidate) in target.candidates.iter().enumerate() {
+        let counted = candidate.args_count;
+        let layout = candidate.layout.is_known().then(|| candidate.layout.count());
 
-            unsafe {
-                libc::umount2(mp.as_ptr(), libc::MNT_DETACH);
-            }
+        if expected.contains(&counted) && !layout.is_some_and(|count| count != counted) {
+            continue
         }
-    };
 
-    if let Err(err) = res {
-        error!("failed to umount module files: {err}");
+        let types = symbols::parameter_types(&candidate.name)
+            .map_or_else(|err| format!("<{err}>"), |types| types.join(", "));
+
+        mismatches.push(format!(
+            "candidate #{id} `{}`: counted {counted}, parsed {layout:?}, expected {expected:?} on SDK {sdk}, parameters: ({types})",
+            candidate.name
+        ));
+    }
+
+    if !mismatches.is_empty() {
+        bail!("unexpected argument count of SpecializeCommon:\n{}", mismatches.join("\n"));
     }
 
-    let _ = kill(Pid::from_raw(pid), Signal::SIGCONT);
+    Ok(())
 }
 
-pub async fn main(bridge: &str, filter: Option<&str>) -> Result<()> {
+fn report_state(state: &str) {
+    if !properties::setprop(STATE_PROPERTY, state) {
+        warn!("failed to report state: {state}");
+    }
+}
+
+pub async fn main(args: &Args) -> Result<()> {
+    let config = load_config(args)?;
+
+    // replaced on reload, in-flight injections keep what they started with
+    let mut bridges = Arc::new(config.bridges);
+    let mut payloads = Arc::new(config.payloads);
+    let mut native_bridge = config.native_bridge;
+    let mut blocked_processes = Arc::new(config.blocked_processes);
+    let mut umount_rules = Arc::new(umount::rules(&config.umount));
+
     bump_rlimit();
+    restrictions::check();
     
-    let mut ebpf = load_ebpf().context("failed to load ebpf program")?;
+    let send_signal = signals::send_signal_supported();
+    if !send_signal {
+        warn!("bpf_send_signal_thread is not supported by the kernel, processes will be stopped from userspace");
+    }
+
+    let mut ebpf = load_ebpf(args.ring_buffer_size, args.children_capacity, send_signal).context("failed to load ebpf program")?;
 
     if EbpfLogger::init(&mut ebpf).is_err() {
         debug!("ebpf logs are not available on release build");
@@ -220,50 +693,287 @@ pub async fn main(bridge: &str, filter: Option<&str>) -> Result<()> {
     let channel = ebpf.take_map("EVENT_CHANNEL").expect("failed to take event channel");
     let channel = RingBuf::try_from(channel).unwrap();
 
+    let dropped = ebpf.take_map("DROPPED_EVENTS").expect("failed to take dropped events");
+    let limited = ebpf.take_map("RATE_LIMITED").expect("failed to take rate limited events");
+    let status = DaemonStatus::default();
+    task::spawn(DropMonitor::new(PerCpuArray::try_from(dropped)?, PerCpuArray::try_from(limited)?, status.clone()).serve());
+
+    let child_zygotes = ebpf.take_map("CHILD_ZYGOTES").expect("failed to take child zygotes");
+    let child_zygotes = ChildZygotes::new(BpfHashMap::try_from(child_zygotes)?);
+
+    let children = ebpf.take_map("ZYGOTE_CHILDREN").expect("failed to take zygote children");
+    let mut children = BpfHashMap::try_from(children)?;
+
+    let specialize_uids = ebpf.take_map("SPECIALIZE_UIDS").expect("failed to take specialize uids");
+    let specialize_uids: BpfHashMap<_, i32, u32> = BpfHashMap::try_from(specialize_uids)?;
+
     attach_tracepoint(&mut ebpf, "task", "task_rename")?;
     attach_tracepoint(&mut ebpf, "task", "task_newtask")?;
     attach_tracepoint(&mut ebpf, "sched", "sched_process_exit")?;
-    attach_tracepoint(&mut ebpf, "raw_syscalls", "sys_enter")?;
-    attach_tracepoint(&mut ebpf, "raw_syscalls", "sys_exit")?;
+    attach_syscall_hooks(&mut ebpf)?;
 
-    let uprobe_lib = "/system/lib64/libandroid_runtime.so";
-    let (func_name, func_addr) = symbols::resolve_for_uprobe(uprobe_lib, "_ZN12_GLOBAL__N_116SpecializeCommonEP7_JNIEnvjjP10_jintArrayiP13_jobjectArraylliP8_jstringS7_bbS7_S7_bS5_S5_bb")?;
-    
-    let args_count = ArgCounter::count(&func_name)?;
-    info!("SpecializeCommon has {args_count} arguments");
+    let running_zygote = seed_zygote_pids(&mut ebpf).unwrap_or_else(|err| {
+        warn!("failed to detect running zygote: {err}");
+        None
+    });
 
-    let uprobe: &mut UProbe = ebpf.program_mut("handle_specialize_common").unwrap().try_into()?;
-    uprobe.load()?;
+    let mut target = UprobeTarget::resolve(&config.specialize_symbols, &Fallbacks {
+        debug_symbols: config.debug_symbols.clone(),
+        signatures: config.specialize_signatures.clone()
+    })?;
+    info!("{} identity: {}", target.library, target.identity());
+    target.log_candidates();
+
+    let caller_text = ebpf.take_map("CALLER_TEXT").expect("failed to take caller text");
+    let mut caller_text = Array::try_from(caller_text)?;
+
+    if let Some(pid) = running_zygote {
+        update_caller_text(&mut caller_text, pid, target.library);
+    }
 
     let mut attached_procs = HashMap::new();
+    let mut zygote = ZygoteGeneration::new(running_zygote);
+    let mut zygote_mode = running_zygote.map(read_zygote_mode).unwrap_or_default();
     let mut tracker = BootloopTracker::new(
         BOOTLOOP_DETECT_DURATION,
-        BOOTLOOP_DETECT_THRESHOLD
+        BOOTLOOP_DETECT_THRESHOLD,
+        config.bootloop.actions
     );
+
+    match safemode::check() {
+        Some(trigger) => {
+            warn!("safe mode, nothing will be injected until {} is removed: {trigger}", safemode::MARKER);
+            tracker.enter_safe_mode();
+            report_state("safe_mode");
+        }
+        None => report_state("running")
+    }
+
+    // observe only rather than inject with the wrong args
+    if let Err(err) = validate_arg_counts(&target) {
+        error!("{err}, nothing will be attached");
+        tracker.set_args_mismatch(true);
+        report_state("args_mismatch");
+    }
     
-    let check_process = if let Some(filter) = filter {
-        unsafe {
+    let filter = match &args.filter {
+        Some(filter) => unsafe {
             let library = Box::new(Library::new(filter)?);
-            let library = Box::leak(library);  // Fixme: don't leak memory
-            let func: Symbol<extern "C" fn(libc::uid_t, *const c_char, *const c_char) -> bool> = library.get(b"check_process")?;
-            Some(func)
+            Some(&*Box::leak(library))  // Fixme: don't leak memory
+        },
+        None => None
+    };
+
+    let check_process = if let Some(library) = filter {
+        unsafe {
+            match library.get(b"check_process_ex") {
+                Ok(func) => Some(Filter::Snapshot(func)),
+                Err(_) => Some(Filter::Simple(library.get(b"check_process")?))
+            }
         }
     } else {
         None
     };
 
-    let mut async_channel = AsyncFd::new(channel)?;
+    // optional, for filters that adapt to how injections went
+    let result_fn = filter.and_then(|library| unsafe {
+        library.get(b"on_result").ok().map(ResultNotifier::new)
+    });
 
-    loop {
-        let mut lock = async_channel.readable_mut().await?;
-        let entry = lock.get_inner_mut().next();
+    if args.uid_allowlist {
+        let library = filter.context("uid allowlist requires a filter")?;
+        let collect: CollectUidsFn = unsafe {
+            library.get(b"collect_uids").context("filter does not export `collect_uids`")?
+        };
+
+        let mode = Array::try_from(ebpf.take_map("UID_FILTER").expect("failed to take uid filter"))?;
+        let uids = BpfHashMap::try_from(ebpf.take_map("TARGET_UIDS").expect("failed to take target uids"))?;
+
+        task::spawn(UidAllowlist::new(mode, uids).serve(collect));
+        info!("uid allowlist enabled, children are still stopped once for the uprobe and only let go there when out of scope");
+    }
+
+    let mut uprobes = Vec::new();
+    let mut uretprobe = None;
+
+    for (name, program) in ebpf.programs_mut() {
+        if name == "handle_specialize_common_ret" {
+            uretprobe = Some(program);
+        } else if let Some(id) = name.strip_prefix("handle_specialize_common_").and_then(|id| id.parse::<usize>().ok()) {
+            uprobes.push((id, program));
+        }
+    }
+
+    // index of the program is the candidate id reported by RequireInject
+    uprobes.sort_by_key(|(id, _)| *id);
+
+    let mut uprobes: Vec<&mut UProbe> = uprobes.into_iter()
+        .map(|(_, program)| program.try_into())
+        .collect::<Result<_, _>>()?;
+
+    for uprobe in uprobes.iter_mut() {
+        uprobe.load()?;
+    }
+
+    let uretprobe: &mut UProbe = uretprobe.unwrap().try_into()?;
+    if args.uretprobe {
+        uretprobe.load()?;
+    }
+
+    let mut uretprobes = [uretprobe];
+
+    let mut attached_retprobes = HashMap::new();
+
+    let latency = LatencyTracker::new(args.latency);
+
+    let deferred = DeferredInjections::new(config.deferred_packages, config.defer_background);
+    let trace = TraceOptions {
+        strict: args.strict,
+        debug_detach: args.debug_detach,
+        breakpoint_retry: args.breakpoint_retry,
+        breakpoint_completion: args.breakpoint_completion
+    };
+
+    task::spawn(deferred.clone().serve(trace));
+
+    let verbose = VerboseTargets::default();
+
+    status.set_zygote(running_zygote);
+    status.set_zygote_mode(&zygote_mode);
+
+    let (mut events, resumer) = drain::spawn(channel, !send_signal, status.clone())?;
+    let heartbeats = Heartbeats::new(status.clone());
+    task::spawn(heartbeats.clone().serve());
 
-        if entry.is_none() {
-            drop(entry);
-            lock.clear_ready();
+    let (reload_tx, mut reload_rx) = mpsc::channel::<ReloadRequest>(1);
+
+    task::spawn(control::serve(ControlContext {
+        verbose: verbose.clone(),
+        status: status.clone(),
+        heartbeats: heartbeats.clone(),
+        reload: reload_tx
+    }));
+
+    // continue with children that a crashed instance left stopped, as if they just required uprobe attach
+    for pid in recovery::stranded_children(&mut children)? {
+        // a uprobe would map `[uprobes]` into them, so they go without injection
+        if args.inline_breakpoint {
+            warn!("[{pid}] stranded by a crashed instance, resumed without injection");
+            let _ = kill(Pid::from_raw(pid), Signal::SIGCONT);
+            continue
+        }
+
+        if tracker.is_taken(BootloopAction::DisableUprobe) || target.is_stale() {
+            warn!("[{pid}] stranded by a crashed instance, resumed without uprobes");
+            let _ = kill(Pid::from_raw(pid), Signal::SIGCONT);
             continue
         }
 
+        let links = attach_candidates(&mut uprobes, &target, pid)?;
+        attached_procs.insert(pid, (zygote.current(), links));
+
+        if args.uretprobe {
+            let links = attach_candidates(&mut uretprobes, &target, pid)?;
+            attached_retprobes.insert(pid, (zygote.current(), links));
+        }
+
+        if let Err(err) = verify_links(&target, pid) {
+            warn!("[{pid}] stranded by a crashed instance, resumed without uprobes: {err}");
+            status.attach_failed();
+
+            if let Some((_, links)) = attached_procs.remove(&pid) {
+                detach_candidates(&mut uprobes, links)?;
+            }
+
+            if let Some((_, links)) = attached_retprobes.remove(&pid) {
+                detach_candidates(&mut uretprobes, links)?;
+            }
+        }
+
+        if let Err(err) = kill(Pid::from_raw(pid), Signal::SIGCONT) {
+            error!("[{pid}] failed to resume: {err}");
+        }
+    }
+
+    // a macro rather than a closure, so that a reload can replace what it reads
+    macro_rules! make_config {
+        ($return_addr: expr, $args_count: expr, $args_layout: expr) => {
+            BridgeConfig {
+                bridges: Arc::clone(&bridges),
+                payloads: Arc::clone(&payloads),
+                filter_fn: check_process.clone(),
+                result_fn: result_fn.clone(),
+                blocked_processes: Arc::clone(&blocked_processes),
+                native_bridge,
+                args_count: $args_count,
+                args_layout: $args_layout,
+                return_addr: $return_addr,
+                trace,
+                uretprobe: args.uretprobe,
+                child_zygotes: child_zygotes.clone(),
+                verbose: verbose.clone(),
+                latency: latency.clone(),
+                deferred: deferred.clone(),
+                status: status.clone(),
+                heartbeats: heartbeats.clone()
+            }
+        };
+    }
+
+    let mut terminate = signal(SignalKind::terminate())?;
+    let mut interrupt = signal(SignalKind::interrupt())?;
+
+    let mut missed_check = time::interval(missed::CHECK_INTERVAL);
+
+    loop {
+        let (event, meta) = tokio::select! {
+            event = events.recv() => match event {
+                Some(event) => event,
+                None => {
+                    error!("drain thread is gone, shutting down");
+                    break
+                }
+            },
+            _ = missed_check.tick() => {
+                detect_missed(&mut uprobes, &mut attached_procs, &status);
+                continue
+            }
+            _ = terminate.recv() => {
+                info!("SIGTERM received, shutting down");
+                break
+            }
+            _ = interrupt.recv() => {
+                info!("SIGINT received, shutting down");
+                break
+            }
+            Some(reply) = reload_rx.recv() => {
+                denylist::reload();
+
+                let message = match load_config(args) {
+                    Ok(config) => {
+                        bridges = Arc::new(config.bridges);
+                        payloads = Arc::new(config.payloads);
+                        native_bridge = config.native_bridge;
+                        blocked_processes = Arc::new(config.blocked_processes);
+                        umount_rules = Arc::new(umount::rules(&config.umount));
+                        info!("config and denylist reloaded");
+
+                        // in-flight injections and the uid allowlist hold its symbols, it isn't swapped under them
+                        match &args.filter {
+                            Some(filter) => format!("ok, filter {filter} is not reloaded, restart the daemon to load it again"),
+                            None => "ok".into()
+                        }
+                    }
+                    Err(err) => {
+                        error!("failed to reload config: {err:#}");
+                        format!("error: {err:#}")
+                    }
+                };
+
+                let _ = reply.send(message);
+                continue
+            }
+        };
         let mut resume_pid = 0;
 
         macro_rules! resume_later {
@@ -273,60 +983,315 @@ pub async fn main(bridge: &str, filter: Option<&str>) -> Result<()> {
         }
 
         let res: Result<()> = try {
-            let buffer: [u8; size_of::<EbpfEvent>()] = (*entry.unwrap()).try_into()?;
-            let event: EbpfEvent = unsafe { mem::transmute(buffer) };
+            status.event();
+
+            debug!(
+                "event from {} (uid={}), delivered in {:?}",
+                comm_string(&meta), meta.uid, event_latency(&meta)
+            );
 
             match event {
-                EbpfEvent::ZygoteStarted(pid) => {
+                EbpfEvent::ZygoteStarted(pid, ZygoteFlavor::Zygote32) => {
+                    info!("32-bit zygote (re)started: {pid}");
+                }
+                EbpfEvent::ZygoteStarted(pid, ZygoteFlavor::Zygote64) => {
+                    if !zygote.started(pid) {
+                        debug!("duplicate zygote start event: {pid}");
+                        continue
+                    }
+
                     info!("zygote (re)started: {pid}");
+                    status.set_zygote(Some(pid));
+
+                    zygote_mode = read_zygote_mode(pid);
+                    status.set_zygote_mode(&zygote_mode);
+                    detach_stale(&mut uprobes, &mut attached_procs, zygote.current());
+                    detach_stale(&mut uretprobes, &mut attached_retprobes, zygote.current());
+
+                    // an OTA may have replaced the runtime, offsets resolved for the old one would hit wrong addresses
+                    match target.refresh() {
+                        Ok(true) => match validate_arg_counts(&target) {
+                            Ok(()) => if tracker.args_mismatch {
+                                info!("args match after the runtime update, attaching again");
+                                tracker.set_args_mismatch(false);
+                                report_state(tracker.state());
+                            }
+                            Err(err) => {
+                                error!("{err}, nothing will be attached");
+                                tracker.set_args_mismatch(true);
+                                report_state("args_mismatch");
+                            }
+                        }
+                        Ok(false) => (),
+                        Err(err) => error!("failed to check for runtime update, uprobes are suspended: {err}")
+                    }
+
+                    update_caller_text(&mut caller_text, pid, target.library);
                 }
                 EbpfEvent::ZygoteForked(pid) => {
                     debug!("zygote forked: {pid}");
+                    latency.record(pid, Stage::Forked, meta.timestamp);
+                }
+                EbpfEvent::ZygoteCrashed(pid, ZygoteFlavor::Zygote32) => {
+                    warn!("32-bit zygote crashed: {pid}");
                 }
-                EbpfEvent::ZygoteCrashed(pid) => {
+                EbpfEvent::ZygoteCrashed(pid, ZygoteFlavor::Zygote64) => {
+                    if !zygote.crashed(pid) {
+                        debug!("stale zygote crash event: {pid}");
+                        continue
+                    }
+
                     warn!("zygote crashed: {pid}");
-                    if tracker.zygote_crashed() {
-                        error!("zygote crashed too many times, exiting...");
-                        break
+                    status.set_zygote(None);
+                    detach_stale(&mut uprobes, &mut attached_procs, zygote.current());
+                    detach_stale(&mut uretprobes, &mut attached_retprobes, zygote.current());
+
+                    if let Some(action) = tracker.zygote_crashed() {
+                        error!("zygote crashed too many times, degrading: {action}");
+                        report_state(action.state());
+
+                        if action == BootloopAction::Exit {
+                            // the service may restart us right away, stay away from zygote until the user steps in
+                            if let Err(err) = safemode::enter("zygote crashed too many times") {
+                                error!("failed to enter safe mode: {err}");
+                            }
+
+                            break
+                        }
                     }
                 }
                 EbpfEvent::RequireUprobeAttach(pid) => {
                     debug!("[{pid}] uprobe attach required");
                     resume_later!(pid);
+                    latency.record(pid, Stage::Stopped, meta.timestamp);
+
+                    if tracker.is_taken(BootloopAction::DisableUprobe) {
+                        debug!("[{pid}] uprobe disabled, skipped");
+                        latency.discard(pid);
+                    } else if target.is_stale() {
+                        warn!("[{pid}] {} changed but couldn't be resolved again, skipped", target.library);
+                        latency.discard(pid);
+                    } else if args.inline_breakpoint && (tracker.is_taken(BootloopAction::DisableInjection) || status.is_paused()) {
+                        debug!("[{pid}] injection disabled or paused, breakpoint skipped");
+                        latency.discard(pid);
+                    } else if args.inline_breakpoint {
+                        // the child stays stopped until the breakpoints are in, the task resumes it
+                        resume_later!(0);
+
+                        let library = target.library;
+                        let offsets: Vec<_> = target.candidates.iter().map(|candidate| candidate.func_addr).collect();
+                        let mut configs: Vec<_> = target.candidates.iter()
+                            .map(|candidate| make_config!(0, candidate.args_count, candidate.layout))
+                            .collect();
+                        let token = zygote.token();
+                        let latency = latency.clone();
+                        let status = status.clone();
+                        let usap_pool = zygote_mode.usap_pool;
+
+                        // no uprobe runs, so the uid isn't known for the umount decision and modules are hidden
+                        let inject = move || {
+                            if token.is_stale() {
+                                debug!("[{pid}] zygote is gone, dropping injection");
+                                let _ = kill(Pid::from_raw(pid), Signal::SIGCONT);
+                                return
+                            }
+
+                            let (index, return_addr) = match loader::run_to_specialize(pid, library, &offsets, trace) {
+                                Ok(hit) => hit,
+                                Err(err) => {
+                                    error!("[{pid}] failed to break at SpecializeCommon: {err}");
+                                    status.attach_failed();
+                                    let _ = kill(Pid::from_raw(pid), Signal::SIGCONT);
+                                    return
+                                }
+                            };
+
+                            if usap_pool {
+                                latency.discard(pid);
+                            }
+
+                            latency.record_now(pid, Stage::UprobeHit);
+
+                            let mut config = configs.swap_remove(index);
+                            config.return_addr = return_addr;
+
+                            if let Err(err) = context::run(pid, "attach", || loader::handle_proc(pid, &config)) {
+                                error!("failed to inject {err}");
+                            }
+                        };
+
+                        // ptrace and waitpid block, and a child kept in the usap pool holds the thread until it is used
+                        task::spawn(async move {
+                            signals::ensure_stopped(pid, !send_signal).await;
+                            task::spawn_blocking(inject);
+                        });
+                    } else {
+                        let links = attach_candidates(&mut uprobes, &target, pid).inspect_err(|_| status.attach_failed())?;
+                        attached_procs.insert(pid, (zygote.current(), links));
+
+                        if args.uretprobe {
+                            let links = attach_candidates(&mut uretprobes, &target, pid).inspect_err(|_| status.attach_failed())?;
+                            attached_retprobes.insert(pid, (zygote.current(), links));
+                        }
+
+                        if let Err(err) = verify_links(&target, pid) {
+                            status.attach_failed();
+
+                            // the child is resumed unprobed, its links mustn't outlive the attempt
+                            if let Some((_, links)) = attached_procs.remove(&pid) {
+                                detach_candidates(&mut uprobes, links)?;
+                            }
+
+                            if let Some((_, links)) = attached_retprobes.remove(&pid) {
+                                detach_candidates(&mut uretprobes, links)?;
+                            }
 
-                    let link_id = uprobe.attach(None, func_addr, uprobe_lib, Some(pid))?;
-                    attached_procs.insert(pid, link_id);
+                            Err(err)?;
+                        }
+
+                        latency.record_now(pid, Stage::UprobeAttached);
+
+                        // checked off the loop and only resumed after, a running one could pass SpecializeCommon unprobed
+                        resume_later!(0);
+                        let resumer = resumer.clone();
+
+                        task::spawn(async move {
+                            signals::ensure_stopped(pid, !send_signal).await;
+
+                            if !missed::wait_stopped(pid).await {
+                                warn!("[{pid}] not stopped across uprobe attach, the hook may be missed");
+                            }
+
+                            resumer.resume(pid);
+                        });
+                    }
                 }
-                EbpfEvent::RequireInject(pid, return_addr) => {
-                    debug!("[{pid}] inject required");
+                EbpfEvent::RequireInject(pid, return_addr, source, id) => {
+                    debug!("[{pid}] inject required by candidate #{id}, return address 0x{return_addr:x} from {source:?}");
                     // resume_later!(pid);
 
-                    if let Some(link_id) = attached_procs.remove(&pid) {
-                        uprobe.detach(link_id)?;
+                    // the time a child spent in the usap pool would make up most of its timeline
+                    if zygote_mode.usap_pool {
+                        latency.discard(pid);
+                    }
+
+                    latency.record(pid, Stage::UprobeHit, meta.timestamp);
+
+                    if let Some((_, links)) = attached_procs.remove(&pid) {
+                        detach_candidates(&mut uprobes, links)?;
                         debug!("[{pid}] uprobe detached");
                     } else {
                         error!("uprobe appears to be attached to {pid}, but there is no record in the map");
                     }
 
-                    let config = BridgeConfig {
-                        library: bridge.into(),
-                        filter_fn: check_process.clone(),
-                        args_count,
-                        return_addr
-                    };
+                    if tracker.is_taken(BootloopAction::DisableInjection) {
+                        debug!("[{pid}] injection disabled, skipped");
+                        resume_later!(pid);
+                        latency.discard(pid);
+
+                        // nothing left to do after specialize either, the child mustn't be stopped there again
+                        if let Some((_, links)) = attached_retprobes.remove(&pid) {
+                            detach_candidates(&mut uretprobes, links)?;
+                            debug!("[{pid}] uretprobe detached");
+                        }
+                    } else if status.is_paused() {
+                        debug!("[{pid}] injection paused, skipped");
+                        resume_later!(pid);
+                        latency.discard(pid);
+
+                        if let Some((_, links)) = attached_retprobes.remove(&pid) {
+                            detach_candidates(&mut uretprobes, links)?;
+                            debug!("[{pid}] uretprobe detached");
+                        }
+                    } else {
+                        let candidate = target.candidates.get(id).context(format!("[{pid}] unknown candidate #{id}"))?;
+                        let config = make_config!(return_addr, candidate.args_count, candidate.layout);
+                        let token = zygote.token();
+
+                        task::spawn(async move {
+                            signals::ensure_stopped(pid, !send_signal).await;
+
+                            if token.is_stale() {
+                                debug!("[{pid}] zygote is gone, dropping injection");
+                                let _ = kill(Pid::from_raw(pid), Signal::SIGCONT);
+                                return
+                            }
+
+                            if let Err(err) = context::run(pid, "attach", || loader::handle_proc(pid, &config)) {
+                                error!("failed to inject {err}");
+                            }
+                        });
+                    }
+                }
+                EbpfEvent::RequirePostSpecialize(pid) => {
+                    debug!("[{pid}] post specialize required");
+
+                    if let Some((_, links)) = attached_retprobes.remove(&pid) {
+                        detach_candidates(&mut uretprobes, links)?;
+                        debug!("[{pid}] uretprobe detached");
+                    }
+
+                    // arguments are only read before specialize
+                    let config = make_config!(0, 0, ArgLayout::UNKNOWN);
 
                     task::spawn(async move {
-                        if let Err(err) = loader::handle_proc(pid, &config) {
-                            error!("failed to inject {pid}: {err}");
+                        signals::ensure_stopped(pid, !send_signal).await;
+
+                        if let Err(err) = context::run(pid, "attach", || loader::handle_post_specialize(pid, &config)) {
+                            error!("failed to call post specialize hook {err}");
                         }
                     });
                 }
+                EbpfEvent::UprobeSkipped(pid) => {
+                    debug!("[{pid}] uid not in scope, skipped");
+                    latency.discard(pid);
+
+                    if let Some((_, links)) = attached_procs.remove(&pid) {
+                        detach_candidates(&mut uprobes, links)?;
+                        debug!("[{pid}] uprobe detached");
+                    }
+
+                    if let Some((_, links)) = attached_retprobes.remove(&pid) {
+                        detach_candidates(&mut uretprobes, links)?;
+                        debug!("[{pid}] uretprobe detached");
+                    }
+                }
+                EbpfEvent::TrackingFailed(pid) => {
+                    warn!("[{pid}] failed to track zygote child, it won't be injected or hidden");
+                }
                 EbpfEvent::RequireUmount(pid) => {
-                    debug!("[{pid}] umount required");
-                    fork_daemon(|| {
-                        umount_module_files(pid);
-                        process::exit(0);
-                    });
+                    // unknown without the uprobe, such as while nothing is in scope, modules are hidden then
+                    let umount = match specialize_uids.get(&pid, 0) {
+                        Ok(uid) => {
+                            let packages = packages::packages_of(uid).unwrap_or_else(|err| {
+                                warn!("[{pid}] failed to resolve packages of uid {uid}: {err}");
+                                Vec::new()
+                            });
+
+                            let umount = denylist::check(uid, &packages);
+                            debug!("[{pid}] uid {uid} ({}), umount: {umount}", packages.join(", "));
+
+                            umount
+                        }
+                        Err(_) => true
+                    };
+
+                    if umount {
+                        debug!("[{pid}] umount required");
+                        let umount_rules = Arc::clone(&umount_rules);
+
+                        task::spawn(async move {
+                            signals::ensure_stopped(pid, !send_signal).await;
+
+                            // waits for the intermediate child, which mustn't hold up a worker of the runtime
+                            task::spawn_blocking(move || fork_daemon(|| {
+                                umount::umount_module_files(pid, &umount_rules);
+                                process::exit(0);
+                            }));
+                        });
+                    } else {
+                        let _ = kill(Pid::from_raw(pid), Signal::SIGCONT);
+                    }
                 }
             }
 
@@ -338,14 +1303,12 @@ pub async fn main(bridge: &str, filter: Option<&str>) -> Result<()> {
         }
 
         if resume_pid != 0 {
-            if let Err(err) = kill(Pid::from_raw(resume_pid), Signal::SIGCONT) {
-                if err == Errno::ESRCH {
-                    continue
-                }
-                bail!(err);
-            }
+            resumer.resume(resume_pid);
         }
     }
 
+    shutdown(&mut uprobes, &mut uretprobes, &mut attached_procs, &mut attached_retprobes, &children);
+
+    // the remaining programs are detached as `ebpf` is dropped
     Ok(())
 }
@@ -0,0 +1,61 @@
+use std::ffi::CString;
+use std::ptr;
+
+use libloading::Symbol;
+use log::{debug, error};
+use nix::libc;
+use nix::libc::c_char;
+use tokio::sync::mpsc;
+use tokio::task;
+
+pub type ResultFn = Symbol<'static, extern "C" fn(libc::uid_t, *const c_char, bool)>;
+
+struct Outcome {
+    uid: libc::uid_t,
+    package: Option<String>,
+    success: bool
+}
+
+// tells the filter how each injection went, if it exports `on_result`, without holding up the injection
+#[derive(Clone)]
+pub struct ResultNotifier {
+    tx: mpsc::UnboundedSender<Outcome>
+}
+
+impl ResultNotifier {
+    pub fn new(on_result: ResultFn) -> Self {
+        let (tx, rx) = mpsc::unbounded_channel();
+        task::spawn(serve(rx, on_result));
+
+        Self { tx }
+    }
+
+    pub fn notify(&self, uid: libc::uid_t, package: Option<String>, success: bool) {
+        if self.tx.send(Outcome { uid, package, success }).is_err() {
+            error!("result notifier is gone, outcome of uid {uid} dropped");
+        }
+    }
+}
+
+async fn serve(mut rx: mpsc::UnboundedReceiver<Outcome>, on_result: ResultFn) {
+    let mut batch = Vec::new();
+
+    while rx.recv_many(&mut batch, usize::MAX).await != 0 {
+        let outcomes: Vec<_> = batch.drain(..).collect();
+        let on_result = on_result.clone();
+
+        // filter code may block, keep it off the runtime threads
+        let result = task::spawn_blocking(move || {
+            for Outcome { uid, package, success } in outcomes {
+                debug!("notifying filter: uid={uid}, package={package:?}, success={success}");
+
+                let package = package.and_then(|package| CString::new(package).ok());
+                on_result(uid, package.as_ref().map_or(ptr::null(), |package| package.as_ptr()), success);
+            }
+        }).await;
+
+        if let Err(err) = result {
+            error!("filter panicked in on_result: {err}");
+        }
+    }
+}
@@ -0,0 +1,53 @@
+use std::collections::HashMap;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::sync::Mutex;
+
+use anyhow::Result;
+use log::debug;
+
+const PACKAGES_LIST: &str = "/data/system/packages.list";
+
+pub const PER_USER_RANGE: u32 = 100000;
+
+struct Table {
+    // (mtime, size) of the file it was parsed from, package manager rewrites it on every install
+    modified: (i64, u64),
+    // app id to the packages sharing it
+    apps: HashMap<u32, Vec<String>>
+}
+
+static TABLE: Mutex<Option<Table>> = Mutex::new(None);
+
+fn read_table(modified: (i64, u64)) -> Result<Table> {
+    let mut apps: HashMap<u32, Vec<String>> = HashMap::new();
+
+    // `<package> <app id> <debuggable> <data dir> ...`
+    for line in fs::read_to_string(PACKAGES_LIST)?.lines() {
+        let mut fields = line.split_whitespace();
+
+        if let (Some(package), Some(Ok(app_id))) = (fields.next(), fields.next().map(str::parse)) {
+            apps.entry(app_id).or_default().push(package.into());
+        }
+    }
+
+    debug!("{} apps in {PACKAGES_LIST}", apps.len());
+
+    Ok(Table { modified, apps })
+}
+
+// packages of the uid in any user, more than one for a shared uid, none for isolated and app zygote uids
+pub fn packages_of(uid: u32) -> Result<Vec<String>> {
+    let metadata = fs::metadata(PACKAGES_LIST)?;
+    let modified = (metadata.mtime(), metadata.size());
+
+    let mut table = TABLE.lock().unwrap();
+
+    if !table.as_ref().is_some_and(|table| table.modified == modified) {
+        *table = Some(read_table(modified)?);
+    }
+
+    let apps = &table.as_ref().unwrap().apps;
+
+    Ok(apps.get(&(uid % PER_USER_RANGE)).cloned().unwrap_or_default())
+}
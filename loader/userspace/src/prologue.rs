@@ -0,0 +1,73 @@
+use anyhow::{bail, Result};
+
+// the first instruction of SpecializeCommon, which the uprobe has stepped over by the time the child stops,
+// it is undone so that it runs again after the return address is replaced
+#[derive(Debug, Clone, Copy)]
+pub struct Prologue {
+    pub name: &'static str,
+    // how far pc has moved past it
+    pub len: usize,
+    // how far it has moved sp down
+    pub stack: usize
+}
+
+impl Prologue {
+    const fn new(name: &'static str, len: usize, stack: usize) -> Self {
+        Self { name, len, stack }
+    }
+}
+
+// `before` is the word that ends right at pc of the stopped child
+#[cfg(target_arch = "x86_64")]
+pub fn decode(before: u64) -> Result<Prologue> {
+    let bytes = before.to_le_bytes();
+
+    // `endbr64`, a landing pad on CET builds
+    if bytes[4 ..] == [0xf3, 0x0f, 0x1e, 0xfa] {
+        return Ok(Prologue::new("endbr64", 4, 0))
+    }
+
+    match &bytes[6 ..] {
+        // `push %r8` - `push %r15`
+        [0x41, 0x50 ..= 0x57] => Ok(Prologue::new("push", 2, 8)),
+        // `push %rax` - `push %rdi`, usually `push %rbp`
+        [_, 0x50 ..= 0x57] => Ok(Prologue::new("push", 1, 8)),
+        _ => bail!("unknown prologue before pc: {before:016x}")
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+pub fn decode(before: u64) -> Result<Prologue> {
+    let insn = (before >> 32) as u32;
+
+    let prologue = match insn {
+        // landing pads of BTI builds
+        0xd503245f => Prologue::new("bti c", 4, 0),
+        0xd50324df => Prologue::new("bti jc", 4, 0),
+        // signs lr, which has to happen again for the replaced return address
+        0xd503233f => Prologue::new("paciasp", 4, 0),
+        0xd503237f => Prologue::new("pacibsp", 4, 0),
+        // `stp x29, x30, [sp, #-n]!`, lr is already on the stack
+        _ if insn & 0xffc07fff == 0xa9807bfd => {
+            let offset = ((insn >> 15) & 0x7f) as i32;
+            // signed 7-bit immediate, scaled by 8
+            let offset = ((offset << 25) >> 25) * 8;
+
+            if offset >= 0 {
+                bail!("unexpected stp offset in prologue: {insn:08x}");
+            }
+
+            Prologue::new("stp x29, x30", 4, -offset as usize)
+        }
+        // `sub sp, sp, #n`
+        _ if insn & 0xff8003ff == 0xd10003ff => {
+            let imm = ((insn >> 10) & 0xfff) as usize;
+            let shift = if insn & (1 << 22) != 0 { 12 } else { 0 };
+
+            Prologue::new("sub sp", 4, imm << shift)
+        }
+        _ => bail!("unknown prologue instruction: {insn:08x}")
+    };
+
+    Ok(prologue)
+}
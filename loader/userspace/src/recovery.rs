@@ -0,0 +1,45 @@
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use aya::maps::{HashMap, MapData};
+use log::{debug, info};
+use procfs::process::Process;
+
+use ebpf_common::protocol::PROTOCOL_VERSION;
+use ebpf_common::ProcessState;
+
+const BPFFS_ROOT: &str = "/sys/fs/bpf/zloader";
+
+// maps pinned by another protocol version may have a different layout, keep them apart
+pub fn pin_path() -> Result<PathBuf> {
+    let path = PathBuf::from(BPFFS_ROOT).join(format!("v{PROTOCOL_VERSION}"));
+    fs::create_dir_all(&path).context(format!("failed to create pin path {path:?}"))?;
+
+    Ok(path)
+}
+
+// find children that a previous instance stopped for uprobe attaching but never resumed
+pub fn stranded_children(children: &mut HashMap<MapData, i32, u32>) -> Result<Vec<i32>> {
+    let entries: Vec<_> = children.iter().flatten().collect();
+    let mut stranded = Vec::new();
+
+    for (pid, state) in entries {
+        let stat = match Process::new(pid).and_then(|process| process.stat()) {
+            Ok(stat) => stat,
+            Err(_) => {
+                debug!("[{pid}] exited while nobody was watching, dropping");
+                children.remove(&pid)?;
+                continue
+            }
+        };
+
+        // the state is switched right before the child stops itself
+        if state == ProcessState::WaitForUmount as u32 && stat.state == 'T' {
+            info!("[{pid}] left stopped by a previous instance");
+            stranded.push(pid);
+        }
+    }
+
+    Ok(stranded)
+}
@@ -0,0 +1,78 @@
+use std::collections::HashSet;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use log::{error, info};
+use procfs::process::MemoryMap;
+
+const REPORT_ROOT: &str = "/data/adb/zloader/reports";
+
+// packages marked for verbose injection, each mark is consumed by the next launch
+#[derive(Clone, Default)]
+pub struct VerboseTargets {
+    packages: Arc<Mutex<HashSet<String>>>
+}
+
+impl VerboseTargets {
+    pub fn mark(&self, package: &str) {
+        self.packages.lock().unwrap().insert(package.into());
+    }
+
+    pub fn unmark(&self, package: &str) -> bool {
+        self.packages.lock().unwrap().remove(package)
+    }
+
+    pub fn marked(&self) -> Vec<String> {
+        self.packages.lock().unwrap().iter().cloned().collect()
+    }
+
+    // returns a report for the package if it was marked
+    pub fn take(&self, package: &str, pid: i32) -> Option<Report> {
+        if !self.unmark(package) {
+            return None
+        }
+
+        match Report::create(package, pid) {
+            Ok(report) => Some(report),
+            Err(err) => {
+                error!("[{pid}] failed to create verbose report: {err}");
+                None
+            }
+        }
+    }
+}
+
+// a directory collecting diagnostics of a single verbose injection
+pub struct Report {
+    pid: i32,
+    dir: PathBuf
+}
+
+impl Report {
+    fn create(package: &str, pid: i32) -> Result<Self> {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let dir = PathBuf::from(REPORT_ROOT).join(format!("{package}-{pid}-{timestamp}"));
+
+        fs::create_dir_all(&dir).context(format!("failed to create {dir:?}"))?;
+        info!("[{pid}] verbose injection, report goes to {dir:?}");
+
+        Ok(Self { pid, dir })
+    }
+
+    pub fn write(&self, name: &str, content: &str) {
+        if let Err(err) = fs::write(self.dir.join(name), content) {
+            error!("[{}] failed to write {name} into verbose report: {err}", self.pid);
+        }
+    }
+
+    pub fn write_maps(&self, name: &str, maps: &[MemoryMap]) {
+        let content: String = maps.iter()
+            .map(|map| format!("{:x}-{:x} {} {:x} {:?}\n", map.address.0, map.address.1, map.perms.as_str(), map.offset, map.pathname))
+            .collect();
+
+        self.write(name, &content);
+    }
+}
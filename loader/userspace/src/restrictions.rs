@@ -0,0 +1,140 @@
+use std::fmt::{Display, Formatter};
+use std::fs;
+
+use anyhow::{bail, Result};
+use log::{debug, warn};
+use nix::libc;
+
+const YAMA_PTRACE_SCOPE: &str = "/proc/sys/kernel/yama/ptrace_scope";
+const SYSLOG_ACTION_READ_ALL: libc::c_int = 3;
+const KMSG_BUFFER_SIZE: usize = 1 << 20;
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum PtraceRestriction {
+    YamaNoAttach,
+    SelinuxDenial { scontext: String, tcontext: String }
+}
+
+impl Display for PtraceRestriction {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PtraceRestriction::YamaNoAttach => write!(
+                fmt,
+                "Yama ptrace_scope is 3 (no attach), which can't be lowered until reboot; \
+                check the ROM's init scripts or kernel cmdline for what sets it"
+            ),
+            PtraceRestriction::SelinuxDenial { scontext, tcontext } => write!(
+                fmt,
+                "SELinux denied ptrace from `{scontext}` to `{tcontext}`; \
+                add `allow {} {} process ptrace` to the module's sepolicy.rule",
+                selinux_type(scontext), selinux_type(tcontext)
+            )
+        }
+    }
+}
+
+fn selinux_type(context: &str) -> &str {
+    context.split(':').nth(2).unwrap_or(context)
+}
+
+pub fn yama_ptrace_scope() -> Option<u32> {
+    fs::read_to_string(YAMA_PTRACE_SCOPE).ok()?.trim().parse().ok()
+}
+
+fn read_kmsg() -> Result<String> {
+    let mut buffer = vec![0u8; KMSG_BUFFER_SIZE];
+
+    let len = unsafe {
+        libc::klogctl(SYSLOG_ACTION_READ_ALL, buffer.as_mut_ptr() as _, buffer.len() as _)
+    };
+
+    if len < 0 {
+        bail!("failed to read kernel log: {}", std::io::Error::last_os_error());
+    }
+
+    buffer.truncate(len as usize);
+
+    Ok(String::from_utf8_lossy(&buffer).into())
+}
+
+// recent SELinux denials, whichever process they are about
+pub fn audit_entries(limit: usize) -> Result<Vec<String>> {
+    let kmsg = read_kmsg()?;
+    let denials: Vec<_> = kmsg.lines().filter(|line| line.contains("avc:") && line.contains("denied")).collect();
+
+    Ok(denials[denials.len().saturating_sub(limit) ..].iter().map(|line| line.to_string()).collect())
+}
+
+fn find_field<'a>(line: &'a str, key: &str) -> Option<&'a str> {
+    line.split_whitespace().find_map(|field| field.strip_prefix(key))
+}
+
+fn selinux_context(proc: &str) -> Option<String> {
+    let context = fs::read_to_string(format!("/proc/{proc}/attr/current")).ok()?;
+    Some(context.trim_end_matches(['\0', '\n']).into())
+}
+
+fn ptrace_denials(pid: i32) -> Vec<PtraceRestriction> {
+    // only denials of ptrace from us to the target are ours, any other domain is none of our business
+    let (Some(source), Some(target)) = (selinux_context("self"), selinux_context(&pid.to_string())) else {
+        return Vec::new()
+    };
+
+    let kmsg = match read_kmsg() {
+        Ok(kmsg) => kmsg,
+        Err(err) => {
+            debug!("{err}");
+            return Vec::new()
+        }
+    };
+
+    let mut denials = Vec::new();
+
+    for line in kmsg.lines() {
+        if !line.contains("avc:") || !line.contains("denied") || !line.contains("{ ptrace }") {
+            continue
+        }
+
+        let scontext = find_field(line, "scontext=");
+        let tcontext = find_field(line, "tcontext=");
+
+        if let (Some(scontext), Some(tcontext)) = (scontext, tcontext) {
+            if scontext != source || tcontext != target {
+                continue
+            }
+
+            let denial = PtraceRestriction::SelinuxDenial {
+                scontext: scontext.into(),
+                tcontext: tcontext.into()
+            };
+
+            if !denials.contains(&denial) {
+                denials.push(denial);
+            }
+        }
+    }
+
+    denials
+}
+
+// find out why ptrace failed with EPERM even though we are root
+pub fn diagnose_ptrace(pid: i32) -> Vec<PtraceRestriction> {
+    let mut restrictions = Vec::new();
+
+    if yama_ptrace_scope() == Some(3) {
+        restrictions.push(PtraceRestriction::YamaNoAttach);
+    }
+
+    restrictions.extend(ptrace_denials(pid));
+
+    restrictions
+}
+
+// report restrictions that are known to break injection before any process is traced
+pub fn check() {
+    match yama_ptrace_scope() {
+        Some(3) => warn!("{}", PtraceRestriction::YamaNoAttach),
+        Some(scope) => debug!("yama ptrace_scope: {scope}"),
+        None => debug!("yama is not enabled")
+    }
+}
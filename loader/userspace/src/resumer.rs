@@ -0,0 +1,102 @@
+use std::sync::Arc;
+use std::sync::mpsc::{Receiver, Sender};
+use std::time::{Duration, Instant};
+
+use log::{debug, error, warn};
+use nix::errno::Errno;
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+
+use crate::drain::Wakeup;
+use crate::status::DaemonStatus;
+
+const RESUME_ATTEMPTS: usize = 3;
+const RETRY_DELAY: Duration = Duration::from_millis(10);
+
+// sends SIGCONT from the drain thread, a child that can't be resumed must not take the daemon down
+#[derive(Clone)]
+pub struct Resumer {
+    tx: Sender<i32>,
+    wakeup: Arc<Wakeup>
+}
+
+impl Resumer {
+    pub fn new(tx: Sender<i32>, wakeup: Arc<Wakeup>) -> Self {
+        Self { tx, wakeup }
+    }
+
+    pub fn resume(&self, pid: i32) {
+        if self.tx.send(pid).is_err() {
+            error!("[{pid}] resumer is gone, not resumed");
+            return
+        }
+
+        self.wakeup.notify();
+    }
+}
+
+// resumes pending on the drain thread, a failed attempt is retried later rather than slept on
+pub struct Resumes {
+    rx: Receiver<i32>,
+    status: DaemonStatus,
+    // pid, attempts so far and when to try again
+    retries: Vec<(i32, usize, Instant)>
+}
+
+impl Resumes {
+    pub fn new(rx: Receiver<i32>, status: DaemonStatus) -> Self {
+        Self { rx, status, retries: Vec::new() }
+    }
+
+    // true once it is done with the pid, either way
+    fn resume_one(&self, pid: i32, attempt: usize) -> bool {
+        match kill(Pid::from_raw(pid), Signal::SIGCONT) {
+            Ok(_) => {
+                self.status.resumed();
+                true
+            }
+            // exited meanwhile, nothing left to resume
+            Err(Errno::ESRCH) => {
+                debug!("[{pid}] exited before resume");
+                true
+            }
+            Err(err) if attempt < RESUME_ATTEMPTS => {
+                warn!("[{pid}] failed to resume (attempt {attempt}/{RESUME_ATTEMPTS}): {err}");
+                false
+            }
+            Err(err) => {
+                error!("[{pid}] failed to resume, it stays stopped: {err}");
+                self.status.resume_failed();
+                true
+            }
+        }
+    }
+
+    // also for children the drain thread lets go by itself
+    pub fn resume(&mut self, pid: i32) {
+        if !self.resume_one(pid, 1) {
+            self.retries.push((pid, 1, Instant::now() + RETRY_DELAY));
+        }
+    }
+
+    pub fn run(&mut self) {
+        while let Ok(pid) = self.rx.try_recv() {
+            self.resume(pid);
+        }
+
+        let now = Instant::now();
+        let due: Vec<_> = self.retries.iter().copied().filter(|(_, _, at)| *at <= now).collect();
+        self.retries.retain(|(_, _, at)| *at > now);
+
+        for (pid, attempt, _) in due {
+            if !self.resume_one(pid, attempt + 1) {
+                self.retries.push((pid, attempt + 1, now + RETRY_DELAY));
+            }
+        }
+    }
+
+    // how long the drain thread may wait for events before a retry is due
+    pub fn next_retry(&self) -> Option<Duration> {
+        self.retries.iter().map(|(_, _, at)| at.saturating_duration_since(Instant::now())).min()
+    }
+}
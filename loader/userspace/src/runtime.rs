@@ -0,0 +1,233 @@
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use log::{debug, info, warn};
+use object::{File, Object};
+
+use common::features;
+use common::properties::getprop;
+use common::zygote::ArgLayout;
+use ebpf_common::MAX_UPROBE_TARGETS;
+
+use crate::config::{DebugSymbolsConfig, SpecializeSignature};
+use crate::debuginfo;
+use crate::symbols::{self, ArgCounter, BytePattern, MappedFile, SymbolIndex};
+
+const RUNTIME_LIBRARY: &str = "/system/lib64/libandroid_runtime.so";
+// the signature of SDK 29, which the ones of SDK 30 to 34 only append to
+const SPECIALIZE_COMMON: &str = "_ZN12_GLOBAL__N_116SpecializeCommonEP7_JNIEnvjjP10_jintArrayiP13_jobjectArraylliP8_jstringS7_bbS7_S7_";
+
+// the mock zygote has its own SpecializeCommon, elsewhere it's always the one of the system
+pub fn runtime_library() -> &'static str {
+    features::RUNTIME_LIBRARY.raw().unwrap_or(RUNTIME_LIBRARY)
+}
+
+// build-id of the library, or its size and mtime when it has none
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum LibraryIdentity {
+    BuildId(Vec<u8>),
+    Metadata(u64, i64)
+}
+
+impl LibraryIdentity {
+    pub fn read<P: AsRef<Path>>(library: P) -> Result<Self> {
+        let library = library.as_ref();
+        let file = MappedFile::open(library)?;
+        let object = File::parse(file.data())?;
+
+        if let Some(build_id) = object.build_id()? {
+            return Ok(LibraryIdentity::BuildId(build_id.into()))
+        }
+
+        let metadata = fs::metadata(library)?;
+
+        Ok(LibraryIdentity::Metadata(metadata.size(), metadata.mtime()))
+    }
+}
+
+impl Display for LibraryIdentity {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LibraryIdentity::BuildId(build_id) => {
+                build_id.iter().try_for_each(|byte| write!(fmt, "{byte:02x}"))
+            }
+            LibraryIdentity::Metadata(size, mtime) => write!(fmt, "size={size}, mtime={mtime}")
+        }
+    }
+}
+
+// a symbol the uprobe goes to, vendors may rename or duplicate SpecializeCommon with another signature
+pub struct SpecializeCandidate {
+    pub name: String,
+    pub func_addr: u64,
+    pub args_count: usize,
+    // unknown if the signature can't be made sense of, the bridge falls back to the layout of the SDK then
+    pub layout: ArgLayout
+}
+
+// only a pattern matching exactly once is trusted, a uprobe in the middle of another function would stop anything
+fn scan_signatures(signatures: &[SpecializeSignature]) -> Result<Option<SpecializeCandidate>> {
+    let sdk = getprop("ro.build.version.sdk").parse().unwrap_or(0);
+    let library = runtime_library();
+
+    for signature in signatures.iter().filter(|signature| signature.applies_to(sdk)) {
+        let pattern = BytePattern::parse(&signature.pattern).context(format!("invalid pattern of `{}`", signature.name))?;
+        let matches = symbols::scan_text(library, &pattern)?;
+
+        match matches[..] {
+            [func_addr] => {
+                warn!(
+                    "SpecializeCommon located by pattern `{}` at 0x{func_addr:x}, assuming {} arguments and the layout of SDK {sdk}",
+                    signature.name, signature.args_count
+                );
+
+                return Ok(Some(SpecializeCandidate {
+                    name: format!("{} (pattern)", signature.name),
+                    func_addr,
+                    args_count: signature.args_count,
+                    layout: ArgLayout::UNKNOWN
+                }))
+            }
+            [] => info!("pattern `{}` not found", signature.name),
+            _ => warn!("pattern `{}` matched {} times, ignored", signature.name, matches.len())
+        }
+    }
+
+    warn!("no prologue pattern for SDK {sdk} matched in {library}");
+    Ok(None)
+}
+
+// where the uprobes go, which moves whenever libandroid_runtime is updated by an OTA
+pub struct UprobeTarget {
+    pub library: &'static str,
+    pub candidates: Vec<SpecializeCandidate>,
+    prefixes: Vec<String>,
+    fallbacks: Fallbacks,
+    identity: LibraryIdentity,
+    // set while the library changed but couldn't be resolved again, offsets may point anywhere
+    stale: bool
+}
+
+// what is tried in turn when none of the symbols are in the library itself
+#[derive(Debug, Clone, Default)]
+pub struct Fallbacks {
+    pub debug_symbols: DebugSymbolsConfig,
+    pub signatures: Vec<SpecializeSignature>
+}
+
+fn find_candidates(index: &SymbolIndex, prefixes: &[String]) -> Result<Vec<SpecializeCandidate>> {
+    let mut candidates: Vec<SpecializeCandidate> = Vec::new();
+
+    for prefix in prefixes {
+        let (name, func_addr) = match index.resolve_for_uprobe(prefix) {
+            Ok(symbol) => symbol,
+            Err(err) => {
+                warn!("failed to resolve `{prefix}`: {err}");
+                continue
+            }
+        };
+
+        // aliases of the same function would stop the process twice
+        if candidates.iter().any(|candidate| candidate.func_addr == func_addr) {
+            continue
+        }
+
+        if candidates.len() == MAX_UPROBE_TARGETS {
+            warn!("too many SpecializeCommon candidates, `{name}` ignored");
+            continue
+        }
+
+        let args_count = ArgCounter::count(&name)?;
+        let layout = symbols::arg_layout(&name).unwrap_or_else(|err| {
+            warn!("{err}");
+            ArgLayout::UNKNOWN
+        });
+
+        candidates.push(SpecializeCandidate { name, func_addr, args_count, layout });
+    }
+
+    Ok(candidates)
+}
+
+impl UprobeTarget {
+    pub fn resolve(prefixes: &[String], fallbacks: &Fallbacks) -> Result<Self> {
+        let prefixes = if prefixes.is_empty() {
+            vec![SPECIALIZE_COMMON.into()]
+        } else {
+            prefixes.to_vec()
+        };
+
+        let library = runtime_library();
+        let identity = LibraryIdentity::read(library)?;
+        let mut index = SymbolIndex::open(library)?;
+        let mut candidates = find_candidates(&index, &prefixes)?;
+
+        // symbols dropped in by the user, rather than patterns which may match the wrong function
+        let debug = match &identity {
+            LibraryIdentity::BuildId(build_id) if candidates.is_empty() => {
+                debuginfo::find_debug_file(build_id, &fallbacks.debug_symbols)
+            }
+            _ => None
+        };
+
+        if let Some(debug) = debug {
+            warn!("none of {prefixes:?} found in {library}, trying debug file {}", debug.display());
+            index.add_debug_file(Path::new(library), debug.as_path())?;
+            candidates = find_candidates(&index, &prefixes)?;
+        }
+
+        if candidates.is_empty() && !fallbacks.signatures.is_empty() {
+            warn!("none of {prefixes:?} found in {library}, the symbol may be stripped, falling back to prologue patterns");
+            candidates.extend(scan_signatures(&fallbacks.signatures)?);
+        }
+
+        if candidates.is_empty() {
+            bail!("none of {prefixes:?} found in {library}");
+        }
+
+        let fallbacks = fallbacks.clone();
+        Ok(Self { library, candidates, prefixes, fallbacks, identity, stale: false })
+    }
+
+    // re-resolve if the library changed since the last resolution, return whether anything was updated
+    pub fn refresh(&mut self) -> Result<bool> {
+        // stays stale on any error below, until a later refresh succeeds
+        self.stale = true;
+
+        let identity = LibraryIdentity::read(self.library)?;
+
+        if identity == self.identity {
+            self.stale = false;
+            return Ok(false)
+        }
+
+        let previous = self.identity.clone();
+        *self = Self::resolve(&self.prefixes, &self.fallbacks)?;
+
+        info!("runtime updated: {} changed from {previous} to {}", self.library, self.identity);
+        self.log_candidates();
+
+        Ok(true)
+    }
+
+    pub fn is_stale(&self) -> bool {
+        self.stale
+    }
+
+    pub fn log_candidates(&self) {
+        for (id, candidate) in self.candidates.iter().enumerate() {
+            info!(
+                "SpecializeCommon candidate #{id}: {} at 0x{:x} with {} arguments",
+                candidate.name, candidate.func_addr, candidate.args_count
+            );
+            debug!("SpecializeCommon candidate #{id} layout: {}", candidate.layout);
+        }
+    }
+
+    pub fn identity(&self) -> String {
+        self.identity.to_string()
+    }
+}
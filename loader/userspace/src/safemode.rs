@@ -0,0 +1,81 @@
+use std::fmt::{Display, Formatter};
+use std::fs::{self, File};
+use std::os::fd::AsRawFd;
+
+use anyhow::Result;
+use log::debug;
+use nix::libc;
+
+use common::features;
+
+// removed by the user once whatever made zygote crash is sorted out
+pub const MARKER: &str = "/data/adb/zloader/safe_mode";
+
+const INPUT_DEVICES: &str = "/dev/input";
+const KEY_VOLUMEDOWN: usize = 114;
+const KEY_MAX: usize = 0x2ff;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Trigger {
+    Marker,
+    Property,
+    VolumeKey
+}
+
+impl Display for Trigger {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Trigger::Marker => write!(fmt, "{MARKER} exists"),
+            Trigger::Property => write!(fmt, "{} is set", features::SAFE_MODE),
+            Trigger::VolumeKey => write!(fmt, "volume down is held")
+        }
+    }
+}
+
+// `EVIOCGKEY(len)`, the same encoding on every architecture we run on
+const fn eviocgkey(len: usize) -> libc::c_ulong {
+    (2 << 30) | ((len as libc::c_ulong) << 16) | ((b'E' as libc::c_ulong) << 8) | 0x18
+}
+
+fn volume_down_held() -> bool {
+    let entries = match fs::read_dir(INPUT_DEVICES) {
+        Ok(entries) => entries,
+        Err(err) => {
+            debug!("failed to list input devices: {err}");
+            return false
+        }
+    };
+
+    entries.flatten()
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with("event"))
+        .filter_map(|entry| File::open(entry.path()).ok())
+        .any(|device| {
+            let mut keys = [0u8; KEY_MAX / 8 + 1];
+            let res = unsafe { libc::ioctl(device.as_raw_fd(), eviocgkey(keys.len()) as _, keys.as_mut_ptr()) };
+
+            res >= 0 && keys[KEY_VOLUMEDOWN / 8] & (1 << (KEY_VOLUMEDOWN % 8)) != 0
+        })
+}
+
+// the daemon only observes while in safe mode, nothing is attached or injected
+pub fn check() -> Option<Trigger> {
+    if fs::metadata(MARKER).is_ok() {
+        return Some(Trigger::Marker)
+    }
+
+    if features::safe_mode() {
+        return Some(Trigger::Property)
+    }
+
+    if volume_down_held() {
+        return Some(Trigger::VolumeKey)
+    }
+
+    None
+}
+
+// persist safe mode, so that a restarted daemon doesn't bring the bootloop back
+pub fn enter(reason: &str) -> Result<()> {
+    fs::write(MARKER, format!("{reason}\n"))?;
+    Ok(())
+}
@@ -0,0 +1,61 @@
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use log::{debug, warn};
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+use procfs::process::Process;
+use tokio::time;
+
+use crate::features;
+
+const STOP_RETRIES: usize = 50;
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(1);
+const STOP_RESEND_EVERY: usize = 10;
+
+pub fn send_signal_supported() -> bool {
+    match features::helper_supported(features::BPF_FUNC_SEND_SIGNAL_THREAD) {
+        Ok(_) => true,
+        Err(err) => {
+            debug!("bpf_send_signal_thread probe failed: {err}");
+            false
+        }
+    }
+}
+
+fn is_stopped(pid: i32) -> Result<bool> {
+    Ok(Process::new(pid)?.stat()?.state == 'T')
+}
+
+// used when the kernel can't stop the process by itself, the process keeps running until the signal lands
+pub fn stop_process(pid: i32) {
+    if let Err(err) = kill(Pid::from_raw(pid), Signal::SIGSTOP) {
+        warn!("[{pid}] failed to stop from userspace: {err}");
+    }
+}
+
+async fn wait_stopped(pid: i32) -> Result<()> {
+    for retry in 0 .. STOP_RETRIES {
+        if retry != 0 && retry % STOP_RESEND_EVERY == 0 {
+            kill(Pid::from_raw(pid), Signal::SIGSTOP)?;
+        }
+
+        if is_stopped(pid)? {
+            debug!("[{pid}] stopped from userspace after {retry} retries");
+            return Ok(())
+        }
+
+        time::sleep(STOP_POLL_INTERVAL).await;
+    }
+
+    bail!("[{pid}] still running after {STOP_RETRIES} attempts to stop it");
+}
+
+// awaited by whatever handles the child next rather than by the event loop, a no-op when the kernel stopped it
+pub async fn ensure_stopped(pid: i32, from_userspace: bool) {
+    if from_userspace {
+        if let Err(err) = wait_stopped(pid).await {
+            warn!("{err}, handled anyway");
+        }
+    }
+}
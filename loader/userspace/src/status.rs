@@ -0,0 +1,191 @@
+use std::fmt::{Display, Formatter};
+use std::collections::BTreeSet;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicI32, AtomicU64, Ordering};
+use std::time::Duration;
+
+use crate::zygote_mode::ZygoteMode;
+
+// what the daemon is doing, shared between the event loop, injection tasks and the control socket
+#[derive(Clone, Default)]
+pub struct DaemonStatus {
+    inner: Arc<StatusInner>
+}
+
+#[derive(Default)]
+struct StatusInner {
+    // 0 while no 64-bit zygote is running
+    zygote: AtomicI32,
+    events: AtomicU64,
+    attempted: AtomicU64,
+    injected: AtomicU64,
+    failed: AtomicU64,
+    attach_failed: AtomicU64,
+    missed: AtomicU64,
+    resumed: AtomicU64,
+    resume_failed: AtomicU64,
+    // total time spent in injections that were attempted, in microseconds
+    injection_time: AtomicU64,
+    dropped: AtomicU64,
+    rate_limited: AtomicU64,
+    // events of stopped children let go by the drain thread while the event loop lagged behind
+    shed: AtomicU64,
+    missed_heartbeats: AtomicU64,
+    unmounted: AtomicU64,
+    umount_failed: AtomicU64,
+    // children with module files left mounted
+    incomplete_umounts: AtomicU64,
+    // packages whose bridge didn't report after a successful injection
+    half_injected: Mutex<BTreeSet<String>>,
+    // what the running zygote was started with, see `ZygoteMode`
+    zygote_flags: Mutex<Vec<&'static str>>,
+    paused: AtomicBool
+}
+
+impl DaemonStatus {
+    pub fn set_zygote(&self, pid: Option<i32>) {
+        self.inner.zygote.store(pid.unwrap_or(0), Ordering::Relaxed);
+    }
+
+    pub fn set_zygote_mode(&self, mode: &ZygoteMode) {
+        *self.inner.zygote_flags.lock().unwrap() = mode.flags();
+    }
+
+    pub fn event(&self) {
+        self.inner.events.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn attempted(&self, elapsed: Duration) {
+        self.inner.attempted.fetch_add(1, Ordering::Relaxed);
+        self.inner.injection_time.fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub fn attach_failed(&self) {
+        self.inner.attach_failed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn missed(&self) {
+        self.inner.missed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn resumed(&self) {
+        self.inner.resumed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn resume_failed(&self) {
+        self.inner.resume_failed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // the total is kept by eBPF, only mirrored here
+    pub fn set_dropped(&self, total: u64) {
+        self.inner.dropped.store(total, Ordering::Relaxed);
+    }
+
+    pub fn set_rate_limited(&self, total: u64) {
+        self.inner.rate_limited.store(total, Ordering::Relaxed);
+    }
+
+    pub fn shed(&self) {
+        self.inner.shed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn injected(&self) {
+        self.inner.injected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn failed(&self) {
+        self.inner.failed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn heartbeat_missed(&self) {
+        self.inner.missed_heartbeats.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn umounted(&self, unmounted: u64, failed: u64) {
+        self.inner.unmounted.fetch_add(unmounted, Ordering::Relaxed);
+        self.inner.umount_failed.fetch_add(failed, Ordering::Relaxed);
+
+        if failed > 0 {
+            self.inner.incomplete_umounts.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn half_injected(&self, package: &str) {
+        self.inner.half_injected.lock().unwrap().insert(package.into());
+    }
+
+    fn half_injected_packages(&self) -> Vec<String> {
+        self.inner.half_injected.lock().unwrap().iter().cloned().collect()
+    }
+
+    // return false if nothing changed
+    pub fn set_paused(&self, paused: bool) -> bool {
+        self.inner.paused.swap(paused, Ordering::Relaxed) != paused
+    }
+
+    // zygote children are still tracked and resumed while paused, only injection is skipped
+    pub fn is_paused(&self) -> bool {
+        self.inner.paused.load(Ordering::Relaxed)
+    }
+
+    // flat JSON object for module managers, so that nobody has to parse logcat
+    pub fn stats(&self) -> String {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+
+        let attempted = load(&self.inner.attempted);
+        let average = load(&self.inner.injection_time).checked_div(attempted).unwrap_or(0);
+
+        // package names never need escaping
+        let half_injected: Vec<_> = self.half_injected_packages().iter().map(|package| format!("\"{package}\"")).collect();
+        let zygote_flags: Vec<_> = self.inner.zygote_flags.lock().unwrap().iter().map(|flag| format!("\"{flag}\"")).collect();
+
+        format!(
+            "{{\"events\":{},\"injections_attempted\":{attempted},\"injections_succeeded\":{},\"injections_failed\":{},\
+            \"uprobe_attach_failures\":{},\"missed_hooks\":{},\"resumed\":{},\"resume_failures\":{},\
+            \"average_injection_us\":{average},\"dropped_events\":{},\"rate_limited\":{},\"shed_events\":{},\"missed_heartbeats\":{},\
+            \"unmounted\":{},\"umount_failures\":{},\"incomplete_umounts\":{},\"half_injected\":[{}],\
+            \"zygote_flags\":[{}],\"paused\":{}}}",
+            load(&self.inner.events),
+            load(&self.inner.injected),
+            load(&self.inner.failed),
+            load(&self.inner.attach_failed),
+            load(&self.inner.missed),
+            load(&self.inner.resumed),
+            load(&self.inner.resume_failed),
+            load(&self.inner.dropped),
+            load(&self.inner.rate_limited),
+            load(&self.inner.shed),
+            load(&self.inner.missed_heartbeats),
+            load(&self.inner.unmounted),
+            load(&self.inner.umount_failed),
+            load(&self.inner.incomplete_umounts),
+            half_injected.join(","),
+            zygote_flags.join(","),
+            self.is_paused()
+        )
+    }
+}
+
+impl Display for DaemonStatus {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> std::fmt::Result {
+        let zygote = match self.inner.zygote.load(Ordering::Relaxed) {
+            0 => "none".into(),
+            pid => pid.to_string()
+        };
+
+        write!(
+            fmt, "zygote={zygote} injected={} failed={} paused={}",
+            self.inner.injected.load(Ordering::Relaxed),
+            self.inner.failed.load(Ordering::Relaxed),
+            self.is_paused()
+        )?;
+
+        let half_injected = self.half_injected_packages();
+
+        if !half_injected.is_empty() {
+            write!(fmt, " half_injected={}", half_injected.join(","))?;
+        }
+
+        Ok(())
+    }
+}
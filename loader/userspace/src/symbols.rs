@@ -1,8 +1,19 @@
-use std::{fmt, fs};
+use std::collections::HashMap;
+use std::ffi::c_void;
+use std::fmt;
+use std::fs::File as FsFile;
+use std::os::fd::AsRawFd;
 use std::path::Path;
-use anyhow::{bail, Context, Result};
+use std::{ptr, slice};
+use anyhow::{anyhow, bail, Context, Result};
+use common::zygote::{ArgKind, ArgLayout};
 use cpp_demangle::{DemangleOptions, DemangleWrite, Symbol};
-use object::{File, Object, ObjectKind, ObjectSection, ObjectSymbol};
+use nix::errno::Errno;
+use nix::libc;
+use object::{Endianness, File, Object, ObjectKind, ObjectSection, ObjectSymbol, SectionKind};
+use object::elf::{FileHeader64, DT_NEEDED};
+use object::read::elf::{Dyn, FileHeader};
+use crate::arch_select;
 
 pub struct ArgCounter {
     count: usize
@@ -35,68 +46,307 @@ impl ArgCounter {
     }
 }
 
-pub fn resolve<P : AsRef<Path>>(library: P, name: &str) -> Result<usize> {
-    let data = fs::read(library)?;
-    let object = File::parse(data.as_slice())?;
-
-    object.dynamic_symbols().chain(object.symbols())
-        .find_map(|sym| {
-            if sym.name() == Ok(name) {
-                Some(sym.address() as usize)
-            } else {
-                None
+// parameter types of a mangled function, as the demangler spells them, such as `_jstring*` or `unsigned int`
+pub fn parameter_types(sym: &str) -> Result<Vec<String>> {
+    let demangled = Symbol::new(sym)?.demangle(&DemangleOptions::default())?;
+    let params = demangled.strip_suffix(')').context(format!("`{demangled}` is not a function"))?;
+
+    // the parameter list is what the last closing parenthesis closes, parentheses also show up in names
+    let mut depth = 0;
+    let mut start = None;
+
+    for (index, c) in params.char_indices().rev() {
+        match c {
+            ')' => depth += 1,
+            '(' if depth == 0 => {
+                start = Some(index + 1);
+                break
             }
-        })
-        .context(format!("failed to resolve symbol {name}"))
+            '(' => depth -= 1,
+            _ => ()
+        }
+    }
+
+    let params = &params[start.context(format!("unbalanced parentheses in `{demangled}`"))? ..];
+    let mut types = Vec::new();
+    let mut current = String::new();
+    let mut depth = 0;
+
+    for c in params.chars() {
+        match c {
+            '(' | '<' => depth += 1,
+            ')' | '>' => depth -= 1,
+            ',' if depth == 0 => {
+                types.push(current.trim().to_string());
+                current.clear();
+                continue
+            }
+            _ => ()
+        }
+
+        current.push(c);
+    }
+
+    if !current.trim().is_empty() {
+        types.push(current.trim().to_string());
+    }
+
+    Ok(types)
 }
 
-pub fn resolve_for_uprobe<P : AsRef<Path>>(library: P, prefix: &str) -> Result<(String, u64)> {
-    let data = fs::read(library)?;
+fn arg_kind(ty: &str) -> ArgKind {
+    match ty.replace(" *", "*").as_str() {
+        "_JNIEnv*" => ArgKind::Env,
+        "unsigned int" => ArgKind::UInt,
+        "int" => ArgKind::Int,
+        "long" | "long long" => ArgKind::Long,
+        "bool" => ArgKind::Bool,
+        "_jintArray*" => ArgKind::IntArray,
+        "_jobjectArray*" => ArgKind::ObjectArray,
+        "_jstring*" => ArgKind::String,
+        _ => ArgKind::Other
+    }
+}
 
-    fn internal(data: &[u8], prefix: &str, mirror: Option<&File>) -> Result<(String, u64)> {
+// where each argument SpecializeArgs knows of is, from the actual signature rather than the SDK version
+pub fn arg_layout(sym: &str) -> Result<ArgLayout> {
+    let signature: Vec<_> = parameter_types(sym)?.iter().map(|ty| arg_kind(ty)).collect();
+    ArgLayout::derive(&signature).map_err(|err| anyhow!("unexpected signature of `{sym}`: {err}"))
+}
+
+// a library mapped read-only, only the pages actually parsed are read from disk
+pub struct MappedFile {
+    addr: *mut c_void,
+    len: usize
+}
+
+// never written through, and unmapped only on drop
+unsafe impl Send for MappedFile {}
+unsafe impl Sync for MappedFile {}
+
+impl MappedFile {
+    pub fn open<P : AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let file = FsFile::open(path).context(format!("failed to open {path:?}"))?;
+        let len = file.metadata()?.len() as usize;
+
+        if len == 0 {
+            bail!("{path:?} is empty");
+        }
+
+        let addr = unsafe {
+            libc::mmap(ptr::null_mut(), len, libc::PROT_READ, libc::MAP_PRIVATE, file.as_raw_fd(), 0)
+        };
+
+        if addr == libc::MAP_FAILED {
+            bail!("failed to map {path:?}: {}", Errno::last());
+        }
+
+        Ok(Self { addr, len })
+    }
+
+    pub fn data(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.addr as *const u8, self.len) }
+    }
+}
+
+impl Drop for MappedFile {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.addr, self.len);
+        }
+    }
+}
+
+struct IndexedSymbol {
+    name: String,
+    address: u64,
+    // where it is in the file, which uprobes are attached by, none if it isn't backed by the file
+    file_offset: Option<u64>
+}
+
+// defined symbols of a library, those of `.gnu_debugdata` included, parsed once and looked up many times
+pub struct SymbolIndex {
+    // in the order they were looked up before: dynamic, static, then those of `.gnu_debugdata`
+    symbols: Vec<IndexedSymbol>,
+    // the first one of each name
+    by_name: HashMap<String, usize>
+}
+
+impl SymbolIndex {
+    pub fn open<P : AsRef<Path>>(library: P) -> Result<Self> {
+        let file = MappedFile::open(library)?;
+        let mut index = Self { symbols: Vec::new(), by_name: HashMap::new() };
+
+        index.add(file.data(), None)?;
+
+        Ok(index)
+    }
+
+    // `mirror` is the object a `.gnu_debugdata` came from, its sections are where the file offsets are
+    fn add(&mut self, data: &[u8], mirror: Option<&File>) -> Result<()> {
         let object = File::parse(data)?;
 
-        let mut symbols = object.dynamic_symbols().chain(object.symbols());
-        let symbol = symbols.find(|sym| sym.name().is_ok_and(|name| name.starts_with(prefix)));
-
-        if let Some(symbol) = symbol {
-            let name: String = symbol.name()?.into();
-            let addr = match object.kind() {
-                ObjectKind::Dynamic | ObjectKind::Executable => {
-                    let index = symbol.section_index()
-                        .context(format!("symbol `{prefix}` does not appear in section"))?;
-
-                    let section = if let Some(mirror) = mirror {
-                        let name = object.section_by_index(index)?.name()?;
-                        mirror
-                            .section_by_name(name)
-                            .context(format!("can't find section `{name}` in mirror"))?
-                    } else {
-                        object.section_by_index(index)?
-                    };
+        for symbol in object.dynamic_symbols().chain(object.symbols()) {
+            let name = match symbol.name() {
+                Ok(name) if !name.is_empty() && !symbol.is_undefined() => name,
+                _ => continue
+            };
 
-                    let (offset, _length) = section.file_range()
-                        .context(format!("symbol `{prefix}` in section `{:?}` which has no offset", section.name()?))?;
+            let file_offset = match object.kind() {
+                ObjectKind::Dynamic | ObjectKind::Executable => symbol.section_index().and_then(|index| {
+                    let section = match mirror {
+                        Some(mirror) => mirror.section_by_name(object.section_by_index(index).ok()?.name().ok()?)?,
+                        None => object.section_by_index(index).ok()?
+                    };
 
-                    symbol.address() - section.address() + offset
-                }
-                _ => symbol.address()
+                    let (offset, _length) = section.file_range()?;
+                    Some(symbol.address() - section.address() + offset)
+                }),
+                _ => Some(symbol.address())
             };
-            
-            return Ok((name, addr))
+
+            self.by_name.entry(name.into()).or_insert(self.symbols.len());
+            self.symbols.push(IndexedSymbol { name: name.into(), address: symbol.address(), file_offset });
         }
 
-        if let Some(section) = object.section_by_name(".gnu_debugdata") {
-            let mut buffer: &[u8] = section.data()?;
-            let mut inner = Vec::new();
+        if mirror.is_none() {
+            if let Some(section) = object.section_by_name(".gnu_debugdata") {
+                let mut buffer: &[u8] = section.data()?;
+                let mut inner = Vec::new();
 
-            lzma_rs::xz_decompress(&mut buffer, &mut inner)?;
+                lzma_rs::xz_decompress(&mut buffer, &mut inner)?;
 
-            return internal(&inner, prefix, Some(&object));
+                self.add(&inner, Some(&object))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // symbols of a detached debug file, whose sections are only placeholders for those of the library
+    pub fn add_debug_file<P : AsRef<Path>>(&mut self, library: P, debug: P) -> Result<()> {
+        let library = MappedFile::open(library)?;
+        let debug = MappedFile::open(debug)?;
+        let object = File::parse(library.data())?;
+
+        self.add(debug.data(), Some(&object))
+    }
+
+    pub fn count(&self) -> usize {
+        self.symbols.len()
+    }
+
+    pub fn resolve(&self, name: &str) -> Result<usize> {
+        self.by_name.get(name)
+            .map(|index| self.symbols[*index].address as usize)
+            .context(format!("failed to resolve symbol {name}"))
+    }
+
+    pub fn resolve_many(&self, names: &[&str]) -> Result<Vec<usize>> {
+        names.iter().map(|name| self.resolve(name)).collect()
+    }
+
+    pub fn resolve_for_uprobe(&self, prefix: &str) -> Result<(String, u64)> {
+        let symbol = self.symbols.iter()
+            .find(|symbol| symbol.name.starts_with(prefix))
+            .context(format!("failed to resolve symbol `{prefix}`"))?;
+
+        let offset = symbol.file_offset
+            .context(format!("symbol `{}` is not backed by the file", symbol.name))?;
+
+        Ok((symbol.name.clone(), offset))
+    }
+}
+
+pub fn resolve<P : AsRef<Path>>(library: P, name: &str) -> Result<usize> {
+    SymbolIndex::open(library)?.resolve(name)
+}
+
+pub fn resolve_many<P : AsRef<Path>>(library: P, names: &[&str]) -> Result<Vec<usize>> {
+    SymbolIndex::open(library)?.resolve_many(names)
+}
+
+// DT_NEEDED entries of a shared library
+pub fn needed_libraries<P : AsRef<Path>>(library: P) -> Result<Vec<String>> {
+    let file = MappedFile::open(library)?;
+    let data = file.data();
+    let header = FileHeader64::<Endianness>::parse(data)?;
+    let endian = header.endian()?;
+    let sections = header.sections(endian, data)?;
+
+    let (entries, link) = match sections.dynamic(endian, data)? {
+        Some(dynamic) => dynamic,
+        None => return Ok(Vec::new())
+    };
+
+    let strings = sections.strings(endian, data, link)?;
+    let mut needed = Vec::new();
+
+    for entry in entries {
+        if entry.tag32(endian) == Some(DT_NEEDED) {
+            needed.push(String::from_utf8_lossy(entry.string(endian, strings)?).into());
         }
+    }
+
+    Ok(needed)
+}
+
+pub fn resolve_for_uprobe<P : AsRef<Path>>(library: P, prefix: &str) -> Result<(String, u64)> {
+    SymbolIndex::open(library)?.resolve_for_uprobe(prefix)
+}
+
+// a function can only start at an instruction boundary
+const INSTRUCTION_ALIGN: usize = arch_select!(1, 4);
+
+// hex bytes separated by whitespace, `??` matches any byte
+#[derive(Debug, Clone)]
+pub struct BytePattern {
+    bytes: Vec<Option<u8>>
+}
+
+impl BytePattern {
+    pub fn parse(pattern: &str) -> Result<Self> {
+        let bytes = pattern.split_whitespace()
+            .map(|byte| match byte {
+                "??" => Ok(None),
+                byte => u8::from_str_radix(byte, 16).map(Some).context(format!("invalid byte `{byte}` in pattern"))
+            })
+            .collect::<Result<Vec<_>>>()?;
 
-        bail!("failed to resolve symbol `{prefix}`")
+        // a leading wildcard would make every match ambiguous by one byte
+        if !matches!(bytes.first(), Some(Some(_))) {
+            bail!("pattern must start with a concrete byte: {pattern}");
+        }
+
+        Ok(Self { bytes })
+    }
+
+    fn matches(&self, data: &[u8]) -> bool {
+        data.len() >= self.bytes.len() && self.bytes.iter().zip(data).all(|(byte, actual)| byte.is_none() || *byte == Some(*actual))
+    }
+}
+
+// file offsets of every match in the executable sections of a library, for when the symbol is stripped
+pub fn scan_text<P : AsRef<Path>>(library: P, pattern: &BytePattern) -> Result<Vec<u64>> {
+    let file = MappedFile::open(library)?;
+    let object = File::parse(file.data())?;
+    let mut matches = Vec::new();
+
+    for section in object.sections().filter(|section| section.kind() == SectionKind::Text) {
+        let (offset, _length) = match section.file_range() {
+            Some(range) => range,
+            None => continue
+        };
+
+        let data = section.data()?;
+
+        for start in (0 .. data.len()).step_by(INSTRUCTION_ALIGN) {
+            if pattern.matches(&data[start ..]) {
+                matches.push(offset + start as u64);
+            }
+        }
     }
 
-    internal(&data, prefix, None)
+    Ok(matches)
 }
@@ -0,0 +1,234 @@
+use std::collections::{HashMap, HashSet};
+use std::ffi::CString;
+use std::fs::File;
+use std::io;
+use std::os::fd::{AsFd, OwnedFd};
+use std::path::{Path, PathBuf};
+use std::ptr;
+
+use anyhow::Result;
+use log::{debug, error, warn};
+use nix::errno::Errno;
+use nix::libc;
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+use procfs::process::{MountInfo, MountOptFields, Process};
+use rustix::path::Arg;
+use rustix::thread;
+
+use common::features;
+
+use crate::config::{UmountConfig, UmountRule};
+use crate::control;
+
+fn rule() -> UmountRule {
+    UmountRule::default()
+}
+
+// what the managers mount as of writing, kept as rules so that a config can make up for a newer one
+fn kernelsu_rules() -> Vec<UmountRule> {
+    vec![
+        UmountRule { mount_point: Some("/data/adb/modules".into()), keep: true, ..rule() },
+        UmountRule { mount_point: Some("/data/adb".into()), ..rule() },
+        UmountRule { source: Some("KSU".into()), fs_types: vec!["overlay".into(), "tmpfs".into()], ..rule() },
+        // the loop device of the modules image, mounted elsewhere
+        UmountRule { source_of: Some("/data/adb/modules".into()), ..rule() }
+    ]
+}
+
+fn magisk_rules() -> Vec<UmountRule> {
+    vec![
+        UmountRule { source: Some("magisk".into()), ..rule() },
+        UmountRule { source: Some("worker".into()), ..rule() },
+        UmountRule { root: Some("/adb/modules".into()), ..rule() }
+    ]
+}
+
+pub fn rules(config: &UmountConfig) -> Vec<UmountRule> {
+    let mut rules = if config.replace_defaults {
+        Vec::new()
+    } else if features::kernelsu() {
+        kernelsu_rules()
+    } else {
+        magisk_rules()
+    };
+
+    rules.extend(config.rules.iter().cloned());
+    rules
+}
+
+fn matches(rule: &UmountRule, info: &MountInfo, mounts: &[MountInfo]) -> bool {
+    if rule.source.as_ref().is_some_and(|source| info.mount_source.as_ref() != Some(source)) {
+        return false
+    }
+
+    if !rule.fs_types.is_empty() && !rule.fs_types.contains(&info.fs_type) {
+        return false
+    }
+
+    if rule.mount_point.as_ref().is_some_and(|prefix| !info.mount_point.starts_with(prefix)) {
+        return false
+    }
+
+    if rule.root.as_ref().is_some_and(|prefix| !info.root.starts_with(prefix.as_str())) {
+        return false
+    }
+
+    if let Some(point) = &rule.source_of {
+        let point = Path::new(point);
+
+        // the last one mounted there is the one in effect
+        let source = mounts.iter().rev()
+            .find(|mount| mount.mount_point == point)
+            .and_then(|mount| mount.mount_source.as_ref());
+
+        if source.is_none() || info.mount_source.as_ref() != source || info.mount_point == point {
+            return false
+        }
+    }
+
+    true
+}
+
+// mounts to unmount, in the order they were mounted
+fn select<'a>(mounts: &'a [MountInfo], rules: &[UmountRule]) -> Vec<&'a MountInfo> {
+    mounts.iter()
+        .filter(|info| {
+            let matched: Vec<_> = rules.iter().filter(|rule| matches(rule, info, mounts)).collect();
+            !matched.is_empty() && matched.iter().all(|rule| !rule.keep)
+        })
+        .collect()
+}
+
+fn path_cstring(path: &Path) -> Result<CString> {
+    Ok(CString::new(path.to_string_lossy().to_string())?)
+}
+
+// a peer or a slave still receives mounts from the namespace of zygote
+fn receives_propagation(info: &MountInfo) -> bool {
+    info.opt_fields.iter().any(|field| matches!(field, MountOptFields::Shared(_) | MountOptFields::Master(_)))
+}
+
+// otherwise a module mounted again in the namespace of zygote, or a mount over the same spot, would propagate
+// into the child after the umount pass; only the selected mounts and what they are mounted on are cut off,
+// `/` stays a slave since storage mounts of vold reach apps through it
+fn make_private(pid: i32, mounts: &[MountInfo], selected: &[&MountInfo]) -> Result<()> {
+    let ids: HashSet<_> = selected.iter().flat_map(|info| [info.mnt_id, info.pid]).collect();
+
+    let targets = mounts.iter().filter(|info| {
+        ids.contains(&info.mnt_id) && info.mount_point != Path::new("/") && receives_propagation(info)
+    });
+
+    for info in targets {
+        let mp = path_cstring(&info.mount_point)?;
+        debug!("[{pid}] making private: {} ({:?})", info.mount_point.display(), info.opt_fields);
+
+        if unsafe { libc::mount(ptr::null(), mp.as_ptr(), ptr::null(), libc::MS_PRIVATE, ptr::null()) } != 0 {
+            warn!("[{pid}] failed to make {} private: {}", info.mount_point.display(), io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
+
+// passes over mountinfo before giving up, a mount stacked under another only shows up once the top one is gone
+const UMOUNT_PASSES: usize = 3;
+
+#[derive(Default)]
+struct Summary {
+    unmounted: usize,
+    // the error of the last attempt for each mount point, none if umount2 succeeded but it is still mounted
+    errors: HashMap<PathBuf, Errno>,
+    remaining: Vec<PathBuf>
+}
+
+impl Summary {
+    // `umount <pid> <unmounted> <failed> [<errno>...]`, see control
+    fn command(&self, pid: i32) -> String {
+        let errors: Vec<_> = self.remaining.iter()
+            .map(|mount_point| self.errors.get(mount_point).map_or("-".into(), |errno| format!("{errno:?}")))
+            .collect();
+
+        format!("umount {pid} {} {} {}", self.unmounted, self.remaining.len(), errors.join(" "))
+    }
+}
+
+fn umount_pass(pid: i32, proc: &Process, rules: &[UmountRule], summary: &mut Summary) -> Result<bool> {
+    let mounts: Vec<MountInfo> = proc.mountinfo()?.into_iter().collect();
+    let selected = select(&mounts, rules);
+
+    if selected.is_empty() {
+        return Ok(false)
+    }
+
+    debug!("[{pid}] found {} files to umount", selected.len());
+
+    make_private(pid, &mounts, &selected)?;
+
+    for mount in selected.into_iter().rev() {
+        let mp = path_cstring(&mount.mount_point)?;
+        debug!("[{pid}] umount: {}", mp.as_str()?);
+
+        if unsafe { libc::umount2(mp.as_ptr(), libc::MNT_DETACH) } == 0 {
+            summary.unmounted += 1;
+            summary.errors.remove(&mount.mount_point);
+        } else {
+            summary.errors.insert(mount.mount_point.clone(), Errno::last());
+        }
+    }
+
+    Ok(true)
+}
+
+fn umount_all(pid: i32, rules: &[UmountRule]) -> Result<Summary> {
+    let link: OwnedFd = File::open(format!("/proc/{}/ns/mnt", pid))?.into();
+    debug!("switching into mount namespace: {pid}");
+    thread::move_into_link_name_space(link.as_fd(), None)?;
+
+    let proc = Process::myself()?;
+    let mut summary = Summary::default();
+
+    for _ in 0 .. UMOUNT_PASSES {
+        if !umount_pass(pid, &proc, rules, &mut summary)? {
+            break
+        }
+    }
+
+    // what is still there after the last pass
+    let mounts: Vec<MountInfo> = proc.mountinfo()?.into_iter().collect();
+    summary.remaining = select(&mounts, rules).into_iter().map(|info| info.mount_point.clone()).collect();
+
+    for mount_point in &summary.remaining {
+        match summary.errors.get(mount_point) {
+            Some(errno) => warn!("[{pid}] failed to umount {}: {errno}", mount_point.display()),
+            None => warn!("[{pid}] {} is still mounted", mount_point.display())
+        }
+    }
+
+    Ok(summary)
+}
+
+pub fn umount_module_files(pid: i32, rules: &[UmountRule]) {
+    // connected before switching, the socket may live under a mount that is about to be unmounted
+    let stream = match control::connect() {
+        Ok(stream) => Some(stream),
+        Err(err) => {
+            warn!("[{pid}] umount results won't be reported: {err}");
+            None
+        }
+    };
+
+    let summary = umount_all(pid, rules);
+
+    if let Err(err) = &summary {
+        error!("failed to umount module files: {err}");
+    }
+
+    let _ = kill(Pid::from_raw(pid), Signal::SIGCONT);
+
+    if let (Some(stream), Ok(summary)) = (stream, summary) {
+        if let Err(err) = control::exchange(stream, &summary.command(pid)) {
+            warn!("[{pid}] failed to report umount results: {err}");
+        }
+    }
+}
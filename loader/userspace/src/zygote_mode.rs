@@ -0,0 +1,85 @@
+use std::fmt::{Display, Formatter};
+use std::fs;
+
+use anyhow::{Context, Result};
+use procfs::process::Process;
+
+use common::properties::getprop;
+
+// flags the primary zygote may be started with, as far as they change what its children go through
+const LAZY_PRELOAD: &str = "--enable-lazy-preload";
+const START_SYSTEM_SERVER: &str = "--start-system-server";
+const SOCKET_NAME: &str = "--socket-name=";
+
+// where the service of `ro.zygote` is defined, before and after the init scripts moved
+const INIT_SCRIPT_DIRS: &[&str] = &["/system/etc/init/hw", "/"];
+
+// unspecialized processes forked ahead of time and kept in a pool, see `ZygoteServer.fillUsapPool()`
+const USAP_POOL_PROPERTY: &str = "persist.device_config.runtime_native.usap_pool_enabled";
+
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct ZygoteMode {
+    // classes and resources are preloaded on the first fork request rather than at start
+    pub lazy_preload: bool,
+    pub start_system_server: bool,
+    pub socket_name: Option<String>,
+    // children may wait in the pool for minutes before they specialize
+    pub usap_pool: bool
+}
+
+impl ZygoteMode {
+    fn parse<'a>(args: impl Iterator<Item = &'a str>) -> Self {
+        let mut mode = Self::default();
+
+        for arg in args {
+            match arg {
+                LAZY_PRELOAD => mode.lazy_preload = true,
+                START_SYSTEM_SERVER => mode.start_system_server = true,
+                arg => if let Some(name) = arg.strip_prefix(SOCKET_NAME) {
+                    mode.socket_name = Some(name.into());
+                }
+            }
+        }
+
+        mode.usap_pool = getprop(USAP_POOL_PROPERTY) == "true";
+        mode
+    }
+
+    // zygote overwrites its argv with its nice name right as it renames itself, so the init script is read
+    // instead once only the name is left
+    pub fn read(pid: i32) -> Result<Self> {
+        let cmdline = Process::new(pid)?.cmdline()?;
+
+        if cmdline.len() > 1 {
+            return Ok(Self::parse(cmdline.iter().map(String::as_str)))
+        }
+
+        let script = format!("init.{}.rc", getprop("ro.zygote"));
+
+        let content = INIT_SCRIPT_DIRS.iter()
+            .find_map(|dir| fs::read_to_string(format!("{dir}/{script}")).ok())
+            .context(format!("{script} is not found"))?;
+
+        // `service zygote /system/bin/app_process64 -Xzygote /system/bin --zygote ...`
+        let service = content.lines()
+            .map(str::trim)
+            .find(|line| line.starts_with("service zygote "))
+            .context(format!("no zygote service in {script}"))?;
+
+        Ok(Self::parse(service.split_whitespace()))
+    }
+
+    pub fn flags(&self) -> Vec<&'static str> {
+        [
+            (self.lazy_preload, "lazy_preload"),
+            (self.start_system_server, "start_system_server"),
+            (self.usap_pool, "usap_pool")
+        ].into_iter().filter_map(|(set, name)| set.then_some(name)).collect()
+    }
+}
+
+impl Display for ZygoteMode {
+    fn fmt(&self, fmt: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(fmt, "socket={} flags=[{}]", self.socket_name.as_deref().unwrap_or("?"), self.flags().join(","))
+    }
+}
@@ -0,0 +1,28 @@
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use aya::maps::{HashMap, MapData};
+use log::info;
+
+// child zygotes (app_zygote, webview_zygote) fork their own children, which must go
+// through the same attach and inject flow as children of the main zygote
+#[derive(Clone)]
+pub struct ChildZygotes {
+    map: Arc<Mutex<HashMap<MapData, i32, u8>>>
+}
+
+impl ChildZygotes {
+    pub fn new(map: HashMap<MapData, i32, u8>) -> Self {
+        Self { map: Arc::new(Mutex::new(map)) }
+    }
+
+    pub fn promote(&self, pid: i32) -> Result<()> {
+        let mut map = self.map.lock().unwrap();
+        map.insert(pid, 1, 0)?;
+
+        info!("[{pid}] tracking child zygote");
+
+        Ok(())
+    }
+
+}
@@ -10,7 +10,19 @@ pub struct Args {
     pub release: bool,
 
     #[clap(long)]
-    pub run: bool
+    pub run: bool,
+
+    /// Payload crates to build along with the loader core
+    #[clap(long, value_delimiter = ',', default_value = "zygisk,lsposed")]
+    pub payloads: Vec<Payload>,
+
+    /// Build only the loader core and the api bridge
+    #[clap(long, conflicts_with = "payloads")]
+    pub minimal: bool,
+
+    /// Start zloader with `--debug-detach` in the packaged modules, works for release builds too
+    #[clap(long)]
+    pub debug_detach: bool
 }
 
 #[derive(EnumString, Debug, Copy, Clone)]
@@ -22,6 +34,24 @@ pub enum Device {
     Physical
 }
 
+#[derive(EnumString, Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Payload {
+    #[strum(serialize = "zygisk")]
+    Zygisk,
+
+    #[strum(serialize = "lsposed")]
+    Lsposed
+}
+
+impl Payload {
+    pub fn package(&self) -> &'static str {
+        match self {
+            Payload::Zygisk => "zygisk-compat",
+            Payload::Lsposed => "lsposed-loader"
+        }
+    }
+}
+
 impl Device {
     pub fn target(&self) -> String {
         match self {
@@ -43,6 +43,7 @@ fn build_userspace(build_configs: &BuildConfigs) -> Result<()> {
         .arg("build")
         .args(["--target", &build_configs.target])
         .also(|cmd| if build_configs.release { cmd.arg("--release"); })
+        .also(|cmd| build_configs.packages().into_iter().for_each(|package| { cmd.args(["-p", package]); }))
         .env(format!("CARGO_TARGET_{}_AR", target_triple), ar)
         .env(format!("CARGO_TARGET_{}_LINKER", target_triple), linker)
         .env("PROFILE", build_configs.profile())
@@ -89,8 +90,13 @@ fn build_ebpf(build_configs: &BuildConfigs) -> Result<()> {
 }
 
 fn build_modules(build_configs: &BuildConfigs) -> Result<()> {
+    let packages = build_configs.packages();
     let makefiles: Vec<_> = glob(&format!("{}/**/Makefile.toml", env!("PROJECT_ROOT")))?
         .flatten()
+        .filter(|makefile| {
+            let dir = makefile.parent().and_then(|dir| dir.file_name());
+            dir.is_some_and(|dir| packages.iter().any(|package| dir == *package))
+        })
         .collect();
 
     for makefile in makefiles {
@@ -99,6 +105,7 @@ fn build_modules(build_configs: &BuildConfigs) -> Result<()> {
             .arg("make")
             .env("PROFILE", build_configs.profile())
             .env("TARGET", &build_configs.target)
+            .env("ZLOADER_ARGS", build_configs.zloader_args())
             .status()?;
     }
     
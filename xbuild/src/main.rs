@@ -1,6 +1,6 @@
 use anyhow::Result;
 
-use crate::args::Args;
+use crate::args::{Args, Payload};
 
 mod args;
 mod build;
@@ -10,19 +10,38 @@ mod adb;
 
 struct BuildConfigs {
     target: String,
-    release: bool
+    release: bool,
+    payloads: Vec<Payload>,
+    debug_detach: bool
 }
 
 impl From<&Args> for BuildConfigs {
     fn from(args: &Args) -> Self {
         Self {
             target: args.device.target(),
-            release: args.release
+            release: args.release,
+            payloads: if args.minimal { Vec::new() } else { args.payloads.clone() },
+            debug_detach: args.debug_detach
         }
     }
 }
 
 impl BuildConfigs {
+    fn packages(&self) -> Vec<&'static str> {
+        let mut packages = vec!["loader", "bridge"];
+        packages.extend(self.payloads.iter().map(|payload| payload.package()));
+        packages
+    }
+
+    // passed on to zloader by the scripts of the packaged modules
+    fn zloader_args(&self) -> &str {
+        if self.debug_detach {
+            "--debug-detach"
+        } else {
+            ""
+        }
+    }
+
     fn profile(&self) -> &str {
         if self.release {
             "release"